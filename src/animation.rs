@@ -0,0 +1,219 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Helpers for compositor-driven animations.
+
+use euclid::point::TypedPoint2D;
+use euclid::scale_factor::ScaleFactor;
+use geometry::{DevicePixel, LayerPixel};
+use layers::{BufferRequest, Layer, LayerBuffer};
+use scene::Scene;
+
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Animates the scale of a scene, e.g. for double-tap zoom.
+///
+/// During the animation the existing tiles are stretched on the GPU, since tiles are positioned
+/// in layer coordinates. Buffers are requested at the destination scale as soon as the animation
+/// starts, so that the content is sharp when the animation arrives rather than one paint later.
+pub struct ScaleAnimation {
+    start_scale: f32,
+    end_scale: f32,
+    start_time: Instant,
+    duration: Duration,
+
+    /// Whether the buffers at the destination scale have been requested.
+    requested_destination: bool,
+}
+
+impl ScaleAnimation {
+    pub fn new(start_scale: ScaleFactor<f32, LayerPixel, DevicePixel>,
+               end_scale: ScaleFactor<f32, LayerPixel, DevicePixel>,
+               start_time: Instant,
+               duration: Duration)
+               -> ScaleAnimation {
+        ScaleAnimation {
+            start_scale: start_scale.get(),
+            end_scale: end_scale.get(),
+            start_time: start_time,
+            duration: duration,
+            requested_destination: false,
+        }
+    }
+
+    /// The scale the animation ends at.
+    pub fn end_scale(&self) -> ScaleFactor<f32, LayerPixel, DevicePixel> {
+        ScaleFactor::new(self.end_scale)
+    }
+
+    /// The progress of the animation at `now`, from 0.0 to 1.0, before easing.
+    fn progress(&self, now: Instant) -> f32 {
+        progress(self.start_time, self.duration, now)
+    }
+
+    pub fn is_finished(&self, now: Instant) -> bool {
+        self.progress(now) >= 1.0
+    }
+
+    /// The scale at `now`. The animation eases in and out, and interpolates the scale
+    /// logarithmically so that zooming in and out feel equally fast.
+    pub fn scale_at(&self, now: Instant) -> ScaleFactor<f32, LayerPixel, DevicePixel> {
+        let t = self.progress(now);
+        if t >= 1.0 || self.start_scale <= 0.0 || self.end_scale <= 0.0 {
+            return ScaleFactor::new(if t >= 1.0 { self.end_scale } else { self.start_scale });
+        }
+        let eased = ease_in_out(t);
+        let (start, end) = (self.start_scale.ln(), self.end_scale.ln());
+        ScaleFactor::new((start + (end - start) * eased).exp())
+    }
+
+    /// Sets the scale of the scene for a frame composited at `now`. Returns true while the
+    /// animation is running, i.e. if another frame should be scheduled.
+    pub fn tick<T>(&self, scene: &mut Scene<T>, now: Instant) -> bool {
+        scene.scale = self.scale_at(now);
+        !self.is_finished(now)
+    }
+
+    /// Returns the buffer requests needed to display the scene at the destination scale. This
+    /// should be used instead of `Scene::get_buffer_requests` while the animation is running;
+    /// intermediate scales are never painted. The first call invalidates the contents of every
+    /// layer, since existing buffers were painted at the starting scale.
+    pub fn get_buffer_requests<T>(&mut self,
+                                  scene: &mut Scene<T>,
+                                  requests: &mut Vec<(Rc<Layer<T>>, Vec<BufferRequest>)>,
+                                  unused_buffers: &mut Vec<Box<LayerBuffer>>) {
+        if !self.requested_destination {
+            scene.mark_layer_contents_as_changed_recursively();
+            self.requested_destination = true;
+        }
+
+        let current_scale = scene.scale;
+        scene.scale = self.end_scale();
+        scene.get_buffer_requests(requests, unused_buffers);
+        scene.scale = current_scale;
+    }
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, ScaleAnimation);
+
+/// Animates the scroll offset of the main root layer of a scene, e.g. for smooth scrolling to an
+/// anchor. See `Scene::animate_scroll_to_rect`.
+pub struct ScrollAnimation {
+    start_offset: TypedPoint2D<f32, LayerPixel>,
+    end_offset: TypedPoint2D<f32, LayerPixel>,
+    start_time: Instant,
+    duration: Duration,
+}
+
+impl ScrollAnimation {
+    /// Animates from one content offset of the root layer to another.
+    pub fn new(start_offset: TypedPoint2D<f32, LayerPixel>,
+               end_offset: TypedPoint2D<f32, LayerPixel>,
+               start_time: Instant,
+               duration: Duration)
+               -> ScrollAnimation {
+        ScrollAnimation {
+            start_offset: start_offset,
+            end_offset: end_offset,
+            start_time: start_time,
+            duration: duration,
+        }
+    }
+
+    /// The content offset the animation ends at.
+    pub fn end_offset(&self) -> TypedPoint2D<f32, LayerPixel> {
+        self.end_offset
+    }
+
+    pub fn is_finished(&self, now: Instant) -> bool {
+        progress(self.start_time, self.duration, now) >= 1.0
+    }
+
+    /// The content offset at `now`, eased in and out.
+    pub fn offset_at(&self, now: Instant) -> TypedPoint2D<f32, LayerPixel> {
+        let eased = ease_in_out(progress(self.start_time, self.duration, now));
+        self.start_offset + (self.end_offset - self.start_offset) * eased
+    }
+
+    /// Sets the content offset of the root layer for a frame composited at `now`. Returns true
+    /// while the animation is running, i.e. if another frame should be scheduled.
+    pub fn tick<T>(&self, scene: &mut Scene<T>, now: Instant) -> bool {
+        if let Some(root_layer) = scene.root() {
+            *root_layer.content_offset.borrow_mut() = self.offset_at(now);
+        }
+        !self.is_finished(now)
+    }
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, ScrollAnimation);
+
+/// Animates the scale and the scroll offset of a scene together, e.g. for double-tap zoom to a
+/// block of text. See `Scene::animate_scale_to_fit_rect`.
+pub struct ZoomAnimation {
+    pub scale: ScaleAnimation,
+    pub scroll: ScrollAnimation,
+}
+
+impl ZoomAnimation {
+    pub fn is_finished(&self, now: Instant) -> bool {
+        self.scale.is_finished(now) && self.scroll.is_finished(now)
+    }
+
+    /// Sets the scale of the scene, the size of its root layer and the content offset of the
+    /// root layer for a frame composited at `now`. Returns true while the animation is running.
+    pub fn tick<T>(&self, scene: &mut Scene<T>, now: Instant) -> bool {
+        self.scale.tick(scene, now);
+        let viewport_size = scene.viewport.size;
+        scene.set_root_layer_size(viewport_size);
+        self.scroll.tick(scene, now);
+        !self.is_finished(now)
+    }
+
+    /// Returns the buffer requests needed at the destination scale and scroll offset. See
+    /// `ScaleAnimation::get_buffer_requests`.
+    pub fn get_buffer_requests<T>(&mut self,
+                                  scene: &mut Scene<T>,
+                                  requests: &mut Vec<(Rc<Layer<T>>, Vec<BufferRequest>)>,
+                                  unused_buffers: &mut Vec<Box<LayerBuffer>>) {
+        let root_layer = match scene.root() {
+            Some(root_layer) => root_layer.clone(),
+            None => return,
+        };
+        let current_offset = *root_layer.content_offset.borrow();
+        let current_bounds = *root_layer.bounds.borrow();
+        *root_layer.content_offset.borrow_mut() = self.scroll.end_offset();
+        root_layer.bounds.borrow_mut().size = scene.viewport.size / self.scale.end_scale();
+        self.scale.get_buffer_requests(scene, requests, unused_buffers);
+        *root_layer.content_offset.borrow_mut() = current_offset;
+        *root_layer.bounds.borrow_mut() = current_bounds;
+    }
+}
+
+/// The progress of an animation at `now`, from 0.0 to 1.0, before easing.
+fn progress(start_time: Instant, duration: Duration, now: Instant) -> f32 {
+    if now <= start_time {
+        return 0.0;
+    }
+    let duration = duration_in_seconds(duration);
+    if duration <= 0.0 {
+        return 1.0;
+    }
+    (duration_in_seconds(now.duration_since(start_time)) / duration).min(1.0)
+}
+
+fn ease_in_out(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn duration_in_seconds(duration: Duration) -> f32 {
+    duration.as_secs() as f32 + duration.subsec_nanos() as f32 / 1.0e9
+}
@@ -0,0 +1,220 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The optional features of the GL implementation.
+//!
+//! They are queried from the version and extension strings of the context, once per thread,
+//! when the first render context is created, instead of being guessed from the target OS.
+//! Everything that relies on an optional feature consults `GlCapabilities::current` and falls
+//! back as documented on the field when it is missing.
+
+use glcapture as gl;
+use gleam::gl::GLuint;
+
+use libc::c_char;
+use std::cell::Cell;
+use std::ffi::CStr;
+
+/// What the GL context supports beyond OpenGL 2.1 and OpenGL ES 2.0.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct GlCapabilities {
+    /// Whether this is OpenGL ES rather than desktop OpenGL.
+    pub is_gles: bool,
+
+    /// The major and minor version of the context.
+    pub version: (u32, u32),
+
+    /// The largest width and height of a texture, in pixels.
+    pub max_texture_size: i32,
+
+    /// Whether the context is at least OpenGL 3.2 or OpenGL ES 3.0, which the compositor
+    /// renders to as core profiles require: the shaders are compiled as GLSL 1.50 or GLSL ES
+    /// 3.00, vertex attributes are set up in a vertex array object, and the fixed-function
+    /// texturing enables are left alone. Otherwise the shaders are compiled as GLSL 1.10 or
+    /// GLSL ES 1.00.
+    pub core_profile: bool,
+
+    /// Whether textures whose sizes aren't powers of two can be mipmapped and repeated. Without
+    /// it, tiles and other textures of arbitrary sizes are still used, since they are clamped to
+    /// the edge and never mipmapped, and repeating wrap modes fall back to clamping for them.
+    pub npot_textures: bool,
+
+    /// Whether rectangle textures are supported, which IOSurfaces must be bound to. Without
+    /// them, buffers are always uploaded into 2D textures.
+    pub rectangle_textures: bool,
+
+    /// Whether fence sync objects are supported. Without them, the background upload thread
+    /// finishes each upload before handing the texture to the compositor.
+    pub sync_objects: bool,
+
+    /// Whether timer queries can measure how long the GPU spends on a frame. Profiling
+    /// measures CPU time either way.
+    pub timer_queries: bool,
+
+    /// Whether instanced drawing is supported. Quads are drawn with a draw call each either
+    /// way.
+    pub instancing: bool,
+
+    /// Whether multisampled renderbuffers and framebuffer blits are supported. Without them,
+    /// the requested sample count is ignored and scenes are rendered without multisampling.
+    pub framebuffer_multisample: bool,
+
+    /// Whether sampler objects are supported. Without them, filter modes are set on the
+    /// textures themselves.
+    pub sampler_objects: bool,
+
+    /// Whether sampling sRGB textures can skip the conversion to linear, with
+    /// `GL_EXT_texture_sRGB_decode`. Without it, sRGB textures are always linearized.
+    pub srgb_decode: bool,
+
+    /// Whether the conversion to sRGB on writes to the framebuffer can be switched on and off.
+    /// Without it, the framebuffer converts if and only if it is sRGB-encoded.
+    pub srgb_write_control: bool,
+
+    /// Whether uniform buffers can be used from the shaders. Without them, the frame constants
+    /// are plain uniforms of each program.
+    pub uniform_buffers: bool,
+
+    /// Whether texture channels can be swizzled when sampled. Without it, BGRA data is
+    /// converted on the CPU when the GL implementation can't take it directly.
+    pub texture_swizzle: bool,
+
+    /// Whether BGRA data can be uploaded to OpenGL ES textures directly, with
+    /// `GL_EXT_texture_format_BGRA8888`. Desktop OpenGL always can.
+    pub bgra_textures: bool,
+
+    /// Whether textures can be uploaded from pixel buffer objects, which lets the driver copy
+    /// the data to the GPU asynchronously. Without them, `UploadMethod::PixelBuffer` falls back
+    /// to `TexSubImage`.
+    pub pixel_buffers: bool,
+
+    /// Whether buffer objects can be mapped into client memory with `glMapBufferRange`, as from
+    /// OpenGL 3.0, OpenGL ES 3.0 or `GL_ARB_map_buffer_range`.
+    pub map_buffer_range: bool,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, GlCapabilities);
+
+thread_local!(static CAPABILITIES: Cell<Option<GlCapabilities>> = Cell::new(None));
+
+impl GlCapabilities {
+    /// Queries the capabilities of the GL context current on this thread.
+    pub fn query() -> GlCapabilities {
+        let version_string = gl::get_string(gl::VERSION);
+        let is_gles = version_string.starts_with("OpenGL ES");
+        let version = parse_version(version_string.trim_left_matches("OpenGL ES "));
+        let extensions = query_extensions(version);
+        let has_extension = |name: &str| extensions.iter().any(|ext| ext == name);
+        let max_texture_size = gl::get_integer_v(gl::MAX_TEXTURE_SIZE);
+
+        if is_gles {
+            let gles3 = version >= (3, 0);
+            GlCapabilities {
+                is_gles: true,
+                version: version,
+                max_texture_size: max_texture_size,
+                core_profile: gles3,
+                npot_textures: gles3 || has_extension("GL_OES_texture_npot"),
+                rectangle_textures: false,
+                sync_objects: gles3 || has_extension("GL_APPLE_sync"),
+                timer_queries: has_extension("GL_EXT_disjoint_timer_query"),
+                instancing: gles3 || has_extension("GL_EXT_instanced_arrays"),
+                framebuffer_multisample: gles3,
+                sampler_objects: gles3,
+                srgb_decode: has_extension("GL_EXT_texture_sRGB_decode"),
+                srgb_write_control: has_extension("GL_EXT_sRGB_write_control"),
+                // GLSL ES 1.00 has no uniform blocks.
+                uniform_buffers: gles3,
+                texture_swizzle: gles3,
+                bgra_textures: has_extension("GL_EXT_texture_format_BGRA8888"),
+                pixel_buffers: gles3 || has_extension("GL_NV_pixel_buffer_object"),
+                map_buffer_range: gles3 || has_extension("GL_EXT_map_buffer_range"),
+            }
+        } else {
+            GlCapabilities {
+                is_gles: false,
+                version: version,
+                max_texture_size: max_texture_size,
+                core_profile: version >= (3, 2),
+                npot_textures: version >= (2, 0) ||
+                               has_extension("GL_ARB_texture_non_power_of_two"),
+                rectangle_textures: version >= (3, 1) ||
+                                    has_extension("GL_ARB_texture_rectangle") ||
+                                    has_extension("GL_EXT_texture_rectangle"),
+                sync_objects: version >= (3, 2) || has_extension("GL_ARB_sync"),
+                timer_queries: version >= (3, 3) || has_extension("GL_ARB_timer_query"),
+                instancing: version >= (3, 3) || has_extension("GL_ARB_instanced_arrays"),
+                framebuffer_multisample: version >= (3, 0) ||
+                                         has_extension("GL_ARB_framebuffer_object"),
+                sampler_objects: version >= (3, 3) || has_extension("GL_ARB_sampler_objects"),
+                srgb_decode: has_extension("GL_EXT_texture_sRGB_decode"),
+                srgb_write_control: version >= (3, 0) ||
+                                    has_extension("GL_ARB_framebuffer_sRGB") ||
+                                    has_extension("GL_EXT_framebuffer_sRGB"),
+                uniform_buffers: version >= (3, 2) ||
+                                 has_extension("GL_ARB_uniform_buffer_object"),
+                texture_swizzle: version >= (3, 3) || has_extension("GL_ARB_texture_swizzle"),
+                bgra_textures: true,
+                pixel_buffers: version >= (2, 1) ||
+                               has_extension("GL_ARB_pixel_buffer_object"),
+                map_buffer_range: version >= (3, 0) ||
+                                  has_extension("GL_ARB_map_buffer_range"),
+            }
+        }
+    }
+
+    /// The capabilities of the GL context current on this thread, queried on first use. All
+    /// contexts used by a thread are assumed to be of the same kind.
+    pub fn current() -> GlCapabilities {
+        CAPABILITIES.with(|capabilities| {
+            if let Some(current) = capabilities.get() {
+                return current;
+            }
+            let current = GlCapabilities::query();
+            info!("GL capabilities: {:?}", current);
+            capabilities.set(Some(current));
+            current
+        })
+    }
+
+    /// Overrides the queried capabilities for this thread, e.g. to work around drivers that
+    /// misreport them, or to test the fallbacks. `None` queries them again on the next use.
+    pub fn set_current(capabilities: Option<GlCapabilities>) {
+        CAPABILITIES.with(|current| current.set(capabilities));
+    }
+}
+
+/// The extensions of the context current on this thread. Core profiles only list them one by
+/// one, which OpenGL 3.0 and OpenGL ES 3.0 support.
+fn query_extensions(version: (u32, u32)) -> Vec<String> {
+    if version < (3, 0) {
+        return gl::get_string(gl::EXTENSIONS).split_whitespace()
+                                             .map(|extension| extension.to_owned())
+                                             .collect();
+    }
+    let count = gl::get_integer_v(gl::NUM_EXTENSIONS) as GLuint;
+    (0..count).filter_map(|index| {
+        let name = unsafe { gl::GetStringi(gl::EXTENSIONS, index) };
+        if name.is_null() {
+            return None;
+        }
+        let name = unsafe { CStr::from_ptr(name as *const c_char) };
+        Some(name.to_string_lossy().into_owned())
+    }).collect()
+}
+
+/// Parses the major and minor version at the start of a GL version string, e.g. "3.3.0 NVIDIA".
+fn parse_version(version: &str) -> (u32, u32) {
+    let mut numbers = version.split(|c: char| !c.is_digit(10))
+                             .map(|number| number.parse::<u32>().unwrap_or(0));
+    let major = numbers.next().unwrap_or(0);
+    let minor = numbers.next().unwrap_or(0);
+    (major, minor)
+}
@@ -0,0 +1,408 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Streams of composited frames, for tab capture, screen recording and screen sharing.
+//!
+//! `Scene::capture_frames` returns the receiving end of a stream. After each composite, the
+//! frame is read back from the framebuffer for every stream that is due for one according to
+//! its frame rate, downscaled on the GPU to the stream's scale first so that less data crosses
+//! the bus. Streams with the same scale share the readback. Dropping the receiver ends the
+//! stream.
+//!
+//! With pixel buffer objects, the frame is read back into a buffer that is only mapped after
+//! the next composite, so that the compositor doesn't wait for the GPU to finish the frame; the
+//! frame is then sent one composite late. Without them, the frame is read back synchronously.
+//!
+//! Each stream queues at most `MAX_QUEUED_FRAMES` frames. Frames captured while the receiver
+//! is that far behind are dropped rather than queued without bound.
+
+use glcapture as gl;
+use gleam::gl::{GLbitfield, GLenum, GLint, GLsizei, GLsizeiptr, GLuint};
+
+use capabilities::GlCapabilities;
+use euclid::point::Point2D;
+use euclid::rect::Rect;
+use euclid::size::Size2D;
+use std::cell::RefCell;
+use std::cmp;
+use std::ptr;
+use std::slice;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::time::{Duration, Instant};
+
+/// The number of frames a stream holds for its receiver before dropping new ones.
+pub const MAX_QUEUED_FRAMES: usize = 3;
+
+// From OpenGL 2.1, OpenGL ES 3.0 and `GL_ARB_map_buffer_range`, which the bindings may not
+// include.
+const PIXEL_PACK_BUFFER: GLenum = 0x88EB;
+const STREAM_READ: GLenum = 0x88E1;
+const MAP_READ_BIT: GLbitfield = 0x0001;
+/// How often, and at which size, frames are captured for a stream.
+#[derive(Copy, Clone, Debug)]
+pub struct CaptureConfig {
+    /// The maximum number of frames per second sent on the stream. Frames composited more often
+    /// are skipped.
+    pub max_frame_rate: f32,
+
+    /// The size of the captured frames relative to the viewport, at most 1.
+    pub scale: f32,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, CaptureConfig);
+
+impl CaptureConfig {
+    pub fn new(max_frame_rate: f32, scale: f32) -> CaptureConfig {
+        CaptureConfig {
+            max_frame_rate: max_frame_rate,
+            scale: scale,
+        }
+    }
+
+    /// The shortest time between two frames of the stream.
+    fn frame_interval(&self) -> Duration {
+        if !(self.max_frame_rate > 0.0) {
+            return Duration::new(0, 0);
+        }
+        let nanos = (1.0e9 / self.max_frame_rate as f64) as u64;
+        Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+    }
+
+    /// The size of the frames captured from a viewport of the given size.
+    fn frame_size(&self, viewport_size: &Size2D<GLsizei>) -> Size2D<GLsizei> {
+        let scale = self.scale.max(0.0).min(1.0);
+        Size2D::new(cmp::max((viewport_size.width as f32 * scale).round() as GLsizei, 1),
+                    cmp::max((viewport_size.height as f32 * scale).round() as GLsizei, 1))
+    }
+}
+
+/// A composited frame sent on a capture stream.
+#[derive(Clone, Debug)]
+pub struct CapturedFrame {
+    /// The size of the frame in pixels.
+    pub size: Size2D<usize>,
+
+    /// Tightly packed RGBA rows, ordered from top to bottom.
+    pub pixels: Vec<u8>,
+
+    /// When the frame was composited.
+    pub composited_at: Instant,
+}
+
+struct CaptureStream {
+    config: CaptureConfig,
+    sender: SyncSender<CapturedFrame>,
+
+    /// When the last frame was captured for the stream.
+    last_capture: Option<Instant>,
+
+    /// The size of the frame being read back for the stream, to be sent after the next
+    /// composite.
+    pending_size: Option<Size2D<GLsizei>>,
+}
+
+impl CaptureStream {
+    /// Sends a frame, or drops it if the receiver is behind. Returns false if the receiver was
+    /// dropped.
+    fn send(&self, frame: CapturedFrame) -> bool {
+        match self.sender.try_send(frame) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                debug!("Dropping a captured frame, {} frames are queued", MAX_QUEUED_FRAMES);
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+/// A frame being read back into a pixel buffer object.
+struct PendingFrame {
+    size: Size2D<GLsizei>,
+    buffer: GLuint,
+    composited_at: Instant,
+}
+
+/// The capture streams of a scene.
+pub struct CaptureStreams {
+    streams: RefCell<Vec<CaptureStream>>,
+
+    /// The frames read back after the last composite, one per size.
+    pending_frames: RefCell<Vec<PendingFrame>>,
+
+    /// Pixel buffer objects to read the next frames back into.
+    free_buffers: RefCell<Vec<GLuint>>,
+}
+
+impl CaptureStreams {
+    pub fn new() -> CaptureStreams {
+        CaptureStreams {
+            streams: RefCell::new(vec!()),
+            pending_frames: RefCell::new(vec!()),
+            free_buffers: RefCell::new(vec!()),
+        }
+    }
+
+    /// Starts a new stream of the frames composited from now on.
+    pub fn subscribe(&self, config: CaptureConfig) -> Receiver<CapturedFrame> {
+        let (sender, receiver) = mpsc::sync_channel(MAX_QUEUED_FRAMES);
+        self.streams.borrow_mut().push(CaptureStream {
+            config: config,
+            sender: sender,
+            last_capture: None,
+            pending_size: None,
+        });
+        receiver
+    }
+
+    /// The number of streams that haven't been dropped yet, as of the last captured frame.
+    pub fn len(&self) -> usize {
+        self.streams.borrow().len()
+    }
+
+    /// Sends the frames read back after the last composite, then captures the frame just
+    /// composited into `viewport` of the current framebuffer for the streams that are due for
+    /// a frame. Streams whose receiver was dropped are removed.
+    pub fn capture(&self, viewport: &Rect<GLint>, composited_at: Instant) {
+        self.send_pending_frames();
+
+        let mut streams = self.streams.borrow_mut();
+        if streams.is_empty() {
+            self.delete_buffers();
+            return;
+        }
+
+        let capabilities = GlCapabilities::current();
+        let asynchronous = capabilities.pixel_buffers && capabilities.map_buffer_range;
+
+        // Frames read back synchronously at each size, shared by the streams that want them.
+        let mut frames: Vec<(Size2D<GLsizei>, Vec<u8>)> = vec!();
+        let mut closed_streams = vec!();
+        for (index, stream) in streams.iter_mut().enumerate() {
+            if let Some(last_capture) = stream.last_capture {
+                if composited_at.duration_since(last_capture) < stream.config.frame_interval() {
+                    continue;
+                }
+            }
+            stream.last_capture = Some(composited_at);
+
+            let size = stream.config.frame_size(&viewport.size);
+            if asynchronous {
+                self.start_read_back(viewport, &size, composited_at);
+                stream.pending_size = Some(size);
+                continue;
+            }
+
+            let pixels = match frames.iter().position(|&(frame_size, _)| frame_size == size) {
+                Some(frame_index) => frames[frame_index].1.clone(),
+                None => {
+                    let pixels = read_back(viewport, &size);
+                    frames.push((size, pixels.clone()));
+                    pixels
+                }
+            };
+            let frame = CapturedFrame {
+                size: Size2D::new(size.width as usize, size.height as usize),
+                pixels: pixels,
+                composited_at: composited_at,
+            };
+            if !stream.send(frame) {
+                closed_streams.push(index);
+            }
+        }
+
+        for index in closed_streams.into_iter().rev() {
+            debug!("Capture stream {} was closed", index);
+            streams.remove(index);
+        }
+    }
+
+    /// Starts reading back `viewport` of the current framebuffer at `size` into a pixel buffer
+    /// object, unless a frame of that size is already being read back.
+    fn start_read_back(&self,
+                       viewport: &Rect<GLint>,
+                       size: &Size2D<GLsizei>,
+                       composited_at: Instant) {
+        let mut pending_frames = self.pending_frames.borrow_mut();
+        if pending_frames.iter().any(|frame| frame.size == *size) {
+            return;
+        }
+
+        let buffer = match self.free_buffers.borrow_mut().pop() {
+            Some(buffer) => buffer,
+            None => gl::gen_buffers(1)[0],
+        };
+        gl::bind_buffer(PIXEL_PACK_BUFFER, buffer);
+        unsafe {
+            gl::BufferData(PIXEL_PACK_BUFFER,
+                           frame_byte_size(size) as GLsizeiptr,
+                           ptr::null(),
+                           STREAM_READ);
+        }
+        with_scaled_frame(viewport, size, |x, y| {
+            unsafe {
+                gl::ReadPixels(x, y, size.width, size.height, gl::RGBA, gl::UNSIGNED_BYTE,
+                               ptr::null_mut());
+            }
+        });
+        gl::bind_buffer(PIXEL_PACK_BUFFER, 0);
+
+        pending_frames.push(PendingFrame {
+            size: *size,
+            buffer: buffer,
+            composited_at: composited_at,
+        });
+    }
+
+    /// Maps the frames read back after the last composite and sends them to the streams they
+    /// were read back for. By now the GPU has usually finished writing them.
+    fn send_pending_frames(&self) {
+        let pending_frames: Vec<_> = self.pending_frames.borrow_mut().drain(..).collect();
+        if pending_frames.is_empty() {
+            return;
+        }
+
+        let mut streams = self.streams.borrow_mut();
+        for pending_frame in pending_frames {
+            let size = pending_frame.size;
+            gl::bind_buffer(PIXEL_PACK_BUFFER, pending_frame.buffer);
+            let pixels = unsafe {
+                let length = frame_byte_size(&size);
+                let data = gl::MapBufferRange(PIXEL_PACK_BUFFER,
+                                              0,
+                                              length as GLsizeiptr,
+                                              MAP_READ_BIT);
+                if data.is_null() {
+                    None
+                } else {
+                    let pixels = slice::from_raw_parts(data as *const u8, length).to_vec();
+                    gl::UnmapBuffer(PIXEL_PACK_BUFFER);
+                    Some(pixels)
+                }
+            };
+            gl::bind_buffer(PIXEL_PACK_BUFFER, 0);
+            self.free_buffers.borrow_mut().push(pending_frame.buffer);
+
+            let pixels = pixels.map(|pixels| flip_rows(pixels, &size));
+            if pixels.is_none() {
+                warn!("Couldn't map a captured frame of {:?}", size);
+            }
+
+            let mut closed_streams = vec!();
+            for (index, stream) in streams.iter_mut().enumerate() {
+                if stream.pending_size != Some(size) {
+                    continue;
+                }
+                stream.pending_size = None;
+                if let Some(ref pixels) = pixels {
+                    let frame = CapturedFrame {
+                        size: Size2D::new(size.width as usize, size.height as usize),
+                        pixels: pixels.clone(),
+                        composited_at: pending_frame.composited_at,
+                    };
+                    if !stream.send(frame) {
+                        closed_streams.push(index);
+                    }
+                }
+            }
+            for index in closed_streams.into_iter().rev() {
+                debug!("Capture stream {} was closed", index);
+                streams.remove(index);
+            }
+        }
+    }
+
+    fn delete_buffers(&self) {
+        let mut free_buffers = self.free_buffers.borrow_mut();
+        if !free_buffers.is_empty() {
+            gl::delete_buffers(&free_buffers);
+            free_buffers.clear();
+        }
+    }
+
+    /// Forgets the pixel buffer objects of the streams without deleting them, for when the GL
+    /// context they belonged to was lost. The frames being read back are dropped.
+    pub fn forget_buffers(&self) {
+        self.pending_frames.borrow_mut().clear();
+        self.free_buffers.borrow_mut().clear();
+        for stream in self.streams.borrow_mut().iter_mut() {
+            stream.pending_size = None;
+        }
+    }
+}
+
+impl Drop for CaptureStreams {
+    fn drop(&mut self) {
+        let buffers: Vec<_> = self.pending_frames.borrow().iter().map(|frame| {
+            frame.buffer
+        }).collect();
+        self.free_buffers.borrow_mut().extend(buffers);
+        self.delete_buffers();
+    }
+}
+
+fn frame_byte_size(size: &Size2D<GLsizei>) -> usize {
+    size.width as usize * size.height as usize * 4
+}
+
+/// Binds a framebuffer holding `viewport` of the current framebuffer scaled to `size` with
+/// linear filtering for reading, and calls `read` with the origin of the frame in it.
+fn with_scaled_frame<F, R>(viewport: &Rect<GLint>, size: &Size2D<GLsizei>, read: F) -> R
+                           where F: FnOnce(GLint, GLint) -> R {
+    if *size == viewport.size {
+        return read(viewport.origin.x, viewport.origin.y);
+    }
+
+    let source_framebuffer = gl::get_integer_v(gl::FRAMEBUFFER_BINDING) as GLuint;
+    let renderbuffer = gl::gen_renderbuffers(1)[0];
+    gl::bind_renderbuffer(gl::RENDERBUFFER, renderbuffer);
+    gl::renderbuffer_storage(gl::RENDERBUFFER, gl::RGBA8, size.width, size.height);
+    gl::bind_renderbuffer(gl::RENDERBUFFER, 0);
+
+    let framebuffer = gl::gen_framebuffers(1)[0];
+    gl::bind_framebuffer(gl::DRAW_FRAMEBUFFER, framebuffer);
+    gl::framebuffer_renderbuffer(gl::DRAW_FRAMEBUFFER,
+                                 gl::COLOR_ATTACHMENT0,
+                                 gl::RENDERBUFFER,
+                                 renderbuffer);
+    let max = Point2D::new(viewport.max_x(), viewport.max_y());
+    unsafe {
+        gl::BlitFramebuffer(viewport.origin.x, viewport.origin.y, max.x, max.y,
+                            0, 0, size.width, size.height,
+                            gl::COLOR_BUFFER_BIT,
+                            gl::LINEAR);
+    }
+
+    gl::bind_framebuffer(gl::READ_FRAMEBUFFER, framebuffer);
+    let result = read(0, 0);
+    gl::bind_framebuffer(gl::FRAMEBUFFER, source_framebuffer);
+    gl::delete_framebuffers(&[framebuffer]);
+    gl::delete_renderbuffers(&[renderbuffer]);
+    result
+}
+
+/// Reads back `viewport` of the current framebuffer, scaled to `size` with linear filtering, as
+/// RGBA rows ordered from top to bottom.
+fn read_back(viewport: &Rect<GLint>, size: &Size2D<GLsizei>) -> Vec<u8> {
+    let pixels = with_scaled_frame(viewport, size, |x, y| {
+        gl::read_pixels(x, y, size.width, size.height, gl::RGBA, gl::UNSIGNED_BYTE)
+    });
+    flip_rows(pixels, size)
+}
+
+/// Orders the rows read back from OpenGL, which returns them bottom-up, from top to bottom.
+fn flip_rows(pixels: Vec<u8>, size: &Size2D<GLsizei>) -> Vec<u8> {
+    let stride = size.width as usize * 4;
+    let mut flipped = Vec::with_capacity(pixels.len());
+    for row in (0..size.height as usize).rev() {
+        flipped.extend_from_slice(&pixels[row * stride..(row + 1) * stride]);
+    }
+    flipped
+}
@@ -17,3 +17,38 @@ pub struct Color {
 
 #[cfg(feature = "heapsize")]
 known_heap_size!(0, Color);
+
+impl Color {
+    /// The color of a white light of the given temperature in kelvins, as the factors each
+    /// channel is multiplied with to shift the white point of a display. 6600K gives white;
+    /// lower temperatures are warmer, e.g. 3400K for a typical night light, and higher ones
+    /// cooler. This uses Tanner Helland's fit of the blackbody colors, valid from 1000K to
+    /// 40000K.
+    pub fn from_temperature(kelvins: f32) -> Color {
+        let t = kelvins.max(1000.0).min(40000.0) / 100.0;
+        let red = if t <= 66.0 {
+            255.0
+        } else {
+            329.698727446 * (t - 60.0).powf(-0.1332047592)
+        };
+        let green = if t <= 66.0 {
+            99.4708025861 * t.ln() - 161.1195681661
+        } else {
+            288.1221695283 * (t - 60.0).powf(-0.0755148492)
+        };
+        let blue = if t >= 66.0 {
+            255.0
+        } else if t <= 19.0 {
+            0.0
+        } else {
+            138.5177312231 * (t - 10.0).ln() - 305.0447927307
+        };
+        let channel = |value: f32| value.max(0.0).min(255.0) / 255.0;
+        Color {
+            r: channel(red),
+            g: channel(green),
+            b: channel(blue),
+            a: 1.0,
+        }
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Image comparison utilities for reftests, meant to be used on the output of
+//! `rendergl::read_back_scene`.
+
+use std::io::{self, Write};
+
+/// The largest possible squared YIQ distance between two colors.
+const MAX_YIQ_DELTA: f32 = 35215.0;
+
+/// Tolerances used when comparing two images.
+#[derive(Copy, Clone, Debug)]
+pub struct CompareOptions {
+    /// Channel differences up to this value are not counted as differing pixels.
+    pub channel_tolerance: u8,
+
+    /// The perceptual difference, from 0.0 to 1.0, above which a pixel is considered visibly
+    /// different.
+    pub perceptual_threshold: f32,
+}
+
+impl CompareOptions {
+    /// Options that only accept identical images.
+    pub fn exact() -> CompareOptions {
+        CompareOptions {
+            channel_tolerance: 0,
+            perceptual_threshold: 0.0,
+        }
+    }
+}
+
+/// The result of comparing two RGBA images of the same size.
+pub struct ImageComparison {
+    /// The largest difference of any single channel of any pixel.
+    pub max_channel_delta: u8,
+
+    /// The number of pixels with a channel difference above the tolerance.
+    pub differing_pixels: usize,
+
+    /// The number of pixels whose perceptual difference is above the threshold.
+    pub perceptually_differing_pixels: usize,
+
+    /// The largest perceptual difference of any pixel, from 0.0 to 1.0.
+    pub max_perceptual_delta: f32,
+
+    /// An RGBA image of the same size as the inputs, with differing pixels drawn in red over a
+    /// faded copy of the first image.
+    pub diff_image: Vec<u8>,
+
+    pub width: usize,
+    pub height: usize,
+}
+
+impl ImageComparison {
+    /// Returns true if no pixel differs beyond the tolerances used for the comparison.
+    pub fn is_match(&self) -> bool {
+        self.differing_pixels == 0 && self.perceptually_differing_pixels == 0
+    }
+
+    /// Writes the diff image as a binary PPM, which most image viewers understand.
+    pub fn write_diff_image<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_ppm(writer, &self.diff_image, self.width, self.height)
+    }
+}
+
+/// Converts an RGB color to its luma and chroma components.
+fn rgb_to_yiq(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+    (r * 0.29889531 + g * 0.58662247 + b * 0.11448223,
+     r * 0.59597799 - g * 0.27417610 - b * 0.32180189,
+     r * 0.21147017 - g * 0.52261711 + b * 0.31114694)
+}
+
+/// Returns the perceptual difference between two RGBA pixels, from 0.0 to 1.0. Pixels are
+/// blended against white first, so that differences in invisible pixels don't count.
+fn perceptual_delta(a: &[u8], b: &[u8]) -> f32 {
+    let blend = |pixel: &[u8], channel: usize| {
+        let alpha = pixel[3] as f32 / 255.0;
+        255.0 + (pixel[channel] as f32 - 255.0) * alpha
+    };
+
+    let (y1, i1, q1) = rgb_to_yiq(blend(a, 0), blend(a, 1), blend(a, 2));
+    let (y2, i2, q2) = rgb_to_yiq(blend(b, 0), blend(b, 1), blend(b, 2));
+    let (dy, di, dq) = (y1 - y2, i1 - i2, q1 - q2);
+    (0.5053 * dy * dy + 0.299 * di * di + 0.1957 * dq * dq) / MAX_YIQ_DELTA
+}
+
+/// Compares two tightly packed RGBA images of the given size.
+pub fn compare_images(expected: &[u8],
+                      actual: &[u8],
+                      width: usize,
+                      height: usize,
+                      options: &CompareOptions)
+                      -> ImageComparison {
+    let len = width * height * 4;
+    assert!(expected.len() >= len && actual.len() >= len, "Images are smaller than their size");
+
+    let mut comparison = ImageComparison {
+        max_channel_delta: 0,
+        differing_pixels: 0,
+        perceptually_differing_pixels: 0,
+        max_perceptual_delta: 0.0,
+        diff_image: Vec::with_capacity(len),
+        width: width,
+        height: height,
+    };
+
+    for (a, b) in expected[..len].chunks(4).zip(actual[..len].chunks(4)) {
+        let channel_delta = (0..4).map(|i| (a[i] as i32 - b[i] as i32).abs() as u8).max().unwrap();
+        let delta = perceptual_delta(a, b);
+
+        comparison.max_channel_delta = comparison.max_channel_delta.max(channel_delta);
+        comparison.max_perceptual_delta = comparison.max_perceptual_delta.max(delta);
+
+        let differs = channel_delta > options.channel_tolerance;
+        let perceptually_differs = delta > options.perceptual_threshold;
+        if differs {
+            comparison.differing_pixels += 1;
+        }
+        if perceptually_differs {
+            comparison.perceptually_differing_pixels += 1;
+        }
+
+        if differs || perceptually_differs {
+            comparison.diff_image.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            let (y, _, _) = rgb_to_yiq(a[0] as f32, a[1] as f32, a[2] as f32);
+            let faded = (255.0 - (255.0 - y) * 0.1) as u8;
+            comparison.diff_image.extend_from_slice(&[faded, faded, faded, 255]);
+        }
+    }
+
+    comparison
+}
+
+/// Writes a tightly packed RGBA image as a binary PPM, dropping the alpha channel.
+pub fn write_ppm<W: Write>(writer: &mut W, pixels: &[u8], width: usize, height: usize)
+                           -> io::Result<()> {
+    try!(write!(writer, "P6\n{} {}\n255\n", width, height));
+    for pixel in pixels[..width * height * 4].chunks(4) {
+        try!(writer.write_all(&pixel[..3]));
+    }
+    Ok(())
+}
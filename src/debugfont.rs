@@ -0,0 +1,164 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A tiny embedded bitmap font for debug labels and overlay text, so that text can be drawn
+//! without any font machinery on the compositor side. The glyphs are uploaded once into an
+//! atlas texture, and each character is drawn as a textured quad.
+//!
+//! Glyphs are 3 pixels wide and 5 pixels tall. Each row is stored as a 3-bit mask, with the most
+//! significant bit being the leftmost pixel. Lowercase letters are drawn as uppercase, and
+//! characters without a glyph are drawn as `?`.
+
+use euclid::{Point2D, Rect, Size2D};
+use std::cmp;
+
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+
+/// The horizontal distance between the origins of two consecutive glyphs, in font pixels.
+pub const GLYPH_ADVANCE: usize = GLYPH_WIDTH + 1;
+
+/// The vertical distance between two consecutive lines, in font pixels.
+pub const LINE_ADVANCE: usize = GLYPH_HEIGHT + 1;
+
+type Glyph = [u8; GLYPH_HEIGHT];
+
+static UNKNOWN_GLYPH: Glyph = [0b111, 0b001, 0b010, 0b000, 0b010];
+
+fn glyph(character: char) -> Glyph {
+    let character = if character >= 'a' && character <= 'z' {
+        ((character as u8) - b'a' + b'A') as char
+    } else {
+        character
+    };
+    match character {
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b011, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        '[' => [0b011, 0b010, 0b010, 0b010, 0b011],
+        ']' => [0b110, 0b010, 0b010, 0b010, 0b110],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '*' => [0b000, 0b101, 0b010, 0b101, 0b000],
+        '<' => [0b001, 0b010, 0b100, 0b010, 0b001],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        _ => UNKNOWN_GLYPH,
+    }
+}
+
+/// The size of `text` in font pixels, as `(width, height)`. Lines are separated by `\n`.
+pub fn text_size(text: &str) -> (usize, usize) {
+    let mut width = 0;
+    let mut lines = 0;
+    for line in text.lines() {
+        width = cmp::max(width, line.chars().count() * GLYPH_ADVANCE);
+        lines += 1;
+    }
+    (width.saturating_sub(1), (lines * LINE_ADVANCE).saturating_sub(1))
+}
+
+/// The first and last characters with a glyph in the atlas. Other characters are drawn as `?`.
+const ATLAS_FIRST_CHARACTER: char = ' ';
+const ATLAS_LAST_CHARACTER: char = '~';
+
+fn atlas_index(character: char) -> usize {
+    let character = if character >= ATLAS_FIRST_CHARACTER && character <= ATLAS_LAST_CHARACTER {
+        character
+    } else {
+        '?'
+    };
+    character as usize - ATLAS_FIRST_CHARACTER as usize
+}
+
+/// The size of the glyph atlas in font pixels. Glyphs are laid out in a single row, with a
+/// blank column between them so that they don't bleed into each other when sampled.
+pub fn atlas_size() -> Size2D<usize> {
+    let glyph_count = atlas_index(ATLAS_LAST_CHARACTER) + 1;
+    Size2D::new(glyph_count * GLYPH_ADVANCE, GLYPH_HEIGHT)
+}
+
+/// The pixels of the glyph atlas as tightly packed BGRA rows, white where glyphs are lit and
+/// transparent elsewhere.
+pub fn atlas_pixels() -> Vec<u8> {
+    let size = atlas_size();
+    let mut pixels = vec!(0; size.width * size.height * 4);
+    for index in 0..(atlas_index(ATLAS_LAST_CHARACTER) + 1) {
+        let character = (ATLAS_FIRST_CHARACTER as u8 + index as u8) as char;
+        for (row, &mask) in glyph(character).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if mask & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                    let offset = (row * size.width + index * GLYPH_ADVANCE + column) * 4;
+                    for byte in &mut pixels[offset..offset + 4] {
+                        *byte = 255;
+                    }
+                }
+            }
+        }
+    }
+    pixels
+}
+
+/// Calls `f` with the position of each glyph of `text`, in font pixels relative to the top left
+/// of the text, and the rect of its glyph in the atlas.
+pub fn for_each_glyph<F>(text: &str, mut f: F) where F: FnMut(Point2D<usize>, Rect<usize>) {
+    for (line_index, line) in text.lines().enumerate() {
+        for (column, character) in line.chars().enumerate() {
+            if character == ' ' {
+                continue;
+            }
+            let atlas_rect = Rect::new(Point2D::new(atlas_index(character) * GLYPH_ADVANCE, 0),
+                                       Size2D::new(GLYPH_WIDTH, GLYPH_HEIGHT));
+            f(Point2D::new(column * GLYPH_ADVANCE, line_index * LINE_ADVANCE), atlas_rect);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Artificial delays simulating a slow GPU and slow painting, for testing how embedders
+//! schedule frames, handle checkerboarding and prioritize tiles under stress.
+//!
+//! The delays block the compositor thread where a slow driver would make it wait. Painted
+//! buffers are instead held by their layer, as if the renderer replied late, and shown by the
+//! first composite after their delay; see `Scene::next_delayed_buffer_release`. Like the upload
+//! method, the delays apply to the thread they are set on, and are usually set with
+//! `RenderContextBuilder::simulated_delays`. There are none by default.
+
+use std::cell::Cell;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to stall the compositor thread at each step, or to hold painted buffers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SimulatedDelays {
+    /// Added to each texture upload of a tile.
+    pub upload: Duration,
+
+    /// Added to each draw call of textured content, such as a tile or the repetitions of a tile.
+    pub draw_batch: Duration,
+
+    /// How long each painted buffer handed to a layer is held before its tile shows it.
+    pub buffer_reply: Duration,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, SimulatedDelays);
+
+thread_local!(static SIMULATED_DELAYS: Cell<SimulatedDelays> =
+                  Cell::new(SimulatedDelays::none()));
+
+impl SimulatedDelays {
+    /// No delays at all.
+    pub fn none() -> SimulatedDelays {
+        SimulatedDelays {
+            upload: Duration::new(0, 0),
+            draw_batch: Duration::new(0, 0),
+            buffer_reply: Duration::new(0, 0),
+        }
+    }
+
+    /// The delays simulated on this thread.
+    pub fn current() -> SimulatedDelays {
+        SIMULATED_DELAYS.with(|delays| delays.get())
+    }
+
+    /// Sets the delays simulated on this thread.
+    pub fn set_current(delays: SimulatedDelays) {
+        if delays != SimulatedDelays::none() {
+            warn!("Simulating delays of {:?}", delays);
+        }
+        SIMULATED_DELAYS.with(|current| current.set(delays));
+    }
+}
+
+/// Stalls the current thread for `delay`, unless it is zero.
+pub fn simulate(delay: Duration) {
+    if delay != Duration::new(0, 0) {
+        thread::sleep(delay);
+    }
+}
+
+/// When something delayed by `delay` from now is due, unless the delay is zero.
+pub fn delayed_until(delay: Duration) -> Option<Instant> {
+    if delay == Duration::new(0, 0) {
+        None
+    } else {
+        Some(Instant::now() + delay)
+    }
+}
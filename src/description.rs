@@ -0,0 +1,283 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Building whole layer trees from a declarative description.
+//!
+//! Tests, examples and embedders that generate layer trees from their own display lists can
+//! describe the tree as nested `LayerDescription`s, with the content of each layer given as a
+//! `ContentSource`, and build it in one call:
+//!
+//! `let tree = LayerTree::build(LayerDescription::new(bounds, ()).child(...));`
+//!
+//! The content is painted on demand, e.g. through a `CpuRasterizer` calling `LayerTree::paint`.
+
+use color::Color;
+use euclid::Matrix4D;
+use euclid::point::{Point2D, TypedPoint2D};
+use euclid::rect::TypedRect;
+use euclid::scale_factor::ScaleFactor;
+use euclid::size::Size2D;
+use geometry::{DevicePixel, LayerPixel};
+use layers::Layer;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// The width and height of the tiles of described layers, unless set otherwise.
+pub const DEFAULT_TILE_SIZE: usize = 256;
+
+/// A bitmap stretched over the bounds of a layer.
+#[derive(Clone, Debug)]
+pub struct Bitmap {
+    /// The size of the bitmap in pixels.
+    pub size: Size2D<usize>,
+
+    /// Tightly packed, premultiplied BGRA rows, ordered from top to bottom.
+    pub pixels: Vec<u8>,
+}
+
+/// What is painted into the tiles of a described layer.
+#[derive(Clone, Debug)]
+pub enum ContentSource {
+    /// The layer has no content of its own, only its background color.
+    None,
+    /// The layer is filled with a single color.
+    SolidColor(Color),
+    /// The layer shows a bitmap, stretched over its bounds and sampled with the nearest filter.
+    Bitmap(Bitmap),
+}
+
+/// The description of a layer and its subtree.
+pub struct LayerDescription<T> {
+    pub bounds: TypedRect<f32, LayerPixel>,
+    pub content_offset: TypedPoint2D<f32, LayerPixel>,
+    pub transform: Matrix4D<f32>,
+    pub perspective: Matrix4D<f32>,
+    pub background_color: Color,
+    pub opacity: f32,
+    pub masks_to_bounds: bool,
+    pub establishes_3d_context: bool,
+    pub tile_size: Size2D<usize>,
+    pub debug_name: Option<String>,
+    pub content: ContentSource,
+    pub data: T,
+
+    /// The children of the layer, in paint order.
+    pub children: Vec<LayerDescription<T>>,
+}
+
+impl<T> LayerDescription<T> {
+    /// Describes a transparent layer without content or children, with an identity transform.
+    pub fn new(bounds: TypedRect<f32, LayerPixel>, data: T) -> LayerDescription<T> {
+        LayerDescription {
+            bounds: bounds,
+            content_offset: TypedPoint2D::zero(),
+            transform: Matrix4D::identity(),
+            perspective: Matrix4D::identity(),
+            background_color: Color { r: 0.0, g: 0.0, b: 0.0, a: 0.0 },
+            opacity: 1.0,
+            masks_to_bounds: false,
+            establishes_3d_context: false,
+            tile_size: Size2D::new(DEFAULT_TILE_SIZE, DEFAULT_TILE_SIZE),
+            debug_name: None,
+            content: ContentSource::None,
+            data: data,
+            children: vec!(),
+        }
+    }
+
+    pub fn content_offset(mut self, content_offset: TypedPoint2D<f32, LayerPixel>)
+                          -> LayerDescription<T> {
+        self.content_offset = content_offset;
+        self
+    }
+
+    pub fn transform(mut self, transform: Matrix4D<f32>) -> LayerDescription<T> {
+        self.transform = transform;
+        self
+    }
+
+    pub fn background_color(mut self, background_color: Color) -> LayerDescription<T> {
+        self.background_color = background_color;
+        self
+    }
+
+    pub fn opacity(mut self, opacity: f32) -> LayerDescription<T> {
+        self.opacity = opacity;
+        self
+    }
+
+    pub fn masks_to_bounds(mut self, masks_to_bounds: bool) -> LayerDescription<T> {
+        self.masks_to_bounds = masks_to_bounds;
+        self
+    }
+
+    pub fn tile_size(mut self, tile_size: Size2D<usize>) -> LayerDescription<T> {
+        self.tile_size = tile_size;
+        self
+    }
+
+    pub fn debug_name(mut self, name: &str) -> LayerDescription<T> {
+        self.debug_name = Some(name.to_owned());
+        self
+    }
+
+    pub fn content(mut self, content: ContentSource) -> LayerDescription<T> {
+        self.content = content;
+        self
+    }
+
+    /// Appends a child, above the children added before it.
+    pub fn child(mut self, child: LayerDescription<T>) -> LayerDescription<T> {
+        self.children.push(child);
+        self
+    }
+}
+
+/// A layer tree built from a description, along with the content of its layers.
+pub struct LayerTree<T> {
+    pub root: Rc<Layer<T>>,
+
+    /// The content of each layer that has some, keyed by the address of the layer. The layer
+    /// is kept alive so that its address isn't reused.
+    contents: HashMap<*const Layer<T>, (Rc<Layer<T>>, ContentSource)>,
+}
+
+impl<T> LayerTree<T> {
+    /// Creates the layers of `description`. The tree is built without recursion, so
+    /// descriptions can be arbitrarily deep.
+    pub fn build(description: LayerDescription<T>) -> LayerTree<T> {
+        let mut contents = HashMap::new();
+        let mut root = None;
+        let mut stack: Vec<(LayerDescription<T>, Option<Rc<Layer<T>>>)> =
+            vec!((description, None));
+        while let Some((description, parent)) = stack.pop() {
+            let LayerDescription {
+                bounds,
+                content_offset,
+                transform,
+                perspective,
+                background_color,
+                opacity,
+                masks_to_bounds,
+                establishes_3d_context,
+                tile_size,
+                debug_name,
+                content,
+                data,
+                children,
+            } = description;
+
+            let layer = Rc::new(Layer::new(bounds,
+                                           tile_size,
+                                           background_color,
+                                           opacity,
+                                           establishes_3d_context,
+                                           data));
+            *layer.content_offset.borrow_mut() = content_offset;
+            *layer.transform.borrow_mut() = transform;
+            *layer.perspective.borrow_mut() = perspective;
+            *layer.masks_to_bounds.borrow_mut() = masks_to_bounds;
+            if let Some(ref name) = debug_name {
+                layer.set_debug_name(name);
+            }
+            match content {
+                ContentSource::None => {}
+                content => {
+                    contents.insert(&*layer as *const _, (layer.clone(), content));
+                }
+            }
+
+            match parent {
+                Some(parent) => parent.add_child(layer.clone()),
+                None => root = Some(layer.clone()),
+            }
+
+            // Children are popped in paint order, so each is added above its earlier siblings.
+            for child in children.into_iter().rev() {
+                stack.push((child, Some(layer.clone())));
+            }
+        }
+
+        LayerTree {
+            root: root.unwrap(),
+            contents: contents,
+        }
+    }
+
+    /// The content of a layer of the tree.
+    pub fn content(&self, layer: &Rc<Layer<T>>) -> Option<&ContentSource> {
+        self.contents.get(&(&**layer as *const _)).map(|&(_, ref content)| content)
+    }
+
+    /// Paints the part of `layer` covered by `tile_rect` into a BGRA buffer of the tile's
+    /// device size, with the signature expected by `CpuRasterizer`. Layers without content are
+    /// left transparent.
+    pub fn paint(&self,
+                 layer: &Rc<Layer<T>>,
+                 tile_rect: &TypedRect<f32, LayerPixel>,
+                 scale: ScaleFactor<f32, LayerPixel, DevicePixel>,
+                 pixels: &mut [u8]) {
+        let content = match self.content(layer) {
+            Some(content) => content,
+            None => return,
+        };
+
+        // The buffer is as large as the tile's device rect, whose size is rounded up to whole
+        // pixels, so the row stride follows from its length and the rounded up height.
+        let scale = scale.get();
+        let height = (tile_rect.size.height * scale).ceil() as usize;
+        if height == 0 {
+            return;
+        }
+        let width = pixels.len() / 4 / height;
+        if width == 0 {
+            return;
+        }
+        let bounds_size = layer.bounds.borrow().size;
+        let bounds_are_empty = !(bounds_size.width > 0.0 && bounds_size.height > 0.0);
+        for (index, pixel) in pixels.chunks_mut(4).enumerate() {
+            let color = match *content {
+                ContentSource::None => return,
+                ContentSource::SolidColor(ref color) => color_to_bgra(color),
+                ContentSource::Bitmap(_) if bounds_are_empty => [0; 4],
+                ContentSource::Bitmap(ref bitmap) => {
+                    // Sample the bitmap at the center of the device pixel.
+                    let device = Point2D::new((index % width) as f32 + 0.5,
+                                              (index / width) as f32 + 0.5);
+                    let x = (tile_rect.origin.x + device.x / scale) / bounds_size.width;
+                    let y = (tile_rect.origin.y + device.y / scale) / bounds_size.height;
+                    sample_bitmap(bitmap, x, y)
+                }
+            };
+            pixel.copy_from_slice(&color);
+        }
+    }
+}
+
+fn color_to_bgra(color: &Color) -> [u8; 4] {
+    let channel = |value: f32| (value.max(0.0).min(1.0) * 255.0).round() as u8;
+    [channel(color.b * color.a), channel(color.g * color.a), channel(color.r * color.a),
+     channel(color.a)]
+}
+
+/// Returns the pixel of `bitmap` at the given fractions of its width and height, or a
+/// transparent pixel outside of it.
+fn sample_bitmap(bitmap: &Bitmap, x: f32, y: f32) -> [u8; 4] {
+    if !(x >= 0.0 && x < 1.0 && y >= 0.0 && y < 1.0) {
+        return [0; 4];
+    }
+    let column = (x * bitmap.size.width as f32) as usize;
+    let row = (y * bitmap.size.height as f32) as usize;
+    let offset = (row * bitmap.size.width + column) * 4;
+    if offset + 4 > bitmap.pixels.len() {
+        return [0; 4];
+    }
+    let pixel = &bitmap.pixels[offset..offset + 4];
+    [pixel[0], pixel[1], pixel[2], pixel[3]]
+}
@@ -0,0 +1,198 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The constants shared by the vertex shaders of all programs: the projection, the scene scale
+//! and the debug flags.
+//!
+//! With uniform buffers, from core profiles or `GL_ARB_uniform_buffer_object`, they live in a
+//! uniform buffer that is bound once per frame, and each constant is uploaded once whenever it
+//! changes instead of with every quad drawn by every program. Without them, including on
+//! OpenGL ES 2.0, whose shaders are written in GLSL ES 1.00, they are plain uniforms of each
+//! program and are set with each draw.
+//!
+//! Both variants are declared by the preamble that `vertex_shader_preamble` returns, so a new
+//! global parameter is added here, to both declarations and to `FrameConstants`, without
+//! touching the programs.
+
+use glcapture as gl;
+use gleam::gl::{GLint, GLsizeiptr, GLuint};
+
+use capabilities::GlCapabilities;
+use euclid::Matrix4D;
+use std::cell::Cell;
+use std::mem;
+
+/// Set while the overdraw view is shown.
+pub const DEBUG_FLAG_SHOW_OVERDRAW: i32 = 1 << 0;
+/// Set while only the outlines of layers and tiles are drawn.
+pub const DEBUG_FLAG_WIREFRAME: i32 = 1 << 1;
+/// Set while tiles are tinted by the age of their content.
+pub const DEBUG_FLAG_TILE_AGE_HEATMAP: i32 = 1 << 2;
+/// Set while debug borders are shown.
+pub const DEBUG_FLAG_DEBUG_BORDERS: i32 = 1 << 3;
+
+/// The uniform buffer binding point of the block.
+const FRAME_CONSTANTS_BINDING: GLuint = 0;
+
+/// The size of the block with the std140 layout: the projection at offset 0, the scene scale at
+/// offset 64 and the debug flags at offset 68, rounded up to a multiple of 16.
+const FRAME_CONSTANTS_SIZE: usize = 80;
+
+static UNIFORM_BLOCK_EXTENSION: &'static str = "
+    #extension GL_ARB_uniform_buffer_object : require
+";
+
+static UNIFORM_BLOCK_PREAMBLE: &'static str = "
+    layout(std140) uniform FrameConstants {
+        mat4 uPMatrix;
+        float uSceneScale;
+        int uDebugFlags;
+    };
+";
+
+static UNIFORMS_PREAMBLE: &'static str = "
+    uniform mat4 uPMatrix;
+    uniform float uSceneScale;
+    uniform int uDebugFlags;
+";
+
+/// Whether the GL context current on this thread keeps the constants in a uniform buffer.
+pub fn uniform_buffers_supported() -> bool {
+    GlCapabilities::current().uniform_buffers
+}
+
+/// The declarations of the constants, to be prepended to the source of every vertex shader,
+/// after the `#version` directive if any.
+pub fn vertex_shader_preamble() -> String {
+    let capabilities = GlCapabilities::current();
+    if !capabilities.uniform_buffers {
+        UNIFORMS_PREAMBLE.to_owned()
+    } else if capabilities.core_profile {
+        UNIFORM_BLOCK_PREAMBLE.to_owned()
+    } else {
+        format!("{}{}", UNIFORM_BLOCK_EXTENSION, UNIFORM_BLOCK_PREAMBLE)
+    }
+}
+
+/// Where a program takes the constants from. The locations are -1 when they are in the uniform
+/// buffer, or when the program doesn't use them.
+#[derive(Copy, Clone)]
+pub struct FrameUniforms {
+    projection: GLint,
+    scene_scale: GLint,
+    debug_flags: GLint,
+}
+
+impl FrameUniforms {
+    /// Looks up the constants of a linked program whose vertex shader starts with the preamble,
+    /// and connects its block to the uniform buffer.
+    pub fn new(program: GLuint) -> FrameUniforms {
+        if uniform_buffers_supported() {
+            unsafe {
+                let name = b"FrameConstants\0";
+                let block_index = gl::GetUniformBlockIndex(program, name.as_ptr() as *const _);
+                if block_index != gl::INVALID_INDEX {
+                    gl::UniformBlockBinding(program, block_index, FRAME_CONSTANTS_BINDING);
+                }
+            }
+        }
+        FrameUniforms {
+            projection: gl::get_uniform_location(program, "uPMatrix"),
+            scene_scale: gl::get_uniform_location(program, "uSceneScale"),
+            debug_flags: gl::get_uniform_location(program, "uDebugFlags"),
+        }
+    }
+}
+
+/// The constants of a render context, and the uniform buffer holding them if supported.
+pub struct FrameConstants {
+    buffer: Option<GLuint>,
+    scene_scale: Cell<f32>,
+    debug_flags: Cell<i32>,
+
+    /// The projection in the uniform buffer, or `None` if the buffer is out of date.
+    uploaded_projection: Cell<Option<[f32; 16]>>,
+}
+
+impl FrameConstants {
+    pub fn new() -> FrameConstants {
+        let buffer = if uniform_buffers_supported() {
+            let buffer = gl::gen_buffers(1)[0];
+            gl::bind_buffer(gl::UNIFORM_BUFFER, buffer);
+            gl::buffer_data(gl::UNIFORM_BUFFER, &[0u8; FRAME_CONSTANTS_SIZE], gl::DYNAMIC_DRAW);
+            gl::bind_buffer(gl::UNIFORM_BUFFER, 0);
+            Some(buffer)
+        } else {
+            None
+        };
+        FrameConstants {
+            buffer: buffer,
+            scene_scale: Cell::new(1.0),
+            debug_flags: Cell::new(0),
+            uploaded_projection: Cell::new(None),
+        }
+    }
+
+    /// Sets the constants of the frame about to be drawn, and binds the uniform buffer.
+    pub fn begin_frame(&self, scene_scale: f32, debug_flags: i32) {
+        if self.scene_scale.get() != scene_scale || self.debug_flags.get() != debug_flags {
+            self.scene_scale.set(scene_scale);
+            self.debug_flags.set(debug_flags);
+            self.uploaded_projection.set(None);
+        }
+        if let Some(buffer) = self.buffer {
+            unsafe {
+                gl::BindBufferBase(gl::UNIFORM_BUFFER, FRAME_CONSTANTS_BINDING, buffer);
+            }
+        }
+    }
+
+    /// Makes the constants available to the program about to draw with the given projection.
+    pub fn bind(&self, uniforms: &FrameUniforms, projection: &Matrix4D<f32>) {
+        let projection = projection.to_row_major_array();
+        let buffer = match self.buffer {
+            Some(buffer) => buffer,
+            None => {
+                gl::uniform_matrix_4fv(uniforms.projection, false, &projection);
+                if uniforms.scene_scale != -1 {
+                    gl::uniform_1f(uniforms.scene_scale, self.scene_scale.get());
+                }
+                if uniforms.debug_flags != -1 {
+                    gl::uniform_1i(uniforms.debug_flags, self.debug_flags.get());
+                }
+                return;
+            }
+        };
+
+        if self.uploaded_projection.get() == Some(projection) {
+            return;
+        }
+        let mut block = [0f32; FRAME_CONSTANTS_SIZE / 4];
+        block[..16].copy_from_slice(&projection);
+        block[16] = self.scene_scale.get();
+        block[17] = unsafe { mem::transmute::<i32, f32>(self.debug_flags.get()) };
+        gl::bind_buffer(gl::UNIFORM_BUFFER, buffer);
+        unsafe {
+            gl::BufferSubData(gl::UNIFORM_BUFFER,
+                              0,
+                              FRAME_CONSTANTS_SIZE as GLsizeiptr,
+                              block.as_ptr() as *const _);
+        }
+        gl::bind_buffer(gl::UNIFORM_BUFFER, 0);
+        self.uploaded_projection.set(Some(projection));
+    }
+}
+
+impl Drop for FrameConstants {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer {
+            gl::delete_buffers(&[buffer]);
+        }
+    }
+}
@@ -7,7 +7,11 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-// Units for use with euclid::length and euclid::scale_factor.
+// Units for use with euclid::length and euclid::scale_factor, and transforms between them.
+
+use euclid::Matrix4D;
+use std::fmt;
+use std::marker::PhantomData;
 
 /// One hardware pixel.
 ///
@@ -27,3 +31,59 @@ pub enum LayerPixel {}
 
 #[cfg(feature = "heapsize")]
 known_heap_size!(0, LayerPixel);
+
+/// A point in clip space, which the projection maps device pixels to and GL maps to the
+/// viewport.
+#[derive(Copy, Clone, RustcEncodable, Debug)]
+pub enum ClipSpace {}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, ClipSpace);
+
+/// A transform from `Src` units to `Dst` units, so that transforms between different spaces
+/// can't be mixed up: the transforms that draw layers in device pixels can only be made with
+/// the scene scale. Transforms within the space of the layers stay untyped.
+pub struct TypedTransform<Src, Dst> {
+    matrix: Matrix4D<f32>,
+    _units: PhantomData<(Src, Dst)>,
+}
+
+impl<Src, Dst> Copy for TypedTransform<Src, Dst> {}
+
+impl<Src, Dst> Clone for TypedTransform<Src, Dst> {
+    fn clone(&self) -> TypedTransform<Src, Dst> {
+        *self
+    }
+}
+
+impl<Src, Dst> fmt::Debug for TypedTransform<Src, Dst> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.matrix, formatter)
+    }
+}
+
+impl<Src, Dst> TypedTransform<Src, Dst> {
+    /// Tags `matrix` as a transform from `Src` to `Dst` units.
+    pub fn from_untyped(matrix: &Matrix4D<f32>) -> TypedTransform<Src, Dst> {
+        TypedTransform {
+            matrix: *matrix,
+            _units: PhantomData,
+        }
+    }
+
+    /// The matrix of this transform, for the GL uniforms and geometry helpers that take it.
+    pub fn to_untyped(&self) -> Matrix4D<f32> {
+        self.matrix
+    }
+
+    /// This transform, with `transform` applied first within the `Src` space.
+    pub fn pre_mul(&self, transform: &Matrix4D<f32>) -> TypedTransform<Src, Dst> {
+        TypedTransform::from_untyped(&self.matrix.pre_mul(transform))
+    }
+}
+
+impl<Unit> TypedTransform<Unit, Unit> {
+    pub fn identity() -> TypedTransform<Unit, Unit> {
+        TypedTransform::from_untyped(&Matrix4D::identity())
+    }
+}
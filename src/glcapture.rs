@@ -0,0 +1,364 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! One-shot capture of the GL commands issued while compositing a frame.
+//!
+//! The compositor issues GL calls through this module, which wraps every GL function it uses
+//! and re-exports only the constants and types it needs from `gleam::gl`, so that a call that
+//! isn't captured doesn't compile. Only the platform surfaces, which bind native buffers to
+//! textures, call gleam directly. After `capture_next_frame`, every call made by the next
+//! `render_scene` is written to a file with its arguments and result, one call per line, which
+//! makes a broken frame debuggable on devices where external GL tracers aren't available.
+
+pub use gleam::gl::{GLsync, GLuint};
+pub use gleam::gl::{ALPHA, ARRAY_BUFFER, BLEND, BLUE, CLAMP_TO_EDGE, COLOR_ATTACHMENT0,
+                    COLOR_BUFFER_BIT, COMPILE_STATUS, DEPTH_ATTACHMENT, DEPTH_BUFFER_BIT,
+                    DEPTH_COMPONENT24, DEPTH_TEST, DRAW_FRAMEBUFFER, DST_COLOR, DYNAMIC_DRAW,
+                    EXTENSIONS, FRAGMENT_SHADER, FRAMEBUFFER, FRAMEBUFFER_BINDING,
+                    FRAMEBUFFER_COMPLETE, GREEN, INVALID_INDEX, LEQUAL, LINEAR, LINE_STRIP,
+                    LINK_STATUS, MAX_SAMPLES, MAX_TEXTURE_SIZE, MIRRORED_REPEAT, NEAREST,
+                    NUM_EXTENSIONS, ONE, ONE_MINUS_SRC_ALPHA, READ_FRAMEBUFFER, RED, RENDERBUFFER,
+                    RENDERER, REPEAT, RGBA, RGBA8, SAMPLE_BUFFERS, SCISSOR_TEST, STREAM_DRAW,
+                    SYNC_GPU_COMMANDS_COMPLETE, TEXTURE, TEXTURE0, TEXTURE_2D, TEXTURE_MAG_FILTER,
+                    TEXTURE_MIN_FILTER, TEXTURE_WRAP_S, TEXTURE_WRAP_T, TIMEOUT_IGNORED, TRIANGLES,
+                    TRIANGLE_STRIP, UNIFORM_BUFFER, UNSIGNED_BYTE, VENDOR, VERSION, VERTEX_SHADER,
+                    VIEWPORT, ZERO};
+
+use gleam::gl;
+use gleam::gl::{GLbitfield, GLboolean, GLchar, GLenum, GLfloat, GLint, GLintptr, GLsizei};
+use gleam::gl::{GLsizeiptr, GLubyte, GLuint64, GLvoid};
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+enum CaptureState {
+    Idle,
+    /// A capture was requested for the next frame.
+    Pending(PathBuf),
+    /// The current frame is being captured.
+    Capturing(PathBuf, Vec<String>),
+}
+
+thread_local!(static CAPTURE: RefCell<CaptureState> = RefCell::new(CaptureState::Idle));
+
+/// Requests that the GL commands of the next frame composited on this thread be written to
+/// `path`.
+pub fn capture_next_frame<P: AsRef<Path>>(path: P) {
+    CAPTURE.with(|capture| *capture.borrow_mut() = CaptureState::Pending(path.as_ref().to_owned()));
+}
+
+/// Starts capturing if a capture was requested. Called at the start of each frame.
+pub fn begin_frame() {
+    CAPTURE.with(|capture| {
+        let mut capture = capture.borrow_mut();
+        let path = match *capture {
+            CaptureState::Pending(ref path) => path.clone(),
+            _ => return,
+        };
+        *capture = CaptureState::Capturing(path, vec!());
+    });
+}
+
+/// Finishes the capture of the current frame, if any, and writes it out.
+pub fn end_frame() -> io::Result<()> {
+    let state = CAPTURE.with(|capture| {
+        match *capture.borrow() {
+            CaptureState::Capturing(..) => {}
+            _ => return CaptureState::Idle,
+        }
+        ::std::mem::replace(&mut *capture.borrow_mut(), CaptureState::Idle)
+    });
+
+    if let CaptureState::Capturing(path, commands) = state {
+        let mut writer = BufWriter::new(try!(File::create(&path)));
+        for command in &commands {
+            try!(writeln!(writer, "{}", command));
+        }
+        try!(writer.flush());
+        info!("Captured {} GL commands to {}", commands.len(), path.display());
+    }
+    Ok(())
+}
+
+fn record<F>(command: F) where F: FnOnce() -> String {
+    CAPTURE.with(|capture| {
+        if let CaptureState::Capturing(_, ref mut commands) = *capture.borrow_mut() {
+            commands.push(command());
+        }
+    });
+}
+
+fn describe_call(name: &str, args: &[&Debug]) -> String {
+    let args: Vec<String> = args.iter().map(|arg| format!("{:?}", arg)).collect();
+    format!("{}({})", name, args.join(", "))
+}
+
+/// Wraps calls without a result, or with a result that is recorded after the arguments.
+macro_rules! captured {
+    ($(fn $name:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            pub fn $name($($arg: $ty),*) {
+                record(|| describe_call(stringify!($name), &[$(&$arg),*]));
+                gl::$name($($arg),*)
+            }
+        )*
+    };
+    ($(fn $name:ident($($arg:ident: $ty:ty),*) -> $ret:ty;)*) => {
+        $(
+            pub fn $name($($arg: $ty),*) -> $ret {
+                let result = gl::$name($($arg),*);
+                record(|| {
+                    format!("{} -> {:?}", describe_call(stringify!($name), &[$(&$arg),*]), result)
+                });
+                result
+            }
+        )*
+    };
+}
+
+/// Like `captured!`, for the raw entry points that gleam has no safe wrappers for. Pointers are
+/// recorded as addresses.
+macro_rules! captured_unsafe {
+    ($(fn $name:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            #[allow(non_snake_case)]
+            pub unsafe fn $name($($arg: $ty),*) {
+                record(|| describe_call(stringify!($name), &[$(&$arg),*]));
+                gl::$name($($arg),*)
+            }
+        )*
+    };
+    ($(fn $name:ident($($arg:ident: $ty:ty),*) -> $ret:ty;)*) => {
+        $(
+            #[allow(non_snake_case)]
+            pub unsafe fn $name($($arg: $ty),*) -> $ret {
+                let result = gl::$name($($arg),*);
+                record(|| {
+                    format!("{} -> {:?}", describe_call(stringify!($name), &[$(&$arg),*]), result)
+                });
+                result
+            }
+        )*
+    };
+}
+
+captured! {
+    fn active_texture(texture: GLenum);
+    fn attach_shader(program: GLuint, shader: GLuint);
+    fn bind_buffer(target: GLenum, buffer: GLuint);
+    fn bind_framebuffer(target: GLenum, framebuffer: GLuint);
+    fn bind_renderbuffer(target: GLenum, renderbuffer: GLuint);
+    fn bind_texture(target: GLenum, texture: GLuint);
+    fn bind_vertex_array(vao: GLuint);
+    fn blend_func(sfactor: GLenum, dfactor: GLenum);
+    fn clear(buffer_mask: GLbitfield);
+    fn clear_color(r: GLfloat, g: GLfloat, b: GLfloat, a: GLfloat);
+    fn compile_shader(shader: GLuint);
+    fn copy_tex_sub_image_2d(target: GLenum,
+                             level: GLint,
+                             xoffset: GLint,
+                             yoffset: GLint,
+                             x: GLint,
+                             y: GLint,
+                             width: GLsizei,
+                             height: GLsizei);
+    fn delete_buffers(buffers: &[GLuint]);
+    fn delete_framebuffers(framebuffers: &[GLuint]);
+    fn delete_renderbuffers(renderbuffers: &[GLuint]);
+    fn delete_textures(textures: &[GLuint]);
+    fn depth_func(func: GLenum);
+    fn disable(cap: GLenum);
+    fn disable_vertex_attrib_array(index: GLuint);
+    fn draw_arrays(mode: GLenum, first: GLint, count: GLsizei);
+    fn enable(cap: GLenum);
+    fn enable_vertex_attrib_array(index: GLuint);
+    fn finish();
+    fn flush();
+    fn framebuffer_renderbuffer(target: GLenum,
+                                attachment: GLenum,
+                                renderbuffertarget: GLenum,
+                                renderbuffer: GLuint);
+    fn framebuffer_texture_2d(target: GLenum,
+                              attachment: GLenum,
+                              textarget: GLenum,
+                              texture: GLuint,
+                              level: GLint);
+    fn line_width(width: GLfloat);
+    fn link_program(program: GLuint);
+    fn renderbuffer_storage(target: GLenum,
+                            internalformat: GLenum,
+                            width: GLsizei,
+                            height: GLsizei);
+    fn scissor(x: GLint, y: GLint, width: GLsizei, height: GLsizei);
+    fn tex_parameter_i(target: GLenum, pname: GLenum, param: GLint);
+    fn uniform_1f(location: GLint, v0: GLfloat);
+    fn uniform_1i(location: GLint, v0: GLint);
+    fn uniform_2f(location: GLint, v0: GLfloat, v1: GLfloat);
+    fn uniform_4f(location: GLint, x: GLfloat, y: GLfloat, z: GLfloat, w: GLfloat);
+    fn uniform_matrix_4fv(location: GLint, transpose: bool, value: &[f32]);
+    fn use_program(program: GLuint);
+    fn vertex_attrib_pointer_f32(index: GLuint,
+                                 size: GLint,
+                                 normalized: bool,
+                                 stride: GLsizei,
+                                 offset: GLuint);
+    fn viewport(x: GLint, y: GLint, width: GLsizei, height: GLsizei);
+}
+
+captured! {
+    fn check_frame_buffer_status(target: GLenum) -> GLenum;
+    fn create_program() -> GLuint;
+    fn create_shader(shader_type: GLenum) -> GLuint;
+    fn gen_buffers(n: GLsizei) -> Vec<GLuint>;
+    fn gen_framebuffers(n: GLsizei) -> Vec<GLuint>;
+    fn gen_renderbuffers(n: GLsizei) -> Vec<GLuint>;
+    fn gen_textures(n: GLsizei) -> Vec<GLuint>;
+    fn gen_vertex_arrays(n: GLsizei) -> Vec<GLuint>;
+    fn get_attrib_location(program: GLuint, name: &str) -> GLint;
+    fn get_integer_v(name: GLenum) -> GLint;
+    fn get_program_info_log(program: GLuint) -> String;
+    fn get_program_iv(program: GLuint, pname: GLenum) -> GLint;
+    fn get_shader_info_log(shader: GLuint) -> String;
+    fn get_shader_iv(shader: GLuint, pname: GLenum) -> GLint;
+    fn get_string(which: GLenum) -> String;
+    fn get_uniform_location(program: GLuint, name: &str) -> GLint;
+    fn is_enabled(cap: GLenum) -> GLboolean;
+}
+
+captured_unsafe! {
+    fn BindBufferBase(target: GLenum, index: GLuint, buffer: GLuint);
+    fn BindSampler(unit: GLuint, sampler: GLuint);
+    fn BlitFramebuffer(src_x0: GLint,
+                       src_y0: GLint,
+                       src_x1: GLint,
+                       src_y1: GLint,
+                       dst_x0: GLint,
+                       dst_y0: GLint,
+                       dst_x1: GLint,
+                       dst_y1: GLint,
+                       mask: GLbitfield,
+                       filter: GLenum);
+    fn BufferData(target: GLenum, size: GLsizeiptr, data: *const GLvoid, usage: GLenum);
+    fn BufferSubData(target: GLenum, offset: GLintptr, size: GLsizeiptr, data: *const GLvoid);
+    fn DeleteSamplers(count: GLsizei, samplers: *const GLuint);
+    fn DeleteSync(sync: GLsync);
+    fn GenSamplers(count: GLsizei, samplers: *mut GLuint);
+    fn GetIntegerv(pname: GLenum, data: *mut GLint);
+    fn ReadPixels(x: GLint,
+                  y: GLint,
+                  width: GLsizei,
+                  height: GLsizei,
+                  format: GLenum,
+                  pixel_type: GLenum,
+                  pixels: *mut GLvoid);
+    fn RenderbufferStorageMultisample(target: GLenum,
+                                      samples: GLsizei,
+                                      internalformat: GLenum,
+                                      width: GLsizei,
+                                      height: GLsizei);
+    fn SamplerParameteri(sampler: GLuint, pname: GLenum, param: GLint);
+    fn TexSubImage2D(target: GLenum,
+                     level: GLint,
+                     xoffset: GLint,
+                     yoffset: GLint,
+                     width: GLsizei,
+                     height: GLsizei,
+                     format: GLenum,
+                     pixel_type: GLenum,
+                     pixels: *const GLvoid);
+    fn UniformBlockBinding(program: GLuint, block_index: GLuint, block_binding: GLuint);
+    fn WaitSync(sync: GLsync, flags: GLbitfield, timeout: GLuint64);
+}
+
+captured_unsafe! {
+    fn FenceSync(condition: GLenum, flags: GLbitfield) -> GLsync;
+    fn GetStringi(name: GLenum, index: GLuint) -> *const GLubyte;
+    fn GetUniformBlockIndex(program: GLuint, name: *const GLchar) -> GLuint;
+    fn MapBufferRange(target: GLenum,
+                      offset: GLintptr,
+                      length: GLsizeiptr,
+                      access: GLbitfield)
+                      -> *mut GLvoid;
+    fn UnmapBuffer(target: GLenum) -> GLboolean;
+}
+
+/// Only the size of the data is recorded.
+pub fn buffer_data<T>(target: GLenum, data: &[T], usage: GLenum) {
+    record(|| {
+        format!("buffer_data({:?}, <{} bytes>, {:?})",
+                target,
+                data.len() * ::std::mem::size_of::<T>(),
+                usage)
+    });
+    gl::buffer_data(target, data, usage)
+}
+
+/// Only the size of the pixels read is recorded.
+pub fn read_pixels(x: GLint,
+                   y: GLint,
+                   width: GLsizei,
+                   height: GLsizei,
+                   format: GLenum,
+                   pixel_type: GLenum)
+                   -> Vec<u8> {
+    let pixels = gl::read_pixels(x, y, width, height, format, pixel_type);
+    record(|| {
+        format!("read_pixels({:?}, {:?}, {:?}, {:?}, {:?}, {:?}) -> <{} bytes>",
+                x, y, width, height, format, pixel_type, pixels.len())
+    });
+    pixels
+}
+
+/// Only the lengths of the sources are recorded.
+pub fn shader_source(shader: GLuint, strings: &[&[u8]]) {
+    record(|| {
+        let lengths: Vec<String> = strings.iter().map(|string| {
+            format!("<{} bytes>", string.len())
+        }).collect();
+        format!("shader_source({:?}, [{}])", shader, lengths.join(", "))
+    });
+    gl::shader_source(shader, strings)
+}
+
+/// Only the size of the data is recorded.
+pub fn tex_image_2d(target: GLenum,
+                    level: GLint,
+                    internal_format: GLint,
+                    width: GLsizei,
+                    height: GLsizei,
+                    border: GLint,
+                    format: GLenum,
+                    ty: GLenum,
+                    opt_data: Option<&[u8]>) {
+    record(|| {
+        let data = opt_data.map_or("None".to_owned(), |data| format!("<{} bytes>", data.len()));
+        format!("tex_image_2d({:?}, {:?}, {:?}, {:?}, {:?}, {:?}, {:?}, {:?}, {})",
+                target, level, internal_format, width, height, border, format, ty, data)
+    });
+    gl::tex_image_2d(target, level, internal_format, width, height, border, format, ty, opt_data)
+}
+
+/// Only the size of the data is recorded.
+pub fn tex_sub_image_2d(target: GLenum,
+                        level: GLint,
+                        xoffset: GLint,
+                        yoffset: GLint,
+                        width: GLsizei,
+                        height: GLsizei,
+                        format: GLenum,
+                        ty: GLenum,
+                        data: &[u8]) {
+    record(|| {
+        format!("tex_sub_image_2d({:?}, {:?}, {:?}, {:?}, {:?}, {:?}, {:?}, {:?}, <{} bytes>)",
+                target, level, xoffset, yoffset, width, height, format, ty, data.len())
+    });
+    gl::tex_sub_image_2d(target, level, xoffset, yoffset, width, height, format, ty, data)
+}
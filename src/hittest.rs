@@ -0,0 +1,137 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Hit testing against the layer tree, so that the compositor can decide locally what to do
+//! with input, e.g. whether a touch can start scrolling right away or must wait for the
+//! content to handle it. Devtools and accessibility can also list the layers under a rect in
+//! paint order.
+
+use euclid::point::TypedPoint2D;
+use euclid::rect::TypedRect;
+use euclid::scale_factor::ScaleFactor;
+use geometry::{DevicePixel, LayerPixel};
+use layers::{EventRegionKind, Layer};
+use rendergl::RenderContext3D;
+use util::unproject_point_from_screen;
+
+use euclid::Point2D;
+use std::rc::Rc;
+
+/// The topmost layer under a point.
+pub struct HitTestResult<T> {
+    pub layer: Rc<Layer<T>>,
+
+    /// The point in layer coordinates, relative to the origin of the layer.
+    pub point: TypedPoint2D<f32, LayerPixel>,
+
+    /// Whether the point is in a `BlockingTouch` event region of the layer.
+    pub blocks_on_content: bool,
+}
+
+/// A layer intersecting a rect, with the part of its screen rect left by the clip rect it is
+/// drawn with.
+pub struct PaintedLayer<T> {
+    pub layer: Rc<Layer<T>>,
+    pub screen_rect: TypedRect<f32, DevicePixel>,
+}
+
+/// Returns true if `point`, in unscaled screen coordinates, is inside the clip region of
+/// `layer`, or if the layer has none.
+fn clip_region_contains<T>(layer: &Layer<T>, point: &Point2D<f32>) -> bool {
+    let clip_region = match *layer.clip_region.borrow() {
+        Some(clip_region) => clip_region,
+        None => return true,
+    };
+    let ts = layer.transform_state.borrow();
+    match unproject_point_from_screen(point, &ts.final_transform) {
+        Some(world_point) => {
+            clip_region.contains(&TypedPoint2D::from_untyped(&(world_point - ts.world_rect.origin)))
+        }
+        None => false,
+    }
+}
+
+/// Returns the layers whose screen rects intersect `rect`, in back-to-front paint order. The
+/// transform state of the layers must be up to date.
+pub fn layers_in_rect<T>(root_layer: &Rc<Layer<T>>,
+                         rect: &TypedRect<f32, DevicePixel>,
+                         scale: ScaleFactor<f32, LayerPixel, DevicePixel>)
+                         -> Vec<PaintedLayer<T>> {
+    let rect = (*rect / scale).to_untyped();
+    let layers = RenderContext3D::layers_in_paint_order(root_layer.clone());
+    layers.into_iter().filter_map(|(layer, clip_rect, _)| {
+        let screen_rect = match layer.transform_state.borrow().screen_rect {
+            Some(ref screen_rect) => screen_rect.rect,
+            None => return None,
+        };
+        let screen_rect = match clip_rect {
+            Some(clip_rect) => match clip_rect.intersection(&screen_rect) {
+                Some(screen_rect) => screen_rect,
+                None => return None,
+            },
+            None => screen_rect,
+        };
+        if !screen_rect.intersects(&rect) {
+            return None;
+        }
+        let screen_rect: TypedRect<f32, LayerPixel> = TypedRect::from_untyped(&screen_rect);
+        Some(PaintedLayer {
+            layer: layer,
+            screen_rect: screen_rect * scale,
+        })
+    }).collect()
+}
+
+/// Returns the topmost layer under `point`, in unscaled screen coordinates, skipping layers
+/// whose event regions make them transparent to input there. The transform state of the
+/// layers must be up to date.
+pub fn hit_test<T>(root_layer: &Rc<Layer<T>>, point: &Point2D<f32>) -> Option<HitTestResult<T>> {
+    // Test the layers from the top down.
+    let layers = RenderContext3D::layers_in_paint_order(root_layer.clone());
+    for &(ref layer, clip_rect, ref clip_layers) in layers.iter().rev() {
+        if clip_rect.map_or(false, |clip_rect| !clip_rect.contains(point)) {
+            continue;
+        }
+        if !clip_layers.iter().all(|clip_layer| clip_region_contains(clip_layer, point)) {
+            continue;
+        }
+
+        let ts = layer.transform_state.borrow();
+        let world_point = match unproject_point_from_screen(point, &ts.final_transform) {
+            Some(world_point) => world_point,
+            None => continue,
+        };
+        if !ts.world_rect.contains(&world_point) {
+            continue;
+        }
+
+        let layer_point = TypedPoint2D::from_untyped(&(world_point - ts.world_rect.origin));
+        let mut blocks_on_content = false;
+        let mut hit_testable = true;
+        for region in layer.event_regions.borrow().iter() {
+            if !region.rect.contains(&layer_point) {
+                continue;
+            }
+            match region.kind {
+                EventRegionKind::BlockingTouch => blocks_on_content = true,
+                EventRegionKind::NotHitTestable => hit_testable = false,
+            }
+        }
+        if !hit_testable {
+            continue;
+        }
+
+        return Some(HitTestResult {
+            layer: layer.clone(),
+            point: layer_point,
+            blocks_on_content: blocks_on_content,
+        });
+    }
+    None
+}
@@ -8,8 +8,9 @@
 // except according to those terms.
 
 use color::Color;
+use delays::{self, SimulatedDelays};
 use geometry::{DevicePixel, LayerPixel};
-use tiling::{Tile, TileGrid};
+use tiling::{Tile, TileEviction, TileGrid, TileId, TileInfo, device_rect_to_layer_rect};
 
 use euclid::Matrix4D;
 use euclid::scale_factor::ScaleFactor;
@@ -17,11 +18,20 @@ use euclid::size::{Size2D, TypedSize2D};
 use euclid::point::{Point2D, TypedPoint2D};
 use euclid::rect::{Rect, TypedRect};
 use platform::surface::{NativeDisplay, NativeSurface};
-use std::cell::{RefCell, RefMut};
+use rastercache::{CachePolicy, RasterCache};
+use sequence::Epoch;
+use std::cell::{Cell, RefCell, RefMut};
+use std::mem;
+use std::ops::BitOr;
 use std::rc::Rc;
-use util::{project_rect_to_screen, ScreenRect};
+use std::time::Instant;
+use texturegl::Texture;
+use texturelayer::TextureLayer;
+use upload::{UploadBudget, UploadFence};
+use util::{classify_transform, project_rect_to_screen, transform_scale, ScreenRect, TransformKind};
+use video::VideoFrame;
 
-#[derive(Clone, Copy, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
 pub struct ContentAge {
     age: usize,
 }
@@ -53,6 +63,17 @@ pub struct TransformState {
 
     /// True if this layer has a non-identity transform
     pub has_transform: bool,
+
+    /// The class of the final transform, so that drawing code can pick a fast path without
+    /// inspecting the matrix again.
+    pub transform_kind: TransformKind,
+
+    /// The opacity the layer is drawn with this frame, taking overrides into account.
+    pub opacity: f32,
+
+    /// Whether the layer itself is drawn this frame. Layers that are transparent, projected
+    /// away or culled to the viewport aren't, although their descendants may be.
+    pub visible: bool,
 }
 
 #[cfg(feature = "heapsize")]
@@ -65,46 +86,507 @@ impl TransformState {
             screen_rect: None,
             world_rect: Rect::zero(),
             has_transform: false,
+            transform_kind: TransformKind::Identity,
+            opacity: 1.0,
+            visible: false,
+        }
+    }
+}
+
+/// One side of a layer border.
+#[derive(Copy, Clone, Debug)]
+pub struct BorderSide {
+    /// The color of this side.
+    pub color: Color,
+
+    /// The thickness of this side in layer pixels. Zero means the side isn't drawn.
+    pub width: f32,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, BorderSide);
+
+/// A border drawn by the compositor along the inside edges of a layer, on top of its contents.
+/// This is useful for focus rings and debugging outlines.
+#[derive(Copy, Clone, Debug)]
+pub struct Border {
+    pub top: BorderSide,
+    pub right: BorderSide,
+    pub bottom: BorderSide,
+    pub left: BorderSide,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, Border);
+
+impl Border {
+    /// Creates a border with the same color and width on all sides.
+    pub fn uniform(color: Color, width: f32) -> Border {
+        let side = BorderSide {
+            color: color,
+            width: width,
+        };
+        Border {
+            top: side,
+            right: side,
+            bottom: side,
+            left: side,
+        }
+    }
+}
+
+/// The horizontal and vertical radii of the corners of a rounded rect, in layer pixels. Corners
+/// with a zero radius are square.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BorderRadii {
+    pub top_left: TypedSize2D<f32, LayerPixel>,
+    pub top_right: TypedSize2D<f32, LayerPixel>,
+    pub bottom_right: TypedSize2D<f32, LayerPixel>,
+    pub bottom_left: TypedSize2D<f32, LayerPixel>,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, BorderRadii);
+
+impl BorderRadii {
+    /// Creates radii with the same circular radius at every corner.
+    pub fn uniform(radius: f32) -> BorderRadii {
+        let radius = TypedSize2D::new(radius, radius);
+        BorderRadii {
+            top_left: radius,
+            top_right: radius,
+            bottom_right: radius,
+            bottom_left: radius,
+        }
+    }
+
+    /// Whether all corners are square.
+    pub fn is_zero(&self) -> bool {
+        [self.top_left, self.top_right, self.bottom_right, self.bottom_left].iter().all(|radius| {
+            radius.width <= 0.0 || radius.height <= 0.0
+        })
+    }
+}
+
+/// A rect with rounded corners that the content of a layer and of its descendants is clipped
+/// to, as for an element with both `border-radius` and `overflow: hidden`. The clip is applied
+/// per pixel while compositing, so the content doesn't need to be rasterized with it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ClipRegion {
+    /// The clip rect in layer coordinates, relative to the origin of the layer.
+    pub rect: TypedRect<f32, LayerPixel>,
+
+    /// The radii of the corners of the clip rect.
+    pub radii: BorderRadii,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, ClipRegion);
+
+impl ClipRegion {
+    pub fn new(rect: TypedRect<f32, LayerPixel>, radii: BorderRadii) -> ClipRegion {
+        ClipRegion {
+            rect: rect,
+            radii: radii,
+        }
+    }
+
+    /// The radii scaled down so that adjacent corners don't overlap, as CSS does when they add
+    /// up to more than the side they share.
+    pub fn normalized_radii(&self) -> BorderRadii {
+        let radii = self.radii;
+        let ratio = |length: f32, first: f32, second: f32| {
+            if first + second > length && first + second > 0.0 {
+                length / (first + second)
+            } else {
+                1.0
+            }
+        };
+        let factor = ratio(self.rect.size.width, radii.top_left.width, radii.top_right.width)
+            .min(ratio(self.rect.size.width, radii.bottom_left.width, radii.bottom_right.width))
+            .min(ratio(self.rect.size.height, radii.top_left.height, radii.bottom_left.height))
+            .min(ratio(self.rect.size.height, radii.top_right.height, radii.bottom_right.height))
+            .max(0.0);
+        let scale = |radius: TypedSize2D<f32, LayerPixel>| {
+            TypedSize2D::new(radius.width.max(0.0) * factor, radius.height.max(0.0) * factor)
+        };
+        BorderRadii {
+            top_left: scale(radii.top_left),
+            top_right: scale(radii.top_right),
+            bottom_right: scale(radii.bottom_right),
+            bottom_left: scale(radii.bottom_left),
+        }
+    }
+
+    /// Returns true if `point`, in layer coordinates, is inside the rounded clip rect. Corners
+    /// with a zero radius are square.
+    pub fn contains(&self, point: &TypedPoint2D<f32, LayerPixel>) -> bool {
+        if !self.rect.contains(point) {
+            return false;
+        }
+        // Each corner of the rect with its radii and the direction it points to.
+        let (radii, rect) = (self.normalized_radii(), self.rect);
+        let corners = [
+            (radii.top_left, rect.min_x(), rect.min_y(), -1.0, -1.0),
+            (radii.top_right, rect.max_x(), rect.min_y(), 1.0, -1.0),
+            (radii.bottom_right, rect.max_x(), rect.max_y(), 1.0, 1.0),
+            (radii.bottom_left, rect.min_x(), rect.max_y(), -1.0, 1.0),
+        ];
+        corners.iter().all(|&(radius, x, y, direction_x, direction_y)| {
+            if radius.width <= 0.0 || radius.height <= 0.0 {
+                return true;
+            }
+            let center = Point2D::new(x - direction_x * radius.width,
+                                      y - direction_y * radius.height);
+            let dx = (point.x - center.x) * direction_x / radius.width;
+            let dy = (point.y - center.y) * direction_y / radius.height;
+            dx <= 0.0 || dy <= 0.0 || dx * dx + dy * dy <= 1.0
+        })
+    }
+}
+
+/// A drop shadow drawn by the compositor behind a layer.
+#[derive(Copy, Clone, Debug)]
+pub struct BoxShadow {
+    /// The offset of the shadow from the layer, in layer pixels.
+    pub offset: Point2D<f32>,
+
+    /// The blur radius in layer pixels. Zero gives a shadow with hard edges.
+    pub blur_radius: f32,
+
+    /// The color of the shadow.
+    pub color: Color,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, BoxShadow);
+
+/// A filter applied to the already composited content behind a layer, as with the CSS
+/// `backdrop-filter` property, before the layer itself is drawn over it.
+#[derive(Copy, Clone, Debug)]
+pub struct BackdropFilter {
+    /// The standard deviation of the gaussian blur in layer pixels, as with CSS `blur()`.
+    pub blur_radius: f32,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, BackdropFilter);
+
+/// A part of a layer under a translucent native UI element, such as a toolbar, behind which the
+/// composited scene is blurred.
+#[derive(Copy, Clone, Debug)]
+pub struct BlurBehindRegion {
+    /// The region in layer coordinates, relative to the origin of the layer.
+    pub rect: TypedRect<f32, LayerPixel>,
+
+    /// The standard deviation of the gaussian blur in layer pixels.
+    pub blur_radius: f32,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, BlurBehindRegion);
+
+/// How the compositor treats input over part of a layer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EventRegionKind {
+    /// Touches must be sent to the content and wait for it before scrolling, e.g. over
+    /// `touch-action: none` areas or non-passive touch event listeners.
+    BlockingTouch,
+    /// Input passes through to the layers below, e.g. over `pointer-events: none` areas.
+    NotHitTestable,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, EventRegionKind);
+
+/// A part of a layer where input is handled in a particular way.
+#[derive(Copy, Clone, Debug)]
+pub struct EventRegion {
+    /// The region in layer coordinates, relative to the origin of the layer.
+    pub rect: TypedRect<f32, LayerPixel>,
+
+    pub kind: EventRegionKind,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, EventRegion);
+
+/// A translucent color drawn by the compositor over part of a layer's content, e.g. for the
+/// current selection or find-in-page matches.
+#[derive(Copy, Clone, Debug)]
+pub struct Highlight {
+    /// The highlighted rect in layer coordinates, relative to the origin of the layer.
+    pub rect: TypedRect<f32, LayerPixel>,
+
+    /// The color drawn over the rect. It should be translucent, so the content stays visible.
+    pub color: Color,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, Highlight);
+
+/// A temporary override of the visual properties of a layer, e.g. for the pressed state of a
+/// button, applied by the compositor without waiting for the content to update the layer.
+#[derive(Copy, Clone, Debug)]
+pub struct PropertyOverride {
+    /// Replaces the opacity of the layer.
+    pub opacity: Option<f32>,
+
+    /// Replaces the transform of the layer.
+    pub transform: Option<Matrix4D<f32>>,
+
+    /// When the override is removed automatically, or `None` to keep it until it is removed
+    /// explicitly.
+    pub expires_at: Option<Instant>,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, PropertyOverride);
+
+impl PropertyOverride {
+    pub fn new() -> PropertyOverride {
+        PropertyOverride {
+            opacity: None,
+            transform: None,
+            expires_at: None,
         }
     }
 }
 
+/// Identifies an override pushed onto a layer, for removing it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PropertyOverrideId(usize);
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, PropertyOverrideId);
+
+/// The directions in which a layer's content repeats.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RepeatMode {
+    RepeatX,
+    RepeatY,
+    RepeatXY,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, RepeatMode);
+
+impl RepeatMode {
+    pub fn repeats_x(self) -> bool {
+        self != RepeatMode::RepeatY
+    }
+
+    pub fn repeats_y(self) -> bool {
+        self != RepeatMode::RepeatX
+    }
+}
+
+/// Describes a layer whose content is a small pattern repeated by the compositor across the
+/// layer's bounds. Only the pattern itself is painted.
+#[derive(Copy, Clone, Debug)]
+pub struct ContentRepeat {
+    pub mode: RepeatMode,
+
+    /// The size of the repeated pattern, starting at the layer's content origin.
+    pub pattern_size: TypedSize2D<f32, LayerPixel>,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, ContentRepeat);
+
+/// What layout expects to change about a layer, e.g. from CSS `will-change`. The compositor
+/// uses the hints to avoid work that the changes would soon throw away. Combine them with `|`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CompositingHints(u8);
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, CompositingHints);
+
+/// The transform of the layer will be animated.
+pub const WILL_ANIMATE_TRANSFORM: CompositingHints = CompositingHints(1 << 0);
+/// The opacity of the layer will be animated.
+pub const WILL_ANIMATE_OPACITY: CompositingHints = CompositingHints(1 << 1);
+/// The content of the layer will be repainted often, e.g. a video or a canvas.
+pub const FREQUENTLY_INVALIDATES: CompositingHints = CompositingHints(1 << 2);
+
+impl CompositingHints {
+    pub fn empty() -> CompositingHints {
+        CompositingHints(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns true if all of the hints in `other` are set.
+    pub fn contains(&self, other: CompositingHints) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for CompositingHints {
+    type Output = CompositingHints;
+
+    fn bitor(self, other: CompositingHints) -> CompositingHints {
+        CompositingHints(self.0 | other.0)
+    }
+}
+
+/// A painted buffer held back by the simulated buffer reply delay, by how it was handed to the
+/// layer.
+enum DelayedBuffer {
+    /// By `add_buffer`.
+    Added(Box<LayerBuffer>),
+    /// By `assign_painted_buffers`, with the update it was painted for.
+    Assigned(Box<LayerBuffer>, Option<Epoch>),
+    /// By `add_uploaded_buffer`, with its texture and the fence of its upload.
+    Uploaded(Box<LayerBuffer>, Texture, UploadFence),
+}
+
+/// Identifies a native widget placed behind the GL surface, such as a plugin or a platform video
+/// view, as chosen by the embedder.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HoleId(pub u64);
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, HoleId);
+
 pub struct Layer<T> {
     pub children: RefCell<Vec<Rc<Layer<T>>>>,
     pub transform: RefCell<Matrix4D<f32>>,
     pub perspective: RefCell<Matrix4D<f32>>,
-    pub tile_size: usize,
     pub extra_data: RefCell<T>,
     tile_grid: RefCell<TileGrid>,
 
+    /// Whether the tile size was chosen with `set_tile_size`, rather than given to `new`.
+    tile_size_is_set: Cell<bool>,
+
     /// The boundaries of this layer in the coordinate system of the parent layer.
     pub bounds: RefCell<TypedRect<f32, LayerPixel>>,
 
     /// A monotonically increasing counter that keeps track of the current content age.
     pub content_age: RefCell<ContentAge>,
 
+    /// The update of the layer tree this layer was last changed in, if the embedder numbers
+    /// its updates. See the `sequence` module.
+    pub epoch: RefCell<Option<Epoch>>,
+
     /// The content offset for this layer in unscaled layer pixels.
     pub content_offset: RefCell<TypedPoint2D<f32, LayerPixel>>,
 
     /// Whether this layer clips its children to its boundaries.
     pub masks_to_bounds: RefCell<bool>,
 
+    /// The rounded rect this layer and its descendants are clipped to, if any, within the clip
+    /// regions of its ancestors. Hit tests are clipped by it too.
+    pub clip_region: RefCell<Option<ClipRegion>>,
+
     /// The background color for this layer.
     pub background_color: RefCell<Color>,
 
     /// The opacity of this layer, from 0.0 (fully transparent) to 1.0 (fully opaque).
     pub opacity: RefCell<f32>,
 
+    /// The border drawn along the inside edges of this layer, if any.
+    pub border: RefCell<Option<Border>>,
+
+    /// Whether the content of this layer repeats across its bounds.
+    pub content_repeat: RefCell<Option<ContentRepeat>>,
+
+    /// The drop shadow drawn behind this layer, if any.
+    pub shadow: RefCell<Option<BoxShadow>>,
+
+    /// The filter applied to the content behind this layer, if any. Subtrees containing such
+    /// layers aren't raster cached, since their rendering depends on what is behind them.
+    pub backdrop_filter: RefCell<Option<BackdropFilter>>,
+
+    /// The regions of this layer blurred once the whole scene is composited, because the
+    /// embedder shows native UI over them. Unlike a backdrop filter, they blur everything drawn
+    /// under them, including the layers above this one. They should be set with
+    /// `set_blur_behind_regions` so that the composited frame is damaged.
+    pub blur_behind_regions: RefCell<Vec<BlurBehindRegion>>,
+
+    /// The color drawn in place of missing tiles when the scene uses solid color placeholders.
+    pub placeholder_color: RefCell<Option<Color>>,
+
+    /// A texture whose alpha channel masks the content of this layer, stretched over its
+    /// bounds. It is sampled in the same draw as each tile, video frame or placeholder. An
+    /// empty mask hides all of the content.
+    pub mask: RefCell<Option<Texture>>,
+
+    /// The decoded video frame shown by this layer, if any, stretched over its bounds in place
+    /// of painted tiles.
+    pub video_frame: RefCell<Option<VideoFrame>>,
+
+    /// The texture owned by the embedder shown by this layer, if any, stretched over its bounds
+    /// in place of painted tiles. A video frame is shown instead if there is one.
+    pub texture_layer: RefCell<Option<TextureLayer>>,
+
+    /// If set, this layer is a hole through which the native widget with this ID shows: instead
+    /// of its content, it draws transparent pixels over its bounds, replacing whatever was drawn
+    /// under it. Layers above it are drawn over the widget. The widget only shows if the window
+    /// system blends the GL surface over it by its alpha. `Scene::punched_holes` lists the
+    /// holes of the last frame.
+    pub hole: RefCell<Option<HoleId>>,
+
+    /// The parts of this layer where input is handled specially, consulted by hit testing.
+    pub event_regions: RefCell<Vec<EventRegion>>,
+
+    /// The highlights drawn over this layer's content, in order. Changing them doesn't require
+    /// repainting the content, but they should be set with `set_highlights` so that the
+    /// composited frame is damaged.
+    pub highlights: RefCell<Vec<Highlight>>,
+
+    /// Temporary overrides of the opacity and transform of this layer. Later overrides take
+    /// precedence over earlier ones, and the layer's own properties apply once all of them
+    /// are removed.
+    property_overrides: RefCell<Vec<(PropertyOverrideId, PropertyOverride)>>,
+    next_property_override_id: Cell<usize>,
+
+    /// A low-resolution rendering of the content, stretched over missing tiles when the scene
+    /// uses preview placeholders.
+    low_res_preview: RefCell<Option<Tile>>,
+
+    /// Painted buffers held back by `SimulatedDelays::buffer_reply`, each until the time paired
+    /// with it, in the order they were handed to this layer.
+    delayed_buffers: RefCell<Vec<(Instant, DelayedBuffer)>>,
+
     /// Whether this stacking context creates a new 3d rendering context.
     pub establishes_3d_context: bool,
 
     /// Collection of state related to transforms for this layer.
     pub transform_state: RefCell<TransformState>,
+
+    /// The factor by which the tiles of this layer are magnified beyond the scene scale, as of
+    /// the last buffer requests, so that content its transforms scale up is painted sharp.
+    raster_magnification: Cell<f32>,
+
+    /// Whether the subtree rooted at this layer may be cached in a texture.
+    cache_policy: RefCell<CachePolicy>,
+
+    /// What layout expects to change about this layer.
+    compositing_hints: RefCell<CompositingHints>,
+
+    /// The cached rendering of the subtree rooted at this layer.
+    pub raster_cache: RefCell<RasterCache>,
+
+    /// The part of the content of this layer that changed since the damage was last taken.
+    damage: RefCell<Option<TypedRect<f32, LayerPixel>>>,
+
+    /// When damage was first added to this layer since the last `take_damage_time`.
+    damaged_since: RefCell<Option<Instant>>,
+
+    /// Damage in world coordinates, where the layer was drawn before its transform changed,
+    /// which damage in layer coordinates, projected with the new transform, misses.
+    world_damage: RefCell<Option<Rect<f32>>>,
+
+    /// A human-readable name for this layer, used in logs, dumps and profiles.
+    debug_name: RefCell<Option<String>>,
 }
 
 impl<T> Layer<T> {
     pub fn new(bounds: TypedRect<f32, LayerPixel>,
-               tile_size: usize,
+               tile_size: Size2D<usize>,
                background_color: Color,
                opacity: f32,
                establishes_3d_context: bool,
@@ -115,19 +597,170 @@ impl<T> Layer<T> {
             transform: RefCell::new(Matrix4D::identity()),
             perspective: RefCell::new(Matrix4D::identity()),
             bounds: RefCell::new(bounds),
-            tile_size: tile_size,
             extra_data: RefCell::new(data),
             tile_grid: RefCell::new(TileGrid::new(tile_size)),
+            tile_size_is_set: Cell::new(false),
             content_age: RefCell::new(ContentAge::new()),
+            epoch: RefCell::new(None),
             masks_to_bounds: RefCell::new(false),
+            clip_region: RefCell::new(None),
             content_offset: RefCell::new(TypedPoint2D::zero()),
             background_color: RefCell::new(background_color),
             opacity: RefCell::new(opacity),
+            border: RefCell::new(None),
+            shadow: RefCell::new(None),
+            backdrop_filter: RefCell::new(None),
+            placeholder_color: RefCell::new(None),
+            mask: RefCell::new(None),
+            video_frame: RefCell::new(None),
+            texture_layer: RefCell::new(None),
+            hole: RefCell::new(None),
+            blur_behind_regions: RefCell::new(vec!()),
+            event_regions: RefCell::new(vec!()),
+            highlights: RefCell::new(vec!()),
+            property_overrides: RefCell::new(vec!()),
+            next_property_override_id: Cell::new(0),
+            low_res_preview: RefCell::new(None),
+            delayed_buffers: RefCell::new(vec!()),
+            content_repeat: RefCell::new(None),
             establishes_3d_context: establishes_3d_context,
             transform_state: RefCell::new(TransformState::new()),
+            raster_magnification: Cell::new(1.0),
+            cache_policy: RefCell::new(CachePolicy::Automatic),
+            compositing_hints: RefCell::new(CompositingHints::empty()),
+            raster_cache: RefCell::new(RasterCache::new()),
+            damage: RefCell::new(None),
+            damaged_since: RefCell::new(None),
+            world_damage: RefCell::new(None),
+            debug_name: RefCell::new(None),
+        }
+    }
+
+    /// Sets the name used for this layer in logs, dumps and profiles, e.g. "div#content scroll
+    /// layer".
+    pub fn set_debug_name(&self, name: &str) {
+        *self.debug_name.borrow_mut() = Some(name.to_owned());
+    }
+
+    /// The name set with `set_debug_name`, or the address of the layer if there's none.
+    pub fn debug_name(&self) -> String {
+        match *self.debug_name.borrow() {
+            Some(ref name) => name.clone(),
+            None => format!("layer {:p}", self),
         }
     }
 
+    /// Sets whether the subtree rooted at this layer may be cached in a texture.
+    pub fn set_cache_policy(&self, policy: CachePolicy) {
+        *self.cache_policy.borrow_mut() = policy;
+    }
+
+    pub fn cache_policy(&self) -> CachePolicy {
+        *self.cache_policy.borrow()
+    }
+
+    /// Tells the compositor what is expected to change about this layer, replacing the hints
+    /// set before. Subtrees containing layers that will change aren't cached in textures or
+    /// collapsed, and layers that are repainted often keep their old buffers to paint into.
+    pub fn set_compositing_hints(&self, hints: CompositingHints) {
+        *self.compositing_hints.borrow_mut() = hints;
+    }
+
+    pub fn compositing_hints(&self) -> CompositingHints {
+        *self.compositing_hints.borrow()
+    }
+
+    /// Pushes a temporary override of the properties of this layer. The transform state must
+    /// be updated for a transform override to take effect.
+    pub fn push_property_override(&self, property_override: PropertyOverride)
+                                  -> PropertyOverrideId {
+        let id = PropertyOverrideId(self.next_property_override_id.get());
+        self.next_property_override_id.set(id.0 + 1);
+        self.property_overrides.borrow_mut().push((id, property_override));
+        self.damage_subtree();
+        id
+    }
+
+    /// Removes an override pushed with `push_property_override`, if it hasn't expired yet.
+    pub fn remove_property_override(&self, id: PropertyOverrideId) {
+        let count = self.property_overrides.borrow().len();
+        self.property_overrides.borrow_mut().retain(|&(override_id, _)| override_id != id);
+        if self.property_overrides.borrow().len() != count {
+            self.damage_subtree();
+        }
+    }
+
+    /// Removes the overrides that expired by `now`. Returns true if a transform override was
+    /// removed, in which case the transform state needs to be updated.
+    pub fn expire_property_overrides(&self, now: Instant) -> bool {
+        let mut removed = false;
+        let mut removed_transform = false;
+        self.property_overrides.borrow_mut().retain(|&(_, ref property_override)| {
+            let expired = property_override.expires_at.map_or(false, |expires_at| {
+                expires_at <= now
+            });
+            removed = removed || expired;
+            removed_transform = removed_transform || (expired &&
+                                                      property_override.transform.is_some());
+            !expired
+        });
+        if removed {
+            self.damage_subtree();
+        }
+        removed_transform
+    }
+
+    /// When the first of the property overrides of this layer that expire does, so that the
+    /// embedder can composite a frame then.
+    pub fn next_property_override_expiry(&self) -> Option<Instant> {
+        self.property_overrides.borrow().iter().filter_map(|&(_, ref property_override)| {
+            property_override.expires_at
+        }).min()
+    }
+
+    /// Damages this layer and its descendants where they are drawn now and, once the transform
+    /// state is updated, where they are drawn then, as when the effective opacity or transform
+    /// of this layer changes.
+    fn damage_subtree(&self) {
+        self.damage_bounds();
+        let mut stack = self.children().clone();
+        while let Some(layer) = stack.pop() {
+            layer.damage_bounds();
+            stack.extend(layer.children().iter().cloned());
+        }
+    }
+
+    fn damage_bounds(&self) {
+        if let Some(ref screen_rect) = self.transform_state.borrow().screen_rect {
+            let mut world_damage = self.world_damage.borrow_mut();
+            *world_damage = Some(match *world_damage {
+                Some(ref damage) => damage.union(&screen_rect.rect),
+                None => screen_rect.rect,
+            });
+        }
+        self.add_damage(TypedRect::new(TypedPoint2D::zero(), self.bounds.borrow().size));
+    }
+
+    /// Returns the damage in world coordinates added since the last call, where this layer was
+    /// drawn before its transform changed, and resets it. `Scene::take_damage` includes it.
+    pub fn take_world_damage(&self) -> Option<Rect<f32>> {
+        self.world_damage.borrow_mut().take()
+    }
+
+    /// The opacity the layer is drawn with, taking overrides into account.
+    pub fn effective_opacity(&self) -> f32 {
+        self.property_overrides.borrow().iter().rev().filter_map(|&(_, ref property_override)| {
+            property_override.opacity
+        }).next().unwrap_or(*self.opacity.borrow())
+    }
+
+    /// The transform the layer is drawn with, taking overrides into account.
+    pub fn effective_transform(&self) -> Matrix4D<f32> {
+        self.property_overrides.borrow().iter().rev().filter_map(|&(_, ref property_override)| {
+            property_override.transform
+        }).next().unwrap_or(*self.transform.borrow())
+    }
+
     pub fn children(&self) -> RefMut<Vec<Rc<Layer<T>>>> {
         self.children.borrow_mut()
     }
@@ -141,20 +774,133 @@ impl<T> Layer<T> {
     }
 
     /// Returns buffer requests inside the given dirty rect, and simultaneously throws out tiles
-    /// outside the given viewport rect.
+    /// outside the given viewport rect. The requested tiles are considered in flight as part of
+    /// `request_frame` until their textures are created. Tiles are laid out at the scene scale,
+    /// magnified for content that the transforms of the layer and its ancestors, followed by
+    /// `pinch_transform` if any, scale up on screen; see `tile_scale`.
     pub fn get_buffer_requests(&self,
                                rect_in_layer: TypedRect<f32, LayerPixel>,
                                viewport_in_layer: TypedRect<f32, LayerPixel>,
-                               scale: ScaleFactor<f32, LayerPixel, DevicePixel>)
+                               scale: ScaleFactor<f32, LayerPixel, DevicePixel>,
+                               pinch_transform: Option<&Matrix4D<f32>>,
+                               request_frame: u64)
                                -> Vec<BufferRequest> {
+        // Video frames are decoded and external textures drawn by the embedder, rather than
+        // painted, and holes have no content at all.
+        if self.video_frame.borrow().is_some() || self.texture_layer.borrow().is_some() ||
+                self.hole.borrow().is_some() {
+            return vec!();
+        }
+
+        // Tiles laid out at another scale cover other parts of the layer, so they all go when
+        // the magnification changes, as when the tile size does.
         let mut tile_grid = self.tile_grid.borrow_mut();
-        tile_grid.get_buffer_requests_in_rect(rect_in_layer * scale,
-                                              viewport_in_layer * scale,
-                                              self.bounds.borrow().size * scale,
-                                              &(self.transform_state.borrow().world_rect.origin *
-                                                scale.get()),
-                                              &self.transform_state.borrow().final_transform,
-                                              *self.content_age.borrow())
+        let magnification = raster_magnification(self.on_screen_scale(pinch_transform));
+        if magnification != self.raster_magnification.get() {
+            tile_grid.discard_tiles();
+            self.raster_magnification.set(magnification);
+        }
+        let tile_scale = self.tile_scale(scale);
+
+        let transform_state = self.transform_state.borrow();
+        let world_origin = transform_state.world_rect.origin * scale.get();
+        let (dirty_rect, viewport) = match *self.content_repeat.borrow() {
+            // Every copy of a repeated pattern is drawn from the same tiles, so all of them
+            // are needed no matter which part of the layer is visible. Tiles are tested against
+            // the rects on screen, so the content of the layer is projected like they are.
+            Some(_) => {
+                let content_rect = TypedRect::new(TypedPoint2D::zero(), self.content_size());
+                let content_rect = (content_rect * scale).to_untyped().translate(&world_origin);
+                let screen_rect = project_rect_to_screen(&content_rect,
+                                                         &transform_state.final_transform);
+                let screen_rect = screen_rect.map_or(TypedRect::zero(), |screen_rect| {
+                    TypedRect::from_untyped(&screen_rect.rect)
+                });
+                (screen_rect, screen_rect)
+            }
+            None => (rect_in_layer * scale, viewport_in_layer * scale),
+        };
+
+        // The tiles are tested against those rects at the scene scale.
+        let tile_transform = transform_state.final_transform.pre_scaled(1.0 / magnification,
+                                                                        1.0 / magnification,
+                                                                        1.0);
+        let mut requests =
+            tile_grid.get_buffer_requests_in_rect(dirty_rect,
+                                                  viewport,
+                                                  self.content_size() * tile_scale,
+                                                  &(transform_state.world_rect.origin *
+                                                    tile_scale.get()),
+                                                  &tile_transform,
+                                                  *self.content_age.borrow(),
+                                                  request_frame);
+        for request in &mut requests {
+            request.raster_scale = tile_scale.get();
+        }
+
+        // A layer that is repainted often will soon need surfaces of the same size again, so
+        // its unused buffers are painted into instead of going back to the painter.
+        if self.compositing_hints().contains(FREQUENTLY_INVALIDATES) {
+            let mut unused_buffers = tile_grid.take_unused_buffers();
+            for request in &mut requests {
+                let size = Size2D::new(request.screen_rect.size.width as i32,
+                                       request.screen_rect.size.height as i32);
+                let position = unused_buffers.iter().position(|buffer| {
+                    buffer.native_surface.get_size() == size
+                });
+                if let Some(position) = position {
+                    let buffer = *unused_buffers.swap_remove(position);
+                    request.native_surface = Some(buffer.native_surface);
+                }
+            }
+            for buffer in unused_buffers {
+                tile_grid.add_unused_buffer(Some(buffer));
+            }
+        }
+        requests
+    }
+
+    /// How much the transforms of this layer and its ancestors, followed by `pinch_transform` if
+    /// any, magnify its content on screen, along the axis that is magnified the most.
+    /// Perspective is ignored, and degenerate transforms count as no magnification.
+    pub fn on_screen_scale(&self, pinch_transform: Option<&Matrix4D<f32>>) -> f32 {
+        let transform = match pinch_transform {
+            Some(pinch_transform) => {
+                pinch_transform.pre_mul(&self.transform_state.borrow().final_transform)
+            }
+            None => self.transform_state.borrow().final_transform,
+        };
+        let (scale_x, scale_y) = transform_scale(&transform);
+        let scale = scale_x.max(scale_y);
+        if scale.is_finite() && scale > 0.0 {
+            scale
+        } else {
+            1.0
+        }
+    }
+
+    /// The scale the tiles of this layer are laid out and painted at for the given scene scale:
+    /// the scene scale, magnified by the on-screen scale of the layer as of its last buffer
+    /// requests, rounded up to a power of two. Layers that their transforms shrink are painted
+    /// at the scene scale.
+    pub fn tile_scale(&self, scale: ScaleFactor<f32, LayerPixel, DevicePixel>)
+                      -> ScaleFactor<f32, LayerPixel, DevicePixel> {
+        ScaleFactor::new(scale.get() * self.raster_magnification.get())
+    }
+
+    /// The size of the content painted for this layer. This is the size of the layer, except
+    /// along the axes on which the content repeats.
+    pub fn content_size(&self) -> TypedSize2D<f32, LayerPixel> {
+        let size = self.bounds.borrow().size;
+        match *self.content_repeat.borrow() {
+            Some(ref repeat) => {
+                let pattern_size = repeat.pattern_size;
+                TypedSize2D::new(
+                    if repeat.mode.repeats_x() { pattern_size.width } else { size.width },
+                    if repeat.mode.repeats_y() { pattern_size.height } else { size.height })
+            }
+            None => size,
+        }
     }
 
     pub fn resize(&self, new_size: TypedSize2D<f32, LayerPixel>) {
@@ -162,39 +908,484 @@ impl<T> Layer<T> {
     }
 
     pub fn add_buffer(&self, tile: Box<LayerBuffer>) {
-        self.tile_grid.borrow_mut().add_buffer(tile);
+        if let Some(ready_at) = delays::delayed_until(SimulatedDelays::current().buffer_reply) {
+            self.delayed_buffers.borrow_mut().push((ready_at, DelayedBuffer::Added(tile)));
+            return;
+        }
+        self.show_buffer(tile)
+    }
+
+    fn show_buffer(&self, tile: Box<LayerBuffer>) {
+        let rect = TypedRect::from_untyped(&tile.rect);
+        if self.tile_grid.borrow_mut().add_buffer(tile) {
+            self.add_damage(rect);
+        } else {
+            debug!("{} did not use a buffer for {:?}", self.debug_name(), rect);
+        }
+    }
+
+    /// Hands this layer the buffers painted in reply to its buffer requests, all at once, for the
+    /// update `epoch` of the layer tree. Their textures are created when the scene is next
+    /// composited, within its upload budget and visible tiles first.
+    ///
+    /// Returns the buffers that were rejected, for the caller to destroy or reuse: all of them
+    /// if the layer has changed in a later update than `epoch`, and otherwise those whose tile
+    /// is gone or already shows newer content. Buffers held back by a simulated buffer reply
+    /// delay are rejected as they are released, through `collect_unused_buffers`.
+    pub fn assign_painted_buffers(&self, buffers: Vec<Box<LayerBuffer>>, epoch: Option<Epoch>)
+                                  -> Vec<Box<LayerBuffer>> {
+        if let (Some(epoch), Some(layer_epoch)) = (epoch, *self.epoch.borrow()) {
+            if epoch < layer_epoch {
+                debug!("{} rejected {} buffers painted for {:?}, older than {:?}",
+                       self.debug_name(),
+                       buffers.len(),
+                       epoch,
+                       layer_epoch);
+                let mut tile_grid = self.tile_grid.borrow_mut();
+                for buffer in &buffers {
+                    tile_grid.reject_buffer(buffer);
+                }
+                return buffers;
+            }
+        }
+
+        if let Some(ready_at) = delays::delayed_until(SimulatedDelays::current().buffer_reply) {
+            let mut delayed_buffers = self.delayed_buffers.borrow_mut();
+            delayed_buffers.extend(buffers.into_iter().map(|buffer| {
+                (ready_at, DelayedBuffer::Assigned(buffer, epoch))
+            }));
+            return vec!();
+        }
+
+        let mut rejected_buffers = vec!();
+        let mut tile_grid = self.tile_grid.borrow_mut();
+        for buffer in buffers {
+            let rect = TypedRect::from_untyped(&buffer.rect);
+            match tile_grid.add_buffer_or_reject(buffer, epoch) {
+                Ok(()) => self.add_damage(rect),
+                Err(buffer) => rejected_buffers.push(buffer),
+            }
+        }
+        rejected_buffers
+    }
+
+    /// Tells this layer that the painter dropped one of its buffer requests, e.g. because the
+    /// surface couldn't be allocated, so that the tile is no longer counted as in flight. It is
+    /// requested again by the next pass if it is still needed.
+    pub fn cancel_buffer_request(&self, request: &BufferRequest) {
+        self.tile_grid.borrow_mut().cancel_buffer_request(&request.screen_rect,
+                                                          request.content_age);
+    }
+
+    /// Shows a decoded video frame in this layer, or stops showing one, and returns the frame
+    /// shown before. Layers showing a frame request no tiles.
+    pub fn set_video_frame(&self, frame: Option<VideoFrame>) -> Option<VideoFrame> {
+        self.add_damage(TypedRect::new(TypedPoint2D::zero(), self.bounds.borrow().size));
+        mem::replace(&mut *self.video_frame.borrow_mut(), frame)
+    }
+
+    /// Shows a texture owned by the embedder in this layer, or stops showing one, and returns
+    /// the one shown before. Once it is returned, this layer no longer draws from the texture,
+    /// which the embedder may delete. Layers showing a texture request no tiles.
+    pub fn set_texture_layer(&self, texture_layer: Option<TextureLayer>)
+                             -> Option<TextureLayer> {
+        self.add_damage(TypedRect::new(TypedPoint2D::zero(), self.bounds.borrow().size));
+        mem::replace(&mut *self.texture_layer.borrow_mut(), texture_layer)
+    }
+
+    /// Records that the embedder drew new contents into the texture shown by this layer, so
+    /// that the layer is recomposited.
+    pub fn texture_layer_changed(&self) {
+        if let Some(ref mut texture_layer) = *self.texture_layer.borrow_mut() {
+            texture_layer.contents_changed();
+        }
+        self.add_damage(TypedRect::new(TypedPoint2D::zero(), self.bounds.borrow().size));
+    }
+
+    /// Replaces the highlights drawn over this layer's content, damaging the rects of both the
+    /// old and the new highlights.
+    pub fn set_highlights(&self, highlights: Vec<Highlight>) -> Vec<Highlight> {
+        for highlight in self.highlights.borrow().iter().chain(highlights.iter()) {
+            self.add_damage(highlight.rect);
+        }
+        mem::replace(&mut *self.highlights.borrow_mut(), highlights)
+    }
+
+    /// Replaces the blur-behind regions of this layer, damaging both the old and the new
+    /// regions.
+    pub fn set_blur_behind_regions(&self, regions: Vec<BlurBehindRegion>)
+                                   -> Vec<BlurBehindRegion> {
+        for region in self.blur_behind_regions.borrow().iter().chain(regions.iter()) {
+            self.add_damage(region.rect);
+        }
+        mem::replace(&mut *self.blur_behind_regions.borrow_mut(), regions)
+    }
+
+    /// Makes this layer a hole for the native widget with the given ID, or a regular layer
+    /// again. Holes request no tiles.
+    pub fn set_hole(&self, hole: Option<HoleId>) {
+        self.add_damage(TypedRect::new(TypedPoint2D::zero(), self.bounds.borrow().size));
+        *self.hole.borrow_mut() = hole;
+    }
+
+    /// Records that the given part of this layer's content changed since the damage was last
+    /// taken.
+    pub fn add_damage(&self, rect: TypedRect<f32, LayerPixel>) {
+        let mut damage = self.damage.borrow_mut();
+        *damage = Some(match *damage {
+            Some(ref damage) => damage.union(&rect),
+            None => rect,
+        });
+        let mut damaged_since = self.damaged_since.borrow_mut();
+        if damaged_since.is_none() {
+            *damaged_since = Some(Instant::now());
+        }
+    }
+
+    /// Returns when damage was first added to this layer since the last call, and resets it.
+    /// This is independent of `take_damage`; the compositor calls it after each frame, so that
+    /// frame pacing can tell frames that were asked for from idle periods.
+    pub fn take_damage_time(&self) -> Option<Instant> {
+        self.damaged_since.borrow_mut().take()
+    }
+
+    /// Returns the part of this layer's content, in layer pixels, that changed since the last
+    /// call, and resets it.
+    pub fn take_damage(&self) -> Option<TypedRect<f32, LayerPixel>> {
+        self.damage.borrow_mut().take()
+    }
+
+    /// Adds a buffer that was uploaded by a `BackgroundUploader`.
+    pub fn add_uploaded_buffer(&self,
+                               buffer: Box<LayerBuffer>,
+                               texture: Texture,
+                               fence: UploadFence) {
+        if let Some(ready_at) = delays::delayed_until(SimulatedDelays::current().buffer_reply) {
+            let delayed_buffer = DelayedBuffer::Uploaded(buffer, texture, fence);
+            self.delayed_buffers.borrow_mut().push((ready_at, delayed_buffer));
+            return;
+        }
+        self.show_uploaded_buffer(buffer, texture, fence)
+    }
+
+    fn show_uploaded_buffer(&self, buffer: Box<LayerBuffer>, texture: Texture, fence: UploadFence) {
+        let rect = TypedRect::from_untyped(&buffer.rect);
+        if self.tile_grid.borrow_mut().add_uploaded_buffer(buffer, texture, fence) {
+            self.add_damage(rect);
+        }
+    }
+
+    /// Hands the tiles of this layer the buffers held back by the simulated buffer reply delay
+    /// until `now`. Those that `assign_painted_buffers` would have rejected are handed back by
+    /// `collect_unused_buffers` instead.
+    pub fn release_delayed_buffers(&self, now: Instant) {
+        let (ready, held): (Vec<_>, Vec<_>) =
+            self.delayed_buffers.borrow_mut().drain(..).partition(|&(ready_at, _)| {
+                ready_at <= now
+            });
+        *self.delayed_buffers.borrow_mut() = held;
+        for (_, delayed_buffer) in ready {
+            match delayed_buffer {
+                DelayedBuffer::Added(buffer) => self.show_buffer(buffer),
+                DelayedBuffer::Assigned(buffer, epoch) => {
+                    let rect = TypedRect::from_untyped(&buffer.rect);
+                    let mut tile_grid = self.tile_grid.borrow_mut();
+                    match tile_grid.add_buffer_or_reject(buffer, epoch) {
+                        Ok(()) => self.add_damage(rect),
+                        Err(buffer) => tile_grid.add_unused_buffer(Some(buffer)),
+                    }
+                }
+                DelayedBuffer::Uploaded(buffer, texture, fence) => {
+                    self.show_uploaded_buffer(buffer, texture, fence)
+                }
+            }
+        }
+    }
+
+    /// When the first of the buffers held back by the simulated buffer reply delay is due, so
+    /// that the embedder can composite a frame then to show it.
+    pub fn next_delayed_buffer_release(&self) -> Option<Instant> {
+        self.delayed_buffers.borrow().iter().map(|&(ready_at, _)| ready_at).min()
+    }
+
+    /// The size of the tiles of this layer in device pixels. Layers start out with square
+    /// tiles of the size given to `new`.
+    pub fn tile_size(&self) -> Size2D<usize> {
+        self.tile_grid.borrow().tile_size()
+    }
+
+    /// Changes the size of the tiles of this layer, which needn't be square. The current tiles
+    /// are dropped, and their buffers are handed back by `collect_unused_buffers`. The dropped
+    /// tiles are reported as the layer is next walked for buffer requests, or by
+    /// `collect_buffers` if it is removed first.
+    pub fn set_tile_size(&self, tile_size: Size2D<usize>) {
+        self.tile_size_is_set.set(true);
+        self.tile_grid.borrow_mut().set_tile_size(tile_size);
+    }
+
+    /// Like `set_tile_size`, unless the tile size of this layer was already chosen with
+    /// `set_tile_size`, in which case it is kept. The size given to `new` is only a default,
+    /// which this replaces.
+    pub fn set_default_tile_size(&self, tile_size: Size2D<usize>) {
+        if !self.tile_size_is_set.get() {
+            self.tile_grid.borrow_mut().set_tile_size(tile_size);
+        }
     }
 
     pub fn collect_unused_buffers(&self) -> Vec<Box<LayerBuffer>> {
         self.tile_grid.borrow_mut().take_unused_buffers()
     }
 
-    pub fn collect_buffers(&self) -> Vec<Box<LayerBuffer>> {
-        self.tile_grid.borrow_mut().collect_buffers()
+    /// Drops all tiles of this layer, as when it is removed from the tree, and returns their
+    /// buffers along with the tiles it dropped that weren't reported yet. Layers that aren't
+    /// walked by the scene again can't have those reported by it, so they are handed back
+    /// here; `Scene::collect_buffers_for_subtree` reports them through the tile eviction
+    /// callback.
+    pub fn collect_buffers(&self) -> (Vec<Box<LayerBuffer>>, Vec<TileEviction>) {
+        let (mut buffers, evictions) = self.tile_grid.borrow_mut().collect_buffers();
+        let mut delayed_buffers = self.delayed_buffers.borrow_mut();
+        buffers.extend(delayed_buffers.drain(..).map(|(_, delayed_buffer)| {
+            match delayed_buffer {
+                DelayedBuffer::Added(buffer) |
+                DelayedBuffer::Assigned(buffer, _) |
+                DelayedBuffer::Uploaded(buffer, _, _) => buffer,
+            }
+        }));
+        (buffers, evictions)
+    }
+
+    /// Returns the tiles with buffers that this layer dropped since the last call. The scene
+    /// reports them through its tile eviction callback as it requests buffers, or as it
+    /// collects the buffers of the layer.
+    pub fn take_tile_evictions(&self) -> Vec<TileEviction> {
+        self.tile_grid.borrow_mut().take_evictions()
     }
 
     pub fn contents_changed(&self) {
         self.content_age.borrow_mut().next();
     }
 
+    /// Forgets the textures of all tiles after the GL context was lost, without deleting them.
+    pub fn forget_textures(&self) {
+        self.tile_grid.borrow_mut().forget_textures();
+    }
+
     pub fn create_textures(&self, display: &NativeDisplay) {
         self.tile_grid.borrow_mut().create_textures(display);
+        self.create_low_res_preview_texture(display);
+    }
+
+    /// Creates textures for the tiles accepted by `filter`, within the given upload budget.
+    pub fn create_textures_with_budget<F>(&self,
+                                          display: &NativeDisplay,
+                                          budget: &mut UploadBudget,
+                                          filter: F)
+                                          where F: FnMut(&Tile) -> bool {
+        self.tile_grid.borrow_mut().create_textures_with_budget(display, budget, filter);
+        self.create_low_res_preview_texture(display);
+    }
+
+    /// Sets the low-resolution preview of this layer's content. The rect of the buffer is in
+    /// layer coordinates, like the rects of tiles. Returns the previous preview, if any.
+    pub fn set_low_res_preview(&self, buffer: Box<LayerBuffer>) -> Option<Box<LayerBuffer>> {
+        let old_preview = self.take_low_res_preview();
+        *self.low_res_preview.borrow_mut() = Some(Tile::new_with_buffer(buffer));
+        old_preview
+    }
+
+    pub fn take_low_res_preview(&self) -> Option<Box<LayerBuffer>> {
+        self.low_res_preview.borrow_mut().take().and_then(|mut preview| preview.take_buffer())
+    }
+
+    /// Calls `f` with the low-resolution preview, if it has a texture.
+    pub fn with_low_res_preview<F: FnOnce(&Tile)>(&self, f: F) {
+        if let Some(ref preview) = *self.low_res_preview.borrow() {
+            if !preview.texture.is_zero() {
+                f(preview);
+            }
+        }
+    }
+
+    fn create_low_res_preview_texture(&self, display: &NativeDisplay) {
+        if let Some(ref mut preview) = *self.low_res_preview.borrow_mut() {
+            preview.create_texture(display);
+        }
+    }
+
+    /// Returns the rects of the tiles that have no texture to draw yet, in layer coordinates, at
+    /// the given scene scale.
+    pub fn missing_tile_rects(&self, scale: ScaleFactor<f32, LayerPixel, DevicePixel>)
+                              -> Vec<TypedRect<f32, LayerPixel>> {
+        let tile_scale = self.tile_scale(scale);
+        let tile_grid = self.tile_grid.borrow();
+        tile_grid.missing_tile_rects(self.content_size() * tile_scale).into_iter().map(|rect| {
+            device_rect_to_layer_rect(rect, tile_scale)
+        }).collect()
+    }
+
+    /// Returns the index of each tile of this layer and the pixels of its buffer at the given
+    /// scene scale, which are device pixels at `tile_scale`. Tiles are aligned to whole pixels,
+    /// so at fractional scales their edges generally don't fall on whole layer pixels.
+    pub fn tile_device_rects(&self, scale: ScaleFactor<f32, LayerPixel, DevicePixel>)
+                             -> Vec<(Point2D<usize>, TypedRect<usize, DevicePixel>)> {
+        self.tile_grid.borrow().tile_device_rects(self.content_size() * self.tile_scale(scale))
+    }
+
+    /// Returns the ids of the tiles of this layer, in row-major order.
+    pub fn tile_ids(&self) -> Vec<TileId> {
+        let mut ids: Vec<_> = self.tile_grid.borrow().tiles.keys().map(|index| {
+            TileId::from_index(*index)
+        }).collect();
+        ids.sort();
+        ids
+    }
+
+    /// Returns the state of a tile at the given scene scale, or `None` if the layer has no such
+    /// tile.
+    pub fn tile_info(&self, id: TileId, scale: ScaleFactor<f32, LayerPixel, DevicePixel>)
+                     -> Option<TileInfo> {
+        let tile_scale = self.tile_scale(scale);
+        self.tile_grid.borrow().tile_info(id,
+                                          self.content_size() * tile_scale,
+                                          tile_scale,
+                                          *self.content_age.borrow())
+    }
+
+    /// Returns the state of all tiles of this layer at the given scene scale, in row-major order.
+    pub fn tile_infos(&self, scale: ScaleFactor<f32, LayerPixel, DevicePixel>) -> Vec<TileInfo> {
+        self.tile_ids().into_iter().filter_map(|id| self.tile_info(id, scale)).collect()
+    }
+
+    /// Returns true if this layer has buffers that have not been uploaded to textures yet.
+    pub fn has_pending_uploads(&self) -> bool {
+        self.tile_grid.borrow().has_pending_uploads()
+    }
+
+    /// Adds the request frames of the buffers this layer is waiting for to `frames`.
+    pub fn add_request_frames_in_flight(&self, frames: &mut Vec<u64>) {
+        self.tile_grid.borrow().add_request_frames_in_flight(frames);
     }
 
     pub fn do_for_all_tiles<F: FnMut(&Tile)>(&self, f: F) {
         self.tile_grid.borrow().do_for_all_tiles(f);
     }
 
+    /// Like `do_for_all_tiles`, along with the index of each tile in the layer's tile grid.
+    pub fn do_for_all_tiles_with_index<F: FnMut(&Point2D<usize>, &Tile)>(&self, f: F) {
+        self.tile_grid.borrow().do_for_all_tiles_with_index(f);
+    }
+
+    /// The number of tiles of this layer, not including its children.
+    pub fn tile_count(&self) -> usize {
+        let mut count = 0;
+        self.do_for_all_tiles(|_| count += 1);
+        count
+    }
+
+    /// The memory used by the tiles of this layer, not including its children.
+    pub fn tile_memory_usage(&self) -> usize {
+        self.tile_grid.borrow().get_memory_usage()
+    }
+
     pub fn update_transform_state(&self,
                                   parent_transform: &Matrix4D<f32>,
                                   parent_perspective: &Matrix4D<f32>,
                                   parent_origin: &Point2D<f32>) {
-        let mut ts = self.transform_state.borrow_mut();
+        let content_offset = *self.content_offset.borrow();
+        self.update_transform_state_with_offset(parent_transform,
+                                                parent_perspective,
+                                                parent_origin,
+                                                &content_offset);
+    }
+
+    /// Like `update_transform_state`, but with `content_offset` in place of the content offset
+    /// of this layer, which is left as it is, e.g. for a scroll offset overridden for a frame.
+    pub fn update_transform_state_with_offset(&self,
+                                              parent_transform: &Matrix4D<f32>,
+                                              parent_perspective: &Matrix4D<f32>,
+                                              parent_origin: &Point2D<f32>,
+                                              content_offset: &TypedPoint2D<f32, LayerPixel>) {
+        // Layer trees can be deep enough to overflow the call stack, so descendants are updated
+        // from an explicit stack rather than recursively.
+        let mut stack = vec!();
+        let (ts, perspective, origin) =
+            self.compute_transform_state_with_offset(parent_transform,
+                                                     parent_perspective,
+                                                     parent_origin,
+                                                     content_offset);
+        let transform = ts.final_transform;
+        *self.transform_state.borrow_mut() = ts;
+        for child in self.children().iter() {
+            stack.push((child.clone(), transform, perspective, origin));
+        }
+
+        while let Some((layer, transform, perspective, origin)) = stack.pop() {
+            let (transform, perspective, origin) =
+                layer.update_own_transform_state(&transform, &perspective, &origin);
+            for child in layer.children().iter() {
+                stack.push((child.clone(), transform, perspective, origin));
+            }
+        }
+    }
+
+    /// Marks this layer and its descendants as not visible if their screen rects don't
+    /// intersect `visible_rect`, in world coordinates. The transform state must be up to date.
+    pub fn cull_to_rect(&self, visible_rect: &Rect<f32>) {
+        let cull = |ts: &mut TransformState| {
+            ts.visible = ts.visible && ts.screen_rect.as_ref().map_or(false, |screen_rect| {
+                screen_rect.rect.intersects(visible_rect)
+            });
+        };
+        cull(&mut *self.transform_state.borrow_mut());
+        let mut stack: Vec<Rc<Layer<T>>> = self.children().iter().cloned().collect();
+        while let Some(layer) = stack.pop() {
+            cull(&mut *layer.transform_state.borrow_mut());
+            stack.extend(layer.children().iter().cloned());
+        }
+    }
+
+    /// Updates the transform state of this layer alone, returning the transform, perspective
+    /// and origin its children inherit.
+    fn update_own_transform_state(&self,
+                                  parent_transform: &Matrix4D<f32>,
+                                  parent_perspective: &Matrix4D<f32>,
+                                  parent_origin: &Point2D<f32>)
+                                  -> (Matrix4D<f32>, Matrix4D<f32>, Point2D<f32>) {
+        let (ts, perspective_transform, origin) =
+            self.compute_transform_state(parent_transform, parent_perspective, parent_origin);
+        let final_transform = ts.final_transform;
+        *self.transform_state.borrow_mut() = ts;
+        (final_transform, perspective_transform, origin)
+    }
+
+    /// Computes the transform state of this layer from the state inherited from its parent,
+    /// without storing it. Also returns the perspective and origin its children inherit.
+    pub fn compute_transform_state(&self,
+                                   parent_transform: &Matrix4D<f32>,
+                                   parent_perspective: &Matrix4D<f32>,
+                                   parent_origin: &Point2D<f32>)
+                                   -> (TransformState, Matrix4D<f32>, Point2D<f32>) {
+        let content_offset = *self.content_offset.borrow();
+        self.compute_transform_state_with_offset(parent_transform,
+                                                 parent_perspective,
+                                                 parent_origin,
+                                                 &content_offset)
+    }
+
+    /// Like `compute_transform_state`, but with `content_offset` in place of the content offset
+    /// of this layer.
+    pub fn compute_transform_state_with_offset(&self,
+                                               parent_transform: &Matrix4D<f32>,
+                                               parent_perspective: &Matrix4D<f32>,
+                                               parent_origin: &Point2D<f32>,
+                                               content_offset: &TypedPoint2D<f32, LayerPixel>)
+                                               -> (TransformState, Matrix4D<f32>, Point2D<f32>) {
+        let mut ts = TransformState::new();
         let rect_without_scroll = self.bounds.borrow()
                                              .to_untyped()
                                              .translate(parent_origin);
 
-        ts.world_rect = rect_without_scroll.translate(&self.content_offset.borrow().to_untyped());
+        ts.world_rect = rect_without_scroll.translate(&content_offset.to_untyped());
 
         let x0 = ts.world_rect.origin.x;
         let y0 = ts.world_rect.origin.y;
@@ -202,7 +1393,7 @@ impl<T> Layer<T> {
         // Build world space transform
         let local_transform = Matrix4D::identity()
             .pre_translated(x0, y0, 0.0)
-            .pre_mul(&*self.transform.borrow())
+            .pre_mul(&self.effective_transform())
             .pre_translated(-x0, -y0, 0.0);
 
         ts.final_transform = parent_perspective
@@ -216,6 +1407,9 @@ impl<T> Layer<T> {
         // This layer is part of a 3d context if its concatenated transform
         // is not identity, since 2d transforms don't get layers.
         ts.has_transform = ts.final_transform != Matrix4D::identity();
+        ts.transform_kind = classify_transform(&ts.final_transform);
+        ts.opacity = self.effective_opacity();
+        ts.visible = ts.screen_rect.is_some() && ts.opacity > 0.0;
 
         // Build world space perspective transform
         let perspective_transform = Matrix4D::identity()
@@ -223,23 +1417,67 @@ impl<T> Layer<T> {
             .pre_mul(&*self.perspective.borrow())
             .pre_translated(-x0, -y0, 0.0);
 
-        for child in self.children().iter() {
-            child.update_transform_state(&ts.final_transform,
-                                         &perspective_transform,
-                                         &rect_without_scroll.origin);
-        }
+        (ts, perspective_transform, rect_without_scroll.origin)
     }
 
     /// Calculate the amount of memory used by this layer and all its children.
     /// The memory may be allocated on the heap or in GPU memory.
     pub fn get_memory_usage(&self) -> usize {
-        let size_of_children : usize = self.children().iter().map(|ref child| -> usize {
-            child.get_memory_usage()
-        }).sum();
-        size_of_children + self.tile_grid.borrow().get_memory_usage()
+        let mut size = self.tile_grid.borrow().get_memory_usage();
+        for child in self.children().iter() {
+            walk_subtree(child, |layer| {
+                size += layer.tile_grid.borrow().get_memory_usage();
+                true
+            });
+        }
+        size
     }
 }
 
+impl<T> Drop for Layer<T> {
+    fn drop(&mut self) {
+        // Dropping a deep tree would otherwise recurse once per level. Take ownership of the
+        // descendants that aren't shared with anyone else and drop them one at a time.
+        let mut stack = mem::replace(&mut *self.children.borrow_mut(), vec!());
+        while let Some(child) = stack.pop() {
+            if let Ok(child) = Rc::try_unwrap(child) {
+                stack.extend(child.children.borrow_mut().drain(..));
+            }
+        }
+    }
+}
+
+/// Calls `f` on every layer of the subtree rooted at `root`, parents before their children and
+/// siblings in paint order. When `f` returns false, the children of that layer are skipped.
+///
+/// The walk uses an explicit stack, so it's safe to use on arbitrarily deep trees.
+pub fn walk_subtree<T, F>(root: &Rc<Layer<T>>, mut f: F)
+                          where F: FnMut(&Rc<Layer<T>>) -> bool {
+    let mut stack = vec!(root.clone());
+    while let Some(layer) = stack.pop() {
+        if f(&layer) {
+            stack.extend(layer.children().iter().rev().cloned());
+        }
+    }
+}
+
+/// The most the tiles of a layer are magnified beyond the scene scale, so that layers scaled up
+/// by huge factors don't need huge buffers.
+const MAX_RASTER_MAGNIFICATION: f32 = 8.0;
+
+/// The magnification of the tiles of a layer with the given on-screen scale. It is rounded up to
+/// a power of two, so that the buffers are at least as sharp as the content on screen while a
+/// transform animation only lays the tiles out again as it crosses one.
+fn raster_magnification(on_screen_scale: f32) -> f32 {
+    if !(on_screen_scale > 1.0) {
+        return 1.0;
+    }
+    // Leave some slack for the rounding errors of transforms that scale by exactly a power of
+    // two.
+    let exponent = (on_screen_scale.log2() - 1.0e-4).ceil();
+    exponent.exp2().min(MAX_RASTER_MAGNIFICATION)
+}
+
 /// A request from the compositor to the renderer for tiles that need to be (re)displayed.
 pub struct BufferRequest {
     /// The rect in pixels that will be drawn to the screen
@@ -253,6 +1491,15 @@ pub struct BufferRequest {
 
     /// A cached NativeSurface that can be used to avoid allocating a new one.
     pub native_surface: Option<NativeSurface>,
+
+    /// How urgently this tile is needed. Higher priorities should be painted first; tiles that
+    /// were composited while missing (checkerboarded) get a priority above zero.
+    pub priority: u32,
+
+    /// The scale the tile is laid out at, which `screen_rect` and `page_rect` are in pixels of,
+    /// and which its buffer should be painted at: the scene scale, magnified as the transforms
+    /// and the pinch zoom scale the layer up on screen. See `Layer::tile_scale`.
+    pub raster_scale: f32,
 }
 
 impl BufferRequest {
@@ -263,6 +1510,8 @@ impl BufferRequest {
             page_rect: page_rect,
             content_age: content_age,
             native_surface: None,
+            priority: 0,
+            raster_scale: 1.0,
         }
     }
 }
@@ -19,52 +19,88 @@ extern crate libc;
 extern crate log;
 extern crate rustc_serialize;
 extern crate gleam;
+#[cfg(feature = "skia")]
 extern crate skia;
 
-#[cfg(target_os="macos")]
+#[cfg(all(target_os="macos", feature = "native-surfaces"))]
 extern crate core_foundation;
-#[cfg(target_os="macos")]
+#[cfg(all(target_os="macos", feature = "native-surfaces"))]
 extern crate io_surface;
-#[cfg(target_os="macos")]
+#[cfg(all(target_os="macos", feature = "native-surfaces"))]
 extern crate cgl;
 
-#[cfg(target_os="linux")]
+#[cfg(all(target_os="linux", feature = "native-surfaces"))]
 extern crate x11;
-#[cfg(target_os="linux")]
+#[cfg(all(target_os="linux", feature = "native-surfaces"))]
 extern crate glx;
 
-#[cfg(any(target_os = "linux", target_os = "android"))]
+#[cfg(all(any(target_os = "linux", target_os = "android"), feature = "native-surfaces"))]
 extern crate egl;
 
+pub mod animation;
+pub mod capabilities;
+pub mod capturestream;
 pub mod color;
+pub mod compare;
+pub mod debugfont;
+pub mod delays;
+pub mod description;
+pub mod frameconstants;
 pub mod geometry;
+pub mod glcapture;
+pub mod hittest;
 pub mod layers;
+pub mod limits;
+pub mod multisample;
+pub mod overlay;
+pub mod overrides;
+pub mod persist;
+pub mod prelude;
+pub mod profiler;
+pub mod rastercache;
+pub mod rasterizer;
 pub mod rendergl;
+pub mod sampler;
 pub mod scene;
+pub mod scrolling;
+pub mod sequence;
+pub mod slowframe;
+pub mod software;
+pub mod stats;
 pub mod texturegl;
+pub mod texturelayer;
 pub mod tiling;
+pub mod trace;
+pub mod upload;
+pub mod uploadbench;
 pub mod util;
+pub mod validate;
+pub mod video;
 
 pub mod platform {
-    #[cfg(target_os="linux")]
+    #[cfg(all(target_os="linux", feature = "native-surfaces"))]
     pub mod linux {
         pub mod surface;
     }
-    #[cfg(target_os="macos")]
+    #[cfg(all(target_os="macos", feature = "native-surfaces"))]
     pub mod macos {
         pub mod surface;
     }
-    #[cfg(target_os="android")]
+    #[cfg(all(target_os="android", feature = "native-surfaces"))]
     pub mod android {
         pub mod surface;
     }
-    #[cfg(any(target_os="android",target_os="linux"))]
+    #[cfg(all(any(target_os="android",target_os="linux"), feature = "native-surfaces"))]
     pub mod egl {
         pub mod surface;
     }
-    #[cfg(target_os="windows")]
-    pub mod windows {
+    /// The display of platforms without native surfaces, or of builds without them.
+    #[cfg(not(all(any(target_os="linux", target_os="macos", target_os="android"),
+                  feature = "native-surfaces")))]
+    pub mod generic {
         pub mod surface;
     }
+    pub mod lifetime;
     pub mod surface;
+    pub mod transport;
 }
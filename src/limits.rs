@@ -0,0 +1,245 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Limits on the resources used by a layer tree.
+//!
+//! When a tree grows past the limits, the least visible and deepest subtrees are collapsed: they
+//! are rendered once into a raster cache texture, the tiles of their descendants are released,
+//! and no new tiles are requested for them. A collapsed subtree is composited from its cached
+//! texture until it changes, at which point it's expanded again and the limits are reevaluated.
+
+use layers::{Layer, walk_subtree};
+use rastercache::CachePolicy;
+
+use euclid::Rect;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// Resource limits for a layer tree. `None` means unlimited.
+#[derive(Copy, Clone, Debug)]
+pub struct ResourceLimits {
+    /// The maximum number of layers composited each frame.
+    pub max_layers: Option<usize>,
+
+    /// The maximum number of tiles held by all layers.
+    pub max_tiles: Option<usize>,
+
+    /// The maximum number of bytes used by tile buffers and cached subtree textures.
+    pub max_texture_bytes: Option<usize>,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, ResourceLimits);
+
+impl ResourceLimits {
+    /// Limits that never collapse anything.
+    pub fn new() -> ResourceLimits {
+        ResourceLimits {
+            max_layers: None,
+            max_tiles: None,
+            max_texture_bytes: None,
+        }
+    }
+
+    /// Returns true if the given usage is within all of the limits.
+    pub fn allows(&self, usage: &ResourceUsage) -> bool {
+        let within = |limit: Option<usize>, value: usize| limit.map_or(true, |limit| value <= limit);
+        within(self.max_layers, usage.layers) &&
+            within(self.max_tiles, usage.tiles) &&
+            within(self.max_texture_bytes, usage.texture_bytes)
+    }
+}
+
+/// The resources used by a layer tree, or released by collapsing one of its subtrees.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ResourceUsage {
+    pub layers: usize,
+    pub tiles: usize,
+    pub texture_bytes: usize,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, ResourceUsage);
+
+impl ResourceUsage {
+    pub fn new() -> ResourceUsage {
+        ResourceUsage {
+            layers: 0,
+            tiles: 0,
+            texture_bytes: 0,
+        }
+    }
+
+    pub fn add(&mut self, other: &ResourceUsage) {
+        self.layers += other.layers;
+        self.tiles += other.tiles;
+        self.texture_bytes += other.texture_bytes;
+    }
+
+    pub fn subtract(&mut self, other: &ResourceUsage) {
+        self.layers = self.layers.saturating_sub(other.layers);
+        self.tiles = self.tiles.saturating_sub(other.tiles);
+        self.texture_bytes = self.texture_bytes.saturating_sub(other.texture_bytes);
+    }
+
+    fn of_layer<T>(layer: &Layer<T>) -> ResourceUsage {
+        let cache = layer.raster_cache.borrow();
+        let cache_bytes = match cache.texture {
+            Some(_) => cache.rect.size.width as usize * cache.rect.size.height as usize * 4,
+            None => 0,
+        };
+        ResourceUsage {
+            layers: 1,
+            tiles: layer.tile_count(),
+            texture_bytes: layer.tile_memory_usage() + cache_bytes,
+        }
+    }
+}
+
+/// Measures the resources currently used by the tree rooted at `root`. Collapsed subtrees count
+/// as a single layer.
+pub fn measure<T>(root: &Rc<Layer<T>>) -> ResourceUsage {
+    let mut usage = ResourceUsage::new();
+    walk_subtree(root, |layer| {
+        usage.add(&ResourceUsage::of_layer(layer));
+        if layer.raster_cache.borrow().is_collapsed() {
+            for child in layer.children().iter() {
+                walk_subtree(child, |layer| {
+                    let mut descendant_usage = ResourceUsage::of_layer(layer);
+                    descendant_usage.layers = 0;
+                    usage.add(&descendant_usage);
+                    true
+                });
+            }
+            return false;
+        }
+        true
+    });
+    usage
+}
+
+/// Collapses or expands subtrees of the tree rooted at `root` so that it stays within `limits`.
+/// `visible_rect` is the part of the scene that is visible, in unscaled layer pixels.
+pub fn enforce_limits<T>(root: &Rc<Layer<T>>, limits: &ResourceLimits, visible_rect: &Rect<f32>) {
+    let mut usage = measure(root);
+
+    // If the whole tree would fit without any collapsed subtrees, expand them all.
+    let mut full_usage = usage;
+    walk_subtree(root, |layer| {
+        if let Some(savings) = layer.raster_cache.borrow().collapsed_savings() {
+            full_usage.add(&savings);
+        }
+        true
+    });
+    if limits.allows(&full_usage) {
+        if full_usage != usage {
+            walk_subtree(root, |layer| {
+                layer.raster_cache.borrow_mut().expand();
+                true
+            });
+        }
+        return;
+    }
+
+    if limits.allows(&usage) {
+        return;
+    }
+
+    // Collect the subtrees that may be collapsed, along with their depth, and the resources used
+    // by their descendants. Already collapsed subtrees are left alone.
+    let mut layers = vec!();
+    let mut stack = vec!((root.clone(), 0));
+    while let Some((layer, depth)) = stack.pop() {
+        let collapsed = layer.raster_cache.borrow().is_collapsed();
+        if !collapsed {
+            for child in layer.children().iter() {
+                stack.push((child.clone(), depth + 1));
+            }
+        }
+        layers.push((layer, depth, collapsed));
+    }
+
+    // Subtrees with layers hinted to change would be expanded again right away, and holes
+    // can't be punched from a cached texture, so such subtrees are left alone too.
+    let mut subtree_usage: HashMap<*const Layer<T>, ResourceUsage> = HashMap::new();
+    let mut changing_subtrees: HashSet<*const Layer<T>> = HashSet::new();
+    let mut candidates = vec!();
+    for &(ref layer, depth, collapsed) in layers.iter().rev() {
+        let mut descendants = ResourceUsage::new();
+        let mut expects_changes = !layer.compositing_hints().is_empty() ||
+                                  layer.hole.borrow().is_some();
+        if !collapsed {
+            for child in layer.children().iter() {
+                if let Some(child_usage) = subtree_usage.remove(&(&**child as *const _)) {
+                    descendants.add(&child_usage);
+                }
+                expects_changes |= changing_subtrees.remove(&(&**child as *const _));
+            }
+        }
+
+        let mut total = descendants;
+        total.add(&ResourceUsage::of_layer(layer));
+        subtree_usage.insert(&**layer as *const _, total);
+        if expects_changes {
+            changing_subtrees.insert(&**layer as *const _);
+        }
+
+        if depth > 0 && !collapsed && !expects_changes && can_collapse(layer) {
+            let visible_area = layer.transform_state.borrow().screen_rect.as_ref().and_then(|r| {
+                r.rect.intersection(visible_rect)
+            }).map_or(0.0, |r| r.size.width * r.size.height);
+            candidates.push((layer.clone(), depth, visible_area, descendants));
+        }
+    }
+
+    // Collapse the least visible subtrees first, and the deepest among equally visible ones.
+    candidates.sort_by(|a, b| {
+        match a.2.partial_cmp(&b.2).unwrap_or(Ordering::Equal) {
+            Ordering::Equal => b.1.cmp(&a.1),
+            ordering => ordering,
+        }
+    });
+
+    let mut covered: HashSet<*const Layer<T>> = HashSet::new();
+    for (layer, _, _, mut savings) in candidates {
+        if limits.allows(&usage) {
+            break;
+        }
+        if covered.contains(&(&*layer as *const Layer<T>)) {
+            continue;
+        }
+
+        // Collapsed subtrees nested in this one are folded into it.
+        let layer_ptr = &*layer as *const Layer<T>;
+        walk_subtree(&layer, |descendant| {
+            let descendant_ptr = &**descendant as *const Layer<T>;
+            covered.insert(descendant_ptr);
+            if descendant_ptr != layer_ptr {
+                if let Some(nested_savings) = descendant.raster_cache.borrow_mut().expand() {
+                    savings.add(&nested_savings);
+                }
+            }
+            true
+        });
+
+        debug!("Collapsing {} and its {} descendants to stay within resource limits",
+               layer.debug_name(),
+               savings.layers);
+        usage.subtract(&savings);
+        layer.raster_cache.borrow_mut().collapse(savings);
+    }
+}
+
+fn can_collapse<T>(layer: &Layer<T>) -> bool {
+    !layer.children.borrow().is_empty() &&
+        !layer.establishes_3d_context &&
+        layer.cache_policy() != CachePolicy::Never &&
+        layer.transform_state.borrow().screen_rect.is_some()
+}
@@ -0,0 +1,135 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Multisampled render targets.
+//!
+//! Whether the window's framebuffer is multisampled is decided by the embedder when it creates
+//! its GL context. To antialias the composited scene regardless, the compositor can render into
+//! a multisampled framebuffer of its own and resolve it into the target framebuffer with a blit
+//! at the end of the frame. Intermediate surfaces, such as raster cache textures, are rendered
+//! the same way, since textures themselves can't be multisampled in GLES.
+
+use capabilities::GlCapabilities;
+use euclid::{Point2D, Size2D};
+use glcapture as gl;
+use gleam::gl::{GLint, GLsizei, GLuint};
+use std::cmp;
+
+/// A framebuffer with multisampled color and depth renderbuffers.
+pub struct MultisampleTarget {
+    framebuffer: GLuint,
+    color_renderbuffer: GLuint,
+    depth_renderbuffer: GLuint,
+    size: Size2D<GLsizei>,
+    sample_count: GLsizei,
+}
+
+impl Drop for MultisampleTarget {
+    fn drop(&mut self) {
+        gl::delete_framebuffers(&[self.framebuffer]);
+        gl::delete_renderbuffers(&[self.color_renderbuffer, self.depth_renderbuffer]);
+    }
+}
+
+/// Clamps a requested sample count to what the GL context current on this thread supports.
+/// Returns 0 if multisampling isn't supported at all.
+pub fn supported_sample_count(requested: usize) -> usize {
+    if requested <= 1 || !GlCapabilities::current().framebuffer_multisample {
+        return 0;
+    }
+    let max_samples = gl::get_integer_v(gl::MAX_SAMPLES);
+    if max_samples <= 1 {
+        return 0;
+    }
+    cmp::min(requested, max_samples as usize)
+}
+
+impl MultisampleTarget {
+    /// Creates a multisampled target. The framebuffer binding is left unchanged. Returns `None`
+    /// if the framebuffer is incomplete.
+    pub fn new(size: Size2D<GLsizei>, sample_count: usize) -> Option<MultisampleTarget> {
+        let sample_count = sample_count as GLsizei;
+        let renderbuffers = gl::gen_renderbuffers(2);
+        let (color_renderbuffer, depth_renderbuffer) = (renderbuffers[0], renderbuffers[1]);
+        gl::bind_renderbuffer(gl::RENDERBUFFER, color_renderbuffer);
+        unsafe {
+            gl::RenderbufferStorageMultisample(gl::RENDERBUFFER,
+                                               sample_count,
+                                               gl::RGBA8,
+                                               size.width,
+                                               size.height);
+        }
+        gl::bind_renderbuffer(gl::RENDERBUFFER, depth_renderbuffer);
+        unsafe {
+            gl::RenderbufferStorageMultisample(gl::RENDERBUFFER,
+                                               sample_count,
+                                               gl::DEPTH_COMPONENT24,
+                                               size.width,
+                                               size.height);
+        }
+        gl::bind_renderbuffer(gl::RENDERBUFFER, 0);
+
+        let previous_framebuffer = gl::get_integer_v(gl::FRAMEBUFFER_BINDING) as GLuint;
+        let framebuffer = gl::gen_framebuffers(1)[0];
+        gl::bind_framebuffer(gl::FRAMEBUFFER, framebuffer);
+        gl::framebuffer_renderbuffer(gl::FRAMEBUFFER,
+                                     gl::COLOR_ATTACHMENT0,
+                                     gl::RENDERBUFFER,
+                                     color_renderbuffer);
+        gl::framebuffer_renderbuffer(gl::FRAMEBUFFER,
+                                     gl::DEPTH_ATTACHMENT,
+                                     gl::RENDERBUFFER,
+                                     depth_renderbuffer);
+        let complete = gl::check_frame_buffer_status(gl::FRAMEBUFFER) == gl::FRAMEBUFFER_COMPLETE;
+        gl::bind_framebuffer(gl::FRAMEBUFFER, previous_framebuffer);
+
+        let target = MultisampleTarget {
+            framebuffer: framebuffer,
+            color_renderbuffer: color_renderbuffer,
+            depth_renderbuffer: depth_renderbuffer,
+            size: size,
+            sample_count: sample_count,
+        };
+        if complete {
+            Some(target)
+        } else {
+            warn!("Could not create a {}x{} framebuffer with {} samples.",
+                  size.width,
+                  size.height,
+                  sample_count);
+            None
+        }
+    }
+
+    /// Returns true if this target has the given size and sample count, and can be reused
+    /// instead of creating a new one.
+    pub fn matches(&self, size: Size2D<GLsizei>, sample_count: usize) -> bool {
+        self.size == size && self.sample_count == sample_count as GLsizei
+    }
+
+    /// Binds the target for drawing.
+    pub fn bind(&self) {
+        gl::bind_framebuffer(gl::FRAMEBUFFER, self.framebuffer);
+    }
+
+    /// Resolves the samples into `framebuffer`, with the top left of the target at `origin`,
+    /// and leaves `framebuffer` bound.
+    pub fn resolve(&self, framebuffer: GLuint, origin: Point2D<GLint>) {
+        gl::bind_framebuffer(gl::READ_FRAMEBUFFER, self.framebuffer);
+        gl::bind_framebuffer(gl::DRAW_FRAMEBUFFER, framebuffer);
+        unsafe {
+            gl::BlitFramebuffer(0, 0, self.size.width, self.size.height,
+                                origin.x, origin.y,
+                                origin.x + self.size.width, origin.y + self.size.height,
+                                gl::COLOR_BUFFER_BIT,
+                                gl::NEAREST);
+        }
+        gl::bind_framebuffer(gl::FRAMEBUFFER, framebuffer);
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Simple shapes drawn over the composited scene.
+//!
+//! Selection handles, find-in-page highlights and debugging aids are often too short-lived to
+//! be worth a layer of their own. Embedders can instead queue overlay items on the
+//! `RenderContext`, in device pixels, and they are drawn on top of the next frame and then
+//! discarded. Text is drawn with the embedded bitmap font of the `debugfont` module.
+
+use color::Color;
+use euclid::point::{Point2D, TypedPoint2D};
+use euclid::rect::TypedRect;
+use geometry::DevicePixel;
+
+/// A shape drawn over the scene for a single frame.
+#[derive(Clone, Debug)]
+pub enum OverlayItem {
+    /// A filled rectangle.
+    Rect(TypedRect<f32, DevicePixel>, Color),
+
+    /// A line between two points, with the given width.
+    Line(TypedPoint2D<f32, DevicePixel>, TypedPoint2D<f32, DevicePixel>, f32, Color),
+
+    /// A filled rectangle whose corners are rounded with the given radius.
+    RoundedRect(TypedRect<f32, DevicePixel>, f32, Color),
+
+    /// ASCII text with its top left corner at the given point, with each pixel of the bitmap
+    /// font scaled to the given number of device pixels. Lines are separated by `\n`.
+    Text(TypedPoint2D<f32, DevicePixel>, String, f32, Color),
+}
+
+/// A quad in device pixels, with its vertices in triangle strip order: top left, top right,
+/// bottom left and bottom right.
+pub type OverlayQuad = [Point2D<f32>; 4];
+
+fn rect_quad(x0: f32, y0: f32, x1: f32, y1: f32) -> OverlayQuad {
+    [Point2D::new(x0, y0), Point2D::new(x1, y0), Point2D::new(x0, y1), Point2D::new(x1, y1)]
+}
+
+impl OverlayItem {
+    pub fn color(&self) -> Color {
+        match *self {
+            OverlayItem::Rect(_, color) |
+            OverlayItem::Line(_, _, _, color) |
+            OverlayItem::RoundedRect(_, _, color) |
+            OverlayItem::Text(_, _, _, color) => color,
+        }
+    }
+
+    /// Splits the item into quads that can be drawn with a solid color. Rounded corners are
+    /// approximated with a strip of quads per device pixel row. Text has no solid quads, since
+    /// it is drawn from the glyph atlas.
+    pub fn quads(&self) -> Vec<OverlayQuad> {
+        match *self {
+            OverlayItem::Text(..) => vec!(),
+            OverlayItem::Rect(rect, _) => {
+                let rect = rect.to_untyped();
+                vec!(rect_quad(rect.min_x(), rect.min_y(), rect.max_x(), rect.max_y()))
+            }
+            OverlayItem::Line(from, to, width, _) => {
+                let (from, to) = (from.to_untyped(), to.to_untyped());
+                let (dx, dy) = (to.x - from.x, to.y - from.y);
+                let length = (dx * dx + dy * dy).sqrt();
+                if length == 0.0 || width <= 0.0 {
+                    return vec!();
+                }
+                let normal = Point2D::new(-dy / length * width * 0.5, dx / length * width * 0.5);
+                vec!([from + normal, to + normal, from - normal, to - normal])
+            }
+            OverlayItem::RoundedRect(rect, radius, _) => {
+                let rect = rect.to_untyped();
+                let radius = radius.min(rect.size.width * 0.5).min(rect.size.height * 0.5);
+                if radius <= 0.0 {
+                    return vec!(rect_quad(rect.min_x(), rect.min_y(), rect.max_x(), rect.max_y()));
+                }
+
+                let mut quads = vec!(rect_quad(rect.min_x(), rect.min_y() + radius,
+                                               rect.max_x(), rect.max_y() - radius));
+                let rows = radius.ceil() as usize;
+                let row_height = radius / rows as f32;
+                for row in 0..rows {
+                    // Inset each row by the distance from the edge to the arc at its middle.
+                    let dy = radius - (row as f32 + 0.5) * row_height;
+                    let inset = radius - (radius * radius - dy * dy).max(0.0).sqrt();
+                    let (x0, x1) = (rect.min_x() + inset, rect.max_x() - inset);
+                    let top = rect.min_y() + row as f32 * row_height;
+                    let bottom = rect.max_y() - (row + 1) as f32 * row_height;
+                    quads.push(rect_quad(x0, top, x1, top + row_height));
+                    quads.push(rect_quad(x0, bottom, x1, bottom + row_height));
+                }
+                quads
+            }
+        }
+    }
+}
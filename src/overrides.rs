@@ -0,0 +1,81 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Properties that can be overridden from another thread right before a frame is composited.
+//!
+//! Scroll and zoom gestures normally reach the layer tree through the compositor thread, which
+//! may be busy with other work when input arrives. An input thread can instead write the latest
+//! root scroll offset and pinch transform to the scene's `PresentOverridesHandle`, and
+//! `render_scene` applies them just before drawing, so that the frame reflects the most recent
+//! input.
+
+use euclid::Matrix4D;
+use euclid::point::TypedPoint2D;
+use geometry::LayerPixel;
+use std::sync::{Arc, Mutex};
+
+/// The properties overridden for the next frames.
+#[derive(Copy, Clone, Debug)]
+pub struct PresentOverrides {
+    /// Replaces the content offset of the root layer in the transform states of the frames,
+    /// leaving its `content_offset` as it is.
+    pub root_scroll_offset: Option<TypedPoint2D<f32, LayerPixel>>,
+
+    /// A transform applied to the whole scene after scaling, in device pixels, such as the
+    /// scale and translation of an ongoing pinch gesture. Buffer requests paint the tiles of
+    /// layers it scales up at a higher resolution, but which tiles are visible, and frame
+    /// statistics, don't take it into account.
+    pub pinch_transform: Option<Matrix4D<f32>>,
+}
+
+impl PresentOverrides {
+    pub fn new() -> PresentOverrides {
+        PresentOverrides {
+            root_scroll_offset: None,
+            pinch_transform: None,
+        }
+    }
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, PresentOverrides);
+
+/// A shared handle to the overrides of a scene. Clones refer to the same overrides, and can be
+/// sent to other threads.
+#[derive(Clone)]
+pub struct PresentOverridesHandle {
+    overrides: Arc<Mutex<PresentOverrides>>,
+}
+
+impl PresentOverridesHandle {
+    pub fn new() -> PresentOverridesHandle {
+        PresentOverridesHandle {
+            overrides: Arc::new(Mutex::new(PresentOverrides::new())),
+        }
+    }
+
+    /// Returns the current overrides.
+    pub fn get(&self) -> PresentOverrides {
+        *self.overrides.lock().unwrap()
+    }
+
+    pub fn set_root_scroll_offset(&self, offset: Option<TypedPoint2D<f32, LayerPixel>>) {
+        self.overrides.lock().unwrap().root_scroll_offset = offset;
+    }
+
+    pub fn set_pinch_transform(&self, transform: Option<Matrix4D<f32>>) {
+        self.overrides.lock().unwrap().pinch_transform = transform;
+    }
+
+    /// Removes all overrides. The compositor thread should call this once the layer tree has
+    /// caught up with the input that the overrides were set for.
+    pub fn clear(&self) {
+        *self.overrides.lock().unwrap() = PresentOverrides::new();
+    }
+}
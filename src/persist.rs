@@ -0,0 +1,293 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Saving scenes to disk and loading them back, so that a bug report can attach the scene
+//! that shows a problem and it can be rendered offline.
+//!
+//! A saved scene is a JSON file with the viewport, the scale and the layers of each root layer
+//! tree, listed in paint order with their depth, so that neither saving nor loading recurses
+//! on deep trees. The tiles of each layer are stitched into a single bitmap at the scene
+//! scale. Only tiles whose pixels are in CPU memory can be saved; layers painted into GPU
+//! surfaces are saved without content.
+//!
+//! Loading gives back a `LayerTree` per root layer, whose layers show the saved bitmaps, which
+//! can be painted with `LayerTree::paint`, e.g. through `rasterizer::rasterize_scene` and a
+//! `CpuRasterizer`.
+
+use color::Color;
+use description::{Bitmap, ContentSource, LayerDescription, LayerTree};
+use euclid::Matrix4D;
+use euclid::point::TypedPoint2D;
+use euclid::rect::TypedRect;
+use euclid::scale_factor::ScaleFactor;
+use euclid::size::{Size2D, TypedSize2D};
+use layers::Layer;
+use rustc_serialize::base64::{self, FromBase64, ToBase64};
+use rustc_serialize::json;
+use scene::Scene;
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use tiling::Tile;
+
+/// The version of the file format, increased on incompatible changes.
+const FORMAT_VERSION: u32 = 2;
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct SavedScene {
+    version: u32,
+    viewport: Vec<f32>,
+    scale: f32,
+    /// The layers of all root layer trees, in paint order, from the main root.
+    layers: Vec<SavedLayer>,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct SavedLayer {
+    /// The number of ancestors of the layer. Roots have none.
+    depth: usize,
+    debug_name: String,
+    bounds: Vec<f32>,
+    content_offset: Vec<f32>,
+    transform: Vec<f32>,
+    perspective: Vec<f32>,
+    background_color: Vec<f32>,
+    opacity: f32,
+    masks_to_bounds: bool,
+    establishes_3d_context: bool,
+    tile_size: usize,
+    /// The height of the tiles, if they aren't square. Missing from files saved before tiles
+    /// could be rectangular.
+    tile_height: Option<usize>,
+    bitmap: Option<SavedBitmap>,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct SavedBitmap {
+    width: usize,
+    height: usize,
+
+    /// The BGRA pixels, encoded as base64.
+    pixels: String,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn matrix_to_vec(m: &Matrix4D<f32>) -> Vec<f32> {
+    vec!(m.m11, m.m12, m.m13, m.m14,
+         m.m21, m.m22, m.m23, m.m24,
+         m.m31, m.m32, m.m33, m.m34,
+         m.m41, m.m42, m.m43, m.m44)
+}
+
+fn matrix_from_slice(m: &[f32]) -> io::Result<Matrix4D<f32>> {
+    if m.len() != 16 {
+        return Err(invalid_data(format!("a matrix has {} elements", m.len())));
+    }
+    Ok(Matrix4D::new(m[0], m[1], m[2], m[3],
+                     m[4], m[5], m[6], m[7],
+                     m[8], m[9], m[10], m[11],
+                     m[12], m[13], m[14], m[15]))
+}
+
+fn check_length(values: &[f32], length: usize) -> io::Result<()> {
+    if values.len() != length {
+        return Err(invalid_data(format!("expected {} values, found {}", length, values.len())));
+    }
+    Ok(())
+}
+
+/// Stitches the tiles of a layer that are in CPU memory and valid at `scale` into one bitmap.
+fn layer_bitmap<T>(layer: &Layer<T>, scale: f32) -> Option<Bitmap> {
+    let size = layer.bounds.borrow().size;
+    let (width, height) = ((size.width * scale).ceil() as usize,
+                           (size.height * scale).ceil() as usize);
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let mut pixels = vec!(0; width * height * 4);
+    let mut has_content = false;
+    layer.do_for_all_tiles(|tile: &Tile| {
+        let buffer = match tile.buffer() {
+            Some(buffer) if buffer.is_valid(scale) => buffer,
+            _ => return,
+        };
+        let tile_pixels = match buffer.native_surface.cpu_pixels() {
+            Some(tile_pixels) => tile_pixels,
+            None => {
+                debug!("Not saving a tile of {} that isn't in CPU memory", layer.debug_name());
+                return;
+            }
+        };
+        let rect = buffer.screen_pos;
+        if tile_pixels.len() < rect.size.width * rect.size.height * 4 {
+            return;
+        }
+        let columns = cmp::min(rect.size.width, width.saturating_sub(rect.origin.x));
+        for row in 0..cmp::min(rect.size.height, height.saturating_sub(rect.origin.y)) {
+            let source = row * rect.size.width * 4;
+            let destination = ((rect.origin.y + row) * width + rect.origin.x) * 4;
+            pixels[destination..destination + columns * 4]
+                .copy_from_slice(&tile_pixels[source..source + columns * 4]);
+        }
+        has_content = true;
+    });
+
+    if has_content {
+        Some(Bitmap {
+            size: Size2D::new(width, height),
+            pixels: pixels,
+        })
+    } else {
+        None
+    }
+}
+
+fn save_layer<T>(layer: &Layer<T>, depth: usize, scale: f32) -> SavedLayer {
+    let bounds = *layer.bounds.borrow();
+    let content_offset = *layer.content_offset.borrow();
+    let background_color = *layer.background_color.borrow();
+    SavedLayer {
+        depth: depth,
+        debug_name: layer.debug_name(),
+        bounds: vec!(bounds.origin.x, bounds.origin.y, bounds.size.width, bounds.size.height),
+        content_offset: vec!(content_offset.x, content_offset.y),
+        transform: matrix_to_vec(&*layer.transform.borrow()),
+        perspective: matrix_to_vec(&*layer.perspective.borrow()),
+        background_color: vec!(background_color.r, background_color.g,
+                               background_color.b, background_color.a),
+        opacity: *layer.opacity.borrow(),
+        masks_to_bounds: *layer.masks_to_bounds.borrow(),
+        establishes_3d_context: layer.establishes_3d_context,
+        tile_size: layer.tile_size().width,
+        tile_height: Some(layer.tile_size().height),
+        bitmap: layer_bitmap(layer, scale).map(|bitmap| {
+            SavedBitmap {
+                width: bitmap.size.width,
+                height: bitmap.size.height,
+                pixels: bitmap.pixels.to_base64(base64::STANDARD),
+            }
+        }),
+    }
+}
+
+/// Writes the scene, with the content of its layers at the current scale, to `path`.
+pub fn save_scene<T>(scene: &Scene<T>, path: &Path) -> io::Result<()> {
+    let v = scene.viewport.to_untyped();
+    let scale = scene.scale.get();
+    let mut layers = vec!();
+    let mut stack: Vec<_> = scene.roots.iter().rev().map(|root| (root.clone(), 0)).collect();
+    while let Some((layer, depth)) = stack.pop() {
+        for kid in layer.children().iter().rev() {
+            stack.push((kid.clone(), depth + 1));
+        }
+        layers.push(save_layer(&layer, depth, scale));
+    }
+    let saved = SavedScene {
+        version: FORMAT_VERSION,
+        viewport: vec!(v.origin.x, v.origin.y, v.size.width, v.size.height),
+        scale: scale,
+        layers: layers,
+    };
+    let encoded = try!(json::encode(&saved).map_err(|error| invalid_data(error.to_string())));
+    let mut file = try!(File::create(path));
+    try!(file.write_all(encoded.as_bytes()));
+    file.flush()
+}
+
+fn load_layer(saved: SavedLayer) -> io::Result<LayerDescription<()>> {
+    try!(check_length(&saved.bounds, 4));
+    try!(check_length(&saved.content_offset, 2));
+    try!(check_length(&saved.background_color, 4));
+    if saved.tile_size == 0 || saved.tile_height == Some(0) {
+        return Err(invalid_data(format!("the tiles of {} are empty", saved.debug_name)));
+    }
+    let b = &saved.bounds;
+    let bounds = TypedRect::new(TypedPoint2D::new(b[0], b[1]), TypedSize2D::new(b[2], b[3]));
+    let c = &saved.background_color;
+    let mut description = LayerDescription::new(bounds, ())
+        .debug_name(&saved.debug_name)
+        .content_offset(TypedPoint2D::new(saved.content_offset[0], saved.content_offset[1]))
+        .transform(try!(matrix_from_slice(&saved.transform)))
+        .background_color(Color { r: c[0], g: c[1], b: c[2], a: c[3] })
+        .opacity(saved.opacity)
+        .masks_to_bounds(saved.masks_to_bounds);
+    description.perspective = try!(matrix_from_slice(&saved.perspective));
+    description.establishes_3d_context = saved.establishes_3d_context;
+    description.tile_size = Size2D::new(saved.tile_size,
+                                        saved.tile_height.unwrap_or(saved.tile_size));
+
+    if let Some(bitmap) = saved.bitmap {
+        let pixels = try!(bitmap.pixels.from_base64().map_err(|error| {
+            invalid_data(error.to_string())
+        }));
+        if pixels.len() != bitmap.width * bitmap.height * 4 {
+            return Err(invalid_data(format!("the bitmap of {} has the wrong size",
+                                            saved.debug_name)));
+        }
+        description.content = ContentSource::Bitmap(Bitmap {
+            size: Size2D::new(bitmap.width, bitmap.height),
+            pixels: pixels,
+        });
+    }
+    Ok(description)
+}
+
+/// Adds the last layer of `path`, whose descendants are all loaded, to its parent, or to `roots`
+/// if it is a root.
+fn finish_layer(path: &mut Vec<LayerDescription<()>>, roots: &mut Vec<LayerDescription<()>>) {
+    let description = path.pop().unwrap();
+    match path.last_mut() {
+        Some(parent) => parent.children.push(description),
+        None => roots.push(description),
+    }
+}
+
+/// Reads a scene written by `save_scene`. The scene's roots are the roots of the returned trees,
+/// in order.
+pub fn load_scene(path: &Path) -> io::Result<(Scene<()>, Vec<LayerTree<()>>)> {
+    let mut encoded = String::new();
+    try!(try!(File::open(path)).read_to_string(&mut encoded));
+    let saved: SavedScene = try!(json::decode(&encoded).map_err(|error| {
+        invalid_data(error.to_string())
+    }));
+    if saved.version != FORMAT_VERSION {
+        return Err(invalid_data(format!("unsupported scene format version {}", saved.version)));
+    }
+
+    try!(check_length(&saved.viewport, 4));
+    let v = &saved.viewport;
+    let mut scene = Scene::new(TypedRect::new(TypedPoint2D::new(v[0], v[1]),
+                                              TypedSize2D::new(v[2], v[3])));
+    scene.scale = ScaleFactor::new(saved.scale);
+
+    // The descriptions of the layer last loaded and of its ancestors.
+    let mut path = vec!();
+    let mut roots = vec!();
+    for layer in saved.layers {
+        if layer.depth > path.len() {
+            return Err(invalid_data(format!("{} has no parent", layer.debug_name)));
+        }
+        while path.len() > layer.depth {
+            finish_layer(&mut path, &mut roots);
+        }
+        path.push(try!(load_layer(layer)));
+    }
+    while !path.is_empty() {
+        finish_layer(&mut path, &mut roots);
+    }
+
+    let trees: Vec<_> = roots.into_iter().map(LayerTree::build).collect();
+    scene.roots = trees.iter().map(|tree| tree.root.clone()).collect();
+    Ok((scene, trees))
+}
@@ -9,17 +9,25 @@
 
 //! Implementation of cross-process surfaces for Android. This uses EGL surface.
 
+use platform::lifetime::{SurfaceEvent, SurfaceLifetime};
+use platform::surface::NativeSurfaceKind;
 use texturegl::Texture;
 
-use egl::egl::{EGLDisplay, GetCurrentDisplay};
+use egl::egl::{EGLConfig, EGLContext, EGLDisplay, EGLNativeWindowType, EGLSurface};
+use egl::egl::{CreateWindowSurface, DestroySurface, GetCurrentDisplay, GetError, MakeCurrent};
+use egl::egl::SwapBuffers;
 use egl::eglext::{EGLImageKHR, DestroyImageKHR};
 use euclid::size::Size2D;
 use gleam::gl::{egl_image_target_texture2d_oes, TEXTURE_2D, TexImage2D, BGRA_EXT, UNSIGNED_BYTE};
+#[cfg(feature = "skia")]
 use skia::gl_context::{GLContext, PlatformDisplayData};
+#[cfg(feature = "skia")]
 use skia::gl_rasterization_context::GLRasterizationContext;
 use std::iter::repeat;
 use std::mem;
 use std::os::raw::c_void;
+use std::ptr;
+#[cfg(feature = "skia")]
 use std::sync::Arc;
 use std::vec::Vec;
 
@@ -31,6 +39,9 @@ use std::vec::Vec;
 #[derive(Clone, Copy)]
 pub struct NativeDisplay {
     pub display: EGLDisplay,
+
+    /// The kind of surface created for this display, selected when it is created.
+    surface_kind: NativeSurfaceKind,
 }
 unsafe impl Send for NativeDisplay {}
 
@@ -40,11 +51,24 @@ impl NativeDisplay {
     }
 
     pub fn new_with_display(display: EGLDisplay) -> NativeDisplay {
-        NativeDisplay {
+        let mut display = NativeDisplay {
             display: display,
-        }
+            surface_kind: NativeSurfaceKind::MemoryBuffer,
+        };
+        display.surface_kind = NativeSurfaceKind::select(&display);
+        display
+    }
+
+    /// The kind of surface created for this display. See `NativeSurfaceKind::active`.
+    pub fn surface_kind(&self) -> NativeSurfaceKind {
+        self.surface_kind
+    }
+
+    pub fn set_surface_kind(&mut self, surface_kind: NativeSurfaceKind) {
+        self.surface_kind = surface_kind;
     }
 
+    #[cfg(feature = "skia")]
     pub fn platform_display_data(&self) -> PlatformDisplayData {
         PlatformDisplayData {
             display: self.display,
@@ -52,6 +76,107 @@ impl NativeDisplay {
     }
 }
 
+/// The EGL surface of the window the compositor draws into. Android destroys the window when
+/// the application goes into the background, while the EGL context may survive. The surface is
+/// released on pause and acquired again for the new window on resume, keeping the context and
+/// its textures; see also `Scene::pause` and `Scene::resume`.
+pub struct WindowSurface {
+    display: EGLDisplay,
+    config: EGLConfig,
+    context: EGLContext,
+
+    /// The surface of the current window, or `None` while the application is in the background.
+    surface: Option<EGLSurface>,
+}
+
+unsafe impl Send for WindowSurface {}
+
+impl WindowSurface {
+    /// Creates a surface for `window` with the given config, and makes it current along with
+    /// `context`. Returns `None` if the surface can't be created.
+    pub fn new(display: &NativeDisplay,
+               config: EGLConfig,
+               context: EGLContext,
+               window: EGLNativeWindowType)
+               -> Option<WindowSurface> {
+        let mut window_surface = WindowSurface {
+            display: display.display,
+            config: config,
+            context: context,
+            surface: None,
+        };
+        if window_surface.acquire(window) {
+            Some(window_surface)
+        } else {
+            None
+        }
+    }
+
+    /// Whether there is a surface to draw into. Nothing must be drawn while there isn't.
+    pub fn is_acquired(&self) -> bool {
+        self.surface.is_some()
+    }
+
+    /// Creates a surface for `window`, typically a new window after the application was
+    /// resumed, and makes it current along with the context. The previous surface is released
+    /// first. Returns false if the surface can't be created or made current.
+    pub fn acquire(&mut self, window: EGLNativeWindowType) -> bool {
+        self.release();
+
+        let surface = CreateWindowSurface(self.display, self.config, window, ptr::null());
+        if surface.is_null() {
+            warn!(target: "layers::surface",
+                  "Could not create a window surface: EGL error {:#x}",
+                  GetError());
+            return false;
+        }
+        if MakeCurrent(self.display, surface, surface, self.context) == 0 {
+            warn!(target: "layers::surface",
+                  "Could not make the window surface current: EGL error {:#x}",
+                  GetError());
+            DestroySurface(self.display, surface);
+            return false;
+        }
+
+        debug!(target: "layers::surface", "Acquired window surface {:?}", surface);
+        self.surface = Some(surface);
+        true
+    }
+
+    /// Destroys the surface, as Android requires before the window goes away when the
+    /// application is paused. The context stays current without a surface where
+    /// EGL_KHR_surfaceless_context is supported, and is otherwise detached from the thread, but
+    /// it isn't destroyed, so textures and programs remain valid unless the context is lost.
+    pub fn release(&mut self) {
+        let surface = match self.surface.take() {
+            Some(surface) => surface,
+            None => return,
+        };
+
+        let no_surface: EGLSurface = ptr::null_mut();
+        if MakeCurrent(self.display, no_surface, no_surface, self.context) == 0 {
+            MakeCurrent(self.display, no_surface, no_surface, ptr::null_mut());
+        }
+        DestroySurface(self.display, surface);
+        debug!(target: "layers::surface", "Released window surface {:?}", surface);
+    }
+
+    /// Presents the frame drawn into the surface. Returns false, without touching EGL, if the
+    /// surface was released, or if presenting failed because the window went away.
+    pub fn swap_buffers(&self) -> bool {
+        match self.surface {
+            Some(surface) => SwapBuffers(self.display, surface) != 0,
+            None => false,
+        }
+    }
+}
+
+impl Drop for WindowSurface {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
 pub struct EGLImageNativeSurface {
     /// An EGLImage for the case of GPU rendering.
     image: Option<EGLImageKHR>,
@@ -59,8 +184,8 @@ pub struct EGLImageNativeSurface {
     /// A heap-allocated bitmap for the case of CPU rendering.
     bitmap: Option<Vec<u8>>,
 
-    /// Whether this pixmap will leak if the destructor runs. This is for debugging purposes.
-    will_leak: bool,
+    /// Where the surface is in its life, and whether this side owns it.
+    lifetime: SurfaceLifetime,
 
     /// The size of this surface.
     pub size: Size2D<i32>,
@@ -76,7 +201,7 @@ impl EGLImageNativeSurface {
         EGLImageNativeSurface {
             image: None,
             bitmap: Some(bitmap),
-            will_leak: true,
+            lifetime: SurfaceLifetime::new(),
             size: size,
         }
     }
@@ -101,7 +226,8 @@ impl EGLImageNativeSurface {
                     }
                 }
                 None => {
-                    debug!("Cannot bind the buffer(CPU rendering), there is no bitmap");
+                    warn!(target: "layers::surface",
+                          "Cannot bind the buffer (CPU rendering), there is no bitmap");
                 }
             },
             Some(image_khr) => {
@@ -118,7 +244,8 @@ impl EGLImageNativeSurface {
                 bitmap.extend_from_slice(data);
             }
             None => {
-                debug!("Cannot upload the buffer(CPU rendering), there is no bitmap");
+                warn!(target: "layers::surface",
+                      "Cannot upload the buffer (CPU rendering), there is no bitmap");
             }
         }
     }
@@ -130,6 +257,10 @@ impl EGLImageNativeSurface {
         }
     }
 
+    pub fn cpu_pixels(&self) -> Option<&[u8]> {
+        self.bitmap.as_ref().map(|bitmap| &bitmap[..])
+    }
+
     pub fn destroy(&mut self, graphics_context: &NativeDisplay) {
         match self.image {
             None => {},
@@ -138,17 +269,26 @@ impl EGLImageNativeSurface {
                 mem::replace(&mut self.image, None);
             }
         }
-        self.mark_wont_leak()
+        self.lifetime.mark_destroyed()
+    }
+
+    pub fn lifetime(&self) -> SurfaceLifetime {
+        self.lifetime
+    }
+
+    pub fn acknowledge(&mut self, event: SurfaceEvent) {
+        self.lifetime.acknowledge(event)
     }
 
     pub fn mark_will_leak(&mut self) {
-        self.will_leak = true
+        self.lifetime.set_owned(true)
     }
 
     pub fn mark_wont_leak(&mut self) {
-        self.will_leak = false
+        self.lifetime.set_owned(false)
     }
 
+    #[cfg(feature = "skia")]
     pub fn gl_rasterization_context(&mut self,
                                     gl_context: Arc<GLContext>)
                                     -> Option<GLRasterizationContext> {
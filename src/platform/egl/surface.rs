@@ -9,15 +9,25 @@
 
 //! Implementation of cross-process surfaces implementing  EGL surface.
 
+use platform::lifetime::{SurfaceEvent, SurfaceLifetime};
+use platform::surface::DmaBufLayout;
+use platform::transport::SurfaceHandle;
 use texturegl::Texture;
 
-use egl::eglext::EGLImageKHR;
+use egl::egl::{EGLDisplay, EGLint, QueryString};
+use egl::eglext::{CreateImageKHR, DestroyImageKHR, EGLImageKHR};
 use euclid::size::Size2D;
-use gleam::gl::{TEXTURE_2D, TexImage2D, UNSIGNED_BYTE};
+use gleam::gl::{GLenum, TEXTURE_2D, TexImage2D, UNSIGNED_BYTE};
+#[cfg(feature = "skia")]
 use skia::gl_context::GLContext;
+#[cfg(feature = "skia")]
 use skia::gl_rasterization_context::GLRasterizationContext;
+use std::ffi::CStr;
 use std::iter::repeat;
-use std::os::raw::c_void;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+#[cfg(feature = "skia")]
 use std::sync::Arc;
 use std::vec::Vec;
 
@@ -35,6 +45,86 @@ pub use platform::linux::surface::NativeDisplay;
 #[cfg(target_os="android")]
 pub use platform::android::surface::NativeDisplay;
 
+/// The `eglQueryString` name of the extensions of a display.
+const EGL_EXTENSIONS: EGLint = 0x3055;
+
+const EGL_HEIGHT: EGLint = 0x3056;
+const EGL_WIDTH: EGLint = 0x3057;
+const EGL_NONE: EGLint = 0x3038;
+const EGL_LINUX_DMA_BUF_EXT: u32 = 0x3270;
+const EGL_LINUX_DRM_FOURCC_EXT: EGLint = 0x3271;
+const EGL_DMA_BUF_PLANE0_FD_EXT: EGLint = 0x3272;
+const EGL_DMA_BUF_PLANE0_OFFSET_EXT: EGLint = 0x3273;
+const EGL_DMA_BUF_PLANE0_PITCH_EXT: EGLint = 0x3274;
+const EGL_DMA_BUF_PLANE0_MODIFIER_LO_EXT: EGLint = 0x3443;
+const EGL_DMA_BUF_PLANE0_MODIFIER_HI_EXT: EGLint = 0x3444;
+
+/// The DRM format modifier of buffers whose layout is implied by the driver.
+pub const DRM_FORMAT_MOD_INVALID: u64 = 0x00ffffffffffffff;
+
+/// `eglExportDMABUFImageQueryMESA`.
+type ExportDmaBufImageQuery = unsafe extern "C" fn(EGLDisplay,
+                                                   EGLImageKHR,
+                                                   *mut c_int,
+                                                   *mut c_int,
+                                                   *mut u64)
+                                                   -> u32;
+/// `eglExportDMABUFImageMESA`.
+type ExportDmaBufImage = unsafe extern "C" fn(EGLDisplay,
+                                              EGLImageKHR,
+                                              *mut c_int,
+                                              *mut EGLint,
+                                              *mut EGLint)
+                                              -> u32;
+/// `glEGLImageTargetTexture2DOES`.
+type EGLImageTargetTexture2D = unsafe extern "C" fn(GLenum, *const c_void);
+
+extern {
+    fn eglGetProcAddress(name: *const c_char) -> *const c_void;
+}
+
+/// Looks up an extension function by its nul-terminated name.
+fn proc_address(name: &[u8]) -> Option<*const c_void> {
+    let address = unsafe { eglGetProcAddress(name.as_ptr() as *const c_char) };
+    if address.is_null() {
+        None
+    } else {
+        Some(address)
+    }
+}
+
+/// The EGL display of `display`, unless it is a GLX display.
+#[cfg(target_os = "linux")]
+fn egl_display(display: &NativeDisplay) -> Option<EGLDisplay> {
+    match display {
+        &NativeDisplay::EGL(ref info) => Some(info.display),
+        &NativeDisplay::GLX(_) => None,
+    }
+}
+
+/// The EGL display of `display`.
+#[cfg(target_os = "android")]
+fn egl_display(display: &NativeDisplay) -> Option<EGLDisplay> {
+    Some(display.display)
+}
+
+/// Whether `display` has any of the EGL extensions named.
+fn supports_extension(display: EGLDisplay, names: &[&str]) -> bool {
+    let extensions = QueryString(display, EGL_EXTENSIONS);
+    if extensions.is_null() {
+        return false;
+    }
+    let extensions = unsafe {
+        CStr::from_ptr(extensions).to_string_lossy()
+    };
+    extensions.split_whitespace().any(|extension| names.contains(&extension))
+}
+
+/// Whether `display` has EGL images, with `EGL_KHR_image` or `EGL_KHR_image_base`.
+pub fn supports_egl_images(display: EGLDisplay) -> bool {
+    supports_extension(display, &["EGL_KHR_image", "EGL_KHR_image_base"])
+}
+
 pub struct EGLImageNativeSurface {
     /// An EGLImage for the case of GPU rendering.
     image: Option<EGLImageKHR>,
@@ -42,8 +132,8 @@ pub struct EGLImageNativeSurface {
     /// A heap-allocated bitmap for the case of CPU rendering.
     bitmap: Option<Vec<u8>>,
 
-    /// Whether this pixmap will leak if the destructor runs. This is for debugging purposes.
-    will_leak: bool,
+    /// Where the surface is in its life, and whether this side owns it.
+    lifetime: SurfaceLifetime,
 
     /// The size of this surface.
     pub size: Size2D<i32>,
@@ -59,9 +149,141 @@ impl EGLImageNativeSurface {
         EGLImageNativeSurface {
             image: None,
             bitmap: Some(bitmap),
-            will_leak: true,
+            lifetime: SurfaceLifetime::new(),
+            size: size,
+        }
+    }
+
+    /// Adopts an EGL image of the given size that the embedder rendered into. The surface
+    /// destroys the image along with itself.
+    ///
+    /// # Safety
+    ///
+    /// `image` must be a live EGL image of the display the surface is used with, and must not be
+    /// destroyed by anything else.
+    pub unsafe fn from_egl_image(image: EGLImageKHR, size: Size2D<i32>) -> EGLImageNativeSurface {
+        EGLImageNativeSurface {
+            image: Some(image),
+            bitmap: None,
+            lifetime: SurfaceLifetime::new(),
+            size: size,
+        }
+    }
+
+    /// Adopts a dma-buf exported by another process with `export_dma_buf`, importing it as an EGL
+    /// image with `EGL_EXT_image_dma_buf_import`. EGL doesn't take ownership of the file
+    /// descriptor, which the caller closes once this returns. Returns `None` if the display or
+    /// the driver can't import the buffer.
+    pub fn from_dma_buf(display: &NativeDisplay,
+                        layout: DmaBufLayout,
+                        fd: &SurfaceHandle,
+                        size: Size2D<i32>)
+                        -> Option<EGLImageNativeSurface> {
+        let egl_display = match egl_display(display) {
+            Some(egl_display) => egl_display,
+            None => return None,
+        };
+        if !supports_extension(egl_display, &["EGL_EXT_image_dma_buf_import"]) {
+            warn!(target: "layers::surface", "The display can't import dma-bufs");
+            return None;
+        }
+
+        let mut attributes = vec!(EGL_WIDTH, size.width,
+                                  EGL_HEIGHT, size.height,
+                                  EGL_LINUX_DRM_FOURCC_EXT, layout.fourcc as EGLint,
+                                  EGL_DMA_BUF_PLANE0_FD_EXT, fd.as_raw(),
+                                  EGL_DMA_BUF_PLANE0_OFFSET_EXT, layout.offset,
+                                  EGL_DMA_BUF_PLANE0_PITCH_EXT, layout.stride);
+        if layout.modifier != DRM_FORMAT_MOD_INVALID {
+            if !supports_extension(egl_display, &["EGL_EXT_image_dma_buf_import_modifiers"]) {
+                warn!(target: "layers::surface",
+                      "The display can't import dma-bufs with format modifiers");
+                return None;
+            }
+            attributes.extend_from_slice(&[
+                EGL_DMA_BUF_PLANE0_MODIFIER_LO_EXT, layout.modifier as u32 as EGLint,
+                EGL_DMA_BUF_PLANE0_MODIFIER_HI_EXT, (layout.modifier >> 32) as u32 as EGLint,
+            ]);
+        }
+        attributes.push(EGL_NONE);
+
+        let image = CreateImageKHR(egl_display,
+                                   ptr::null_mut(),
+                                   EGL_LINUX_DMA_BUF_EXT,
+                                   ptr::null_mut(),
+                                   attributes.as_ptr());
+        if image.is_null() {
+            warn!(target: "layers::surface", "Could not import a dma-buf of {:?}", size);
+            return None;
+        }
+        Some(EGLImageNativeSurface {
+            image: Some(image),
+            bitmap: None,
+            lifetime: SurfaceLifetime::new(),
             size: size,
+        })
+    }
+
+    /// Whether the surface is an EGL image, rather than a bitmap painted on the CPU.
+    pub fn has_image(&self) -> bool {
+        self.image.is_some()
+    }
+
+    /// Exports the EGL image of the surface as a single-plane dma-buf, with
+    /// `EGL_MESA_image_dma_buf_export`, for another process to adopt with `from_dma_buf`. This
+    /// process's EGL image is destroyed, since the file descriptor keeps the buffer alive.
+    /// Returns `None`, leaving the surface untouched, if it has no image or if the display or the
+    /// driver can't export it.
+    pub fn export_dma_buf(&mut self, display: &NativeDisplay)
+                          -> Option<(DmaBufLayout, SurfaceHandle)> {
+        let (egl_display, image) = match (egl_display(display), self.image) {
+            (Some(egl_display), Some(image)) => (egl_display, image),
+            _ => return None,
+        };
+        if !supports_extension(egl_display, &["EGL_MESA_image_dma_buf_export"]) {
+            return None;
         }
+        let (query, export) = match (proc_address(b"eglExportDMABUFImageQueryMESA\0"),
+                                     proc_address(b"eglExportDMABUFImageMESA\0")) {
+            (Some(query), Some(export)) => unsafe {
+                let query: ExportDmaBufImageQuery = mem::transmute(query);
+                let export: ExportDmaBufImage = mem::transmute(export);
+                (query, export)
+            },
+            _ => return None,
+        };
+
+        let mut layout = DmaBufLayout {
+            fourcc: 0,
+            stride: 0,
+            offset: 0,
+            modifier: DRM_FORMAT_MOD_INVALID,
+        };
+        let fd = unsafe {
+            // The modifiers are written for every plane, so they are only asked for once the
+            // image is known to have a single one.
+            let (mut fourcc, mut planes) = (0, 0);
+            if query(egl_display, image, &mut fourcc, &mut planes, ptr::null_mut()) == 0 ||
+                    planes != 1 ||
+                    query(egl_display, image, &mut fourcc, &mut planes, &mut layout.modifier) == 0 {
+                warn!(target: "layers::surface",
+                      "Could not export an EGL image with {} planes as a dma-buf", planes);
+                return None;
+            }
+            layout.fourcc = fourcc as u32;
+
+            let mut fd = -1;
+            if export(egl_display, image, &mut fd, &mut layout.stride, &mut layout.offset) == 0 ||
+                    fd < 0 {
+                warn!(target: "layers::surface", "Could not export an EGL image as a dma-buf");
+                return None;
+            }
+            SurfaceHandle::from_raw(fd)
+        };
+
+        DestroyImageKHR(egl_display, image);
+        self.image = None;
+        Some((layout, fd))
     }
 
     /// This may only be called on the compositor side.
@@ -84,11 +306,21 @@ impl EGLImageNativeSurface {
                      }
                 }
                 None => {
-                    debug!("Cannot bind the buffer(CPU rendering), there is no bitmap");
+                    warn!(target: "layers::surface",
+                          "Cannot bind the buffer (CPU rendering), there is no bitmap");
                 }
             },
-            Some(_image_khr) => {
-                panic!("TODO: Support GPU rasterizer path on EGL");
+            Some(image_khr) => {
+                match proc_address(b"glEGLImageTargetTexture2DOES\0") {
+                    Some(function) => unsafe {
+                        let target_texture: EGLImageTargetTexture2D = mem::transmute(function);
+                        target_texture(TEXTURE_2D, image_khr as *const c_void);
+                    },
+                    None => {
+                        warn!(target: "layers::surface",
+                              "Cannot bind an EGL image without GL_OES_EGL_image");
+                    }
+                }
             }
         }
     }
@@ -101,7 +333,8 @@ impl EGLImageNativeSurface {
                 bitmap.extend_from_slice(data);
             }
             None => {
-                debug!("Cannot upload the buffer(CPU rendering), there is no bitmap");
+                warn!(target: "layers::surface",
+                      "Cannot upload the buffer (CPU rendering), there is no bitmap");
             }
         }
     }
@@ -113,21 +346,42 @@ impl EGLImageNativeSurface {
         }
     }
 
-    pub fn destroy(&mut self, _graphics_context: &NativeDisplay) {
-        if self.image.is_some() {
-            panic!("TODO: Support GPU rendering path on Android");
+    pub fn cpu_pixels(&self) -> Option<&[u8]> {
+        self.bitmap.as_ref().map(|bitmap| &bitmap[..])
+    }
+
+    pub fn destroy(&mut self, display: &NativeDisplay) {
+        if let Some(image) = self.image.take() {
+            match egl_display(display) {
+                Some(egl_display) => {
+                    DestroyImageKHR(egl_display, image);
+                }
+                None => {
+                    warn!(target: "layers::surface",
+                          "Cannot destroy an EGL image without an EGL display");
+                }
+            }
         }
-        self.mark_wont_leak()
+        self.lifetime.mark_destroyed()
+    }
+
+    pub fn lifetime(&self) -> SurfaceLifetime {
+        self.lifetime
+    }
+
+    pub fn acknowledge(&mut self, event: SurfaceEvent) {
+        self.lifetime.acknowledge(event)
     }
 
     pub fn mark_will_leak(&mut self) {
-        self.will_leak = true
+        self.lifetime.set_owned(true)
     }
 
     pub fn mark_wont_leak(&mut self) {
-        self.will_leak = false
+        self.lifetime.set_owned(false)
     }
 
+    #[cfg(feature = "skia")]
     pub fn gl_rasterization_context(&mut self,
                                     _gl_context: Arc<GLContext>)
                                     -> Option<GLRasterizationContext> {
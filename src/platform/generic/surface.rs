@@ -0,0 +1,42 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use platform::surface::NativeSurfaceKind;
+#[cfg(feature = "skia")]
+use skia::gl_context::PlatformDisplayData;
+
+/// A display for platforms without native surfaces, where buffers are always shared memory
+/// surfaces.
+#[derive(Copy, Clone)]
+pub struct NativeDisplay {
+    surface_kind: NativeSurfaceKind,
+}
+
+impl NativeDisplay {
+    pub fn new() -> NativeDisplay {
+        NativeDisplay {
+            surface_kind: NativeSurfaceKind::MemoryBuffer,
+        }
+    }
+
+    /// The kind of surface created for this display. See `NativeSurfaceKind::active`.
+    pub fn surface_kind(&self) -> NativeSurfaceKind {
+        self.surface_kind
+    }
+
+    pub fn set_surface_kind(&mut self, surface_kind: NativeSurfaceKind) {
+        self.surface_kind = surface_kind;
+    }
+
+    #[cfg(feature = "skia")]
+    pub fn platform_display_data(&self) -> PlatformDisplayData {
+        PlatformDisplayData::new()
+    }
+}
+
@@ -0,0 +1,151 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The life of a native surface shared between the painting side, which produces its content,
+//! and the compositor, which displays it:
+//!
+//! `Created → Painted → InComposite → Returned → Painted → … → Destroyed`
+//!
+//! Each side acknowledges every step with a `SurfaceEvent`. A side owns a surface from the
+//! moment it creates or receives it until it sends it away or destroys it, and dropping an owned
+//! surface leaks it. Events that don't make sense in the current state are programming errors
+//! and panic, so that misuse shows up where it happens rather than as a leak later.
+
+use std::fmt;
+
+/// Where a surface is in its life.
+#[derive(Copy, Clone, Debug, PartialEq, RustcDecodable, RustcEncodable)]
+pub enum SurfaceState {
+    /// The surface was created and hasn't been painted yet.
+    Created,
+    /// The painting side painted the surface.
+    Painted,
+    /// The surface was sent to the compositor, which may be displaying it.
+    InComposite,
+    /// The compositor sent the surface back to be painted again or destroyed.
+    Returned,
+    /// The surface was destroyed and must not be used anymore.
+    Destroyed,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, SurfaceState);
+
+/// An acknowledgment of a step in the life of a surface.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SurfaceEvent {
+    /// The painting side finished painting the surface.
+    Painted,
+    /// The painting side sent the surface to the compositor.
+    Sent,
+    /// The compositor received the surface.
+    Received,
+    /// The compositor sent the surface back to the painting side.
+    Returned,
+    /// The painting side received the surface back.
+    Reclaimed,
+    /// The owner of the surface destroyed it.
+    Destroyed,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, SurfaceEvent);
+
+/// The state of a surface as seen by one side, along with whether that side owns it.
+#[derive(Copy, Clone, PartialEq, RustcDecodable, RustcEncodable)]
+pub struct SurfaceLifetime {
+    state: SurfaceState,
+    owned: bool,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, SurfaceLifetime);
+
+impl fmt::Debug for SurfaceLifetime {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter,
+               "{:?} ({})",
+               self.state,
+               if self.owned { "owned" } else { "not owned" })
+    }
+}
+
+impl SurfaceLifetime {
+    /// The lifetime of a newly created surface, owned by its creator.
+    pub fn new() -> SurfaceLifetime {
+        SurfaceLifetime {
+            state: SurfaceState::Created,
+            owned: true,
+        }
+    }
+
+    pub fn state(&self) -> SurfaceState {
+        self.state
+    }
+
+    /// Whether this side is responsible for sending the surface on or destroying it.
+    pub fn is_owned(&self) -> bool {
+        self.owned
+    }
+
+    /// Whether dropping the surface now would leak the underlying native resource.
+    pub fn leaks_if_dropped(&self) -> bool {
+        self.owned && self.state != SurfaceState::Destroyed
+    }
+
+    /// Returns the lifetime after `event`, or `None` if the event isn't valid now.
+    pub fn after(&self, event: SurfaceEvent) -> Option<SurfaceLifetime> {
+        let (state, owned) = match (self.state, event, self.owned) {
+            (SurfaceState::Created, SurfaceEvent::Painted, true) |
+            (SurfaceState::Painted, SurfaceEvent::Painted, true) |
+            (SurfaceState::Returned, SurfaceEvent::Painted, true) => (SurfaceState::Painted, true),
+            (SurfaceState::Painted, SurfaceEvent::Sent, true) => (SurfaceState::InComposite, false),
+            (SurfaceState::InComposite, SurfaceEvent::Received, false) => {
+                (SurfaceState::InComposite, true)
+            }
+            (SurfaceState::InComposite, SurfaceEvent::Returned, true) => {
+                (SurfaceState::Returned, false)
+            }
+            (SurfaceState::Returned, SurfaceEvent::Reclaimed, false) => {
+                (SurfaceState::Returned, true)
+            }
+            (SurfaceState::Destroyed, _, _) => return None,
+            (_, SurfaceEvent::Destroyed, true) => (SurfaceState::Destroyed, false),
+            _ => return None,
+        };
+        Some(SurfaceLifetime {
+            state: state,
+            owned: owned,
+        })
+    }
+
+    /// Moves on to the next step of the life of the surface. Panics if the event isn't valid in
+    /// the current state.
+    pub fn acknowledge(&mut self, event: SurfaceEvent) {
+        match self.after(event) {
+            Some(lifetime) => *self = lifetime,
+            None => panic!("Invalid surface event {:?} for a surface that is {:?}", event, self),
+        }
+    }
+
+    /// Sets whether this side owns the surface without checking the state, for embedders that
+    /// still use `NativeSurface::mark_will_leak` and `mark_wont_leak` instead of events.
+    pub fn set_owned(&mut self, owned: bool) {
+        self.owned = owned;
+    }
+
+    /// Records that the surface was destroyed, by whichever side. Panics if it already was.
+    pub fn mark_destroyed(&mut self) {
+        if self.state == SurfaceState::Destroyed {
+            panic!("The surface was destroyed twice");
+        }
+        self.state = SurfaceState::Destroyed;
+        self.owned = false;
+    }
+}
@@ -15,18 +15,23 @@
 #[link(name = "EGL")]
 extern {}
 
+use platform::lifetime::{SurfaceEvent, SurfaceLifetime};
+use platform::surface::NativeSurfaceKind;
 use texturegl::Texture;
 
 use euclid::size::Size2D;
 use libc::{c_int, c_uint, c_void};
 use glx;
+#[cfg(feature = "skia")]
 use skia::gl_context::{GLContext, PlatformDisplayData};
+#[cfg(feature = "skia")]
 use skia::gl_rasterization_context::GLRasterizationContext;
 use std::ascii::AsciiExt;
 use std::ffi::CStr;
 use std::mem;
 use std::ptr;
 use std::str;
+#[cfg(feature = "skia")]
 use std::sync::Arc;
 use x11::xlib;
 
@@ -46,10 +51,37 @@ pub struct GLXDisplayInfo {
     pub display: *mut xlib::Display,
     visual_info: *mut xlib::XVisualInfo,
     framebuffer_configuration: Option<glx::types::GLXFBConfig>,
+
+    /// The kind of surface created for this display, selected when it is created.
+    surface_kind: NativeSurfaceKind,
+}
+
+impl GLXDisplayInfo {
+    /// Whether pixmaps can be bound to textures with `GLX_EXT_texture_from_pixmap`. Headless
+    /// displays, without an X server, can't.
+    pub fn supports_texture_from_pixmap(&self) -> bool {
+        if self.display == ptr::null_mut() || self.framebuffer_configuration.is_none() {
+            return false;
+        }
+        unsafe {
+            let screen = xlib::XDefaultScreen(self.display);
+            let extensions = glx::QueryExtensionsString(mem::transmute(self.display), screen);
+            if extensions == ptr::null() {
+                return false;
+            }
+            CStr::from_ptr(extensions).to_string_lossy().split_whitespace().any(|extension| {
+                extension == "GLX_EXT_texture_from_pixmap"
+            })
+        }
+    }
 }
+
 #[derive(Copy, Clone)]
 pub struct EGLDisplayInfo {
     pub display: EGLDisplay,
+
+    /// The kind of surface created for this display, selected when it is created.
+    surface_kind: NativeSurfaceKind,
 }
 
 #[derive(Copy, Clone)]
@@ -69,11 +101,30 @@ impl NativeDisplay {
         let (compositor_visual_info, frambuffer_configuration) =
             NativeDisplay::compositor_visual_info(display);
 
-        NativeDisplay::GLX(GLXDisplayInfo {
+        let mut display = NativeDisplay::GLX(GLXDisplayInfo {
             display: display,
             visual_info: compositor_visual_info,
             framebuffer_configuration: frambuffer_configuration,
-        })
+            surface_kind: NativeSurfaceKind::MemoryBuffer,
+        });
+        let surface_kind = NativeSurfaceKind::select(&display);
+        display.set_surface_kind(surface_kind);
+        display
+    }
+
+    /// The kind of surface created for this display. See `NativeSurfaceKind::active`.
+    pub fn surface_kind(&self) -> NativeSurfaceKind {
+        match *self {
+            NativeDisplay::EGL(ref info) => info.surface_kind,
+            NativeDisplay::GLX(ref info) => info.surface_kind,
+        }
+    }
+
+    pub fn set_surface_kind(&mut self, surface_kind: NativeSurfaceKind) {
+        match *self {
+            NativeDisplay::EGL(ref mut info) => info.surface_kind = surface_kind,
+            NativeDisplay::GLX(ref mut info) => info.surface_kind = surface_kind,
+        }
     }
 
     /// Chooses the compositor visual info using the same algorithm that the compositor uses.
@@ -159,6 +210,7 @@ impl NativeDisplay {
         }
     }
 
+    #[cfg(feature = "skia")]
     pub fn platform_display_data(&self) -> PlatformDisplayData {
         match *self {
             NativeDisplay::GLX(info) => {
@@ -172,19 +224,27 @@ impl NativeDisplay {
     }
 
     pub fn new_egl_display() -> NativeDisplay {
-        NativeDisplay::EGL(EGLDisplayInfo {
-            display: GetCurrentDisplay()
-        })
+        let mut display = NativeDisplay::EGL(EGLDisplayInfo {
+            display: GetCurrentDisplay(),
+            surface_kind: NativeSurfaceKind::MemoryBuffer,
+        });
+        let surface_kind = NativeSurfaceKind::select(&display);
+        display.set_surface_kind(surface_kind);
+        display
     }
 }
 
+/// The largest width and height of a pixmap. Coordinates in the X protocol are signed 16-bit
+/// integers, so the server can't draw into larger pixmaps even when it creates them.
+pub const MAX_PIXMAP_SIZE: i32 = 32767;
+
 #[derive(RustcDecodable, RustcEncodable)]
 pub struct PixmapNativeSurface {
     /// The pixmap.
     pixmap: xlib::Pixmap,
 
-    /// Whether this pixmap will leak if the destructor runs. This is for debugging purposes.
-    will_leak: bool,
+    /// Where the surface is in its life, and whether this side owns it.
+    lifetime: SurfaceLifetime,
 
     /// The size of this surface.
     pub size: Size2D<i32>,
@@ -192,7 +252,7 @@ pub struct PixmapNativeSurface {
 
 impl Drop for PixmapNativeSurface {
     fn drop(&mut self) {
-        if self.will_leak {
+        if self.lifetime.leaks_if_dropped() {
             panic!("You should have disposed of the pixmap properly with destroy()! This pixmap \
                    will leak!");
         }
@@ -215,12 +275,22 @@ impl PixmapNativeSurface {
                                              32);
             PixmapNativeSurface {
                 pixmap: pixmap,
-                will_leak: true,
+                lifetime: SurfaceLifetime::new(),
                 size: size,
             }
         }
     }
 
+    /// Adopts a pixmap created by another client of the same X server, e.g. a painting
+    /// process.
+    pub fn from_pixmap(pixmap: xlib::Pixmap, size: Size2D<i32>) -> PixmapNativeSurface {
+        PixmapNativeSurface {
+            pixmap: pixmap,
+            lifetime: SurfaceLifetime::new(),
+            size: size,
+        }
+    }
+
     /// This may only be called on the compositor side.
     pub fn bind_to_texture(&self, display: &NativeDisplay, texture: &Texture) {
         // Create the GLX pixmap.
@@ -245,6 +315,11 @@ impl PixmapNativeSurface {
                                                    "GLX 1.3 should have a framebuffer_configuration"),
                                                self.pixmap,
                                                pixmap_attributes.as_ptr());
+            if glx_pixmap == 0 {
+                warn!(target: "layers::surface",
+                      "Could not create a GLX pixmap for surface {}", self.pixmap);
+                return;
+            }
 
             let glx_bind_tex_image: extern "C" fn(*mut xlib::Display, glx::types::GLXDrawable, c_int, *mut c_int) =
                 mem::transmute(glx::GetProcAddress(mem::transmute(&"glXBindTexImageEXT\x00".as_bytes()[0])));
@@ -297,6 +372,10 @@ impl PixmapNativeSurface {
         self.pixmap as isize
     }
 
+    pub fn cpu_pixels(&self) -> Option<&[u8]> {
+        None
+    }
+
     pub fn destroy(&mut self, display: &NativeDisplay) {
         unsafe {
             let display = match display {
@@ -306,18 +385,27 @@ impl PixmapNativeSurface {
 
             assert!(self.pixmap != 0);
             xlib::XFreePixmap(display.display, self.pixmap);
-            self.mark_wont_leak()
+            self.lifetime.mark_destroyed()
         }
     }
 
+    pub fn lifetime(&self) -> SurfaceLifetime {
+        self.lifetime
+    }
+
+    pub fn acknowledge(&mut self, event: SurfaceEvent) {
+        self.lifetime.acknowledge(event)
+    }
+
     pub fn mark_will_leak(&mut self) {
-        self.will_leak = true;
+        self.lifetime.set_owned(true)
     }
 
     pub fn mark_wont_leak(&mut self) {
-        self.will_leak = false;
+        self.lifetime.set_owned(false)
     }
 
+    #[cfg(feature = "skia")]
     pub fn gl_rasterization_context(&mut self,
                                     gl_context: Arc<GLContext>)
                                     -> Option<GLRasterizationContext> {
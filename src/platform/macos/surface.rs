@@ -10,6 +10,9 @@
 //! Mac OS-specific implementation of cross-process surfaces. This uses `IOSurface`, introduced
 //! in Mac OS X 10.6 Snow Leopard.
 
+use platform::lifetime::{SurfaceEvent, SurfaceLifetime};
+use platform::surface::NativeSurfaceKind;
+use platform::transport::{SurfaceHandle, mach_port_t};
 use texturegl::Texture;
 
 use cgl;
@@ -21,25 +24,44 @@ use core_foundation::string::CFString;
 use euclid::size::Size2D;
 use io_surface;
 use rustc_serialize::{Decoder, Decodable, Encoder, Encodable};
+#[cfg(feature = "skia")]
 use skia::gl_context::{GLContext, PlatformDisplayData};
+#[cfg(feature = "skia")]
 use skia::gl_rasterization_context::GLRasterizationContext;
+#[cfg(feature = "skia")]
 use std::sync::Arc;
 
 #[derive(Clone, Copy)]
 pub struct NativeDisplay {
     pub pixel_format: cgl::CGLPixelFormatObj,
+
+    /// The kind of surface created for this display, selected when it is created.
+    surface_kind: NativeSurfaceKind,
 }
 unsafe impl Send for NativeDisplay {}
 
 impl NativeDisplay {
     pub fn new() -> NativeDisplay {
-        unsafe {
+        let mut display = unsafe {
             NativeDisplay {
                 pixel_format: cgl::CGLGetPixelFormat(cgl::CGLGetCurrentContext()),
+                surface_kind: NativeSurfaceKind::MemoryBuffer,
             }
-        }
+        };
+        display.surface_kind = NativeSurfaceKind::select(&display);
+        display
+    }
+
+    /// The kind of surface created for this display. See `NativeSurfaceKind::active`.
+    pub fn surface_kind(&self) -> NativeSurfaceKind {
+        self.surface_kind
+    }
+
+    pub fn set_surface_kind(&mut self, surface_kind: NativeSurfaceKind) {
+        self.surface_kind = surface_kind;
     }
 
+    #[cfg(feature = "skia")]
     pub fn platform_display_data(&self) -> PlatformDisplayData {
         PlatformDisplayData {
             pixel_format: self.pixel_format,
@@ -47,9 +69,21 @@ impl NativeDisplay {
     }
 }
 
+/// The largest width and height of an IOSurface. IOSurfaces are bound to rectangle textures,
+/// which no supported Mac GPU allows to be larger than this.
+pub const MAX_IO_SURFACE_SIZE: i32 = 16384;
+
+#[link(name = "IOSurface", kind = "framework")]
+extern {
+    fn IOSurfaceCreateMachPort(surface: io_surface::IOSurfaceRef) -> mach_port_t;
+    fn IOSurfaceLookupFromMachPort(port: mach_port_t) -> io_surface::IOSurfaceRef;
+}
+
 pub struct IOSurfaceNativeSurface {
     surface: Option<io_surface::IOSurface>,
-    will_leak: bool,
+
+    /// Where the surface is in its life, and whether this side owns it.
+    lifetime: SurfaceLifetime,
     pub size: Size2D<i32>,
 }
 
@@ -61,7 +95,7 @@ impl Decodable for IOSurfaceNativeSurface {
         let id: Option<io_surface::IOSurfaceID> = try!(Decodable::decode(d));
         Ok(IOSurfaceNativeSurface {
             surface: id.map(io_surface::lookup),
-            will_leak: try!(Decodable::decode(d)),
+            lifetime: try!(Decodable::decode(d)),
             size: try!(Decodable::decode(d)),
         })
     }
@@ -69,7 +103,7 @@ impl Decodable for IOSurfaceNativeSurface {
 impl Encodable for IOSurfaceNativeSurface {
     fn encode<E: Encoder>(&self, e: &mut E) -> Result<(), E::Error> {
         try!(self.surface.as_ref().map(io_surface::IOSurface::get_id).encode(e));
-        try!(self.will_leak.encode(e));
+        try!(self.lifetime.encode(e));
         try!(self.size.encode(e));
         Ok(())
     }
@@ -106,12 +140,57 @@ impl IOSurfaceNativeSurface {
 
             IOSurfaceNativeSurface {
                 surface: Some(surface),
-                will_leak: true,
+                lifetime: SurfaceLifetime::new(),
                 size: size,
             }
         }
     }
 
+    /// Adopts an existing `IOSurfaceRef`, retaining it. This lets embedders that render into
+    /// IOSurfaces themselves hand them to the compositor without going through Skia.
+    ///
+    /// # Safety
+    ///
+    /// `surface_ref` must point to a live IOSurface.
+    pub unsafe fn from_io_surface_ref(surface_ref: io_surface::IOSurfaceRef, size: Size2D<i32>)
+                                      -> IOSurfaceNativeSurface {
+        let surface: io_surface::IOSurface = TCFType::wrap_under_get_rule(surface_ref);
+
+        IOSurfaceNativeSurface {
+            surface: Some(surface),
+            lifetime: SurfaceLifetime::new(),
+            size: size,
+        }
+    }
+
+    /// The send right to a new mach port for the surface, which another process looks it up
+    /// with. Unlike global IOSurface IDs, this works in sandboxed processes.
+    pub fn mach_port(&self) -> Option<SurfaceHandle> {
+        let surface = match self.surface {
+            Some(ref surface) => surface,
+            None => return None,
+        };
+        match unsafe { IOSurfaceCreateMachPort(surface.obj) } {
+            0 => None,
+            port => Some(unsafe { SurfaceHandle::from_raw(port) }),
+        }
+    }
+
+    /// Adopts the IOSurface that a mach port sent by another process names.
+    pub fn from_mach_port(port: &SurfaceHandle, size: Size2D<i32>)
+                          -> Option<IOSurfaceNativeSurface> {
+        let surface_ref = unsafe { IOSurfaceLookupFromMachPort(port.as_raw()) };
+        if surface_ref.is_null() {
+            warn!(target: "layers::surface", "Could not look up the IOSurface of a mach port");
+            return None;
+        }
+        Some(IOSurfaceNativeSurface {
+            surface: Some(unsafe { TCFType::wrap_under_create_rule(surface_ref) }),
+            lifetime: SurfaceLifetime::new(),
+            size: size,
+        })
+    }
+
     pub fn bind_to_texture(&self, _: &NativeDisplay, texture: &Texture) {
         let _bound_texture = texture.bind();
         let io_surface = self.surface.as_ref().unwrap();
@@ -130,19 +209,32 @@ impl IOSurfaceNativeSurface {
         }
     }
 
+    pub fn cpu_pixels(&self) -> Option<&[u8]> {
+        None
+    }
+
     pub fn destroy(&mut self, _: &NativeDisplay) {
         self.surface = None;
-        self.mark_wont_leak()
+        self.lifetime.mark_destroyed()
+    }
+
+    pub fn lifetime(&self) -> SurfaceLifetime {
+        self.lifetime
+    }
+
+    pub fn acknowledge(&mut self, event: SurfaceEvent) {
+        self.lifetime.acknowledge(event)
     }
 
     pub fn mark_will_leak(&mut self) {
-        self.will_leak = true
+        self.lifetime.set_owned(true)
     }
 
     pub fn mark_wont_leak(&mut self) {
-        self.will_leak = false
+        self.lifetime.set_owned(false)
     }
 
+    #[cfg(feature = "skia")]
     pub fn gl_rasterization_context(&mut self,
                                     gl_context: Arc<GLContext>)
                                     -> Option<GLRasterizationContext> {
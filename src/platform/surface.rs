@@ -10,86 +10,467 @@
 //! Implementation of cross-process surfaces. This delegates to the platform-specific
 //! implementation.
 
-use texturegl::Texture;
+use capabilities::GlCapabilities;
+use platform::lifetime::{SurfaceEvent, SurfaceLifetime};
+#[cfg(windows)]
+use platform::transport::{self, HANDLE};
+use platform::transport::{SurfaceHandle, SurfaceTransfer};
+use profiler;
+use texturegl::{Texture, UploadFormat};
 
 use euclid::size::Size2D;
+#[cfg(feature = "skia")]
 use skia::gl_rasterization_context::GLRasterizationContext;
+#[cfg(feature = "skia")]
 use skia::gl_context::GLContext;
+#[cfg(feature = "skia")]
 use std::sync::Arc;
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+use std::{cmp, fmt, i32};
 
-#[cfg(not(target_os="android"))]
-use gleam::gl;
-
-#[cfg(target_os="macos")]
+#[cfg(all(target_os="macos", feature = "native-surfaces"))]
 pub use platform::macos::surface::{NativeDisplay,
-                                   IOSurfaceNativeSurface};
+                                   IOSurfaceNativeSurface,
+                                   MAX_IO_SURFACE_SIZE};
 
-#[cfg(target_os="linux")]
+#[cfg(all(target_os="linux", feature = "native-surfaces"))]
 pub use platform::linux::surface::{NativeDisplay,
-                                   PixmapNativeSurface};
-#[cfg(target_os="linux")]
-use std::ptr;
+                                   PixmapNativeSurface,
+                                   MAX_PIXMAP_SIZE};
 
-#[cfg(any(target_os="android",target_os="linux"))]
+#[cfg(all(any(target_os="android",target_os="linux"), feature = "native-surfaces"))]
 pub use platform::egl::surface::{EGLImageNativeSurface};
+#[cfg(all(any(target_os="android",target_os="linux"), feature = "native-surfaces"))]
+use platform::egl::surface::supports_egl_images;
 
-#[cfg(target_os="android")]
-pub use platform::android::surface::NativeDisplay;
+#[cfg(all(target_os="android", feature = "native-surfaces"))]
+pub use platform::android::surface::{NativeDisplay, WindowSurface};
 
-#[cfg(target_os="windows")]
-pub use platform::windows::surface::NativeDisplay;
+#[cfg(not(all(any(target_os="linux", target_os="macos", target_os="android"),
+              feature = "native-surfaces")))]
+pub use platform::generic::surface::NativeDisplay;
 
 pub enum NativeSurface {
     MemoryBuffer(MemoryBufferNativeSurface),
-#[cfg(target_os="linux")]
+#[cfg(all(target_os="linux", feature = "native-surfaces"))]
     Pixmap(PixmapNativeSurface),
-#[cfg(target_os="macos")]
+#[cfg(all(target_os="macos", feature = "native-surfaces"))]
     IOSurface(IOSurfaceNativeSurface),
-#[cfg(any(target_os="android",target_os="linux"))]
+#[cfg(all(any(target_os="android",target_os="linux"), feature = "native-surfaces"))]
     EGLImage(EGLImageNativeSurface),
 }
 
-#[cfg(target_os="linux")]
-impl NativeSurface {
-    /// Creates a new native surface with uninitialized data.
-    pub fn new(display: &NativeDisplay, size: Size2D<i32>) -> NativeSurface {
+/// The kinds of native surface, one for each variant of `NativeSurface`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NativeSurfaceKind {
+    MemoryBuffer,
+    Pixmap,
+    IOSurface,
+    EGLImage,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, NativeSurfaceKind);
+
+/// The largest width and height of new surfaces, as set by `NativeSurface::set_max_size`. Zero
+/// means that only the limits of the platform apply.
+static MAX_SURFACE_SIZE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Why a native surface couldn't be created.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SurfaceError {
+    /// The requested size, given first, is larger than the surfaces of the active kind can be.
+    /// The second size is the largest part of the request that can be allocated, which a tiler
+    /// can clamp the request to, or split it into.
+    TooLarge(Size2D<i32>, Size2D<i32>),
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, SurfaceError);
+
+impl fmt::Display for SurfaceError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SurfaceError::TooLarge(requested, fitting) => {
+                write!(formatter,
+                       "A surface of {}x{} is too large, the largest that fits is {}x{}",
+                       requested.width,
+                       requested.height,
+                       fitting.width,
+                       fitting.height)
+            }
+        }
+    }
+}
+
+impl NativeSurfaceKind {
+    /// The kinds of surface that can be used with the display, from the most to the least
+    /// preferred. Shared memory buffers always come last, since they work everywhere.
+    #[cfg(all(target_os="linux", feature = "native-surfaces"))]
+    pub fn candidates(display: &NativeDisplay) -> Vec<NativeSurfaceKind> {
         match display {
-            &NativeDisplay::EGL(_info) => {
-                NativeSurface::EGLImage(EGLImageNativeSurface::new(display, size))
+            &NativeDisplay::EGL(_) => vec!(NativeSurfaceKind::EGLImage,
+                                           NativeSurfaceKind::MemoryBuffer),
+            &NativeDisplay::GLX(_) => vec!(NativeSurfaceKind::Pixmap,
+                                           NativeSurfaceKind::MemoryBuffer),
+        }
+    }
+
+    /// The kinds of surface that can be used with the display, from the most to the least
+    /// preferred. Shared memory buffers always come last, since they work everywhere.
+    #[cfg(all(target_os="macos", feature = "native-surfaces"))]
+    pub fn candidates(_: &NativeDisplay) -> Vec<NativeSurfaceKind> {
+        vec!(NativeSurfaceKind::IOSurface, NativeSurfaceKind::MemoryBuffer)
+    }
+
+    /// The kinds of surface that can be used with the display, from the most to the least
+    /// preferred. Shared memory buffers always come last, since they work everywhere.
+    #[cfg(all(target_os="android", feature = "native-surfaces"))]
+    pub fn candidates(_: &NativeDisplay) -> Vec<NativeSurfaceKind> {
+        vec!(NativeSurfaceKind::EGLImage, NativeSurfaceKind::MemoryBuffer)
+    }
+
+    /// The kinds of surface that can be used with the display, from the most to the least
+    /// preferred. Shared memory buffers always come last, since they work everywhere.
+    #[cfg(not(all(any(target_os="linux", target_os="macos", target_os="android"),
+                  feature = "native-surfaces")))]
+    pub fn candidates(_: &NativeDisplay) -> Vec<NativeSurfaceKind> {
+        vec!(NativeSurfaceKind::MemoryBuffer)
+    }
+
+    /// Whether the display and its driver support surfaces of this kind.
+    pub fn is_available(&self, display: &NativeDisplay) -> bool {
+        match *self {
+            NativeSurfaceKind::MemoryBuffer => true,
+            #[cfg(all(target_os="linux", feature = "native-surfaces"))]
+            NativeSurfaceKind::Pixmap => match display {
+                &NativeDisplay::GLX(ref info) => info.supports_texture_from_pixmap(),
+                &NativeDisplay::EGL(_) => false,
+            },
+            #[cfg(all(target_os="macos", feature = "native-surfaces"))]
+            NativeSurfaceKind::IOSurface => true,
+            #[cfg(all(target_os="linux", feature = "native-surfaces"))]
+            NativeSurfaceKind::EGLImage => match display {
+                &NativeDisplay::EGL(ref info) => supports_egl_images(info.display),
+                &NativeDisplay::GLX(_) => false,
+            },
+            #[cfg(all(target_os="android", feature = "native-surfaces"))]
+            NativeSurfaceKind::EGLImage => supports_egl_images(display.display),
+            _ => {
+                let _ = display;
+                false
             }
-            &NativeDisplay::GLX(info) => {
-                if info.display == ptr::null_mut() {
-                    NativeSurface::MemoryBuffer(MemoryBufferNativeSurface::new(display, size))
-                } else {
-                    NativeSurface::Pixmap(PixmapNativeSurface::new(&info, size))
-                }
+        }
+    }
+
+    /// The kind of surface `NativeSurface::new` creates for the display. It is selected when
+    /// the display is created, so that a single binary works across drivers with differing
+    /// capabilities, and displays of different kinds can be used side by side.
+    pub fn active(display: &NativeDisplay) -> NativeSurfaceKind {
+        display.surface_kind()
+    }
+
+    /// Overrides the kind of surface selected for the display, e.g. to work around drivers that
+    /// misreport their capabilities. `None` selects it again.
+    pub fn set_active(display: &mut NativeDisplay, kind: Option<NativeSurfaceKind>) {
+        let kind = match kind {
+            Some(kind) => kind,
+            None => NativeSurfaceKind::select(display),
+        };
+        display.set_surface_kind(kind);
+    }
+
+    /// Selects the first available kind of surface among the candidates for the display.
+    pub fn select(display: &NativeDisplay) -> NativeSurfaceKind {
+        let kind = NativeSurfaceKind::candidates(display).into_iter().find(|kind| {
+            let available = kind.is_available(display);
+            if !available {
+                info!("{:?} surfaces are not supported, falling back", kind);
             }
+            available
+        }).unwrap_or(NativeSurfaceKind::MemoryBuffer);
+        info!("Using {:?} surfaces", kind);
+        kind
+    }
+
+    /// The largest width and height of surfaces of this kind, within the limit set with
+    /// `NativeSurface::set_max_size`.
+    pub fn max_size(&self) -> Size2D<i32> {
+        let platform_max = match *self {
+            #[cfg(all(target_os="linux", feature = "native-surfaces"))]
+            NativeSurfaceKind::Pixmap => MAX_PIXMAP_SIZE,
+            #[cfg(all(target_os="macos", feature = "native-surfaces"))]
+            NativeSurfaceKind::IOSurface => MAX_IO_SURFACE_SIZE,
+            _ => i32::MAX,
+        };
+        let max = match MAX_SURFACE_SIZE.load(Ordering::SeqCst) {
+            0 => platform_max,
+            max => cmp::min(platform_max, cmp::min(max, i32::MAX as usize) as i32),
+        };
+        Size2D::new(max, max)
+    }
+
+    /// Checks a requested size against the limits of this kind of surface. Besides the largest
+    /// width and height, the pixels of a surface must be addressable with 32-bit offsets.
+    pub fn check_size(&self, size: Size2D<i32>) -> Result<(), SurfaceError> {
+        let max = self.max_size();
+        let mut fitting = Size2D::new(cmp::min(size.width, max.width),
+                                      cmp::min(size.height, max.height));
+        let max_pixels = (i32::MAX / 4) as i64;
+        if fitting.width > 0 && fitting.width as i64 * fitting.height as i64 > max_pixels {
+            fitting.height = (max_pixels / fitting.width as i64) as i32;
+        }
+        if fitting == size {
+            Ok(())
+        } else {
+            Err(SurfaceError::TooLarge(size, fitting))
+        }
+    }
+
+    /// Whether surfaces of this kind are shown by binding them to textures, rather than by
+    /// uploading their pixels.
+    pub fn is_zero_copy(&self) -> bool {
+        match *self {
+            NativeSurfaceKind::Pixmap | NativeSurfaceKind::IOSurface => true,
+            NativeSurfaceKind::MemoryBuffer | NativeSurfaceKind::EGLImage => false,
+        }
+    }
+}
+
+/// What the native surfaces of a display and the compositor's GL context support, so that
+/// embedders and the tiling code can choose an upload strategy at runtime rather than by
+/// target.
+#[derive(Copy, Clone, Debug)]
+pub struct NativeSurfaceCapabilities {
+    /// The kind of surface created for buffers, as selected by `NativeSurfaceKind::active`.
+    pub surface_kind: NativeSurfaceKind,
+
+    /// Whether buffers are shown without copying their pixels.
+    pub zero_copy: bool,
+
+    /// The largest surface the compositor can show as a single texture, in pixels.
+    pub max_surface_size: Size2D<i32>,
+
+    /// How pixels held in CPU memory are uploaded to textures.
+    pub upload_format: UploadFormat,
+
+    /// Whether the GL context supports fence sync objects, which background uploads need.
+    pub fence_sync: bool,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, NativeSurfaceCapabilities);
+
+impl NativeSurfaceCapabilities {
+    /// Queries the capabilities of `display`, with the compositor's GL context current. Those
+    /// of the GL context come from `GlCapabilities::current`.
+    pub fn query(display: &NativeDisplay) -> NativeSurfaceCapabilities {
+        let gl_capabilities = GlCapabilities::current();
+        let surface_kind = NativeSurfaceKind::active(display);
+        let max_texture_size = gl_capabilities.max_texture_size;
+        let max_kind_size = surface_kind.max_size();
+        NativeSurfaceCapabilities {
+            surface_kind: surface_kind,
+            zero_copy: surface_kind.is_zero_copy(),
+            max_surface_size: Size2D::new(cmp::min(max_texture_size, max_kind_size.width),
+                                          cmp::min(max_texture_size, max_kind_size.height)),
+            upload_format: UploadFormat::current(),
+            fence_sync: gl_capabilities.sync_objects,
         }
     }
 }
 
-#[cfg(target_os="macos")]
 impl NativeSurface {
-    /// Creates a new native surface with uninitialized data.
-    pub fn new(display: &NativeDisplay, size: Size2D<i32>) -> NativeSurface {
-        NativeSurface::IOSurface(IOSurfaceNativeSurface::new(display, size))
-   }
+    /// Creates a new native surface with uninitialized data, of the active kind for the display.
+    /// Nothing is allocated for empty or negative sizes, which most platforms reject: the
+    /// surface is an empty memory buffer. Sizes beyond the limits of the active kind are
+    /// rejected before reaching the platform.
+    pub fn new(display: &NativeDisplay, size: Size2D<i32>) -> Result<NativeSurface, SurfaceError> {
+        if size.width <= 0 || size.height <= 0 {
+            debug!(target: "layers::surface", "Not allocating an empty surface of {:?}", size);
+            return Ok(NativeSurface::MemoryBuffer(MemoryBufferNativeSurface::new(display,
+                                                                                Size2D::zero())));
+        }
+
+        let kind = NativeSurfaceKind::active(display);
+        try!(kind.check_size(size));
+        match kind {
+            #[cfg(all(target_os="linux", feature = "native-surfaces"))]
+            NativeSurfaceKind::Pixmap => {
+                if let &NativeDisplay::GLX(ref info) = display {
+                    return Ok(NativeSurface::Pixmap(PixmapNativeSurface::new(info, size)));
+                }
+            }
+            #[cfg(all(target_os="macos", feature = "native-surfaces"))]
+            NativeSurfaceKind::IOSurface => {
+                return Ok(NativeSurface::IOSurface(IOSurfaceNativeSurface::new(display, size)));
+            }
+            #[cfg(all(any(target_os="android",target_os="linux"), feature = "native-surfaces"))]
+            NativeSurfaceKind::EGLImage => {
+                return Ok(NativeSurface::EGLImage(EGLImageNativeSurface::new(display, size)));
+            }
+            _ => {}
+        }
+        Ok(NativeSurface::MemoryBuffer(MemoryBufferNativeSurface::new(display, size)))
+    }
+
+    /// Limits the width and height of new surfaces, on top of the limits of the platform.
+    /// Painting threads and processes without access to the compositor's GL context typically
+    /// set this to its `NativeSurfaceCapabilities::max_surface_size`. `None` removes the limit.
+    pub fn set_max_size(max_size: Option<i32>) {
+        MAX_SURFACE_SIZE.store(max_size.map_or(0, |max_size| cmp::max(max_size, 1) as usize),
+                               Ordering::SeqCst);
+    }
+}
+
+/// A native surface in a form that can be sent to another process, e.g. serialized over an
+/// IPC channel. Descriptors are plain data: surfaces named by a file descriptor, mach port or
+/// handle of the sending process have it passed along separately, in a `SurfaceTransfer`; see
+/// the `transport` module. X pixmaps are named by IDs global to the X server, and surfaces whose
+/// pixels live in CPU memory are copied.
+#[derive(Clone, RustcDecodable, RustcEncodable)]
+pub enum NativeSurfaceDescriptor {
+    /// The size and the BGRA pixels of the surface.
+    MemoryBuffer(Size2D<i32>, Vec<u8>),
+    /// The XID of an X pixmap and its size.
+    Pixmap(u64, Size2D<i32>),
+    /// An IOSurface of the given size, whose mach port is passed along.
+    IOSurface(Size2D<i32>),
+    /// A dma-buf exported from an EGL image of the given size, whose file descriptor is passed
+    /// along.
+    DmaBuf(DmaBufLayout, Size2D<i32>),
+    /// The BGRA pixels of a surface of the given size in a Windows file mapping. The handle of
+    /// the mapping is passed along until `transport::duplicate_into_process` duplicates it into
+    /// the receiving process; it is then the value of the duplicate there.
+    FileMapping(u64, Size2D<i32>),
+}
+
+/// How the pixels of a single-plane dma-buf are laid out, as `EGL_MESA_image_dma_buf_export`
+/// reports them and `EGL_EXT_image_dma_buf_import` takes them.
+#[derive(Clone, Copy, Debug, PartialEq, RustcDecodable, RustcEncodable)]
+pub struct DmaBufLayout {
+    /// The DRM fourcc code of the pixel format.
+    pub fourcc: u32,
+    /// The number of bytes from the start of a row to the start of the next.
+    pub stride: i32,
+    /// The offset of the first pixel in the buffer.
+    pub offset: i32,
+    /// The DRM format modifier, e.g. for tiled layouts, or `DRM_FORMAT_MOD_INVALID` if the
+    /// driver didn't report one.
+    pub modifier: u64,
 }
 
-#[cfg(target_os="android")]
 impl NativeSurface {
-    /// Creates a new native surface with uninitialized data.
-    pub fn new(display: &NativeDisplay, size: Size2D<i32>) -> NativeSurface {
-        NativeSurface::EGLImage(EGLImageNativeSurface::new(display, size))
-   }
+    /// Converts the surface into a descriptor for sending it to another process, along with the
+    /// file descriptor, mach port or handle that names it, if any. The receiving process adopts
+    /// it with `from_descriptor` and becomes responsible for destroying it. IOSurfaces are sent
+    /// as mach ports, which sandboxed processes can look up, unlike global IDs, and EGL images
+    /// as dma-bufs. Surfaces that can't be sent are given back: EGL images that the driver
+    /// can't export, and any other without a handle or CPU pixels.
+    pub fn into_descriptor(mut self, display: &NativeDisplay)
+                           -> Result<SurfaceTransfer, NativeSurface> {
+        let size = self.get_size();
+        let transfer = match self {
+            #[cfg(all(target_os="linux", feature = "native-surfaces"))]
+            NativeSurface::Pixmap(_) => {
+                let descriptor = NativeSurfaceDescriptor::Pixmap(self.get_id() as u64, size);
+                Some(SurfaceTransfer::new(descriptor, None))
+            }
+            #[cfg(all(target_os="macos", feature = "native-surfaces"))]
+            NativeSurface::IOSurface(ref surface) => {
+                surface.mach_port().map(|port| {
+                    SurfaceTransfer::new(NativeSurfaceDescriptor::IOSurface(size), Some(port))
+                })
+            }
+            #[cfg(all(any(target_os="android",target_os="linux"), feature = "native-surfaces"))]
+            NativeSurface::EGLImage(ref mut surface) => {
+                if surface.has_image() {
+                    surface.export_dma_buf(display).map(|(layout, fd)| {
+                        let descriptor = NativeSurfaceDescriptor::DmaBuf(layout, size);
+                        SurfaceTransfer::new(descriptor, Some(fd))
+                    })
+                } else {
+                    surface.cpu_pixels().map(|pixels| SurfaceTransfer::from_pixels(size, pixels))
+                }
+            }
+            _ => {
+                let _ = display;
+                self.cpu_pixels().map(|pixels| SurfaceTransfer::from_pixels(size, pixels))
+            }
+        };
+        let transfer = match transfer {
+            Some(transfer) => transfer,
+            None => return Err(self),
+        };
+
+        // The receiving process owns the surface now.
+        self.mark_wont_leak();
+        Ok(transfer)
+    }
+
+    /// Adopts a surface sent by another process. Returns `None` if this platform can't use
+    /// surfaces of the descriptor's kind, or if the handle they need didn't come along. The
+    /// surface is marked as leaking until it is destroyed or sent back.
+    pub fn from_descriptor(display: &NativeDisplay, transfer: SurfaceTransfer)
+                           -> Option<NativeSurface> {
+        let SurfaceTransfer { descriptor, handle } = transfer;
+        match descriptor {
+            NativeSurfaceDescriptor::MemoryBuffer(size, pixels) => {
+                let mut surface = MemoryBufferNativeSurface::new(display, size);
+                surface.upload(display, &pixels);
+                Some(NativeSurface::MemoryBuffer(surface))
+            }
+            #[cfg(all(target_os="linux", feature = "native-surfaces"))]
+            NativeSurfaceDescriptor::Pixmap(pixmap, size) => {
+                let pixmap = pixmap as ::x11::xlib::Pixmap;
+                Some(NativeSurface::Pixmap(PixmapNativeSurface::from_pixmap(pixmap, size)))
+            }
+            #[cfg(all(target_os="macos", feature = "native-surfaces"))]
+            NativeSurfaceDescriptor::IOSurface(size) => {
+                handle.and_then(|port| IOSurfaceNativeSurface::from_mach_port(&port, size))
+                      .map(NativeSurface::IOSurface)
+            }
+            #[cfg(all(any(target_os="android",target_os="linux"), feature = "native-surfaces"))]
+            NativeSurfaceDescriptor::DmaBuf(layout, size) => {
+                handle.and_then(|fd| {
+                    EGLImageNativeSurface::from_dma_buf(display, layout, &fd, size)
+                }).map(NativeSurface::EGLImage)
+            }
+            #[cfg(windows)]
+            NativeSurfaceDescriptor::FileMapping(mapping, size) => {
+                // Unless the surface is adopted by the process that sent it, the mapping was
+                // duplicated into this one, which owns it now.
+                let mapping = match handle {
+                    Some(handle) => handle,
+                    None => unsafe { SurfaceHandle::from_raw(mapping as usize as HANDLE) },
+                };
+                let pixels = match transport::pixels_from_file_mapping(&mapping, size) {
+                    Some(pixels) => pixels,
+                    None => return None,
+                };
+                let mut surface = MemoryBufferNativeSurface::new(display, size);
+                surface.upload(display, &pixels);
+                Some(NativeSurface::MemoryBuffer(surface))
+            }
+            _ => {
+                drop(handle);
+                None
+            }
+        }
+    }
 }
 
-#[cfg(target_os="windows")]
+#[cfg(all(target_os="macos", feature = "native-surfaces"))]
 impl NativeSurface {
-    /// Creates a new native surface with uninitialized data.
-    pub fn new(display: &NativeDisplay, size: Size2D<i32>) -> NativeSurface {
-        NativeSurface::MemoryBuffer(MemoryBufferNativeSurface::new(display, size))
-   }
+    /// Wraps an existing `IOSurfaceRef` of the given size, retaining it.
+    ///
+    /// # Safety
+    ///
+    /// `surface_ref` must point to a live IOSurface.
+    pub unsafe fn from_io_surface_ref(surface_ref: ::io_surface::IOSurfaceRef, size: Size2D<i32>)
+                                      -> NativeSurface {
+        NativeSurface::IOSurface(IOSurfaceNativeSurface::from_io_surface_ref(surface_ref, size))
+    }
 }
 
 macro_rules! native_surface_method_with_mutability {
@@ -97,13 +478,13 @@ macro_rules! native_surface_method_with_mutability {
         match *$self_ {
             NativeSurface::MemoryBuffer($pattern) =>
                 $surface.$function_name($($argument), *),
-            #[cfg(target_os="linux")]
+            #[cfg(all(target_os="linux", feature = "native-surfaces"))]
             NativeSurface::Pixmap($pattern) =>
                 $surface.$function_name($($argument), *),
-            #[cfg(target_os="macos")]
+            #[cfg(all(target_os="macos", feature = "native-surfaces"))]
             NativeSurface::IOSurface($pattern) =>
                 $surface.$function_name($($argument), *),
-            #[cfg(any(target_os="android",target_os="linux"))]
+            #[cfg(all(any(target_os="android",target_os="linux"), feature = "native-surfaces"))]
             NativeSurface::EGLImage($pattern) =>
                 $surface.$function_name($($argument), *),
         }
@@ -136,11 +517,11 @@ macro_rules! native_surface_property {
     ($self_:ident $property_name:ident) => {
         match *$self_ {
             NativeSurface::MemoryBuffer(ref surface) => surface.$property_name,
-            #[cfg(target_os="linux")]
+            #[cfg(all(target_os="linux", feature = "native-surfaces"))]
             NativeSurface::Pixmap(ref surface) => surface.$property_name,
-            #[cfg(target_os="macos")]
+            #[cfg(all(target_os="macos", feature = "native-surfaces"))]
             NativeSurface::IOSurface(ref surface) => surface.$property_name,
-            #[cfg(any(target_os="android",target_os="linux"))]
+            #[cfg(all(any(target_os="android",target_os="linux"), feature = "native-surfaces"))]
             NativeSurface::EGLImage(ref surface) => surface.$property_name,
         }
     };
@@ -149,11 +530,15 @@ macro_rules! native_surface_property {
 impl NativeSurface {
     /// Binds the surface to a GPU texture. Compositing task only.
     pub fn bind_to_texture(&self, display: &NativeDisplay, texture: &Texture) {
+        trace!(target: "layers::surface",
+               "Binding surface {} to texture {}", self.get_id(), texture.native_texture());
+        let _span = profiler::span("surface bind");
         native_surface_method!(self bind_to_texture (display, texture))
     }
 
     /// Uploads pixel data to the surface. Painting task only.
     pub fn upload(&mut self, display: &NativeDisplay, data: &[u8]) {
+        let _span = profiler::span("surface upload");
         native_surface_method_mut!(self upload (display, data))
     }
 
@@ -162,13 +547,32 @@ impl NativeSurface {
         native_surface_method!(self get_id ())
     }
 
+    /// Returns the pixel data of the surface if it lives in CPU memory, as tightly packed BGRA.
+    pub fn cpu_pixels(&self) -> Option<&[u8]> {
+        native_surface_method!(self cpu_pixels ())
+    }
+
     /// Destroys the surface. After this, it is an error to use the surface. Painting task only.
     pub fn destroy(&mut self, display: &NativeDisplay) {
+        trace!(target: "layers::surface", "Destroying surface {}", self.get_id());
         native_surface_method_mut!(self destroy (display))
     }
 
+    /// Where the surface is in its life, as seen by this side.
+    pub fn lifetime(&self) -> SurfaceLifetime {
+        native_surface_method!(self lifetime ())
+    }
+
+    /// Acknowledges a step in the life of the surface. Panics if the step isn't valid in the
+    /// current state; see the `lifetime` module.
+    pub fn acknowledge(&mut self, event: SurfaceEvent) {
+        trace!(target: "layers::surface", "Surface {}: {:?}", self.get_id(), event);
+        native_surface_method_mut!(self acknowledge (event))
+    }
+
     /// Records that the surface will leak if destroyed. This is done by the compositor immediately
-    /// after receiving the surface.
+    /// after receiving the surface. This sets the ownership of the surface without checking its
+    /// state; `acknowledge(SurfaceEvent::Received)` is preferred.
     pub fn mark_will_leak(&mut self) {
         native_surface_method_mut!(self mark_will_leak ())
     }
@@ -188,11 +592,13 @@ impl NativeSurface {
     ///    leaking, because of the possibility that the compositor will die before the buffers are
     ///    destroyed.
     ///
-    /// This helps debug leaks. For performance this may want to become a no-op in the future.
+    /// This helps debug leaks. Like `mark_will_leak`, this doesn't check the state of the
+    /// surface; acknowledging `SurfaceEvent::Sent` or `SurfaceEvent::Returned` is preferred.
     pub fn mark_wont_leak(&mut self) {
         native_surface_method_mut!(self mark_wont_leak ())
     }
 
+    #[cfg(feature = "skia")]
     pub fn gl_rasterization_context(&mut self,
                                     gl_context: Arc<GLContext>)
                                     -> Option<Arc<GLRasterizationContext>> {
@@ -220,6 +626,7 @@ impl NativeSurface {
 #[derive(RustcDecodable, RustcEncodable)]
 pub struct MemoryBufferNativeSurface {
     bytes: Vec<u8>,
+    lifetime: SurfaceLifetime,
     pub size: Size2D<i32>,
 }
 
@@ -227,28 +634,15 @@ impl MemoryBufferNativeSurface {
     pub fn new(_: &NativeDisplay, size: Size2D<i32>) -> MemoryBufferNativeSurface {
         MemoryBufferNativeSurface{
             bytes: vec!(),
+            lifetime: SurfaceLifetime::new(),
             size: size,
         }
     }
 
     /// This may only be called on the compositor side.
-    #[cfg(not(target_os="android"))]
     pub fn bind_to_texture(&self, _: &NativeDisplay, texture: &Texture) {
-        let _bound = texture.bind();
-        gl::tex_image_2d(gl::TEXTURE_2D,
-                         0,
-                         gl::RGBA as i32,
-                         self.size.width as i32,
-                         self.size.height as i32,
-                         0,
-                         gl::BGRA,
-                         gl::UNSIGNED_BYTE,
-                         Some(&self.bytes));
-    }
-
-    #[cfg(target_os="android")]
-    pub fn bind_to_texture(&self, _: &NativeDisplay, _: &Texture) {
-        panic!("Binding a memory surface to a texture is not yet supported on Android.");
+        texture.upload_bgra(Size2D::new(self.size.width as usize, self.size.height as usize),
+                            &self.bytes);
     }
 
     /// This may only be called on the painting side.
@@ -261,15 +655,34 @@ impl MemoryBufferNativeSurface {
         0
     }
 
+    pub fn cpu_pixels(&self) -> Option<&[u8]> {
+        Some(&self.bytes)
+    }
+
     pub fn destroy(&mut self, _: &NativeDisplay) {
+        self.bytes = vec!();
+        self.lifetime.mark_destroyed()
+    }
+
+    pub fn lifetime(&self) -> SurfaceLifetime {
+        self.lifetime
+    }
+
+    /// Memory buffers are freed when dropped, so they never leak, but their state is tracked
+    /// like that of other surfaces to catch misuse on every platform.
+    pub fn acknowledge(&mut self, event: SurfaceEvent) {
+        self.lifetime.acknowledge(event)
     }
 
     pub fn mark_will_leak(&mut self) {
+        self.lifetime.set_owned(true)
     }
 
     pub fn mark_wont_leak(&mut self) {
+        self.lifetime.set_owned(false)
     }
 
+    #[cfg(feature = "skia")]
     pub fn gl_rasterization_context(&mut self,
                                     _: Arc<GLContext>)
                                     -> Option<GLRasterizationContext> {
@@ -0,0 +1,616 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Sending native surfaces to other processes. `NativeSurface::into_descriptor` converts a
+//! surface into a `NativeSurfaceDescriptor`, which is plain data, along with the file
+//! descriptor, mach port or handle that names the surface, if any. Such handles only mean
+//! something in the process that owns them, so the channel has to pass them along itself:
+//!
+//! * On Linux and Android, `send_surface` and `receive_surface` pass dma-buf file descriptors
+//!   with `SCM_RIGHTS` over a Unix socket.
+//! * On Mac OS, they move the send right of an IOSurface's mach port in a mach message.
+//! * On Windows, `duplicate_into_process` duplicates the handle of the file mapping holding the
+//!   pixels into the receiving process, after which the descriptor can go over any channel.
+//!
+//! The receiving process adopts the surface with `NativeSurface::from_descriptor`.
+
+use platform::surface::NativeSurfaceDescriptor;
+
+use euclid::size::Size2D;
+#[cfg(all(unix, not(target_os = "macos")))]
+use libc;
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+use rustc_serialize::json;
+use std::io;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::io::{Read, Write};
+use std::mem;
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", windows))]
+use std::os::raw::c_void;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::os::raw::c_int;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::os::unix::io::AsRawFd;
+#[cfg(all(unix, not(target_os = "macos")))]
+use std::os::unix::io::RawFd;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::os::unix::net::UnixStream;
+#[cfg(any(target_os = "linux", target_os = "android", windows))]
+use std::ptr;
+#[cfg(any(target_os = "macos", windows))]
+use std::slice;
+
+/// A mach port name, as in `<mach/port.h>`.
+#[cfg(target_os = "macos")]
+#[allow(non_camel_case_types)]
+pub type mach_port_t = u32;
+
+/// A Windows `HANDLE`.
+#[cfg(windows)]
+pub type HANDLE = *mut c_void;
+
+/// The file descriptor, mach port or handle of the platform that names a surface.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub type RawSurfaceHandle = RawFd;
+/// The file descriptor, mach port or handle of the platform that names a surface.
+#[cfg(target_os = "macos")]
+pub type RawSurfaceHandle = mach_port_t;
+/// The file descriptor, mach port or handle of the platform that names a surface.
+#[cfg(windows)]
+pub type RawSurfaceHandle = HANDLE;
+
+/// A file descriptor, send right to a mach port, or handle that names a surface, owned by this
+/// process. It is closed when dropped.
+pub struct SurfaceHandle(RawSurfaceHandle);
+
+unsafe impl Send for SurfaceHandle {}
+
+impl SurfaceHandle {
+    /// Takes ownership of a raw handle.
+    ///
+    /// # Safety
+    ///
+    /// `raw` must be open, and owned by nothing else, since the `SurfaceHandle` closes it.
+    pub unsafe fn from_raw(raw: RawSurfaceHandle) -> SurfaceHandle {
+        SurfaceHandle(raw)
+    }
+
+    /// The raw handle, which stays owned by the `SurfaceHandle`.
+    pub fn as_raw(&self) -> RawSurfaceHandle {
+        self.0
+    }
+
+    /// Gives up ownership of the raw handle, which the caller becomes responsible for closing.
+    pub fn into_raw(self) -> RawSurfaceHandle {
+        let raw = self.0;
+        mem::forget(self);
+        raw
+    }
+}
+
+impl Drop for SurfaceHandle {
+    #[cfg(all(unix, not(target_os = "macos")))]
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    fn drop(&mut self) {
+        unsafe {
+            mach_port_deallocate(mach_task_self_, self.0);
+        }
+    }
+
+    #[cfg(windows)]
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.0);
+        }
+    }
+}
+
+/// A surface on its way to another process: its descriptor, and the handle that must be passed
+/// along with it, if any.
+pub struct SurfaceTransfer {
+    pub descriptor: NativeSurfaceDescriptor,
+    pub handle: Option<SurfaceHandle>,
+}
+
+impl SurfaceTransfer {
+    pub fn new(descriptor: NativeSurfaceDescriptor, handle: Option<SurfaceHandle>)
+               -> SurfaceTransfer {
+        SurfaceTransfer {
+            descriptor: descriptor,
+            handle: handle,
+        }
+    }
+
+    /// A transfer of the BGRA pixels of a surface that lives in CPU memory. They are copied into
+    /// the descriptor.
+    #[cfg(not(windows))]
+    pub fn from_pixels(size: Size2D<i32>, pixels: &[u8]) -> SurfaceTransfer {
+        SurfaceTransfer::new(NativeSurfaceDescriptor::MemoryBuffer(size, pixels.to_vec()), None)
+    }
+
+    /// A transfer of the BGRA pixels of a surface that lives in CPU memory. They are copied into
+    /// a file mapping, whose handle is then duplicated into the receiving process, rather than
+    /// serialized along with the descriptor; they are only copied into the descriptor if the
+    /// mapping can't be created.
+    #[cfg(windows)]
+    pub fn from_pixels(size: Size2D<i32>, pixels: &[u8]) -> SurfaceTransfer {
+        match pixels_to_file_mapping(pixels) {
+            Some(handle) => {
+                SurfaceTransfer::new(NativeSurfaceDescriptor::FileMapping(0, size), Some(handle))
+            }
+            None => {
+                SurfaceTransfer::new(NativeSurfaceDescriptor::MemoryBuffer(size, pixels.to_vec()),
+                                     None)
+            }
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+fn encode_descriptor(descriptor: &NativeSurfaceDescriptor) -> io::Result<Vec<u8>> {
+    match json::encode(descriptor) {
+        Ok(json) => Ok(json.into_bytes()),
+        Err(error) => Err(io::Error::new(io::ErrorKind::InvalidInput, error.to_string())),
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+fn decode_descriptor(bytes: &[u8]) -> io::Result<NativeSurfaceDescriptor> {
+    let invalid = |error: String| io::Error::new(io::ErrorKind::InvalidData, error);
+    let json = try!(String::from_utf8(bytes.to_vec()).map_err(|error| invalid(error.to_string())));
+    json::decode(&json).map_err(|error| invalid(error.to_string()))
+}
+
+/// Sends a surface over a Unix socket, to a process that receives it with `receive_surface`. The
+/// descriptor is prefixed with its length, and the file descriptor of the surface, if any, goes
+/// along with the first bytes as `SCM_RIGHTS` ancillary data. This process's copy of the file
+/// descriptor is closed once it is sent.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn send_surface(socket: &UnixStream, transfer: SurfaceTransfer) -> io::Result<()> {
+    let descriptor = try!(encode_descriptor(&transfer.descriptor));
+    let length = descriptor.len() as u32;
+    let mut message = vec!(length as u8, (length >> 8) as u8, (length >> 16) as u8,
+                           (length >> 24) as u8);
+    message.extend_from_slice(&descriptor);
+
+    let fd = transfer.handle.as_ref().map(SurfaceHandle::as_raw);
+    let sent = try!(send_with_fd(socket, &message, fd));
+    let mut socket = socket;
+    socket.write_all(&message[sent..])
+}
+
+/// Receives a surface sent with `send_surface` over a Unix socket.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn receive_surface(socket: &UnixStream) -> io::Result<SurfaceTransfer> {
+    let mut length = [0; 4];
+    let (received, fd) = try!(receive_with_fd(socket, &mut length));
+    let handle = fd.map(|fd| unsafe { SurfaceHandle::from_raw(fd) });
+    if received == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "The socket was closed"));
+    }
+
+    let mut socket = socket;
+    try!(socket.read_exact(&mut length[received..]));
+    let length = length[0] as usize | (length[1] as usize) << 8 | (length[2] as usize) << 16 |
+                 (length[3] as usize) << 24;
+    let mut descriptor = vec!(0; length);
+    try!(socket.read_exact(&mut descriptor));
+    Ok(SurfaceTransfer::new(try!(decode_descriptor(&descriptor)), handle))
+}
+
+/// Rounds up to the alignment of control messages, as `CMSG_ALIGN` does.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn cmsg_align(length: usize) -> usize {
+    let alignment = mem::size_of::<usize>();
+    (length + alignment - 1) & !(alignment - 1)
+}
+
+/// The room control messages with a single file descriptor take in `msg_control`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn fd_cmsg_space() -> usize {
+    cmsg_align(mem::size_of::<libc::cmsghdr>()) + cmsg_align(mem::size_of::<c_int>())
+}
+
+/// Where the data of a control message starts, as `CMSG_DATA` computes it.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn cmsg_data(cmsg: *mut libc::cmsghdr) -> *mut c_int {
+    (cmsg as *mut u8).offset(cmsg_align(mem::size_of::<libc::cmsghdr>()) as isize) as *mut c_int
+}
+
+/// Sends as much of `bytes` as the socket takes at once, with `fd` as ancillary data.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn send_with_fd(socket: &UnixStream, bytes: &[u8], fd: Option<RawFd>) -> io::Result<usize> {
+    // Enough room for the control message, aligned like a `cmsghdr`.
+    let mut control = [0usize; 4];
+    unsafe {
+        let mut iov = libc::iovec {
+            iov_base: bytes.as_ptr() as *mut c_void,
+            iov_len: bytes.len(),
+        };
+        let mut header: libc::msghdr = mem::zeroed();
+        header.msg_iov = &mut iov;
+        header.msg_iovlen = 1;
+        if let Some(fd) = fd {
+            let cmsg = control.as_mut_ptr() as *mut libc::cmsghdr;
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = (cmsg_align(mem::size_of::<libc::cmsghdr>()) +
+                                mem::size_of::<c_int>()) as _;
+            ptr::write(cmsg_data(cmsg), fd);
+            header.msg_control = cmsg as *mut c_void;
+            header.msg_controllen = fd_cmsg_space() as _;
+        }
+
+        let sent = libc::sendmsg(socket.as_raw_fd(), &header, 0);
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(sent as usize)
+    }
+}
+
+/// Receives up to `bytes.len()` bytes, along with the file descriptor sent with them, if any.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn receive_with_fd(socket: &UnixStream, bytes: &mut [u8]) -> io::Result<(usize, Option<RawFd>)> {
+    let mut control = [0usize; 4];
+    unsafe {
+        let mut iov = libc::iovec {
+            iov_base: bytes.as_mut_ptr() as *mut c_void,
+            iov_len: bytes.len(),
+        };
+        let mut header: libc::msghdr = mem::zeroed();
+        header.msg_iov = &mut iov;
+        header.msg_iovlen = 1;
+        header.msg_control = control.as_mut_ptr() as *mut c_void;
+        header.msg_controllen = fd_cmsg_space() as _;
+
+        let received = libc::recvmsg(socket.as_raw_fd(), &mut header, 0);
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let cmsg = control.as_mut_ptr() as *mut libc::cmsghdr;
+        let fd = if header.msg_controllen as usize >= fd_cmsg_space() &&
+                (*cmsg).cmsg_level == libc::SOL_SOCKET &&
+                (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+            Some(ptr::read(cmsg_data(cmsg)))
+        } else {
+            None
+        };
+        if header.msg_flags & libc::MSG_CTRUNC != 0 {
+            if let Some(fd) = fd {
+                libc::close(fd);
+            }
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "More file descriptors were sent than a surface has"));
+        }
+        Ok((received as usize, fd))
+    }
+}
+
+#[cfg(target_os = "macos")]
+#[allow(non_upper_case_globals)]
+extern {
+    static mach_task_self_: mach_port_t;
+
+    fn mach_msg(message: *mut MachMessageHeader,
+                option: i32,
+                send_size: u32,
+                receive_size: u32,
+                receive_port: mach_port_t,
+                timeout: u32,
+                notify: mach_port_t)
+                -> i32;
+    fn mach_msg_destroy(message: *mut MachMessageHeader);
+    fn mach_port_deallocate(task: mach_port_t, name: mach_port_t) -> i32;
+    fn vm_deallocate(task: mach_port_t, address: usize, size: usize) -> i32;
+}
+
+#[cfg(target_os = "macos")]
+const MACH_PORT_NULL: mach_port_t = 0;
+#[cfg(target_os = "macos")]
+const MACH_MSG_SUCCESS: i32 = 0;
+#[cfg(target_os = "macos")]
+const MACH_SEND_MSG: i32 = 1;
+#[cfg(target_os = "macos")]
+const MACH_RCV_MSG: i32 = 2;
+#[cfg(target_os = "macos")]
+const MACH_MSG_TIMEOUT_NONE: u32 = 0;
+#[cfg(target_os = "macos")]
+const MACH_MSGH_BITS_COMPLEX: u32 = 0x80000000;
+#[cfg(target_os = "macos")]
+const MACH_MSG_TYPE_MOVE_SEND: u8 = 17;
+#[cfg(target_os = "macos")]
+const MACH_MSG_TYPE_COPY_SEND: u32 = 19;
+#[cfg(target_os = "macos")]
+const MACH_MSG_PORT_DESCRIPTOR: u8 = 0;
+#[cfg(target_os = "macos")]
+const MACH_MSG_OOL_DESCRIPTOR: u8 = 1;
+#[cfg(target_os = "macos")]
+const MACH_MSG_VIRTUAL_COPY: u8 = 1;
+
+/// The ID of the mach messages that carry surfaces, "LSUR".
+#[cfg(target_os = "macos")]
+const SURFACE_MESSAGE_ID: i32 = 0x4c535552;
+
+/// `mach_msg_header_t`.
+#[cfg(target_os = "macos")]
+#[repr(C)]
+struct MachMessageHeader {
+    bits: u32,
+    size: u32,
+    remote_port: mach_port_t,
+    local_port: mach_port_t,
+    voucher_port: mach_port_t,
+    id: i32,
+}
+
+/// `mach_msg_port_descriptor_t`.
+#[cfg(target_os = "macos")]
+#[repr(C)]
+struct MachPortDescriptor {
+    name: mach_port_t,
+    pad1: u32,
+    pad2: u16,
+    disposition: u8,
+    kind: u8,
+}
+
+/// `mach_msg_ool_descriptor_t`, as laid out on 64-bit targets.
+#[cfg(target_os = "macos")]
+#[repr(C)]
+struct MachOolDescriptor {
+    address: *mut c_void,
+    deallocate: u8,
+    copy: u8,
+    pad1: u8,
+    kind: u8,
+    size: u32,
+}
+
+/// A mach message carrying a surface: the send right to its port, which may be null, and its
+/// descriptor out of line. The port descriptor comes first, so that the out of line descriptor
+/// is 8-byte aligned without the packing of `<mach/message.h>`.
+#[cfg(target_os = "macos")]
+#[repr(C)]
+struct SurfaceMessage {
+    header: MachMessageHeader,
+    descriptor_count: u32,
+    port: MachPortDescriptor,
+    descriptor: MachOolDescriptor,
+}
+
+/// A surface message as received, followed by room for the trailer the kernel appends.
+#[cfg(target_os = "macos")]
+#[repr(C)]
+struct ReceivedSurfaceMessage {
+    message: SurfaceMessage,
+    trailer: [u32; 17],
+}
+
+#[cfg(target_os = "macos")]
+fn mach_error(function: &str, result: i32) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{} failed: {:#x}", function, result))
+}
+
+/// Sends a surface in a mach message to `port`, a send right to a port on which another process
+/// receives it with `receive_surface`. The send right to the surface's own port is moved into
+/// the message.
+#[cfg(target_os = "macos")]
+pub fn send_surface(port: mach_port_t, transfer: SurfaceTransfer) -> io::Result<()> {
+    let descriptor = try!(encode_descriptor(&transfer.descriptor));
+    let surface_port = transfer.handle.map_or(MACH_PORT_NULL, SurfaceHandle::into_raw);
+    let mut message = SurfaceMessage {
+        header: MachMessageHeader {
+            bits: MACH_MSG_TYPE_COPY_SEND | MACH_MSGH_BITS_COMPLEX,
+            size: mem::size_of::<SurfaceMessage>() as u32,
+            remote_port: port,
+            local_port: MACH_PORT_NULL,
+            voucher_port: MACH_PORT_NULL,
+            id: SURFACE_MESSAGE_ID,
+        },
+        descriptor_count: 2,
+        port: MachPortDescriptor {
+            name: surface_port,
+            pad1: 0,
+            pad2: 0,
+            disposition: MACH_MSG_TYPE_MOVE_SEND,
+            kind: MACH_MSG_PORT_DESCRIPTOR,
+        },
+        descriptor: MachOolDescriptor {
+            address: descriptor.as_ptr() as *mut c_void,
+            deallocate: 0,
+            copy: MACH_MSG_VIRTUAL_COPY,
+            pad1: 0,
+            kind: MACH_MSG_OOL_DESCRIPTOR,
+            size: descriptor.len() as u32,
+        },
+    };
+
+    // Whether the kernel destroyed the rights in a message it couldn't send depends on the
+    // error, so the surface's port is left alone on failure: leaking a send right is better
+    // than deallocating one twice.
+    let result = unsafe {
+        mach_msg(&mut message.header,
+                 MACH_SEND_MSG,
+                 message.header.size,
+                 0,
+                 MACH_PORT_NULL,
+                 MACH_MSG_TIMEOUT_NONE,
+                 MACH_PORT_NULL)
+    };
+    if result != MACH_MSG_SUCCESS {
+        return Err(mach_error("mach_msg", result));
+    }
+    Ok(())
+}
+
+/// Receives a surface sent with `send_surface` on `port`, a receive right of this process.
+#[cfg(target_os = "macos")]
+pub fn receive_surface(port: mach_port_t) -> io::Result<SurfaceTransfer> {
+    unsafe {
+        let mut received: ReceivedSurfaceMessage = mem::zeroed();
+        let result = mach_msg(&mut received.message.header,
+                              MACH_RCV_MSG,
+                              0,
+                              mem::size_of::<ReceivedSurfaceMessage>() as u32,
+                              port,
+                              MACH_MSG_TIMEOUT_NONE,
+                              MACH_PORT_NULL);
+        if result != MACH_MSG_SUCCESS {
+            return Err(mach_error("mach_msg", result));
+        }
+
+        let message = &mut received.message;
+        if message.header.id != SURFACE_MESSAGE_ID ||
+                message.header.bits & MACH_MSGH_BITS_COMPLEX == 0 ||
+                message.descriptor_count != 2 ||
+                message.port.kind != MACH_MSG_PORT_DESCRIPTOR ||
+                message.descriptor.kind != MACH_MSG_OOL_DESCRIPTOR {
+            mach_msg_destroy(&mut message.header);
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "The mach message doesn't carry a surface"));
+        }
+
+        let handle = match message.port.name {
+            MACH_PORT_NULL => None,
+            name => Some(SurfaceHandle::from_raw(name)),
+        };
+        let address = message.descriptor.address;
+        let size = message.descriptor.size as usize;
+        let descriptor = decode_descriptor(slice::from_raw_parts(address as *const u8, size));
+        vm_deallocate(mach_task_self_, address as usize, size);
+        Ok(SurfaceTransfer::new(try!(descriptor), handle))
+    }
+}
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    fn CloseHandle(handle: HANDLE) -> i32;
+    fn CreateFileMappingW(file: HANDLE,
+                          attributes: *mut c_void,
+                          protection: u32,
+                          maximum_size_high: u32,
+                          maximum_size_low: u32,
+                          name: *const u16)
+                          -> HANDLE;
+    fn DuplicateHandle(source_process: HANDLE,
+                       source: HANDLE,
+                       target_process: HANDLE,
+                       target: *mut HANDLE,
+                       access: u32,
+                       inherit: i32,
+                       options: u32)
+                       -> i32;
+    fn GetCurrentProcess() -> HANDLE;
+    fn MapViewOfFile(mapping: HANDLE,
+                     access: u32,
+                     offset_high: u32,
+                     offset_low: u32,
+                     length: usize)
+                     -> *mut c_void;
+    fn UnmapViewOfFile(address: *const c_void) -> i32;
+}
+
+#[cfg(windows)]
+const PAGE_READWRITE: u32 = 0x04;
+#[cfg(windows)]
+const FILE_MAP_WRITE: u32 = 0x02;
+#[cfg(windows)]
+const FILE_MAP_READ: u32 = 0x04;
+#[cfg(windows)]
+const DUPLICATE_CLOSE_SOURCE: u32 = 0x01;
+#[cfg(windows)]
+const DUPLICATE_SAME_ACCESS: u32 = 0x02;
+
+/// Copies pixels into a new file mapping backed by the paging file.
+#[cfg(windows)]
+fn pixels_to_file_mapping(pixels: &[u8]) -> Option<SurfaceHandle> {
+    if pixels.is_empty() {
+        return None;
+    }
+    let length = pixels.len() as u64;
+    unsafe {
+        let invalid_handle_value = !0usize as HANDLE;
+        let mapping = CreateFileMappingW(invalid_handle_value,
+                                         ptr::null_mut(),
+                                         PAGE_READWRITE,
+                                         (length >> 32) as u32,
+                                         length as u32,
+                                         ptr::null());
+        if mapping.is_null() {
+            return None;
+        }
+        let handle = SurfaceHandle(mapping);
+        let view = MapViewOfFile(mapping, FILE_MAP_WRITE, 0, 0, pixels.len());
+        if view.is_null() {
+            return None;
+        }
+        ptr::copy_nonoverlapping(pixels.as_ptr(), view as *mut u8, pixels.len());
+        UnmapViewOfFile(view);
+        Some(handle)
+    }
+}
+
+/// Reads the pixels of a surface of `size` out of a file mapping.
+#[cfg(windows)]
+pub fn pixels_from_file_mapping(handle: &SurfaceHandle, size: Size2D<i32>) -> Option<Vec<u8>> {
+    let length = size.width as usize * size.height as usize * 4;
+    unsafe {
+        let view = MapViewOfFile(handle.0, FILE_MAP_READ, 0, 0, length);
+        if view.is_null() {
+            return None;
+        }
+        let pixels = slice::from_raw_parts(view as *const u8, length).to_vec();
+        UnmapViewOfFile(view);
+        Some(pixels)
+    }
+}
+
+/// Duplicates the handle of a surface into `process`, which must have been opened with
+/// `PROCESS_DUP_HANDLE` access, and gives the descriptor naming the duplicate. The descriptor
+/// can then be sent over any channel, and the receiving process adopts the surface from it
+/// alone. This process's handle is closed.
+#[cfg(windows)]
+pub fn duplicate_into_process(transfer: SurfaceTransfer, process: HANDLE)
+                              -> io::Result<NativeSurfaceDescriptor> {
+    let handle = match transfer.handle {
+        Some(handle) => handle,
+        None => return Ok(transfer.descriptor),
+    };
+    let size = match transfer.descriptor {
+        NativeSurfaceDescriptor::FileMapping(_, size) => size,
+        _ => {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "Only file mappings are sent as handles on Windows"))
+        }
+    };
+
+    let mut duplicate = ptr::null_mut();
+    let duplicated = unsafe {
+        DuplicateHandle(GetCurrentProcess(),
+                        handle.into_raw(),
+                        process,
+                        &mut duplicate,
+                        0,
+                        0,
+                        DUPLICATE_CLOSE_SOURCE | DUPLICATE_SAME_ACCESS)
+    };
+    if duplicated == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(NativeSurfaceDescriptor::FileMapping(duplicate as u64, size))
+}
@@ -0,0 +1,19 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The types most embedders need, independent of the module they are defined in, for use as
+//! `use layers::prelude::*;`.
+
+pub use color::Color;
+pub use geometry::{DevicePixel, LayerPixel};
+pub use layers::{BufferRequest, ContentAge, Layer, LayerBuffer, LayerBufferSet};
+pub use platform::surface::{MemoryBufferNativeSurface, NativeDisplay, NativeSurface};
+pub use rendergl::{RenderContext, RenderContextBuilder, VsyncMode, render_scene};
+pub use scene::{Scene, TilePlaceholder};
+pub use texturegl::Texture;
@@ -0,0 +1,68 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Hooks for time profilers.
+//!
+//! Embedders register an implementation of `ProfilerCallbacks` on the compositor thread, and
+//! the crate reports named spans around composites, uploads and buffer request generation. When
+//! no profiler is registered, spans cost a thread-local lookup.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Receives the spans reported by the compositor. Spans are properly nested: every `begin_span`
+/// is matched by an `end_span` with the same name, in LIFO order.
+pub trait ProfilerCallbacks {
+    fn begin_span(&self, name: &str);
+    fn end_span(&self, name: &str);
+}
+
+thread_local!(static PROFILER: RefCell<Option<Rc<ProfilerCallbacks>>> = RefCell::new(None));
+
+/// Sets the profiler that receives spans reported on the current thread, replacing any
+/// previous one.
+pub fn set_profiler_callbacks(profiler: Option<Rc<ProfilerCallbacks>>) {
+    PROFILER.with(|current| *current.borrow_mut() = profiler);
+}
+
+fn current_profiler() -> Option<Rc<ProfilerCallbacks>> {
+    PROFILER.with(|current| current.borrow().clone())
+}
+
+/// A span that ends when dropped.
+pub struct ProfileSpan {
+    profiler: Option<(Rc<ProfilerCallbacks>, Cow<'static, str>)>,
+}
+
+impl Drop for ProfileSpan {
+    fn drop(&mut self) {
+        if let Some((ref profiler, ref name)) = self.profiler {
+            profiler.end_span(name);
+        }
+    }
+}
+
+/// Begins a span with a fixed name.
+pub fn span(name: &'static str) -> ProfileSpan {
+    span_with(|| Cow::Borrowed(name))
+}
+
+/// Begins a span whose name is only computed if a profiler is registered, e.g. for names that
+/// include the debug name of a layer.
+pub fn span_with<F>(name: F) -> ProfileSpan where F: FnOnce() -> Cow<'static, str> {
+    let profiler = current_profiler().map(|profiler| {
+        let name = name();
+        profiler.begin_span(&name);
+        (profiler, name)
+    });
+    ProfileSpan {
+        profiler: profiler,
+    }
+}
@@ -0,0 +1,421 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Caching of static layer subtrees in textures ("render surfaces").
+//!
+//! Every frame, a signature of each subtree is computed from everything that affects how it is
+//! drawn. Subtrees that stay unchanged for long enough and draw enough quads are rendered once
+//! into a texture, which is then composited as a single quad until the signature changes.
+
+use geometry::DevicePixel;
+use layers::{Layer, walk_subtree};
+use limits::ResourceUsage;
+use texturegl::Texture;
+
+use euclid::Matrix4D;
+use euclid::rect::TypedRect;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::mem;
+use std::rc::Rc;
+
+/// Whether a layer's subtree may be cached in a texture.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CachePolicy {
+    /// Cache the subtree when the heuristics in `RasterCacheConfig` say it's worth it.
+    Automatic,
+    /// Always cache the subtree.
+    Always,
+    /// Never cache the subtree.
+    Never,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, CachePolicy);
+
+/// The heuristics used to decide whether to cache subtrees with `CachePolicy::Automatic`.
+#[derive(Copy, Clone, Debug)]
+pub struct RasterCacheConfig {
+    /// The number of consecutive frames a subtree must stay unchanged before it's cached.
+    pub min_unchanged_frames: usize,
+
+    /// The number of quads a subtree must draw before it's worth caching.
+    pub min_quads: usize,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, RasterCacheConfig);
+
+impl RasterCacheConfig {
+    pub fn new() -> RasterCacheConfig {
+        RasterCacheConfig {
+            min_unchanged_frames: 10,
+            min_quads: 32,
+        }
+    }
+}
+
+/// The per-layer state of the raster cache.
+pub struct RasterCache {
+    /// The signature of the subtree as of the current frame.
+    signature: u64,
+
+    /// The number of quads the subtree draws.
+    quads: usize,
+
+    /// The number of consecutive frames the signature has stayed the same.
+    unchanged_frames: usize,
+
+    /// The signature of the subtree when the cached texture was rendered.
+    rendered_signature: u64,
+
+    /// The cached rendering of the subtree, if any.
+    pub texture: Option<Texture>,
+
+    /// The rectangle covered by the cached texture, in device pixels.
+    pub rect: TypedRect<f32, DevicePixel>,
+
+    /// Set when the subtree was collapsed to stay within the resource limits.
+    collapse: Option<Collapse>,
+}
+
+/// The state of a subtree collapsed by `limits::enforce_limits`.
+struct Collapse {
+    /// The resources released by collapsing the subtree.
+    savings: ResourceUsage,
+
+    /// The signature of the subtree when it was collapsed, once known.
+    signature: Option<u64>,
+}
+
+impl RasterCache {
+    pub fn new() -> RasterCache {
+        RasterCache {
+            signature: 0,
+            quads: 0,
+            unchanged_frames: 0,
+            rendered_signature: 0,
+            texture: None,
+            rect: TypedRect::zero(),
+            collapse: None,
+        }
+    }
+
+    /// Returns true if the subtree should be composited from the cached texture.
+    pub fn is_active(&self) -> bool {
+        self.texture.is_some()
+    }
+
+    /// Drops the cached texture.
+    pub fn invalidate(&mut self) {
+        self.texture = None;
+    }
+
+    /// Forgets the cached texture after the GL context was lost, without deleting it. A
+    /// collapsed subtree is expanded, since it can't be drawn without its cache.
+    pub fn forget(&mut self) {
+        if let Some(mut texture) = self.texture.take() {
+            texture.forget();
+        }
+        self.expand();
+    }
+
+    /// Stores a freshly rendered texture for the current signature.
+    pub fn store(&mut self, texture: Texture, rect: TypedRect<f32, DevicePixel>) {
+        self.texture = Some(texture);
+        self.rect = rect;
+        self.rendered_signature = self.signature;
+    }
+
+    /// Returns true if the subtree is collapsed into its cached texture.
+    pub fn is_collapsed(&self) -> bool {
+        self.collapse.is_some()
+    }
+
+    /// The resources released by collapsing the subtree, if it's collapsed.
+    pub fn collapsed_savings(&self) -> Option<ResourceUsage> {
+        self.collapse.as_ref().map(|collapse| collapse.savings)
+    }
+
+    /// Collapses the subtree into its cached texture. The tiles of its descendants are released
+    /// once the texture has been rendered.
+    pub fn collapse(&mut self, savings: ResourceUsage) {
+        self.collapse = Some(Collapse {
+            savings: savings,
+            signature: None,
+        });
+    }
+
+    /// Expands a collapsed subtree again, returning the resources it had released. The cached
+    /// texture is dropped, so that the descendants are drawn, and request tiles, normally.
+    pub fn expand(&mut self) -> Option<ResourceUsage> {
+        self.collapse.take().map(|collapse| {
+            self.invalidate();
+            collapse.savings
+        })
+    }
+
+    fn update_signature(&mut self, signature: u64, quads: usize) {
+        // A collapsed subtree has released its tiles, so it can't be rendered again once it
+        // changes. Expand it instead.
+        let changed_since_collapse = match self.collapse {
+            Some(ref mut collapse) => {
+                let changed = collapse.signature.map_or(false, |old| old != signature);
+                collapse.signature = Some(signature);
+                changed
+            }
+            None => false,
+        };
+        if changed_since_collapse {
+            debug!("Expanding a collapsed subtree that changed");
+            self.expand();
+        }
+
+        if signature == self.signature {
+            self.unchanged_frames += 1;
+        } else {
+            self.unchanged_frames = 0;
+        }
+        self.signature = signature;
+        self.quads = quads;
+    }
+}
+
+fn hash_f32s(hasher: &mut DefaultHasher, values: &[f32]) {
+    for value in values {
+        hasher.write_u32(unsafe { mem::transmute::<f32, u32>(*value) });
+    }
+}
+
+fn hash_matrix(hasher: &mut DefaultHasher, m: &Matrix4D<f32>) {
+    hash_f32s(hasher, &m.to_row_major_array());
+}
+
+/// Recomputes the signatures of all subtrees, bottom-up. Returns the signature of the subtree
+/// rooted at `layer` and the number of quads it draws.
+pub fn update_signatures<T>(layer: &Rc<Layer<T>>) -> (u64, usize) {
+    // Visiting the layers in reverse pre-order guarantees that children are done before their
+    // parents, without recursing.
+    // The tiles of the descendants of collapsed layers are released, which must not count as
+    // a change, so they are left out of the signatures.
+    let mut layers = vec!();
+    let mut stack = vec!((layer.clone(), false));
+    while let Some((layer, in_collapsed_subtree)) = stack.pop() {
+        let children_in_collapsed_subtree =
+            in_collapsed_subtree || layer.raster_cache.borrow().is_collapsed();
+        for child in layer.children().iter() {
+            stack.push((child.clone(), children_in_collapsed_subtree));
+        }
+        layers.push((layer, in_collapsed_subtree));
+    }
+
+    let mut subtrees: HashMap<*const Layer<T>, (u64, usize)> = HashMap::new();
+    for &(ref layer, in_collapsed_subtree) in layers.iter().rev() {
+        let result = {
+            let children = layer.children();
+            let child_results = children.iter().map(|child| subtrees[&(&**child as *const _)]);
+            update_signature(layer, !in_collapsed_subtree, child_results)
+        };
+        for child in layer.children().iter() {
+            subtrees.remove(&(&**child as *const _));
+        }
+        subtrees.insert(&**layer as *const _, result);
+    }
+    subtrees[&(&**layer as *const _)]
+}
+
+/// Updates the signature of a single layer from its own state and the signatures of its
+/// children's subtrees.
+fn update_signature<T, I>(layer: &Rc<Layer<T>>, include_tiles: bool, child_results: I)
+                          -> (u64, usize)
+                          where I: Iterator<Item = (u64, usize)> {
+    let mut hasher = DefaultHasher::new();
+    let mut quads = 0;
+
+    {
+        let ts = layer.transform_state.borrow();
+        hash_matrix(&mut hasher, &ts.final_transform);
+        let r = ts.world_rect;
+        hash_f32s(&mut hasher, &[r.origin.x, r.origin.y, r.size.width, r.size.height]);
+        hasher.write_u8(ts.screen_rect.is_some() as u8);
+    }
+
+    let background_color = *layer.background_color.borrow();
+    hash_f32s(&mut hasher, &[background_color.r, background_color.g,
+                             background_color.b, background_color.a,
+                             layer.effective_opacity()]);
+    if background_color.a != 0.0 {
+        quads += 1;
+    }
+
+    hasher.write_u8(*layer.masks_to_bounds.borrow() as u8);
+    if let Some(ref clip_region) = *layer.clip_region.borrow() {
+        let r = clip_region.rect;
+        let radii = clip_region.radii;
+        hash_f32s(&mut hasher, &[r.origin.x, r.origin.y, r.size.width, r.size.height]);
+        for radius in &[radii.top_left, radii.top_right, radii.bottom_right, radii.bottom_left] {
+            hash_f32s(&mut hasher, &[radius.width, radius.height]);
+        }
+    }
+    if let Some(ref border) = *layer.border.borrow() {
+        for side in &[border.top, border.right, border.bottom, border.left] {
+            hash_f32s(&mut hasher, &[side.width, side.color.r, side.color.g,
+                                     side.color.b, side.color.a]);
+        }
+        quads += 4;
+    }
+    if let Some(ref shadow) = *layer.shadow.borrow() {
+        hash_f32s(&mut hasher, &[shadow.offset.x, shadow.offset.y, shadow.blur_radius,
+                                 shadow.color.r, shadow.color.g, shadow.color.b,
+                                 shadow.color.a]);
+        quads += 1;
+    }
+    for highlight in layer.highlights.borrow().iter() {
+        let r = highlight.rect;
+        hash_f32s(&mut hasher, &[r.origin.x, r.origin.y, r.size.width, r.size.height,
+                                 highlight.color.r, highlight.color.g, highlight.color.b,
+                                 highlight.color.a]);
+        quads += 1;
+    }
+    if let Some(ref backdrop_filter) = *layer.backdrop_filter.borrow() {
+        hash_f32s(&mut hasher, &[backdrop_filter.blur_radius]);
+        quads += 1;
+    }
+    if let Some(ref repeat) = *layer.content_repeat.borrow() {
+        hash_f32s(&mut hasher, &[repeat.pattern_size.width, repeat.pattern_size.height]);
+        hasher.write_u8(repeat.mode as u8);
+    }
+
+    if let Some(ref frame) = *layer.video_frame.borrow() {
+        hasher.write_usize(frame.id());
+        hash_matrix(&mut hasher, &frame.color_matrix);
+        quads += 1;
+    } else if let Some(ref texture_layer) = *layer.texture_layer.borrow() {
+        hasher.write_u32(texture_layer.native_texture());
+        hasher.write_usize(texture_layer.generation());
+        hasher.write_u8(texture_layer.texture().orientation.flip_y as u8);
+        quads += 1;
+    }
+
+    // Tiles are stored in a hash map, so combine them in an order-independent way.
+    let mut tile_count = 0;
+    let mut texture_sum: u64 = 0;
+    if include_tiles {
+        layer.do_for_all_tiles(|tile| {
+            if !tile.texture.is_zero() {
+                tile_count += 1;
+                texture_sum = texture_sum.wrapping_add(tile.texture.native_texture() as u64);
+            }
+        });
+    }
+    hasher.write_usize(tile_count);
+    hasher.write_u64(texture_sum);
+    quads += tile_count;
+
+    for (child_signature, child_quads) in child_results {
+        hasher.write_u64(child_signature);
+        quads += child_quads;
+    }
+
+    let signature = hasher.finish();
+    layer.raster_cache.borrow_mut().update_signature(signature, quads);
+    (signature, quads)
+}
+
+/// Decides which subtrees are cached this frame, top-down, and returns the layers whose cached
+/// texture needs to be (re)rendered. Caches of layers that no longer qualify are dropped.
+pub fn select_cached_layers<T>(layer: &Rc<Layer<T>>,
+                               config: &RasterCacheConfig,
+                               layers_to_render: &mut Vec<Rc<Layer<T>>>) {
+    walk_subtree(layer, |layer| {
+        let wants_cache = {
+            let cache = layer.raster_cache.borrow();
+            if cache.is_collapsed() {
+                return select_collapsed_layer(layer, layers_to_render);
+            }
+
+            let eligible = !layer.children.borrow().is_empty() &&
+                           !layer.establishes_3d_context &&
+                           layer.transform_state.borrow().screen_rect.is_some() &&
+                           !draws_through_backdrop(layer);
+            eligible && match layer.cache_policy() {
+                CachePolicy::Never => false,
+                CachePolicy::Always => true,
+                CachePolicy::Automatic => cache.unchanged_frames >= config.min_unchanged_frames &&
+                                          cache.quads >= config.min_quads &&
+                                          !expects_changes(layer),
+            }
+        };
+
+        if wants_cache {
+            let mut cache = layer.raster_cache.borrow_mut();
+            if !cache.is_active() || cache.rendered_signature != cache.signature {
+                cache.invalidate();
+                for child in layer.children().iter() {
+                    invalidate_caches(child);
+                }
+                layers_to_render.push(layer.clone());
+            }
+            return false;
+        }
+
+        layer.raster_cache.borrow_mut().invalidate();
+        true
+    });
+}
+
+/// Returns true if a layer in the subtree samples the content behind it or punches a hole
+/// through it, neither of which works when the subtree is rendered into a cache texture.
+fn draws_through_backdrop<T>(layer: &Rc<Layer<T>>) -> bool {
+    let mut found = false;
+    walk_subtree(layer, |layer| {
+        found = found || layer.backdrop_filter.borrow().is_some() || layer.hole.borrow().is_some();
+        !found
+    });
+    found
+}
+
+/// Returns true if a layer in the subtree has compositing hints, which say that the subtree
+/// will change soon, and with it any cached rendering of it.
+pub fn expects_changes<T>(layer: &Rc<Layer<T>>) -> bool {
+    let mut found = false;
+    walk_subtree(layer, |layer| {
+        found = found || !layer.compositing_hints().is_empty();
+        !found
+    });
+    found
+}
+
+/// Makes sure a collapsed subtree has a cached texture. Collapsed subtrees are expanded as soon
+/// as they change, so an existing texture is always up to date.
+fn select_collapsed_layer<T>(layer: &Rc<Layer<T>>, layers_to_render: &mut Vec<Rc<Layer<T>>>)
+                             -> bool {
+    if !layer.raster_cache.borrow().is_active() {
+        debug!("Rendering collapsed subtree {}", layer.debug_name());
+        for child in layer.children().iter() {
+            invalidate_caches(child);
+        }
+        layers_to_render.push(layer.clone());
+    }
+    false
+}
+
+/// Drops the cached textures of a whole subtree. Collapsed subtrees keep theirs, since their
+/// tiles are gone and they can only be drawn from the cache.
+pub fn invalidate_caches<T>(layer: &Rc<Layer<T>>) {
+    walk_subtree(layer, |layer| {
+        let mut cache = layer.raster_cache.borrow_mut();
+        if cache.is_collapsed() && cache.is_active() {
+            return false;
+        }
+        cache.invalidate();
+        true
+    });
+}
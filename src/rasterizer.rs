@@ -0,0 +1,261 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Driving tile rasterization directly from the buffer request machinery, for embedders that
+//! don't have a separate paint task, or handing the requests to one, such as Servo's, with
+//! `PaintTaskAdapter`.
+
+use geometry::{DevicePixel, LayerPixel};
+use layers::{BufferRequest, Layer, LayerBuffer};
+use platform::surface::{NativeDisplay, NativeSurface, SurfaceError};
+use scene::Scene;
+use sequence::Epoch;
+
+use euclid::rect::TypedRect;
+use euclid::scale_factor::ScaleFactor;
+use euclid::size::Size2D;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::mpsc::{Receiver, Sender};
+
+/// Something that can paint the contents of a layer tile into a native surface.
+pub trait TileRasterizer<T> {
+    /// Paints the part of `layer` covered by `tile_rect`, at the given scale, into `surface`.
+    fn rasterize(&mut self,
+                 layer: &Rc<Layer<T>>,
+                 tile_rect: &TypedRect<f32, LayerPixel>,
+                 scale: ScaleFactor<f32, LayerPixel, DevicePixel>,
+                 surface: &mut NativeSurface);
+
+    /// Whether the surfaces filled by this rasterizer were painted on the CPU.
+    fn paints_with_cpu(&self) -> bool {
+        true
+    }
+}
+
+/// A rasterizer that paints into a BGRA pixel buffer and uploads it to the surface, the same way
+/// Servo's paint task handles CPU-painted tiles.
+pub struct CpuRasterizer<F> {
+    display: NativeDisplay,
+    paint: F,
+    pixels: Vec<u8>,
+}
+
+impl<F> CpuRasterizer<F> {
+    /// Creates a rasterizer that calls `paint` with a zeroed, tightly packed BGRA buffer of the
+    /// tile's device size.
+    pub fn new(display: NativeDisplay, paint: F) -> CpuRasterizer<F> {
+        CpuRasterizer {
+            display: display,
+            paint: paint,
+            pixels: vec!(),
+        }
+    }
+}
+
+impl<T, F> TileRasterizer<T> for CpuRasterizer<F>
+    where F: FnMut(&Rc<Layer<T>>,
+                   &TypedRect<f32, LayerPixel>,
+                   ScaleFactor<f32, LayerPixel, DevicePixel>,
+                   &mut [u8]) {
+    fn rasterize(&mut self,
+                 layer: &Rc<Layer<T>>,
+                 tile_rect: &TypedRect<f32, LayerPixel>,
+                 scale: ScaleFactor<f32, LayerPixel, DevicePixel>,
+                 surface: &mut NativeSurface) {
+        let size = surface.get_size();
+        self.pixels.clear();
+        self.pixels.resize(size.width as usize * size.height as usize * 4, 0);
+        (self.paint)(layer, tile_rect, scale, &mut self.pixels);
+        surface.upload(&self.display, &self.pixels);
+    }
+}
+
+/// Requests buffers for every tile of the scene that needs painting, rasterizes them with
+/// `rasterizer` and hands the results back to their layers. Buffers that the scene no longer
+/// needs, or that their layers reject, are destroyed.
+pub fn rasterize_scene<T, R>(scene: &mut Scene<T>, rasterizer: &mut R, display: &NativeDisplay)
+                             where R: TileRasterizer<T> {
+    let mut layers_and_requests = Vec::new();
+    let mut unused_buffers = Vec::new();
+    scene.get_buffer_requests(&mut layers_and_requests, &mut unused_buffers);
+
+    for buffer in unused_buffers {
+        buffer.destroy(display);
+    }
+
+    for (layer, requests) in layers_and_requests {
+        let mut buffers = vec!();
+        for mut request in requests {
+            match rasterize_request(&layer, &mut request, rasterizer, display) {
+                Ok(buffer) => buffers.push(buffer),
+                Err(error) => {
+                    warn!("Not painting a tile of {}: {}", layer.debug_name(), error);
+                    layer.cancel_buffer_request(&request);
+                }
+            }
+        }
+        let epoch = *layer.epoch.borrow();
+        for buffer in layer.assign_painted_buffers(buffers, epoch) {
+            buffer.destroy(display);
+        }
+    }
+}
+
+fn rasterize_request<T, R>(layer: &Rc<Layer<T>>,
+                           request: &mut BufferRequest,
+                           rasterizer: &mut R,
+                           display: &NativeDisplay)
+                           -> Result<Box<LayerBuffer>, SurfaceError>
+                           where R: TileRasterizer<T> {
+    let size = Size2D::new(request.screen_rect.size.width as i32,
+                           request.screen_rect.size.height as i32);
+    let mut surface = match request.native_surface.take() {
+        Some(surface) => surface,
+        None => try!(NativeSurface::new(display, size)),
+    };
+
+    // Buffer requests are expressed in device pixels at their raster scale; rasterizers work in
+    // layer pixels.
+    let scale: ScaleFactor<f32, LayerPixel, DevicePixel> = ScaleFactor::new(request.raster_scale);
+    let tile_rect = TypedRect::from_untyped(&(request.page_rect / scale.get()));
+    rasterizer.rasterize(layer, &tile_rect, scale, &mut surface);
+    surface.mark_wont_leak();
+
+    Ok(Box::new(LayerBuffer {
+        native_surface: surface,
+        rect: tile_rect.to_untyped(),
+        screen_pos: request.screen_rect,
+        resolution: scale.get(),
+        painted_with_cpu: rasterizer.paints_with_cpu(),
+        content_age: request.content_age,
+    }))
+}
+
+/// Identifies the layer a `PaintRequest` was made for, when its reply comes back.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PaintRequestId(pub u64);
+
+/// The buffer requests of one layer, in the form Servo's paint task takes them: they are painted
+/// on the paint task's own thread, which answers with a `PaintReply`.
+pub struct PaintRequest {
+    pub id: PaintRequestId,
+    pub buffer_requests: Vec<BufferRequest>,
+
+    /// The scale the buffers are painted at: the scene scale, magnified for layers that their
+    /// transforms or the pinch zoom scale up on screen. See `Layer::tile_scale`.
+    pub scale: f32,
+
+    /// The update of the layer tree the layer was painted for.
+    pub epoch: Option<Epoch>,
+}
+
+/// The buffers painted for a `PaintRequest`, with its id and epoch. Requests that the paint task
+/// couldn't paint are left out, and are requested again once their layer is next walked.
+pub struct PaintReply {
+    pub id: PaintRequestId,
+    pub buffers: Vec<Box<LayerBuffer>>,
+    pub epoch: Option<Epoch>,
+}
+
+/// Drives a paint task on another thread from the buffer request machinery, in place of a
+/// `TileRasterizer`. Requests are sent as the scene needs them and the painted buffers are
+/// handed to their layers as the replies come back, without blocking compositing.
+pub struct PaintTaskAdapter<T> {
+    sender: Sender<PaintRequest>,
+    receiver: Receiver<PaintReply>,
+    next_id: u64,
+
+    /// The layers whose requests haven't been answered yet.
+    in_flight: HashMap<PaintRequestId, Rc<Layer<T>>>,
+}
+
+impl<T> PaintTaskAdapter<T> {
+    /// Creates an adapter that sends requests to the paint task through `sender` and receives
+    /// its replies through `receiver`.
+    pub fn new(sender: Sender<PaintRequest>, receiver: Receiver<PaintReply>)
+               -> PaintTaskAdapter<T> {
+        PaintTaskAdapter {
+            sender: sender,
+            receiver: receiver,
+            next_id: 0,
+            in_flight: HashMap::new(),
+        }
+    }
+
+    /// Sends the paint task the buffer requests for every tile of the scene that needs painting,
+    /// and destroys the buffers that the scene no longer needs. Returns false if the paint task
+    /// is gone, in which case the requests are cancelled.
+    pub fn send_requests(&mut self, scene: &mut Scene<T>, display: &NativeDisplay) -> bool {
+        let mut layers_and_requests = Vec::new();
+        let mut unused_buffers = Vec::new();
+        scene.get_buffer_requests(&mut layers_and_requests, &mut unused_buffers);
+
+        for buffer in unused_buffers {
+            buffer.destroy(display);
+        }
+
+        let mut connected = true;
+        for (layer, requests) in layers_and_requests {
+            if !connected {
+                for request in &requests {
+                    layer.cancel_buffer_request(request);
+                }
+                continue;
+            }
+
+            let id = PaintRequestId(self.next_id);
+            self.next_id += 1;
+            let request = PaintRequest {
+                id: id,
+                buffer_requests: requests,
+                scale: layer.tile_scale(scene.scale).get(),
+                epoch: *layer.epoch.borrow(),
+            };
+            match self.sender.send(request) {
+                Ok(()) => {
+                    self.in_flight.insert(id, layer);
+                }
+                Err(error) => {
+                    warn!("The paint task is gone; cancelling the buffer requests of {}",
+                          layer.debug_name());
+                    for request in &error.0.buffer_requests {
+                        layer.cancel_buffer_request(request);
+                    }
+                    connected = false;
+                }
+            }
+        }
+        connected
+    }
+
+    /// Hands the buffers painted so far to their layers, without waiting for the paint task,
+    /// and destroys those that are rejected. Returns true if any were received, for the
+    /// embedder to composite a new frame.
+    pub fn receive_replies(&mut self, display: &NativeDisplay) -> bool {
+        let mut received = false;
+        loop {
+            let reply = match self.receiver.try_recv() {
+                Ok(reply) => reply,
+                Err(_) => return received,
+            };
+            received = true;
+            let rejected_buffers = match self.in_flight.remove(&reply.id) {
+                Some(layer) => layer.assign_painted_buffers(reply.buffers, reply.epoch),
+                None => {
+                    warn!("Received buffers for an unknown paint request {:?}", reply.id);
+                    reply.buffers
+                }
+            };
+            for buffer in rejected_buffers {
+                buffer.destroy(display);
+            }
+        }
+    }
+}
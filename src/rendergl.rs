@@ -7,23 +7,49 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use capabilities::GlCapabilities;
 use color::Color;
-use layers::Layer;
-use scene::Scene;
-use texturegl::Texture;
-use texturegl::Flip::VerticalFlip;
+use debugfont;
+use delays::{self, SimulatedDelays};
+use frameconstants::{self, FrameConstants, FrameUniforms};
+use geometry::{ClipSpace, DevicePixel, LayerPixel, TypedTransform};
+use glcapture;
+use layers::{BackdropFilter, Border, BorderRadii, BoxShadow, Layer, TransformState};
+use layers::walk_subtree;
+use multisample::{self, MultisampleTarget};
+use overlay::OverlayItem;
+use overrides::PresentOverrides;
+use profiler;
+use rastercache;
+use sampler::{BoundSampler, Samplers};
+use scene::{PixelSnapping, PunchedHole, Scene, TilePlaceholder};
+use stats::FrameStats;
+use texturegl::{self, FilterMode, Swizzle, Texture, TextureTarget, WrapMode};
+use texturegl::Orientation;
 use texturegl::TextureTarget::{TextureTarget2D, TextureTargetRectangle};
+use texturelayer::TextureLayer;
 use tiling::Tile;
 use platform::surface::NativeDisplay;
+use util::{TransformKind, classify_transform, project_rect_to_screen, transform_scale};
+use validate;
+use video::{VideoFrame, YuvFormat};
 
 use euclid::{Matrix4D, Point2D, Rect, Size2D};
-use libc::c_int;
-use gleam::gl;
+use euclid::point::TypedPoint2D;
+use euclid::rect::TypedRect;
+use euclid::scale_factor::ScaleFactor;
+use euclid::size::TypedSize2D;
+use libc::{c_char, c_int};
+use glcapture as gl;
 use gleam::gl::{GLenum, GLfloat, GLint, GLsizei, GLuint};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::fmt;
 use std::mem;
 use std::rc::Rc;
-use std::cmp::Ordering;
+use std::time::{Duration, Instant};
+use std::cmp::{self, Ordering};
 
 #[derive(Copy, Clone, Debug)]
 pub struct ColorVertex {
@@ -68,10 +94,160 @@ impl TextureVertex {
 const ORTHO_NEAR_PLANE: f32 = -1000000.0;
 const ORTHO_FAR_PLANE: f32 = 1000000.0;
 
-fn create_ortho(scene_size: &Size2D<f32>) -> Matrix4D<f32> {
-    Matrix4D::ortho(0.0, scene_size.width, scene_size.height, 0.0, ORTHO_NEAR_PLANE, ORTHO_FAR_PLANE)
+/// The projection from device pixels of a viewport of the given size to clip space.
+fn create_ortho(scene_size: &TypedSize2D<f32, DevicePixel>, y_axis_up: bool, mirrored: bool)
+                -> TypedTransform<DevicePixel, ClipSpace> {
+    let scene_size = scene_size.to_untyped();
+    let (left, right) = if mirrored {
+        (scene_size.width, 0.0)
+    } else {
+        (0.0, scene_size.width)
+    };
+    let (bottom, top) = if y_axis_up {
+        (0.0, scene_size.height)
+    } else {
+        (scene_size.height, 0.0)
+    };
+    TypedTransform::from_untyped(&Matrix4D::ortho(left, right, bottom, top,
+                                                  ORTHO_NEAR_PLANE, ORTHO_FAR_PLANE))
 }
 
+/// The transform from layer pixels to device pixels at the given scene scale, which the
+/// transforms of layers are applied before.
+fn scene_transform(scale: ScaleFactor<f32, LayerPixel, DevicePixel>)
+                   -> TypedTransform<LayerPixel, DevicePixel> {
+    TypedTransform::from_untyped(&Matrix4D::identity().pre_scaled(scale.get(), scale.get(), 1.0))
+}
+
+/// The transform from the world coordinates of `layer` to device pixels that `render_scene`
+/// draws it with, at the scene scale and snapped as the scene asks. The transform state of the
+/// layer must be up to date.
+pub fn layer_device_transform<T>(scene: &Scene<T>, layer: &Layer<T>)
+                                 -> TypedTransform<LayerPixel, DevicePixel> {
+    snapped_layer_transform(scene.pixel_snapping,
+                            &layer.transform_state.borrow(),
+                            &scene_transform(scene.scale))
+}
+
+// Translate the shaders, which are written in GLSL 1.10 and GLSL ES 1.00, for core profiles,
+// whose GLSL has no attribute and varying qualifiers, texture2D functions or gl_FragColor.
+// They follow the `#version` directive.
+static CORE_PROFILE_VERTEX_SHADER_HEADER: &'static str = "
+    #define attribute in
+    #define varying out
+";
+
+static CORE_PROFILE_FRAGMENT_SHADER_HEADER: &'static str = "
+    #ifdef GL_ES
+        precision mediump float;
+    #endif
+
+    #define varying in
+    #define texture2D texture
+    #define texture2DRect texture
+    out vec4 oFragColor;
+    #define gl_FragColor oFragColor
+";
+
+// The rounded clip test shared by the programs that support it, prepended to their fragment
+// shaders. Each clip rect and the fragment position in it are in the coordinates of its
+// clipping layer. The outer clip is that of an ancestor of the clipping layer, and is only
+// tested if its scale isn't zero.
+static ROUNDED_CLIP_FRAGMENT_SHADER_SOURCE: &'static str = "
+    #ifdef GL_ES
+        precision mediump float;
+    #endif
+
+    uniform vec4 uClipRect;
+    uniform vec4 uClipRadiiTop;
+    uniform vec4 uClipRadiiBottom;
+    uniform float uClipScale;
+    varying vec4 vClipPosition;
+
+    uniform vec4 uOuterClipRect;
+    uniform vec4 uOuterClipRadiiTop;
+    uniform vec4 uOuterClipRadiiBottom;
+    uniform float uOuterClipScale;
+    varying vec4 vOuterClipPosition;
+
+    // The distance of a point inside an ellipse from its edge, to a first-order
+    // approximation. It is negative outside.
+    float ellipseDistance(vec2 point, vec2 center, vec2 radii) {
+        vec2 lOffset = (point - center) / radii;
+        float lGradient = 2.0 * length(lOffset / radii);
+        return (1.0 - dot(lOffset, lOffset)) / max(lGradient, 0.0001);
+    }
+
+    // The coverage of the fragment at `position` by a rounded rect, ramping over a device
+    // pixel. The radii of a corner are either both positive or both zero.
+    float roundedRectCoverage(vec4 position,
+                              vec4 rect,
+                              vec4 radiiTop,
+                              vec4 radiiBottom,
+                              float scale) {
+        vec2 lPosition = position.xy / position.w;
+        vec2 lInside = min(lPosition - rect.xy, rect.zw - lPosition);
+        float lDistance = min(lInside.x, lInside.y);
+        vec2 lTopLeft = rect.xy + radiiTop.xy;
+        vec2 lTopRight = vec2(rect.z - radiiTop.z, rect.y + radiiTop.w);
+        vec2 lBottomRight = rect.zw - radiiBottom.xy;
+        vec2 lBottomLeft = vec2(rect.x + radiiBottom.z, rect.w - radiiBottom.w);
+        if (lPosition.x < lTopLeft.x && lPosition.y < lTopLeft.y && radiiTop.x > 0.0) {
+            lDistance = ellipseDistance(lPosition, lTopLeft, radiiTop.xy);
+        } else if (lPosition.x > lTopRight.x && lPosition.y < lTopRight.y &&
+                radiiTop.z > 0.0) {
+            lDistance = ellipseDistance(lPosition, lTopRight, radiiTop.zw);
+        } else if (lPosition.x > lBottomRight.x && lPosition.y > lBottomRight.y &&
+                radiiBottom.x > 0.0) {
+            lDistance = ellipseDistance(lPosition, lBottomRight, radiiBottom.xy);
+        } else if (lPosition.x < lBottomLeft.x && lPosition.y > lBottomLeft.y &&
+                radiiBottom.z > 0.0) {
+            lDistance = ellipseDistance(lPosition, lBottomLeft, radiiBottom.zw);
+        }
+        return clamp(lDistance * scale + 0.5, 0.0, 1.0);
+    }
+
+    // The coverage of the fragment by the rounded clip, and by the outer one if any.
+    float clipCoverage() {
+        float lCoverage = roundedRectCoverage(vClipPosition,
+                                              uClipRect,
+                                              uClipRadiiTop,
+                                              uClipRadiiBottom,
+                                              uClipScale);
+        if (uOuterClipScale > 0.0) {
+            lCoverage *= roundedRectCoverage(vOuterClipPosition,
+                                             uOuterClipRect,
+                                             uOuterClipRadiiTop,
+                                             uOuterClipRadiiBottom,
+                                             uOuterClipScale);
+        }
+        return lCoverage;
+    }
+";
+
+// The edge antialiasing shared by the programs that draw layer content, prepended to their
+// fragment shaders. The edge rect and the fragment position are in layer coordinates.
+static EDGE_COVERAGE_FRAGMENT_SHADER_SOURCE: &'static str = "
+    #ifdef GL_ES
+        precision mediump float;
+    #endif
+
+    uniform vec4 uEdgeRect;
+    uniform vec2 uEdgeScale;
+    varying vec2 vLayerPosition;
+
+    // The coverage of the fragment by the edge rect, ramping over a device pixel. Edges are
+    // only antialiased when the scale is set.
+    float edgeCoverage() {
+        if (uEdgeScale.x == 0.0) {
+            return 1.0;
+        }
+        vec2 lInside = min(vLayerPosition - uEdgeRect.xy, uEdgeRect.zw - vLayerPosition);
+        lInside *= uEdgeScale;
+        return clamp(min(lInside.x, lInside.y) + 0.5, 0.0, 1.0);
+    }
+";
+
 static TEXTURE_FRAGMENT_SHADER_SOURCE: &'static str = "
     #ifdef GL_ES
         precision mediump float;
@@ -79,10 +255,62 @@ static TEXTURE_FRAGMENT_SHADER_SOURCE: &'static str = "
 
     varying vec2 vTextureCoord;
     uniform samplerType uSampler;
-    uniform float uOpacity;
+
+    #ifdef USE_NEAREST_FILTER
+        // The size of the texture, or 1 for rectangle textures, which are sampled in texels.
+        uniform vec2 uTextureSize;
+    #endif
+
+    // Video frames are converted from YUV, with their Y plane as the content texture. The
+    // chroma of NV12 frames is interleaved in a single texture.
+    #ifdef USE_YUV
+        uniform sampler2D uUSampler;
+        #ifndef USE_NV12
+            uniform sampler2D uVSampler;
+        #endif
+        uniform mat4 uColorMatrix;
+    #endif
+
+    #ifdef USE_OPACITY
+        uniform float uOpacity;
+    #endif
+
+    #ifdef USE_MASK
+        varying vec2 vMaskCoord;
+        uniform maskSamplerType uMaskSampler;
+    #endif
 
     void main(void) {
-        vec4 lFragColor = uOpacity * samplerFunction(uSampler, vTextureCoord);
+    #ifdef USE_NEAREST_FILTER
+        vec2 lTextureCoord = (floor(vTextureCoord * uTextureSize) + 0.5) / uTextureSize;
+    #else
+        vec2 lTextureCoord = vTextureCoord;
+    #endif
+    #ifdef USE_YUV
+        float lY = samplerFunction(uSampler, lTextureCoord).r;
+    #ifdef USE_NV12
+        vec2 lUv = texture2D(uUSampler, lTextureCoord).rg;
+    #else
+        vec2 lUv = vec2(texture2D(uUSampler, lTextureCoord).r,
+                        texture2D(uVSampler, lTextureCoord).r);
+    #endif
+        vec4 lTexel = vec4(clamp((uColorMatrix * vec4(lY, lUv, 1.0)).rgb, 0.0, 1.0), 1.0);
+    #else
+        vec4 lTexel = samplerFunction(uSampler, lTextureCoord);
+    #endif
+    #ifdef USE_SWIZZLE
+        lTexel = lTexel.bgra;
+    #endif
+        vec4 lFragColor = edgeCoverage() * lTexel;
+    #ifdef USE_OPACITY
+        lFragColor *= uOpacity;
+    #endif
+    #ifdef USE_MASK
+        lFragColor *= maskSamplerFunction(uMaskSampler, vMaskCoord).a;
+    #endif
+    #ifdef USE_ROUNDED_CLIP
+        lFragColor *= clipCoverage();
+    #endif
         gl_FragColor = lFragColor;
     }
 ";
@@ -93,8 +321,13 @@ static SOLID_COLOR_FRAGMENT_SHADER_SOURCE: &'static str = "
     #endif
 
     uniform vec4 uColor;
+
     void main(void) {
-        gl_FragColor = uColor;
+    #ifdef USE_ROUNDED_CLIP
+        gl_FragColor = uColor * edgeCoverage() * clipCoverage();
+    #else
+        gl_FragColor = uColor * edgeCoverage();
+    #endif
     }
 ";
 
@@ -103,14 +336,34 @@ static TEXTURE_VERTEX_SHADER_SOURCE: &'static str = "
     attribute vec2 aVertexUv;
 
     uniform mat4 uMVMatrix;
-    uniform mat4 uPMatrix;
     uniform mat4 uTextureSpaceTransform;
 
     varying vec2 vTextureCoord;
+    varying vec2 vLayerPosition;
+
+    #ifdef USE_MASK
+        uniform mat4 uMaskSpaceTransform;
+        varying vec2 vMaskCoord;
+    #endif
+
+    #ifdef USE_ROUNDED_CLIP
+        uniform mat4 uClipTransform;
+        uniform mat4 uOuterClipTransform;
+        varying vec4 vClipPosition;
+        varying vec4 vOuterClipPosition;
+    #endif
 
     void main(void) {
         gl_Position = uPMatrix * uMVMatrix * vec4(aVertexPosition, 0.0, 1.0);
         vTextureCoord = (uTextureSpaceTransform * vec4(aVertexUv, 0., 1.)).xy;
+        vLayerPosition = aVertexPosition;
+    #ifdef USE_MASK
+        vMaskCoord = (uMaskSpaceTransform * vec4(aVertexPosition, 0., 1.)).xy;
+    #endif
+    #ifdef USE_ROUNDED_CLIP
+        vClipPosition = uClipTransform * vec4(aVertexPosition, 0., 1.);
+        vOuterClipPosition = uOuterClipTransform * vec4(aVertexPosition, 0., 1.);
+    #endif
     }
 ";
 
@@ -118,36 +371,248 @@ static SOLID_COLOR_VERTEX_SHADER_SOURCE: &'static str = "
     attribute vec2 aVertexPosition;
 
     uniform mat4 uMVMatrix;
-    uniform mat4 uPMatrix;
+
+    varying vec2 vLayerPosition;
+
+    #ifdef USE_MASK
+        uniform mat4 uMaskSpaceTransform;
+        varying vec2 vMaskCoord;
+    #endif
+
+    #ifdef USE_ROUNDED_CLIP
+        uniform mat4 uClipTransform;
+        uniform mat4 uOuterClipTransform;
+        varying vec4 vClipPosition;
+        varying vec4 vOuterClipPosition;
+    #endif
+
+    void main(void) {
+        gl_Position = uPMatrix * uMVMatrix * vec4(aVertexPosition, 0.0, 1.0);
+        vLayerPosition = aVertexPosition;
+    #ifdef USE_MASK
+        vMaskCoord = (uMaskSpaceTransform * vec4(aVertexPosition, 0., 1.)).xy;
+    #endif
+    #ifdef USE_ROUNDED_CLIP
+        vClipPosition = uClipTransform * vec4(aVertexPosition, 0., 1.);
+        vOuterClipPosition = uOuterClipTransform * vec4(aVertexPosition, 0., 1.);
+    #endif
+    }
+";
+
+static BOX_SHADOW_VERTEX_SHADER_SOURCE: &'static str = "
+    attribute vec2 aVertexPosition;
+
+    uniform mat4 uMVMatrix;
+
+    varying vec2 vPosition;
 
     void main(void) {
         gl_Position = uPMatrix * uMVMatrix * vec4(aVertexPosition, 0.0, 1.0);
+        vPosition = aVertexPosition;
+    }
+";
+
+// The shadow of a box blurred with a gaussian is the product of the integrals of the gaussian
+// along each axis, which can be evaluated in closed form with the error function.
+static BOX_SHADOW_FRAGMENT_SHADER_SOURCE: &'static str = "
+    #ifdef GL_ES
+        precision mediump float;
+    #endif
+
+    uniform vec4 uColor;
+    uniform vec4 uBox;
+    uniform float uSigma;
+
+    varying vec2 vPosition;
+
+    vec4 erf(vec4 x) {
+        vec4 s = sign(x);
+        vec4 a = abs(x);
+        x = 1.0 + (0.278393 + (0.230389 + 0.078108 * (a * a)) * a) * a;
+        x *= x;
+        return s - s / (x * x);
+    }
+
+    void main(void) {
+        vec4 query = vec4(vPosition - uBox.xy, vPosition - uBox.zw);
+        vec4 integral = 0.5 + 0.5 * erf(query * (sqrt(0.5) / uSigma));
+        float alpha = (integral.z - integral.x) * (integral.w - integral.y);
+        gl_FragColor = uColor * alpha;
+    }
+";
+
+// One pass of the separable gaussian blur of a snapshot of the framebuffer behind a layer. The
+// texel of the source texture under a fragment is its offset from `uDestOrigin` in window
+// coordinates, times `uSourceScale`, and `uTaps` texels are sampled on each side along
+// `uDirection`. Samples are clamped to the `uSourceSize` texels of the texture that hold the
+// snapshot, which may be larger.
+static BACKDROP_BLUR_FRAGMENT_SHADER_SOURCE: &'static str = "
+    #ifdef GL_ES
+        precision mediump float;
+    #endif
+
+    uniform sampler2D uSampler;
+    uniform vec2 uDestOrigin;
+    uniform float uSourceScale;
+    uniform vec2 uSourceSize;
+    uniform vec2 uTextureSize;
+    uniform vec2 uDirection;
+    uniform float uSigma;
+    uniform float uTaps;
+    uniform float uOpacity;
+
+    vec4 sampleSource(vec2 position) {
+        position = clamp(position, vec2(0.5), uSourceSize - 0.5);
+        return texture2D(uSampler, position / uTextureSize);
+    }
+
+    void main(void) {
+        vec2 lCenter = (gl_FragCoord.xy - uDestOrigin) * uSourceScale;
+        vec4 lSum = sampleSource(lCenter);
+        float lWeights = 1.0;
+        for (int i = 1; i <= 32; i++) {
+            float lOffset = float(i);
+            if (lOffset > uTaps) {
+                break;
+            }
+            float lWeight = exp(-0.5 * lOffset * lOffset / (uSigma * uSigma));
+            lSum += lWeight * (sampleSource(lCenter - lOffset * uDirection) +
+                               sampleSource(lCenter + lOffset * uDirection));
+            lWeights += 2.0 * lWeight;
+        }
+        gl_FragColor = uOpacity * lSum / lWeights;
+    }
+";
+
+// Shrinks a snapshot of the framebuffer behind a layer by `uDownsample`, averaging each block of
+// texels, before blurs too wide for the taps of a blur pass.
+static BACKDROP_DOWNSAMPLE_FRAGMENT_SHADER_SOURCE: &'static str = "
+    #ifdef GL_ES
+        precision mediump float;
+    #endif
+
+    uniform sampler2D uSampler;
+    uniform vec2 uSourceSize;
+    uniform vec2 uTextureSize;
+    uniform float uDownsample;
+
+    void main(void) {
+        vec2 lOrigin = floor(gl_FragCoord.xy) * uDownsample;
+        vec4 lSum = vec4(0.0);
+        float lCount = 0.0;
+        for (int x = 0; x < 16; x++) {
+            if (float(x) >= uDownsample) {
+                break;
+            }
+            for (int y = 0; y < 16; y++) {
+                if (float(y) >= uDownsample) {
+                    break;
+                }
+                vec2 lPosition = lOrigin + vec2(float(x), float(y)) + 0.5;
+                lPosition = clamp(lPosition, vec2(0.5), uSourceSize - 0.5);
+                lSum += texture2D(uSampler, lPosition / uTextureSize);
+                lCount += 1.0;
+            }
+        }
+        gl_FragColor = lSum / lCount;
+    }
+";
+
+// Glyphs are drawn from a white atlas, tinted with the text color.
+static GLYPH_FRAGMENT_SHADER_SOURCE: &'static str = "
+    #ifdef GL_ES
+        precision mediump float;
+    #endif
+
+    varying vec2 vTextureCoord;
+    uniform sampler2D uSampler;
+    uniform vec4 uColor;
+
+    void main(void) {
+        gl_FragColor = uColor * texture2D(uSampler, vTextureCoord).a;
     }
 ";
 
+static CHECKERBOARD_FRAGMENT_SHADER_SOURCE: &'static str = "
+    #ifdef GL_ES
+        precision mediump float;
+    #endif
+
+    uniform vec4 uColor0;
+    uniform vec4 uColor1;
+    uniform float uSquareSize;
+
+    #ifdef USE_MASK
+        varying vec2 vMaskCoord;
+        uniform maskSamplerType uMaskSampler;
+    #endif
+
+    void main(void) {
+        vec2 square = floor(gl_FragCoord.xy / uSquareSize);
+        vec4 lFragColor = mix(uColor0, uColor1, mod(square.x + square.y, 2.0));
+    #ifdef USE_MASK
+        lFragColor *= maskSamplerFunction(uMaskSampler, vMaskCoord).a;
+    #endif
+        gl_FragColor = lFragColor;
+    }
+";
+
+/// Tiles uploaded this long ago, or longer, are drawn fully red in the tile age heatmap.
+static TILE_AGE_HEATMAP_STALE_SECONDS: f32 = 5.0;
+static TILE_AGE_HEATMAP_ALPHA: f32 = 0.3;
+
 static TILE_DEBUG_BORDER_COLOR: Color = Color { r: 0., g: 1., b: 1., a: 1.0 };
 static TILE_DEBUG_BORDER_THICKNESS: usize = 1;
 static LAYER_DEBUG_BORDER_COLOR: Color = Color { r: 1., g: 0.5, b: 0., a: 1.0 };
 static LAYER_DEBUG_BORDER_THICKNESS: usize = 2;
 static LAYER_AABB_DEBUG_BORDER_COLOR: Color = Color { r: 1., g: 0.0, b: 0., a: 1.0 };
 static LAYER_AABB_DEBUG_BORDER_THICKNESS: usize = 1;
+static LAYER_DEBUG_LABEL_COLOR: Color = Color { r: 1., g: 1., b: 1., a: 1.0 };
+static LAYER_DEBUG_LABEL_BACKGROUND_COLOR: Color = Color { r: 0., g: 0., b: 0., a: 0.6 };
+static LAYER_DEBUG_LABEL_PIXEL_SIZE: f32 = 2.0;
+static LAYER_DEBUG_LABEL_PADDING: f32 = 2.0;
+
+/// The color added for each layer drawn over a pixel in the overdraw view, so that pixels drawn
+/// ten times are fully red.
+static OVERDRAW_COLOR: Color = Color { r: 0.1, g: 0.02, b: 0., a: 0.1 };
+
+/// The color holes are drawn with. Only its alpha matters: it is how much of what was drawn
+/// before is erased.
+static HOLE_COLOR: Color = Color { r: 0., g: 0., b: 0., a: 1. };
 
-#[derive(Copy, Clone)]
 struct Buffers {
     quad_vertex_buffer: GLuint,
     line_quad_vertex_buffer: GLuint,
+
+    /// The vertex array object that the attributes of all programs are set up in, which core
+    /// profiles require, or `None` for other contexts.
+    vertex_array: Option<GLuint>,
+
+    /// The projection and the other constants shared by all programs.
+    frame_constants: FrameConstants,
 }
 
 #[derive(Copy, Clone)]
 struct ShaderProgram {
     id: GLuint,
+
+    /// Where the program takes the frame constants from, which its vertex shader declares
+    /// through the preamble.
+    frame_uniforms: FrameUniforms,
 }
 
 impl ShaderProgram {
     pub fn new(vertex_shader_source: &str, fragment_shader_source: &str) -> ShaderProgram {
+        let vertex_source = format!("{}{}{}",
+                                    ShaderProgram::header(gl::VERTEX_SHADER),
+                                    frameconstants::vertex_shader_preamble(),
+                                    vertex_shader_source);
+        let fragment_source = format!("{}{}",
+                                      ShaderProgram::header(gl::FRAGMENT_SHADER),
+                                      fragment_shader_source);
         let id = gl::create_program();
-        gl::attach_shader(id, ShaderProgram::compile_shader(fragment_shader_source, gl::FRAGMENT_SHADER));
-        gl::attach_shader(id, ShaderProgram::compile_shader(vertex_shader_source, gl::VERTEX_SHADER));
+        gl::attach_shader(id, ShaderProgram::compile_shader(&fragment_source, gl::FRAGMENT_SHADER));
+        gl::attach_shader(id, ShaderProgram::compile_shader(&vertex_source, gl::VERTEX_SHADER));
         gl::link_program(id);
         if gl::get_program_iv(id, gl::LINK_STATUS) == (0 as GLint) {
             panic!("Failed to compile shader program: {}", gl::get_program_info_log(id));
@@ -155,9 +620,26 @@ impl ShaderProgram {
 
         ShaderProgram {
             id: id,
+            frame_uniforms: FrameUniforms::new(id),
         }
     }
 
+    /// What goes before the source of a shader of the given type: the `#version` directive
+    /// and the translation of the shader for core profiles, or nothing for other contexts.
+    fn header(shader_type: GLenum) -> String {
+        let capabilities = GlCapabilities::current();
+        if !capabilities.core_profile {
+            return String::new();
+        }
+        let version = if capabilities.is_gles { "300 es" } else { "150" };
+        let translation = if shader_type == gl::VERTEX_SHADER {
+            CORE_PROFILE_VERTEX_SHADER_HEADER
+        } else {
+            CORE_PROFILE_FRAGMENT_SHADER_HEADER
+        };
+        format!("#version {}\n{}", version, translation)
+    }
+
     pub fn compile_shader(source_string: &str, shader_type: GLenum) -> GLuint {
         let id = gl::create_shader(shader_type);
         gl::shader_source(id, &[ source_string.as_bytes() ]);
@@ -184,437 +666,2278 @@ struct TextureProgram {
     vertex_position_attr: c_int,
     vertex_uv_attr: c_int,
     modelview_uniform: c_int,
-    projection_uniform: c_int,
     sampler_uniform: c_int,
     texture_space_transform_uniform: c_int,
     opacity_uniform: c_int,
-}
 
-impl TextureProgram {
-    fn new(sampler_function: &str, sampler_type: &str) -> TextureProgram {
-        let fragment_shader_source
-             = fmt::format(format_args!("#define samplerFunction {}\n#define samplerType {}\n{}",
-                                        sampler_function,
-                                        sampler_type,
-                                        TEXTURE_FRAGMENT_SHADER_SOURCE));
-        let program = ShaderProgram::new(TEXTURE_VERTEX_SHADER_SOURCE, &fragment_shader_source);
-        TextureProgram {
-            program: program,
-            vertex_position_attr: program.get_attribute_location("aVertexPosition"),
-            vertex_uv_attr: program.get_attribute_location("aVertexUv"),
-            modelview_uniform: program.get_uniform_location("uMVMatrix"),
-            projection_uniform: program.get_uniform_location("uPMatrix"),
-            sampler_uniform: program.get_uniform_location("uSampler"),
-            texture_space_transform_uniform: program.get_uniform_location("uTextureSpaceTransform"),
-            opacity_uniform: program.get_uniform_location("uOpacity"),
-        }
-    }
+    /// The size of the texture, for the variant that filters to the nearest texel itself.
+    texture_size_uniform: c_int,
 
-    fn bind_uniforms_and_attributes(&self,
-                                    vertices: &[TextureVertex; 4],
-                                    transform: &Matrix4D<f32>,
-                                    projection_matrix: &Matrix4D<f32>,
-                                    texture_space_transform: &Matrix4D<f32>,
-                                    buffers: &Buffers,
-                                    opacity: f32) {
-        gl::uniform_1i(self.sampler_uniform, 0);
-        gl::uniform_matrix_4fv(self.modelview_uniform,
-                               false,
-                               &transform.to_row_major_array());
-        gl::uniform_matrix_4fv(self.projection_uniform,
-                               false,
-                               &projection_matrix.to_row_major_array());
+    /// The samplers of the U and V planes, or of the interleaved chroma and -1, and the matrix
+    /// converting YUV to RGB, for the video variants of the program.
+    chroma_sampler_uniforms: [c_int; 2],
+    color_matrix_uniform: c_int,
 
-        let vertex_size = mem::size_of::<TextureVertex>();
+    /// The mask sampler and the transform from layer coordinates to mask texture coordinates,
+    /// for the masked variant of the program. Both are -1 otherwise.
+    mask_sampler_uniform: c_int,
+    mask_space_transform_uniform: c_int,
 
-        gl::bind_buffer(gl::ARRAY_BUFFER, buffers.quad_vertex_buffer);
-        gl::buffer_data(gl::ARRAY_BUFFER, vertices, gl::DYNAMIC_DRAW);
-        gl::vertex_attrib_pointer_f32(self.vertex_position_attr as GLuint,2, false, vertex_size as i32, 0);
-        gl::vertex_attrib_pointer_f32(self.vertex_uv_attr as GLuint, 2, false, vertex_size as i32, 8);
+    edge_rect_uniform: c_int,
+    edge_scale_uniform: c_int,
 
-        gl::uniform_matrix_4fv(self.texture_space_transform_uniform,
-                               false,
-                               &texture_space_transform.to_row_major_array());
+    /// The rounded clip, for the clipped variant of the program.
+    rounded_clip_uniforms: RoundedClipUniforms,
+}
 
-        gl::uniform_1f(self.opacity_uniform, opacity);
-    }
+/// The texture unit of the content texture.
+const CONTENT_TEXTURE_UNIT: GLuint = 0;
 
-    fn enable_attribute_arrays(&self) {
-        gl::enable_vertex_attrib_array(self.vertex_position_attr as GLuint);
-        gl::enable_vertex_attrib_array(self.vertex_uv_attr as GLuint);
-    }
+/// The texture unit of layer masks.
+const MASK_TEXTURE_UNIT: GLuint = 1;
 
-    fn disable_attribute_arrays(&self) {
-        gl::disable_vertex_attrib_array(self.vertex_uv_attr as GLuint);
-        gl::disable_vertex_attrib_array(self.vertex_position_attr as GLuint);
+/// The texture units of the U and V planes of video frames, or of their interleaved chroma.
+/// The Y plane goes to the content texture unit, and the mask keeps its own unit.
+const CHROMA_TEXTURE_UNITS: [GLuint; 2] = [2, 3];
+
+/// A mask applied to a textured quad: a texture whose alpha channel multiplies the content, and
+/// the transform from layer coordinates to its texture coordinates.
+struct QuadMask<'a> {
+    texture: &'a Texture,
+    transform: Matrix4D<f32>,
+}
+
+impl<'a> QuadMask<'a> {
+    /// The mask `texture` stretched over `rect`, in layer coordinates, or `None` if either is
+    /// empty, in which case nothing shows through the mask.
+    fn new(texture: &'a Texture, rect: &Rect<f32>) -> Option<QuadMask<'a>> {
+        if rect.is_empty() || texture.size.width == 0 || texture.size.height == 0 {
+            return None;
+        }
+        // Rectangle textures are addressed in texels rather than in [0, 1].
+        let texture_size = match texture.target {
+            TextureTargetRectangle => {
+                Size2D::new(texture.size.width as f32, texture.size.height as f32)
+            }
+            _ => Size2D::new(1.0, 1.0),
+        };
+        Some(QuadMask {
+            texture: texture,
+            transform: Matrix4D::identity()
+                .pre_scaled(texture_size.width / rect.size.width,
+                            texture_size.height / rect.size.height,
+                            1.0)
+                .pre_translated(-rect.origin.x, -rect.origin.y, 0.0),
+        })
     }
 
-    fn create_2d_program() -> TextureProgram {
-        TextureProgram::new("texture2D", "sampler2D")
+    /// The `TEXTURE_PROGRAM_*` features of the programs that draw with this mask.
+    fn features(&self) -> u32 {
+        match self.texture.target {
+            TextureTargetRectangle => TEXTURE_PROGRAM_MASK | TEXTURE_PROGRAM_MASK_RECTANGLE,
+            _ => TEXTURE_PROGRAM_MASK,
+        }
     }
+}
 
-    #[cfg(target_os="macos")]
-    fn create_rectangle_program_if_necessary() -> Option<TextureProgram> {
-        gl::enable(gl::TEXTURE_RECTANGLE_ARB);
-        Some(TextureProgram::new("texture2DRect", "sampler2DRect"))
+/// The defines that declare the mask sampler of a program with the given `TEXTURE_PROGRAM_*`
+/// features, if it is masked. Rectangle masks are enabled as for rectangle content.
+fn mask_shader_defines(features: u32) -> &'static str {
+    if features & TEXTURE_PROGRAM_MASK == 0 {
+        ""
+    } else if features & TEXTURE_PROGRAM_MASK_RECTANGLE != 0 {
+        TextureProgram::enable_rectangle_textures();
+        "#define USE_MASK\n\
+         #define maskSamplerFunction texture2DRect\n\
+         #define maskSamplerType sampler2DRect\n"
+    } else {
+        "#define USE_MASK\n\
+         #define maskSamplerFunction texture2D\n\
+         #define maskSamplerType sampler2D\n"
     }
+}
 
-    #[cfg(not(target_os="macos"))]
-    fn create_rectangle_program_if_necessary() -> Option<TextureProgram> {
-        None
+/// The size that the nearest filter variant of the texture program picks texels of `texture`
+/// by: its size, or 1 for rectangle textures, whose coordinates are already in texels.
+fn nearest_filter_size(texture: &Texture) -> Size2D<f32> {
+    match texture.target {
+        TextureTargetRectangle => Size2D::new(1.0, 1.0),
+        _ => Size2D::new(texture.size.width as f32, texture.size.height as f32),
     }
 }
 
-#[derive(Copy, Clone)]
-struct SolidColorProgram {
-    program: ShaderProgram,
-    vertex_position_attr: c_int,
-    modelview_uniform: c_int,
-    projection_uniform: c_int,
-    color_uniform: c_int,
+/// Feathering of the edges of a layer that isn't aligned to the device pixel grid. Quads on the
+/// edges are expanded by a device pixel, and their alpha ramps down across the edge in the
+/// fragment shader. For axis-aligned edges, the alpha is the fraction of the pixel covered.
+#[derive(Copy, Clone, PartialEq)]
+struct EdgeAntialiasing {
+    /// The rect whose edges are antialiased, in layer coordinates.
+    rect: Rect<f32>,
+
+    /// The number of device pixels per layer pixel, along each axis.
+    scale: (f32, f32),
 }
 
-impl SolidColorProgram {
-    fn new() -> SolidColorProgram {
-        let program = ShaderProgram::new(SOLID_COLOR_VERTEX_SHADER_SOURCE,
-                                         SOLID_COLOR_FRAGMENT_SHADER_SOURCE);
-        SolidColorProgram {
-            program: program,
-            vertex_position_attr: program.get_attribute_location("aVertexPosition"),
-            modelview_uniform: program.get_uniform_location("uMVMatrix"),
-            projection_uniform: program.get_uniform_location("uPMatrix"),
-            color_uniform: program.get_uniform_location("uColor"),
+impl EdgeAntialiasing {
+    /// Returns the antialiasing needed for `rect` drawn with `transform`, if it is rotated,
+    /// skewed or in perspective.
+    fn for_transform(rect: &Rect<f32>, transform: &Matrix4D<f32>) -> Option<EdgeAntialiasing> {
+        const EPSILON: f32 = 1.0e-5;
+        if classify_transform(transform).is_axis_aligned() {
+            return None;
         }
-    }
 
-    fn bind_uniforms_and_attributes_common(&self,
-                                           transform: &Matrix4D<f32>,
-                                           projection_matrix: &Matrix4D<f32>,
-                                           color: &Color) {
-        gl::uniform_matrix_4fv(self.modelview_uniform,
-                               false,
-                               &transform.to_row_major_array());
-        gl::uniform_matrix_4fv(self.projection_uniform,
-                               false,
-                               &projection_matrix.to_row_major_array());
-        gl::uniform_4f(self.color_uniform,
-                   color.r as GLfloat,
-                   color.g as GLfloat,
-                   color.b as GLfloat,
-                   color.a as GLfloat);
+        let (scale_x, scale_y) = transform_scale(transform);
+        if scale_x < EPSILON || scale_y < EPSILON {
+            return None;
+        }
+        Some(EdgeAntialiasing {
+            rect: *rect,
+            scale: (scale_x, scale_y),
+        })
     }
 
-    fn bind_uniforms_and_attributes_for_lines(&self,
-                                              vertices: &[ColorVertex; 5],
-                                              transform: &Matrix4D<f32>,
-                                              projection_matrix: &Matrix4D<f32>,
-                                              buffers: &Buffers,
-                                              color: &Color) {
-        self.bind_uniforms_and_attributes_common(transform, projection_matrix, color);
+    /// Returns the antialiasing needed for a clipped `rect` whose edges fall between device
+    /// pixels when drawn with the axis-aligned `transform`. Without it, the partially covered
+    /// pixels are either fully drawn or dropped depending on where their centers fall, which
+    /// makes clip edges shimmer while scrolling by fractional amounts.
+    fn for_fractional_clip(rect: &Rect<f32>, transform: &Matrix4D<f32>)
+                           -> Option<EdgeAntialiasing> {
+        const EPSILON: f32 = 1.0e-3;
+        let (scale_x, scale_y) = (transform.m11.abs(), transform.m22.abs());
+        if scale_x < EPSILON || scale_y < EPSILON {
+            return None;
+        }
 
-        gl::bind_buffer(gl::ARRAY_BUFFER, buffers.line_quad_vertex_buffer);
-        gl::buffer_data(gl::ARRAY_BUFFER, vertices, gl::DYNAMIC_DRAW);
-        gl::vertex_attrib_pointer_f32(self.vertex_position_attr as GLuint, 2, false, 0, 0);
+        let is_fractional = |value: f32| (value - value.round()).abs() > EPSILON;
+        let device_x = |x: f32| x * transform.m11 + transform.m41;
+        let device_y = |y: f32| y * transform.m22 + transform.m42;
+        if !is_fractional(device_x(rect.min_x())) && !is_fractional(device_x(rect.max_x())) &&
+           !is_fractional(device_y(rect.min_y())) && !is_fractional(device_y(rect.max_y())) {
+            return None;
+        }
+        Some(EdgeAntialiasing {
+            rect: *rect,
+            scale: (scale_x, scale_y),
+        })
     }
 
-    fn bind_uniforms_and_attributes_for_quad(&self,
-                                             vertices: &[ColorVertex; 4],
-                                             transform: &Matrix4D<f32>,
-                                             projection_matrix: &Matrix4D<f32>,
-                                             buffers: &Buffers,
-                                             color: &Color) {
-        self.bind_uniforms_and_attributes_common(transform, projection_matrix, color);
+    /// Expands the sides of `rect` that lie on the antialiased edges by a device pixel, to make
+    /// room for the feathering.
+    fn expand(&self, rect: &Rect<f32>) -> Rect<f32> {
+        const EPSILON: f32 = 1.0e-3;
+        let (dx, dy) = (1.0 / self.scale.0, 1.0 / self.scale.1);
+        let expand_side = |side: f32, edge: f32, delta: f32| {
+            if (side - edge).abs() < EPSILON { side + delta } else { side }
+        };
+        let min_x = expand_side(rect.min_x(), self.rect.min_x(), -dx);
+        let min_y = expand_side(rect.min_y(), self.rect.min_y(), -dy);
+        let max_x = expand_side(rect.max_x(), self.rect.max_x(), dx);
+        let max_y = expand_side(rect.max_y(), self.rect.max_y(), dy);
+        Rect::new(Point2D::new(min_x, min_y), Size2D::new(max_x - min_x, max_y - min_y))
+    }
+}
 
-        gl::bind_buffer(gl::ARRAY_BUFFER, buffers.quad_vertex_buffer);
-        gl::buffer_data(gl::ARRAY_BUFFER, vertices, gl::DYNAMIC_DRAW);
-        gl::vertex_attrib_pointer_f32(self.vertex_position_attr as GLuint, 2, false, 0, 0);
+/// Sets the edge antialiasing uniforms of a program. A zero scale disables antialiasing.
+fn bind_edge_antialiasing(edge_rect_uniform: c_int,
+                          edge_scale_uniform: c_int,
+                          edge_antialiasing: Option<&EdgeAntialiasing>) {
+    match edge_antialiasing {
+        Some(edge_antialiasing) => {
+            let rect = edge_antialiasing.rect;
+            let (scale_x, scale_y) = edge_antialiasing.scale;
+            gl::uniform_4f(edge_rect_uniform,
+                           rect.min_x(),
+                           rect.min_y(),
+                           rect.max_x(),
+                           rect.max_y());
+            gl::uniform_2f(edge_scale_uniform, scale_x, scale_y);
+        }
+        None => gl::uniform_2f(edge_scale_uniform, 0.0, 0.0),
     }
+}
 
-    fn enable_attribute_arrays(&self) {
-        gl::enable_vertex_attrib_array(self.vertex_position_attr as GLuint);
-    }
+/// The rounded clip a layer is drawn with: the clip regions of the layer and its ancestors. The
+/// clip regions that contain another one are left out, since they don't clip anything more, and
+/// the shaders test the two nearest of the others.
+#[derive(Copy, Clone, PartialEq)]
+struct RoundedClip {
+    /// The clip region of the nearest clipping layer that is left.
+    inner: ClipShape,
 
-    fn disable_attribute_arrays(&self) {
-        gl::disable_vertex_attrib_array(self.vertex_position_attr as GLuint);
+    /// The clip region of the next one, if any.
+    outer: Option<ClipShape>,
+}
+
+impl RoundedClip {
+    /// Returns the clip of `clip_layers`, from the outermost to the nearest, drawn with the
+    /// outer `transform`, for quads whose vertices are mapped to the same space by
+    /// `vertex_transform`. Clipping layers projected to a line are left out.
+    fn new<T>(clip_layers: &[Rc<Layer<T>>],
+              transform: &Matrix4D<f32>,
+              vertex_transform: &Matrix4D<f32>)
+              -> Option<RoundedClip> {
+        let mut kept_layers: Vec<&Rc<Layer<T>>> = vec!();
+        for clip_layer in clip_layers.iter().rev() {
+            if kept_layers.iter().any(|kept_layer| clip_region_contains(clip_layer, kept_layer)) {
+                continue;
+            }
+            kept_layers.retain(|kept_layer| !clip_region_contains(kept_layer, clip_layer));
+            kept_layers.push(clip_layer);
+        }
+        if kept_layers.len() > 2 {
+            debug!("Only clipping to 2 of {} overlapping rounded clips", kept_layers.len());
+        }
+
+        let mut shapes = kept_layers.iter().filter_map(|clip_layer| {
+            ClipShape::new(clip_layer, transform, vertex_transform)
+        });
+        shapes.next().map(|inner| {
+            RoundedClip {
+                inner: inner,
+                outer: shapes.next(),
+            }
+        })
     }
 }
 
-struct RenderContextChild<T> {
-    layer: Option<Rc<Layer<T>>>,
-    context: Option<RenderContext3D<T>>,
-    paint_order: usize,
-    z_center: f32,
+/// Returns true if the clip region of `outer` contains the one of `inner`, so that clipping to
+/// `inner` is enough. The corners of `inner` are taken as square, and clip regions with a 3d
+/// transform between them are never contained.
+fn clip_region_contains<T>(outer: &Layer<T>, inner: &Layer<T>) -> bool {
+    let (outer_region, inner_region) = match (*outer.clip_region.borrow(),
+                                              *inner.clip_region.borrow()) {
+        (Some(outer_region), Some(inner_region)) => (outer_region, inner_region),
+        _ => return false,
+    };
+    let (outer_ts, inner_ts) = (outer.transform_state.borrow(), inner.transform_state.borrow());
+    if outer_ts.transform_kind.is_3d() || inner_ts.transform_kind.is_3d() {
+        return false;
+    }
+    let from_screen = match outer_ts.final_transform.to_2d().inverse() {
+        Some(from_screen) => from_screen,
+        None => return false,
+    };
+    let to_screen = inner_ts.final_transform.to_2d();
+
+    // The rounded rect of `outer` is convex, so it contains the rect if it contains its corners.
+    let rect = inner_region.rect.to_untyped().translate(&inner_ts.world_rect.origin);
+    [rect.origin, rect.top_right(), rect.bottom_right(), rect.bottom_left()].iter().all(|corner| {
+        let point = from_screen.transform_point(&to_screen.transform_point(corner));
+        outer_region.contains(&TypedPoint2D::from_untyped(&(point - outer_ts.world_rect.origin)))
+    })
 }
 
-pub struct RenderContext3D<T>{
-    children: Vec<RenderContextChild<T>>,
-    clip_rect: Option<Rect<f32>>,
+/// One of the rounded rects of a `RoundedClip`.
+#[derive(Copy, Clone, PartialEq)]
+struct ClipShape {
+    /// The clip rect, in the coordinates of the clipping layer.
+    rect: Rect<f32>,
+
+    /// The radii of the corners, with both radii of square corners set to zero.
+    radii: BorderRadii,
+
+    /// The transform from the coordinates of the vertices drawn to those of the clipping layer.
+    transform: Matrix4D<f32>,
+
+    /// The number of device pixels per pixel of the clipping layer.
+    scale: f32,
 }
 
-impl<T> RenderContext3D<T> {
-    fn new(layer: Rc<Layer<T>>) -> RenderContext3D<T> {
-        let mut render_context = RenderContext3D {
-            children: vec!(),
-            clip_rect: RenderContext3D::calculate_context_clip(layer.clone(), None),
+impl ClipShape {
+    /// Returns the clip region of `clip_layer`, as for `RoundedClip::new`.
+    fn new<T>(clip_layer: &Layer<T>,
+              transform: &Matrix4D<f32>,
+              vertex_transform: &Matrix4D<f32>)
+              -> Option<ClipShape> {
+        let clip_region = match *clip_layer.clip_region.borrow() {
+            Some(clip_region) => clip_region,
+            None => return None,
+        };
+        let clip_ts = clip_layer.transform_state.borrow();
+        let device_transform = transform.pre_mul(&clip_ts.final_transform);
+        let inverse = match device_transform.inverse() {
+            Some(inverse) => inverse,
+            None => return None,
         };
-        layer.build(&mut render_context);
-        render_context.sort_children();
-        render_context
-    }
 
-    fn build_child(layer: Rc<Layer<T>>,
-                   parent_clip_rect: Option<Rect<f32>>)
-                   -> Option<RenderContext3D<T>> {
-        let clip_rect = RenderContext3D::calculate_context_clip(layer.clone(), parent_clip_rect);
-        if let Some(ref clip_rect) = clip_rect {
-            if clip_rect.is_empty() {
-                return None;
+        let square_corners = |radius: TypedSize2D<f32, LayerPixel>| {
+            if radius.width > 0.0 && radius.height > 0.0 {
+                radius
+            } else {
+                TypedSize2D::new(0.0, 0.0)
             }
-        }
-
-        let mut render_context = RenderContext3D {
-            children: vec!(),
-            clip_rect: clip_rect,
+        };
+        let radii = clip_region.normalized_radii();
+        let radii = BorderRadii {
+            top_left: square_corners(radii.top_left),
+            top_right: square_corners(radii.top_right),
+            bottom_right: square_corners(radii.bottom_right),
+            bottom_left: square_corners(radii.bottom_left),
         };
 
-        for child in layer.children().iter() {
-            child.build(&mut render_context);
-        }
-
-        render_context.sort_children();
-        Some(render_context)
+        let (scale_x, scale_y) = transform_scale(&device_transform);
+        Some(ClipShape {
+            rect: clip_region.rect.to_untyped().translate(&clip_ts.world_rect.origin),
+            radii: radii,
+            transform: inverse.pre_mul(vertex_transform),
+            scale: (scale_x + scale_y) * 0.5,
+        })
     }
+}
 
-    fn sort_children(&mut self) {
-        // TODO(gw): This is basically what FF does, which breaks badly
-        // when there are intersecting polygons. Need to split polygons
-        // to handle this case correctly (Blink uses a BSP tree).
-        self.children.sort_by(|a, b| {
-            if a.z_center < b.z_center {
-                Ordering::Less
-            } else if a.z_center > b.z_center {
-                Ordering::Greater
-            } else if a.paint_order < b.paint_order {
-                Ordering::Less
-            } else if a.paint_order > b.paint_order {
-                Ordering::Greater
-            } else {
-                Ordering::Equal
-            }
-        });
+/// The uniforms of the rounded clip test. They are all -1 in programs without it.
+#[derive(Copy, Clone)]
+struct RoundedClipUniforms {
+    inner: ClipShapeUniforms,
+    outer: ClipShapeUniforms,
+}
+
+impl RoundedClipUniforms {
+    fn new(program: &ShaderProgram) -> RoundedClipUniforms {
+        RoundedClipUniforms {
+            inner: ClipShapeUniforms::new(program, "uClip"),
+            outer: ClipShapeUniforms::new(program, "uOuterClip"),
+        }
     }
 
-    fn calculate_context_clip(layer: Rc<Layer<T>>,
-                              parent_clip_rect: Option<Rect<f32>>)
-                              -> Option<Rect<f32>> {
-        // TODO(gw): This doesn't work for iframes that are transformed.
-        if !*layer.masks_to_bounds.borrow() {
-            return parent_clip_rect;
+    fn bind(&self, clip: &RoundedClip) {
+        self.inner.bind(&clip.inner);
+        match clip.outer {
+            Some(ref outer) => self.outer.bind(outer),
+            None => gl::uniform_1f(self.outer.scale, 0.0),
         }
+    }
+}
 
-        let layer_clip = match layer.transform_state.borrow().screen_rect.as_ref() {
-            Some(screen_rect) => screen_rect.rect,
-            None => return Some(Rect::zero()), // Layer is entirely clipped away.
-        };
+/// The uniforms of one of the rounded rects of the rounded clip test, named after `prefix`.
+#[derive(Copy, Clone)]
+struct ClipShapeUniforms {
+    transform: c_int,
+    rect: c_int,
+    radii_top: c_int,
+    radii_bottom: c_int,
+    scale: c_int,
+}
 
-        match parent_clip_rect {
-            Some(parent_clip_rect) => match layer_clip.intersection(&parent_clip_rect) {
-                Some(intersected_clip) => Some(intersected_clip),
-                None => Some(Rect::zero()), // No intersection.
-            },
-            None => Some(layer_clip),
+impl ClipShapeUniforms {
+    fn new(program: &ShaderProgram, prefix: &str) -> ClipShapeUniforms {
+        let location = |name: &str| program.get_uniform_location(&format!("{}{}", prefix, name));
+        ClipShapeUniforms {
+            transform: location("Transform"),
+            rect: location("Rect"),
+            radii_top: location("RadiiTop"),
+            radii_bottom: location("RadiiBottom"),
+            scale: location("Scale"),
         }
     }
 
-    fn add_child(&mut self,
-                 layer: Option<Rc<Layer<T>>>,
-                 child_context: Option<RenderContext3D<T>>,
-                 z_center: f32) {
-        let paint_order = self.children.len();
-        self.children.push(RenderContextChild {
-            layer: layer,
-            context: child_context,
-            z_center: z_center,
-            paint_order: paint_order,
-        });
+    fn bind(&self, shape: &ClipShape) {
+        let radii = &shape.radii;
+        gl::uniform_matrix_4fv(self.transform, false, &shape.transform.to_row_major_array());
+        gl::uniform_4f(self.rect,
+                       shape.rect.min_x(),
+                       shape.rect.min_y(),
+                       shape.rect.max_x(),
+                       shape.rect.max_y());
+        gl::uniform_4f(self.radii_top,
+                       radii.top_left.width,
+                       radii.top_left.height,
+                       radii.top_right.width,
+                       radii.top_right.height);
+        gl::uniform_4f(self.radii_bottom,
+                       radii.bottom_right.width,
+                       radii.bottom_right.height,
+                       radii.bottom_left.width,
+                       radii.bottom_left.height);
+        gl::uniform_1f(self.scale, shape.scale);
     }
 }
 
-pub trait RenderContext3DBuilder<T> {
-    fn build(&self, current_context: &mut RenderContext3D<T>);
+/// Binds textures to texture units for a single draw, and unbinds them when dropped. The
+/// active texture unit is left at unit 0, which the rest of the compositor assumes.
+struct BoundTextureUnits<'a> {
+    textures: &'a [(GLuint, &'a Texture)],
 }
 
-impl<T> RenderContext3DBuilder<T> for Rc<Layer<T>> {
-    fn build(&self, current_context: &mut RenderContext3D<T>) {
-        let (layer, z_center) = match self.transform_state.borrow().screen_rect {
-            Some(ref rect) => (Some(self.clone()), rect.z_center),
-            None => (None, 0.), // Layer is entirely clipped.
-        };
-
-        if !self.children.borrow().is_empty() && self.establishes_3d_context {
-            let child_context =
-                RenderContext3D::build_child(self.clone(), current_context.clip_rect);
-            if child_context.is_some() {
-                current_context.add_child(layer, child_context, z_center);
-                return;
-            }
-        };
+impl<'a> BoundTextureUnits<'a> {
+    fn bind(textures: &'a [(GLuint, &'a Texture)]) -> BoundTextureUnits<'a> {
+        for &(unit, texture) in textures {
+            gl::active_texture(gl::TEXTURE0 + unit);
+            gl::bind_texture(texture.target.as_gl_target(), texture.native_texture());
+        }
+        gl::active_texture(gl::TEXTURE0);
+        BoundTextureUnits {
+            textures: textures,
+        }
+    }
+}
 
-        // If we are completely clipped out, don't add anything to this context.
-        if layer.is_none() {
-            return;
+impl<'a> Drop for BoundTextureUnits<'a> {
+    fn drop(&mut self) {
+        for &(unit, texture) in self.textures {
+            gl::active_texture(gl::TEXTURE0 + unit);
+            gl::bind_texture(texture.target.as_gl_target(), 0);
         }
+        gl::active_texture(gl::TEXTURE0);
+    }
+}
+
+/// Texture program variant that samples a rectangle texture, with texture coordinates in pixels,
+/// instead of a 2D texture.
+const TEXTURE_PROGRAM_RECTANGLE: u32 = 1 << 0;
+
+/// Texture program variant that multiplies the content with the alpha of a mask texture.
+const TEXTURE_PROGRAM_MASK: u32 = 1 << 1;
+
+/// Texture program variant that multiplies the content with an opacity. Opaque quads, which are
+/// most of them, are drawn without.
+const TEXTURE_PROGRAM_OPACITY: u32 = 1 << 2;
 
-        current_context.add_child(layer, None, z_center);
+/// Texture program variant that clips the content to a rounded rect.
+const TEXTURE_PROGRAM_ROUNDED_CLIP: u32 = 1 << 3;
 
-        for child in self.children().iter() {
-            child.build(current_context);
+/// Along with `TEXTURE_PROGRAM_MASK`, the variant whose mask is a rectangle texture.
+const TEXTURE_PROGRAM_MASK_RECTANGLE: u32 = 1 << 4;
+
+/// Texture program variant that picks the nearest texel itself, for nearest filtering without
+/// sampler objects. Textures are then left filtered linearly, which samples texel centers
+/// exactly, instead of having their filter mode changed for each draw.
+const TEXTURE_PROGRAM_NEAREST_FILTER: u32 = 1 << 5;
+
+/// Texture program variant that swaps the red and blue channels of textures whose BGRA data
+/// the GL implementation can't swizzle itself. See `Texture::shader_swizzle`.
+const TEXTURE_PROGRAM_SWIZZLE: u32 = 1 << 6;
+
+/// Texture program variant that converts the planes of a video frame from YUV to RGB. The Y
+/// plane is the content texture.
+const TEXTURE_PROGRAM_YUV: u32 = 1 << 7;
+
+/// Along with `TEXTURE_PROGRAM_YUV`, the variant for frames whose chroma is interleaved in a
+/// single plane.
+const TEXTURE_PROGRAM_NV12: u32 = 1 << 8;
+
+/// The variants of the texture program, keyed by their `TEXTURE_PROGRAM_*` feature bits and
+/// compiled when they are first drawn with. A new shader feature gets a bit and a define here,
+/// instead of a program field in `RenderContext` for each combination with the existing ones.
+/// Video frames are drawn with the YUV variants.
+struct TexturePrograms {
+    programs: RefCell<HashMap<u32, TextureProgram>>,
+}
+
+impl TexturePrograms {
+    fn new() -> TexturePrograms {
+        TexturePrograms {
+            programs: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// The variant with the given features, compiled if it hasn't been drawn with yet.
+    fn get(&self, features: u32) -> TextureProgram {
+        if let Some(program) = self.programs.borrow().get(&features) {
+            return *program;
         }
+        debug!("Compiling texture program variant {:#x}", features);
+        let program = TextureProgram::new(features);
+        self.programs.borrow_mut().insert(features, program);
+        program
     }
 }
 
-#[derive(Copy, Clone)]
-pub struct RenderContext {
-    texture_2d_program: TextureProgram,
-    texture_rectangle_program: Option<TextureProgram>,
-    solid_color_program: SolidColorProgram,
-    buffers: Buffers,
+/// The program, uniforms and textures shared by the quads of a batch.
+#[derive(Copy, Clone, PartialEq)]
+struct QuadBatchState {
+    features: u32,
+    target: TextureTarget,
 
-    /// The platform-specific graphics context.
-    compositing_display: NativeDisplay,
+    /// The size of the textures, for rectangle textures, whose coordinates are scaled by it.
+    rectangle_size: Option<Size2D<usize>>,
 
-    /// Whether to show lines at border and tile boundaries for debugging purposes.
-    show_debug_borders: bool,
+    /// The size of the textures as the program picks the nearest texel, if it does.
+    nearest_filter_size: Option<Size2D<f32>>,
 
-    force_near_texture_filter: bool,
+    orientation: Orientation,
+    wrap_mode: (WrapMode, WrapMode),
+    is_content: bool,
+    edge_antialiasing: Option<EdgeAntialiasing>,
+    rounded_clip: Option<RoundedClip>,
+    transform: Matrix4D<f32>,
+    projection: Matrix4D<f32>,
+    opacity: f32,
 }
 
-impl RenderContext {
-    pub fn new(compositing_display: NativeDisplay,
-               show_debug_borders: bool,
-               force_near_texture_filter: bool) -> RenderContext {
-        gl::enable(gl::TEXTURE_2D);
+/// Textured quads drawn with the same program and uniforms, accumulated so that their vertices
+/// are uploaded and their uniforms set once. Textures are still bound one at a time, so each run
+/// of quads that share a texture takes its own draw call: the tiles of a layer share a vertex
+/// upload but are drawn with a call each, and only the repetitions of a tile of a repeated
+/// pattern are drawn with a single call. Batches don't span layers.
+struct QuadBatch {
+    state: Option<QuadBatchState>,
 
-        // Each layer uses premultiplied alpha!
-        gl::enable(gl::BLEND);
-        gl::blend_func(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+    /// The vertices of the quads, as two triangles each.
+    vertices: Vec<TextureVertex>,
 
-        let texture_2d_program = TextureProgram::create_2d_program();
-        let solid_color_program = SolidColorProgram::new();
-        let texture_rectangle_program = TextureProgram::create_rectangle_program_if_necessary();
+    /// The textures of the quads, each with the number of consecutive vertices drawn with it.
+    runs: Vec<(GLuint, usize)>,
+}
 
-        RenderContext {
-            texture_2d_program: texture_2d_program,
-            texture_rectangle_program: texture_rectangle_program,
-            solid_color_program: solid_color_program,
-            buffers: RenderContext::init_buffers(),
-            compositing_display: compositing_display,
-            show_debug_borders: show_debug_borders,
-            force_near_texture_filter: force_near_texture_filter,
+impl QuadBatch {
+    fn new() -> QuadBatch {
+        QuadBatch {
+            state: None,
+            vertices: vec!(),
+            runs: vec!(),
         }
     }
 
-    fn init_buffers() -> Buffers {
-        let quad_vertex_buffer = gl::gen_buffers(1)[0];
-        gl::bind_buffer(gl::ARRAY_BUFFER, quad_vertex_buffer);
-
-        let line_quad_vertex_buffer = gl::gen_buffers(1)[0];
-        gl::bind_buffer(gl::ARRAY_BUFFER, line_quad_vertex_buffer);
+    /// Whether quads drawn with `state` can join the batch.
+    fn accepts(&self, state: &QuadBatchState) -> bool {
+        self.state.map_or(true, |current| current == *state)
+    }
 
-        Buffers {
-            quad_vertex_buffer: quad_vertex_buffer,
-            line_quad_vertex_buffer: line_quad_vertex_buffer,
+    fn push(&mut self, state: QuadBatchState, texture: GLuint, quad: &[TextureVertex; 4]) {
+        debug_assert!(self.accepts(&state));
+        self.state = Some(state);
+        self.vertices.extend_from_slice(&[quad[0], quad[1], quad[2], quad[2], quad[1], quad[3]]);
+        if let Some(run) = self.runs.last_mut() {
+            if run.0 == texture {
+                run.1 += 6;
+                return;
+            }
         }
+        self.runs.push((texture, 6));
     }
 
-    fn bind_and_render_solid_quad(&self,
-                                  vertices: &[ColorVertex; 4],
-                                  transform: &Matrix4D<f32>,
-                                  projection: &Matrix4D<f32>,
-                                  color: &Color) {
-        self.solid_color_program.enable_attribute_arrays();
-        gl::use_program(self.solid_color_program.program.id);
-        self.solid_color_program.bind_uniforms_and_attributes_for_quad(vertices,
-                                                                       transform,
-                                                                       projection,
-                                                                       &self.buffers,
-                                                                       color);
-        gl::draw_arrays(gl::TRIANGLE_STRIP, 0, 4);
-        self.solid_color_program.disable_attribute_arrays();
+    fn clear(&mut self) {
+        self.state = None;
+        self.vertices.clear();
+        self.runs.clear();
     }
+}
 
-    fn bind_and_render_quad(&self,
-                            vertices: &[TextureVertex; 4],
-                            texture: &Texture,
-                            transform: &Matrix4D<f32>,
-                            projection_matrix: &Matrix4D<f32>,
-                            opacity: f32) {
-        let mut texture_coordinates_need_to_be_scaled_by_size = false;
-        let program = match texture.target {
-            TextureTarget2D => self.texture_2d_program,
-            TextureTargetRectangle => match self.texture_rectangle_program {
-                Some(program) => {
-                    texture_coordinates_need_to_be_scaled_by_size = true;
-                    program
-                }
-                None => panic!("There is no shader program for texture rectangle"),
-            },
+impl TextureProgram {
+    fn new(features: u32) -> TextureProgram {
+        let (sampler_function, sampler_type) = if features & TEXTURE_PROGRAM_RECTANGLE != 0 {
+            TextureProgram::enable_rectangle_textures();
+            ("texture2DRect", "sampler2DRect")
+        } else {
+            ("texture2D", "sampler2D")
         };
-        program.enable_attribute_arrays();
+        let mut defines = String::from(mask_shader_defines(features));
+        if features & TEXTURE_PROGRAM_OPACITY != 0 {
+            defines.push_str("#define USE_OPACITY\n");
+        }
+        if features & TEXTURE_PROGRAM_NEAREST_FILTER != 0 {
+            defines.push_str("#define USE_NEAREST_FILTER\n");
+        }
+        if features & TEXTURE_PROGRAM_SWIZZLE != 0 {
+            defines.push_str("#define USE_SWIZZLE\n");
+        }
+        if features & TEXTURE_PROGRAM_YUV != 0 {
+            defines.push_str("#define USE_YUV\n");
+        }
+        if features & TEXTURE_PROGRAM_NV12 != 0 {
+            defines.push_str("#define USE_NV12\n");
+        }
+        let mut fragment_source = String::from(EDGE_COVERAGE_FRAGMENT_SHADER_SOURCE);
+        if features & TEXTURE_PROGRAM_ROUNDED_CLIP != 0 {
+            defines.push_str("#define USE_ROUNDED_CLIP\n");
+            fragment_source.push_str(ROUNDED_CLIP_FRAGMENT_SHADER_SOURCE);
+        }
+        fragment_source.push_str(TEXTURE_FRAGMENT_SHADER_SOURCE);
+        let fragment_shader_source
+             = fmt::format(format_args!("#define samplerFunction {}\n#define samplerType {}\n{}{}",
+                                        sampler_function,
+                                        sampler_type,
+                                        defines,
+                                        fragment_source));
+        let vertex_shader_source = format!("{}{}", defines, TEXTURE_VERTEX_SHADER_SOURCE);
+        let program = ShaderProgram::new(&vertex_shader_source, &fragment_shader_source);
+        TextureProgram {
+            program: program,
+            vertex_position_attr: program.get_attribute_location("aVertexPosition"),
+            vertex_uv_attr: program.get_attribute_location("aVertexUv"),
+            modelview_uniform: program.get_uniform_location("uMVMatrix"),
+            sampler_uniform: program.get_uniform_location("uSampler"),
+            texture_space_transform_uniform: program.get_uniform_location("uTextureSpaceTransform"),
+            opacity_uniform: program.get_uniform_location("uOpacity"),
+            texture_size_uniform: program.get_uniform_location("uTextureSize"),
+            chroma_sampler_uniforms: [program.get_uniform_location("uUSampler"),
+                                      program.get_uniform_location("uVSampler")],
+            color_matrix_uniform: program.get_uniform_location("uColorMatrix"),
+            mask_sampler_uniform: program.get_uniform_location("uMaskSampler"),
+            mask_space_transform_uniform: program.get_uniform_location("uMaskSpaceTransform"),
+            edge_rect_uniform: program.get_uniform_location("uEdgeRect"),
+            edge_scale_uniform: program.get_uniform_location("uEdgeScale"),
+            rounded_clip_uniforms: RoundedClipUniforms::new(&program),
+        }
+    }
 
-        gl::use_program(program.program.id);
-        gl::active_texture(gl::TEXTURE0);
-        gl::bind_texture(texture.target.as_gl_target(), texture.native_texture());
+    /// Sets the size of the texture for the variant that filters to the nearest texel itself.
+    fn bind_texture_size(&self, texture_size: &Size2D<f32>) {
+        gl::uniform_2f(self.texture_size_uniform, texture_size.width, texture_size.height);
+    }
 
-        let filter_mode = if self.force_near_texture_filter {
-            gl::NEAREST
-        } else {
-            gl::LINEAR
-        } as GLint;
-        gl::tex_parameter_i(texture.target.as_gl_target(), gl::TEXTURE_MAG_FILTER, filter_mode);
-        gl::tex_parameter_i(texture.target.as_gl_target(), gl::TEXTURE_MIN_FILTER, filter_mode);
-
-        // We calculate a transformation matrix for the texture coordinates
-        // which is useful for flipping the texture vertically or scaling the
-        // coordinates when dealing with GL_ARB_texture_rectangle.
-        let mut texture_transform = Matrix4D::identity();
-        if texture.flip == VerticalFlip {
-            texture_transform = texture_transform.pre_scaled(1.0, -1.0, 1.0);
+    /// Sets the chroma samplers and the color matrix of the video variants.
+    fn bind_yuv_uniforms(&self, color_matrix: &Matrix4D<f32>) {
+        let units = CHROMA_TEXTURE_UNITS.iter();
+        for (&uniform, &unit) in self.chroma_sampler_uniforms.iter().zip(units) {
+            if uniform != -1 {
+                gl::uniform_1i(uniform, unit as GLint);
+            }
         }
-        if texture_coordinates_need_to_be_scaled_by_size {
-            texture_transform = texture_transform.pre_scaled(
-                texture.size.width as f32, texture.size.height as f32, 1.0);
+        gl::uniform_matrix_4fv(self.color_matrix_uniform,
+                               false,
+                               &color_matrix.to_row_major_array());
+    }
+
+    fn bind_uniforms_and_attributes(&self,
+                                    vertices: &[TextureVertex],
+                                    transform: &Matrix4D<f32>,
+                                    projection_matrix: &Matrix4D<f32>,
+                                    texture_space_transform: &Matrix4D<f32>,
+                                    mask_space_transform: Option<&Matrix4D<f32>>,
+                                    edge_antialiasing: Option<&EdgeAntialiasing>,
+                                    rounded_clip: Option<&RoundedClip>,
+                                    buffers: &Buffers,
+                                    opacity: f32) {
+        gl::uniform_1i(self.sampler_uniform, CONTENT_TEXTURE_UNIT as GLint);
+        bind_edge_antialiasing(self.edge_rect_uniform,
+                               self.edge_scale_uniform,
+                               edge_antialiasing);
+        if let Some(rounded_clip) = rounded_clip {
+            self.rounded_clip_uniforms.bind(rounded_clip);
         }
-        if texture.flip == VerticalFlip {
-            texture_transform = texture_transform.pre_translated(0.0, -1.0, 0.0);
+        if let Some(mask_space_transform) = mask_space_transform {
+            gl::uniform_1i(self.mask_sampler_uniform, MASK_TEXTURE_UNIT as GLint);
+            gl::uniform_matrix_4fv(self.mask_space_transform_uniform,
+                                   false,
+                                   &mask_space_transform.to_row_major_array());
         }
+        gl::uniform_matrix_4fv(self.modelview_uniform,
+                               false,
+                               &transform.to_row_major_array());
+        buffers.frame_constants.bind(&self.program.frame_uniforms, projection_matrix);
 
-        program.bind_uniforms_and_attributes(vertices,
-                                             transform,
-                                             &projection_matrix,
-                                             &texture_transform,
-                                             &self.buffers,
-                                             opacity);
+        let vertex_size = mem::size_of::<TextureVertex>();
 
-        // Draw!
-        gl::draw_arrays(gl::TRIANGLE_STRIP, 0, 4);
-        gl::bind_texture(gl::TEXTURE_2D, 0);
+        gl::bind_buffer(gl::ARRAY_BUFFER, buffers.quad_vertex_buffer);
+        gl::buffer_data(gl::ARRAY_BUFFER, vertices, gl::DYNAMIC_DRAW);
+        gl::vertex_attrib_pointer_f32(self.vertex_position_attr as GLuint,2, false, vertex_size as i32, 0);
+        gl::vertex_attrib_pointer_f32(self.vertex_uv_attr as GLuint, 2, false, vertex_size as i32, 8);
 
-        gl::bind_texture(texture.target.as_gl_target(), 0);
-        program.disable_attribute_arrays()
+        gl::uniform_matrix_4fv(self.texture_space_transform_uniform,
+                               false,
+                               &texture_space_transform.to_row_major_array());
+
+        if self.opacity_uniform != -1 {
+            gl::uniform_1f(self.opacity_uniform, opacity);
+        }
     }
 
-    pub fn bind_and_render_quad_lines(&self,
-                                      vertices: &[ColorVertex; 5],
-                                      transform: &Matrix4D<f32>,
-                                      projection: &Matrix4D<f32>,
-                                      color: &Color,
-                                      line_thickness: usize) {
-        self.solid_color_program.enable_attribute_arrays();
-        gl::use_program(self.solid_color_program.program.id);
-        self.solid_color_program.bind_uniforms_and_attributes_for_lines(vertices,
-                                                                        transform,
-                                                                        projection,
+    fn enable_attribute_arrays(&self) {
+        gl::enable_vertex_attrib_array(self.vertex_position_attr as GLuint);
+        gl::enable_vertex_attrib_array(self.vertex_uv_attr as GLuint);
+    }
+
+    fn disable_attribute_arrays(&self) {
+        gl::disable_vertex_attrib_array(self.vertex_uv_attr as GLuint);
+        gl::disable_vertex_attrib_array(self.vertex_position_attr as GLuint);
+    }
+
+    fn enable_rectangle_textures() {
+        let capabilities = GlCapabilities::current();
+        if !capabilities.rectangle_textures {
+            panic!("There is no shader program for texture rectangle")
+        }
+        // Core profiles have no fixed-function texturing to enable.
+        if !capabilities.is_gles && !capabilities.core_profile {
+            gl::enable(TextureTargetRectangle.as_gl_target());
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct SolidColorProgram {
+    program: ShaderProgram,
+    vertex_position_attr: c_int,
+    modelview_uniform: c_int,
+    color_uniform: c_int,
+    edge_rect_uniform: c_int,
+    edge_scale_uniform: c_int,
+
+    /// The rounded clip, if the program was compiled with it.
+    rounded_clip_uniforms: RoundedClipUniforms,
+}
+
+impl SolidColorProgram {
+    fn new(rounded_clip: bool) -> SolidColorProgram {
+        let program = if rounded_clip {
+            let defines = "#define USE_ROUNDED_CLIP\n";
+            ShaderProgram::new(&format!("{}{}", defines, SOLID_COLOR_VERTEX_SHADER_SOURCE),
+                               &format!("{}{}{}{}",
+                                        defines,
+                                        EDGE_COVERAGE_FRAGMENT_SHADER_SOURCE,
+                                        ROUNDED_CLIP_FRAGMENT_SHADER_SOURCE,
+                                        SOLID_COLOR_FRAGMENT_SHADER_SOURCE))
+        } else {
+            ShaderProgram::new(SOLID_COLOR_VERTEX_SHADER_SOURCE,
+                               &format!("{}{}",
+                                        EDGE_COVERAGE_FRAGMENT_SHADER_SOURCE,
+                                        SOLID_COLOR_FRAGMENT_SHADER_SOURCE))
+        };
+        SolidColorProgram {
+            program: program,
+            vertex_position_attr: program.get_attribute_location("aVertexPosition"),
+            modelview_uniform: program.get_uniform_location("uMVMatrix"),
+            color_uniform: program.get_uniform_location("uColor"),
+            edge_rect_uniform: program.get_uniform_location("uEdgeRect"),
+            edge_scale_uniform: program.get_uniform_location("uEdgeScale"),
+            rounded_clip_uniforms: RoundedClipUniforms::new(&program),
+        }
+    }
+
+    fn bind_uniforms_and_attributes_common(&self,
+                                           transform: &Matrix4D<f32>,
+                                           projection_matrix: &Matrix4D<f32>,
+                                           buffers: &Buffers,
+                                           color: &Color,
+                                           edge_antialiasing: Option<&EdgeAntialiasing>) {
+        bind_edge_antialiasing(self.edge_rect_uniform,
+                               self.edge_scale_uniform,
+                               edge_antialiasing);
+        gl::uniform_matrix_4fv(self.modelview_uniform,
+                               false,
+                               &transform.to_row_major_array());
+        buffers.frame_constants.bind(&self.program.frame_uniforms, projection_matrix);
+        gl::uniform_4f(self.color_uniform,
+                   color.r as GLfloat,
+                   color.g as GLfloat,
+                   color.b as GLfloat,
+                   color.a as GLfloat);
+    }
+
+    fn bind_uniforms_and_attributes_for_lines(&self,
+                                              vertices: &[ColorVertex; 5],
+                                              transform: &Matrix4D<f32>,
+                                              projection_matrix: &Matrix4D<f32>,
+                                              buffers: &Buffers,
+                                              color: &Color) {
+        self.bind_uniforms_and_attributes_common(transform,
+                                                 projection_matrix,
+                                                 buffers,
+                                                 color,
+                                                 None);
+
+        gl::bind_buffer(gl::ARRAY_BUFFER, buffers.line_quad_vertex_buffer);
+        gl::buffer_data(gl::ARRAY_BUFFER, vertices, gl::DYNAMIC_DRAW);
+        gl::vertex_attrib_pointer_f32(self.vertex_position_attr as GLuint, 2, false, 0, 0);
+    }
+
+    fn bind_uniforms_and_attributes_for_quad(&self,
+                                             vertices: &[ColorVertex; 4],
+                                             transform: &Matrix4D<f32>,
+                                             projection_matrix: &Matrix4D<f32>,
+                                             buffers: &Buffers,
+                                             color: &Color,
+                                             edge_antialiasing: Option<&EdgeAntialiasing>) {
+        self.bind_uniforms_and_attributes_common(transform,
+                                                 projection_matrix,
+                                                 buffers,
+                                                 color,
+                                                 edge_antialiasing);
+
+        gl::bind_buffer(gl::ARRAY_BUFFER, buffers.quad_vertex_buffer);
+        gl::buffer_data(gl::ARRAY_BUFFER, vertices, gl::DYNAMIC_DRAW);
+        gl::vertex_attrib_pointer_f32(self.vertex_position_attr as GLuint, 2, false, 0, 0);
+    }
+
+    fn enable_attribute_arrays(&self) {
+        gl::enable_vertex_attrib_array(self.vertex_position_attr as GLuint);
+    }
+
+    fn disable_attribute_arrays(&self) {
+        gl::disable_vertex_attrib_array(self.vertex_position_attr as GLuint);
+    }
+}
+
+#[derive(Copy, Clone)]
+struct BoxShadowProgram {
+    program: ShaderProgram,
+    vertex_position_attr: c_int,
+    modelview_uniform: c_int,
+    color_uniform: c_int,
+    box_uniform: c_int,
+    sigma_uniform: c_int,
+}
+
+impl BoxShadowProgram {
+    fn new() -> BoxShadowProgram {
+        let program = ShaderProgram::new(BOX_SHADOW_VERTEX_SHADER_SOURCE,
+                                         BOX_SHADOW_FRAGMENT_SHADER_SOURCE);
+        BoxShadowProgram {
+            program: program,
+            vertex_position_attr: program.get_attribute_location("aVertexPosition"),
+            modelview_uniform: program.get_uniform_location("uMVMatrix"),
+            color_uniform: program.get_uniform_location("uColor"),
+            box_uniform: program.get_uniform_location("uBox"),
+            sigma_uniform: program.get_uniform_location("uSigma"),
+        }
+    }
+
+    fn bind_uniforms_and_attributes(&self,
+                                    vertices: &[ColorVertex; 4],
+                                    transform: &Matrix4D<f32>,
+                                    projection_matrix: &Matrix4D<f32>,
+                                    buffers: &Buffers,
+                                    shadow_box: &Rect<f32>,
+                                    sigma: f32,
+                                    color: &Color) {
+        gl::uniform_matrix_4fv(self.modelview_uniform,
+                               false,
+                               &transform.to_row_major_array());
+        buffers.frame_constants.bind(&self.program.frame_uniforms, projection_matrix);
+        gl::uniform_4f(self.color_uniform,
+                       color.r as GLfloat,
+                       color.g as GLfloat,
+                       color.b as GLfloat,
+                       color.a as GLfloat);
+        gl::uniform_4f(self.box_uniform,
+                       shadow_box.min_x(),
+                       shadow_box.min_y(),
+                       shadow_box.max_x(),
+                       shadow_box.max_y());
+        gl::uniform_1f(self.sigma_uniform, sigma);
+
+        gl::bind_buffer(gl::ARRAY_BUFFER, buffers.quad_vertex_buffer);
+        gl::buffer_data(gl::ARRAY_BUFFER, vertices, gl::DYNAMIC_DRAW);
+        gl::vertex_attrib_pointer_f32(self.vertex_position_attr as GLuint, 2, false, 0, 0);
+    }
+
+    fn enable_attribute_arrays(&self) {
+        gl::enable_vertex_attrib_array(self.vertex_position_attr as GLuint);
+    }
+
+    fn disable_attribute_arrays(&self) {
+        gl::disable_vertex_attrib_array(self.vertex_position_attr as GLuint);
+    }
+}
+
+#[derive(Copy, Clone)]
+struct CheckerboardProgram {
+    program: ShaderProgram,
+    vertex_position_attr: c_int,
+    modelview_uniform: c_int,
+    color0_uniform: c_int,
+    color1_uniform: c_int,
+    square_size_uniform: c_int,
+
+    /// The mask, for the masked variants of the program, as in `TextureProgram`.
+    mask_sampler_uniform: c_int,
+    mask_space_transform_uniform: c_int,
+}
+
+impl CheckerboardProgram {
+    /// The program with the mask among the `TEXTURE_PROGRAM_*` `features`.
+    fn new(features: u32) -> CheckerboardProgram {
+        let defines = mask_shader_defines(features);
+        let program =
+            ShaderProgram::new(&format!("{}{}", defines, SOLID_COLOR_VERTEX_SHADER_SOURCE),
+                               &format!("{}{}", defines, CHECKERBOARD_FRAGMENT_SHADER_SOURCE));
+        CheckerboardProgram {
+            program: program,
+            vertex_position_attr: program.get_attribute_location("aVertexPosition"),
+            modelview_uniform: program.get_uniform_location("uMVMatrix"),
+            color0_uniform: program.get_uniform_location("uColor0"),
+            color1_uniform: program.get_uniform_location("uColor1"),
+            square_size_uniform: program.get_uniform_location("uSquareSize"),
+            mask_sampler_uniform: program.get_uniform_location("uMaskSampler"),
+            mask_space_transform_uniform: program.get_uniform_location("uMaskSpaceTransform"),
+        }
+    }
+
+    fn bind_uniforms_and_attributes(&self,
+                                    vertices: &[ColorVertex; 4],
+                                    transform: &Matrix4D<f32>,
+                                    projection_matrix: &Matrix4D<f32>,
+                                    mask_space_transform: Option<&Matrix4D<f32>>,
+                                    buffers: &Buffers,
+                                    colors: (&Color, &Color),
+                                    square_size: f32) {
+        gl::uniform_matrix_4fv(self.modelview_uniform,
+                               false,
+                               &transform.to_row_major_array());
+        if let Some(mask_space_transform) = mask_space_transform {
+            gl::uniform_1i(self.mask_sampler_uniform, MASK_TEXTURE_UNIT as GLint);
+            gl::uniform_matrix_4fv(self.mask_space_transform_uniform,
+                                   false,
+                                   &mask_space_transform.to_row_major_array());
+        }
+        buffers.frame_constants.bind(&self.program.frame_uniforms, projection_matrix);
+        let (color0, color1) = colors;
+        for &(uniform, color) in &[(self.color0_uniform, color0), (self.color1_uniform, color1)] {
+            gl::uniform_4f(uniform,
+                           color.r as GLfloat,
+                           color.g as GLfloat,
+                           color.b as GLfloat,
+                           color.a as GLfloat);
+        }
+        gl::uniform_1f(self.square_size_uniform, square_size.max(1.0));
+
+        gl::bind_buffer(gl::ARRAY_BUFFER, buffers.quad_vertex_buffer);
+        gl::buffer_data(gl::ARRAY_BUFFER, vertices, gl::DYNAMIC_DRAW);
+        gl::vertex_attrib_pointer_f32(self.vertex_position_attr as GLuint, 2, false, 0, 0);
+    }
+
+    fn enable_attribute_arrays(&self) {
+        gl::enable_vertex_attrib_array(self.vertex_position_attr as GLuint);
+    }
+
+    fn disable_attribute_arrays(&self) {
+        gl::disable_vertex_attrib_array(self.vertex_position_attr as GLuint);
+    }
+}
+
+/// The most texels sampled on each side of a texel by a backdrop blur pass, as in the loop of
+/// `BACKDROP_BLUR_FRAGMENT_SHADER_SOURCE`.
+const MAX_BACKDROP_BLUR_TAPS: GLsizei = 32;
+
+/// The most a backdrop is downsampled by, as in the loops of
+/// `BACKDROP_DOWNSAMPLE_FRAGMENT_SHADER_SOURCE`.
+const MAX_BACKDROP_DOWNSAMPLE: GLsizei = 16;
+
+/// The parameters of a pass of a backdrop filter. Positions and sizes are in texels of the
+/// source texture, or window pixels for `dest_origin`.
+struct BackdropPass {
+    dest_origin: Point2D<f32>,
+    source_scale: f32,
+    source_size: Size2D<f32>,
+    texture_size: Size2D<f32>,
+    direction: Point2D<f32>,
+    sigma: f32,
+    taps: f32,
+    downsample: f32,
+    opacity: f32,
+}
+
+/// Draws a pass of a backdrop filter, either a blur along one axis or a downsampling.
+#[derive(Copy, Clone)]
+struct BackdropProgram {
+    program: ShaderProgram,
+    vertex_position_attr: c_int,
+    modelview_uniform: c_int,
+    sampler_uniform: c_int,
+    dest_origin_uniform: c_int,
+    source_scale_uniform: c_int,
+    source_size_uniform: c_int,
+    texture_size_uniform: c_int,
+    direction_uniform: c_int,
+    sigma_uniform: c_int,
+    taps_uniform: c_int,
+    downsample_uniform: c_int,
+    opacity_uniform: c_int,
+}
+
+impl BackdropProgram {
+    fn new(fragment_shader_source: &str) -> BackdropProgram {
+        let program = ShaderProgram::new(SOLID_COLOR_VERTEX_SHADER_SOURCE, fragment_shader_source);
+        BackdropProgram {
+            program: program,
+            vertex_position_attr: program.get_attribute_location("aVertexPosition"),
+            modelview_uniform: program.get_uniform_location("uMVMatrix"),
+            sampler_uniform: program.get_uniform_location("uSampler"),
+            dest_origin_uniform: program.get_uniform_location("uDestOrigin"),
+            source_scale_uniform: program.get_uniform_location("uSourceScale"),
+            source_size_uniform: program.get_uniform_location("uSourceSize"),
+            texture_size_uniform: program.get_uniform_location("uTextureSize"),
+            direction_uniform: program.get_uniform_location("uDirection"),
+            sigma_uniform: program.get_uniform_location("uSigma"),
+            taps_uniform: program.get_uniform_location("uTaps"),
+            downsample_uniform: program.get_uniform_location("uDownsample"),
+            opacity_uniform: program.get_uniform_location("uOpacity"),
+        }
+    }
+
+    fn bind_uniforms_and_attributes(&self,
+                                    vertices: &[ColorVertex; 4],
+                                    transform: &Matrix4D<f32>,
+                                    projection_matrix: &Matrix4D<f32>,
+                                    buffers: &Buffers,
+                                    pass: &BackdropPass) {
+        gl::uniform_1i(self.sampler_uniform, CONTENT_TEXTURE_UNIT as GLint);
+        gl::uniform_matrix_4fv(self.modelview_uniform,
+                               false,
+                               &transform.to_row_major_array());
+        buffers.frame_constants.bind(&self.program.frame_uniforms, projection_matrix);
+        gl::uniform_2f(self.dest_origin_uniform, pass.dest_origin.x, pass.dest_origin.y);
+        gl::uniform_1f(self.source_scale_uniform, pass.source_scale);
+        gl::uniform_2f(self.source_size_uniform, pass.source_size.width, pass.source_size.height);
+        gl::uniform_2f(self.texture_size_uniform,
+                       pass.texture_size.width,
+                       pass.texture_size.height);
+        gl::uniform_2f(self.direction_uniform, pass.direction.x, pass.direction.y);
+        gl::uniform_1f(self.sigma_uniform, pass.sigma);
+        gl::uniform_1f(self.taps_uniform, pass.taps);
+        gl::uniform_1f(self.downsample_uniform, pass.downsample);
+        gl::uniform_1f(self.opacity_uniform, pass.opacity);
+
+        gl::bind_buffer(gl::ARRAY_BUFFER, buffers.quad_vertex_buffer);
+        gl::buffer_data(gl::ARRAY_BUFFER, vertices, gl::DYNAMIC_DRAW);
+        gl::vertex_attrib_pointer_f32(self.vertex_position_attr as GLuint, 2, false, 0, 0);
+    }
+
+    fn enable_attribute_arrays(&self) {
+        gl::enable_vertex_attrib_array(self.vertex_position_attr as GLuint);
+    }
+
+    fn disable_attribute_arrays(&self) {
+        gl::disable_vertex_attrib_array(self.vertex_position_attr as GLuint);
+    }
+}
+
+/// The textures backdrop filters are drawn through, each attached to a framebuffer, kept across
+/// layers and frames and only reallocated to grow.
+struct BackdropTargets {
+    size: Size2D<GLsizei>,
+    textures: [Texture; 2],
+    framebuffers: [GLuint; 2],
+}
+
+impl BackdropTargets {
+    fn new(size: Size2D<GLsizei>) -> BackdropTargets {
+        let textures = [
+            Texture::new(TextureTarget2D, Size2D::new(size.width as usize, size.height as usize)),
+            Texture::new(TextureTarget2D, Size2D::new(size.width as usize, size.height as usize)),
+        ];
+        let framebuffers = gl::gen_framebuffers(2);
+        let previous_framebuffer = gl::get_integer_v(gl::FRAMEBUFFER_BINDING) as GLuint;
+        for (texture, &framebuffer) in textures.iter().zip(framebuffers.iter()) {
+            texture.set_filter_mode(FilterMode::Linear);
+            {
+                let _bound_texture = texture.bind();
+                gl::tex_image_2d(gl::TEXTURE_2D, 0, gl::RGBA as GLint, size.width, size.height, 0,
+                                 gl::RGBA, gl::UNSIGNED_BYTE, None);
+            }
+            gl::bind_framebuffer(gl::FRAMEBUFFER, framebuffer);
+            gl::framebuffer_texture_2d(gl::FRAMEBUFFER,
+                                       gl::COLOR_ATTACHMENT0,
+                                       gl::TEXTURE_2D,
+                                       texture.native_texture(),
+                                       0);
+        }
+        gl::bind_framebuffer(gl::FRAMEBUFFER, previous_framebuffer);
+        BackdropTargets {
+            size: size,
+            textures: textures,
+            framebuffers: [framebuffers[0], framebuffers[1]],
+        }
+    }
+}
+
+impl Drop for BackdropTargets {
+    fn drop(&mut self) {
+        gl::delete_framebuffers(&self.framebuffers);
+    }
+}
+
+#[derive(Copy, Clone)]
+struct GlyphProgram {
+    program: ShaderProgram,
+    vertex_position_attr: c_int,
+    vertex_uv_attr: c_int,
+    modelview_uniform: c_int,
+    texture_space_transform_uniform: c_int,
+    sampler_uniform: c_int,
+    color_uniform: c_int,
+}
+
+impl GlyphProgram {
+    fn new() -> GlyphProgram {
+        let program = ShaderProgram::new(TEXTURE_VERTEX_SHADER_SOURCE,
+                                         GLYPH_FRAGMENT_SHADER_SOURCE);
+        GlyphProgram {
+            program: program,
+            vertex_position_attr: program.get_attribute_location("aVertexPosition"),
+            vertex_uv_attr: program.get_attribute_location("aVertexUv"),
+            modelview_uniform: program.get_uniform_location("uMVMatrix"),
+            texture_space_transform_uniform: program.get_uniform_location("uTextureSpaceTransform"),
+            sampler_uniform: program.get_uniform_location("uSampler"),
+            color_uniform: program.get_uniform_location("uColor"),
+        }
+    }
+
+    fn bind_uniforms_and_attributes(&self,
+                                    vertices: &[TextureVertex; 4],
+                                    projection_matrix: &Matrix4D<f32>,
+                                    buffers: &Buffers,
+                                    color: &Color) {
+        gl::uniform_1i(self.sampler_uniform, CONTENT_TEXTURE_UNIT as GLint);
+        gl::uniform_matrix_4fv(self.modelview_uniform,
+                               false,
+                               &Matrix4D::identity().to_row_major_array());
+        buffers.frame_constants.bind(&self.program.frame_uniforms, projection_matrix);
+        gl::uniform_matrix_4fv(self.texture_space_transform_uniform,
+                               false,
+                               &Matrix4D::identity().to_row_major_array());
+        gl::uniform_4f(self.color_uniform,
+                       color.r as GLfloat,
+                       color.g as GLfloat,
+                       color.b as GLfloat,
+                       color.a as GLfloat);
+
+        let vertex_size = mem::size_of::<TextureVertex>();
+        gl::bind_buffer(gl::ARRAY_BUFFER, buffers.quad_vertex_buffer);
+        gl::buffer_data(gl::ARRAY_BUFFER, vertices, gl::DYNAMIC_DRAW);
+        gl::vertex_attrib_pointer_f32(self.vertex_position_attr as GLuint, 2, false,
+                                      vertex_size as i32, 0);
+        gl::vertex_attrib_pointer_f32(self.vertex_uv_attr as GLuint, 2, false,
+                                      vertex_size as i32, 8);
+    }
+
+    fn enable_attribute_arrays(&self) {
+        gl::enable_vertex_attrib_array(self.vertex_position_attr as GLuint);
+        gl::enable_vertex_attrib_array(self.vertex_uv_attr as GLuint);
+    }
+
+    fn disable_attribute_arrays(&self) {
+        gl::disable_vertex_attrib_array(self.vertex_uv_attr as GLuint);
+        gl::disable_vertex_attrib_array(self.vertex_position_attr as GLuint);
+    }
+}
+
+struct RenderContextChild<T> {
+    layer: Option<Rc<Layer<T>>>,
+    context: Option<RenderContext3D<T>>,
+    paint_order: usize,
+    z_center: f32,
+
+    /// The layers with a clip region among the layer and its ancestors, from the outermost.
+    clip_layers: Vec<Rc<Layer<T>>>,
+}
+
+/// A layer in paint order, with the clip rect in unscaled screen coordinates and the layers
+/// whose clip regions it is drawn with, from the outermost.
+pub type ClippedLayer<T> = (Rc<Layer<T>>, Option<Rect<f32>>, Vec<Rc<Layer<T>>>);
+
+pub struct RenderContext3D<T>{
+    children: Vec<RenderContextChild<T>>,
+    clip_rect: Option<Rect<f32>>,
+
+    /// The layers whose clip regions apply to all layers of this context, from the outermost.
+    clip_layers: Vec<Rc<Layer<T>>>,
+
+    /// Whether this context lists every layer that can be hit, rather than what is drawn: the
+    /// layers of subtrees drawn from their raster cache, and those culled or transparent.
+    hit_testing: bool,
+}
+
+impl<T> RenderContext3D<T> {
+    fn new(layer: Rc<Layer<T>>) -> RenderContext3D<T> {
+        RenderContext3D::new_root(layer, false)
+    }
+
+    fn new_root(layer: Rc<Layer<T>>, hit_testing: bool) -> RenderContext3D<T> {
+        let mut render_context = RenderContext3D {
+            children: vec!(),
+            clip_rect: RenderContext3D::calculate_context_clip(layer.clone(), None),
+            clip_layers: vec!(),
+            hit_testing: hit_testing,
+        };
+        layer.build(&mut render_context);
+        render_context.sort_children();
+        render_context
+    }
+
+    /// Lists the layers of the tree rooted at `layer` in the order they are drawn, from back to
+    /// front, with the clip rect and the clip layers each is drawn with. Layers of 3d contexts
+    /// are sorted by depth, as when drawing them, but cached subtrees are listed layer by layer
+    /// and culled and transparent layers are listed too, so that they can be hit. The transform
+    /// state of the layers must be up to date.
+    pub fn layers_in_paint_order(layer: Rc<Layer<T>>) -> Vec<ClippedLayer<T>> {
+        let mut layers = vec!();
+        RenderContext3D::new_root(layer, true).append_layers_in_paint_order(&mut layers);
+        layers
+    }
+
+    fn append_layers_in_paint_order(&self, layers: &mut Vec<ClippedLayer<T>>) {
+        for child in &self.children {
+            if let Some(ref layer) = child.layer {
+                layers.push((layer.clone(), self.clip_rect, child.clip_layers.clone()));
+            }
+            if let Some(ref context) = child.context {
+                context.append_layers_in_paint_order(layers);
+            }
+        }
+    }
+
+    fn build_child(layer: Rc<Layer<T>>,
+                   parent_clip_rect: Option<Rect<f32>>,
+                   clip_layers: Vec<Rc<Layer<T>>>,
+                   hit_testing: bool)
+                   -> Option<RenderContext3D<T>> {
+        let clip_rect = RenderContext3D::calculate_context_clip(layer.clone(), parent_clip_rect);
+        if let Some(ref clip_rect) = clip_rect {
+            if clip_rect.is_empty() {
+                return None;
+            }
+        }
+
+        let mut render_context = RenderContext3D {
+            children: vec!(),
+            clip_rect: clip_rect,
+            clip_layers: clip_layers,
+            hit_testing: hit_testing,
+        };
+
+        for child in layer.children().iter() {
+            child.build(&mut render_context);
+        }
+
+        render_context.sort_children();
+        Some(render_context)
+    }
+
+    fn sort_children(&mut self) {
+        // TODO(gw): This is basically what FF does, which breaks badly
+        // when there are intersecting polygons. Need to split polygons
+        // to handle this case correctly (Blink uses a BSP tree).
+        self.children.sort_by(|a, b| {
+            if a.z_center < b.z_center {
+                Ordering::Less
+            } else if a.z_center > b.z_center {
+                Ordering::Greater
+            } else if a.paint_order < b.paint_order {
+                Ordering::Less
+            } else if a.paint_order > b.paint_order {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        });
+    }
+
+    fn calculate_context_clip(layer: Rc<Layer<T>>,
+                              parent_clip_rect: Option<Rect<f32>>)
+                              -> Option<Rect<f32>> {
+        // TODO(gw): This doesn't work for iframes that are transformed.
+        if !*layer.masks_to_bounds.borrow() {
+            return parent_clip_rect;
+        }
+
+        let layer_clip = match layer.transform_state.borrow().screen_rect.as_ref() {
+            Some(screen_rect) => screen_rect.rect,
+            None => return Some(Rect::zero()), // Layer is entirely clipped away.
+        };
+
+        match parent_clip_rect {
+            Some(parent_clip_rect) => match layer_clip.intersection(&parent_clip_rect) {
+                Some(intersected_clip) => Some(intersected_clip),
+                None => Some(Rect::zero()), // No intersection.
+            },
+            None => Some(layer_clip),
+        }
+    }
+
+    fn add_child(&mut self,
+                 layer: Option<Rc<Layer<T>>>,
+                 child_context: Option<RenderContext3D<T>>,
+                 z_center: f32,
+                 clip_layers: Vec<Rc<Layer<T>>>) {
+        let paint_order = self.children.len();
+        self.children.push(RenderContextChild {
+            layer: layer,
+            context: child_context,
+            z_center: z_center,
+            paint_order: paint_order,
+            clip_layers: clip_layers,
+        });
+    }
+}
+
+pub trait RenderContext3DBuilder<T> {
+    fn build(&self, current_context: &mut RenderContext3D<T>);
+}
+
+impl<T> RenderContext3DBuilder<T> for Rc<Layer<T>> {
+    fn build(&self, current_context: &mut RenderContext3D<T>) {
+        // Layers that share this 3d context are visited with an explicit stack, so that deep
+        // trees don't overflow the call stack. Only nested 3d contexts recurse.
+        let mut stack = vec!((self.clone(), current_context.clip_layers.clone()));
+        while let Some((this, mut clip_layers)) = stack.pop() {
+            let (layer, z_center) = match this.transform_state.borrow().screen_rect {
+                Some(ref rect) => (Some(this.clone()), rect.z_center),
+                None => (None, 0.), // Layer is entirely clipped.
+            };
+            if this.clip_region.borrow().is_some() {
+                clip_layers.push(this.clone());
+            }
+
+            if !this.children.borrow().is_empty() && this.establishes_3d_context {
+                let child_context = RenderContext3D::build_child(this.clone(),
+                                                                 current_context.clip_rect,
+                                                                 clip_layers.clone(),
+                                                                 current_context.hit_testing);
+                if child_context.is_some() {
+                    current_context.add_child(layer, child_context, z_center, clip_layers);
+                    continue;
+                }
+            };
+
+            // If we are completely clipped out, don't add anything to this context.
+            if layer.is_none() {
+                continue;
+            }
+
+            // A subtree that is cached in a texture is drawn as a single item.
+            if this.raster_cache.borrow().is_active() && !current_context.hit_testing {
+                current_context.add_child(layer, None, z_center, clip_layers);
+                continue;
+            }
+
+            // Culled layers aren't drawn, but their descendants may be.
+            if this.transform_state.borrow().visible || current_context.hit_testing {
+                current_context.add_child(layer, None, z_center, clip_layers.clone());
+            }
+            stack.extend(this.children().iter().rev().map(|child| {
+                (child.clone(), clip_layers.clone())
+            }));
+        }
+    }
+}
+
+/// The signature of `glObjectLabel`, from KHR_debug.
+pub type ObjectLabelFn = extern "system" fn(GLenum, GLuint, GLsizei, *const c_char);
+
+pub struct RenderContext {
+    texture_programs: TexturePrograms,
+    solid_color_program: SolidColorProgram,
+
+    rounded_clip_solid_color_program: SolidColorProgram,
+    box_shadow_program: BoxShadowProgram,
+    checkerboard_program: CheckerboardProgram,
+
+    /// The masked variants of the checkerboard program, by their mask `TEXTURE_PROGRAM_*`
+    /// features, compiled when a masked layer first shows a placeholder.
+    masked_checkerboard_programs: RefCell<HashMap<u32, CheckerboardProgram>>,
+
+    glyph_program: GlyphProgram,
+    backdrop_program: BackdropProgram,
+    backdrop_downsample_program: BackdropProgram,
+
+    /// The textures backdrop filters are drawn through, allocated on first use.
+    backdrop_targets: RefCell<Option<BackdropTargets>>,
+
+    buffers: Buffers,
+
+    /// The glyphs of the embedded bitmap font, for debug labels and overlay text.
+    glyph_atlas: Texture,
+
+    /// The platform-specific graphics context.
+    compositing_display: NativeDisplay,
+
+    /// Whether to show lines at border and tile boundaries for debugging purposes.
+    show_debug_borders: bool,
+
+    force_near_texture_filter: bool,
+
+    /// What to draw in place of missing tiles, and the scale of the scene being rendered. These
+    /// are taken from the scene at the start of each frame.
+    tile_placeholder: TilePlaceholder,
+    scene_scale: ScaleFactor<f32, LayerPixel, DevicePixel>,
+
+    /// Which layers are snapped to device pixels, taken from the scene at the start of each
+    /// frame.
+    pixel_snapping: PixelSnapping,
+
+    /// `glObjectLabel`, if the embedder loaded it, for naming GL objects after layers.
+    object_label: Option<ObjectLabelFn>,
+
+    /// Whether to tint tiles by the age of their content, from green for fresh tiles to red for
+    /// stale ones.
+    show_tile_age_heatmap: bool,
+
+    /// Whether debug borders are labeled with the layer's name, size and scale.
+    show_debug_labels: bool,
+
+    /// Whether to draw only the outlines of layers and tiles, without any content.
+    wireframe: bool,
+
+    /// The coordinate conventions of the scene being rendered, taken from the scene at the
+    /// start of each frame.
+    y_axis_up: bool,
+    mirrored: bool,
+
+    /// Whether to feather the edges of layers that are rotated, skewed or in perspective, and
+    /// to blend pixels partially covered by clips.
+    antialias_edges: bool,
+
+    /// The number of samples per pixel of the framebuffers the scene and intermediate surfaces
+    /// are rendered into, or 0 to render directly without multisampling.
+    sample_count: usize,
+
+    /// The multisampled framebuffer the scene is composited into, kept across frames.
+    scene_target: Option<MultisampleTarget>,
+
+    /// The multisampled framebuffer raster cache textures are rendered into, kept for the next
+    /// one of the same size.
+    raster_cache_target: RefCell<Option<MultisampleTarget>>,
+
+    /// The number of bytes of tiles uploaded per frame, for scenes without an upload budget of
+    /// their own.
+    texture_budget: Option<usize>,
+
+    /// The size the tiles of composited layers are changed to, if any, unless they were given
+    /// one with `Layer::set_tile_size`.
+    tile_size: Option<Size2D<usize>>,
+
+    /// How frames are synchronized to the display, if the scenes' frame pacing is to follow it.
+    vsync: Option<VsyncMode>,
+
+    /// Whether to show how many layers are drawn over each pixel instead of their content.
+    show_overdraw: bool,
+
+    /// Whether the framebuffer is sRGB-encoded, with blending in linear space.
+    srgb: bool,
+
+    /// The shapes to draw over the next frame, in device pixels.
+    overlay_items: Vec<OverlayItem>,
+
+    /// The factors the channels of the composited frame are multiplied with, e.g. to make it
+    /// warmer at night, or `None` to leave the colors alone.
+    white_point: Option<Color>,
+
+    /// The sampler objects content is drawn with, if the context supports them.
+    samplers: Option<Samplers>,
+
+    /// The optional features of the GL context.
+    capabilities: GlCapabilities,
+
+    /// The rounded clip of the layer being drawn, applied to its textured and solid quads.
+    rounded_clip: Cell<Option<RoundedClip>>,
+
+    /// The part of the scene being recomposited, in device pixels before the pinch transform,
+    /// if it's only part of it. Layers and tiles entirely outside of it aren't drawn.
+    damage_rect: Option<TypedRect<f32, DevicePixel>>,
+}
+
+impl Drop for RenderContext {
+    fn drop(&mut self) {
+        // The pixel buffer objects of the `PixelBuffer` upload method are shared by the render
+        // contexts of this thread; they are created again by the next upload.
+        texturegl::delete_pixel_buffers();
+    }
+}
+
+/// How the embedder presents composited frames.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum VsyncMode {
+    /// Frames are presented as soon as they are composited, so no vsync is ever missed.
+    Off,
+    /// Frames are presented at the next refresh of a display refreshing at the given interval.
+    Interval(Duration),
+}
+
+impl VsyncMode {
+    /// The `FramePacing::frame_interval` of scenes presented this way.
+    fn frame_interval(&self) -> Duration {
+        match *self {
+            VsyncMode::Off => Duration::new(0, 0),
+            VsyncMode::Interval(interval) => interval,
+        }
+    }
+}
+
+/// Collects the options of a `RenderContext` before creating it, as in
+/// `RenderContextBuilder::new(display).debug_borders(true).sample_count(4).build()`, instead of
+/// a constructor argument for each of them.
+pub struct RenderContextBuilder {
+    compositing_display: NativeDisplay,
+    show_debug_borders: bool,
+    show_debug_labels: bool,
+    show_tile_age_heatmap: bool,
+    show_overdraw: bool,
+    wireframe: bool,
+    force_near_texture_filter: bool,
+    antialias_edges: bool,
+    srgb: bool,
+    sample_count: usize,
+    texture_budget: Option<usize>,
+    tile_size: Option<Size2D<usize>>,
+    vsync: Option<VsyncMode>,
+    object_label: Option<ObjectLabelFn>,
+    white_point: Option<Color>,
+    simulated_delays: Option<SimulatedDelays>,
+}
+
+impl RenderContextBuilder {
+    pub fn new(compositing_display: NativeDisplay) -> RenderContextBuilder {
+        RenderContextBuilder {
+            compositing_display: compositing_display,
+            show_debug_borders: false,
+            show_debug_labels: false,
+            show_tile_age_heatmap: false,
+            show_overdraw: false,
+            wireframe: false,
+            force_near_texture_filter: false,
+            antialias_edges: true,
+            srgb: false,
+            sample_count: 0,
+            texture_budget: None,
+            tile_size: None,
+            vsync: None,
+            object_label: None,
+            white_point: None,
+            simulated_delays: None,
+        }
+    }
+
+    /// Shows lines at layer and tile boundaries.
+    pub fn debug_borders(mut self, show_debug_borders: bool) -> RenderContextBuilder {
+        self.show_debug_borders = show_debug_borders;
+        self
+    }
+
+    /// Labels debug borders with the layer's name, size and scale.
+    pub fn debug_labels(mut self, show_debug_labels: bool) -> RenderContextBuilder {
+        self.show_debug_labels = show_debug_labels;
+        self
+    }
+
+    /// Tints tiles by the age of their content.
+    pub fn tile_age_heatmap(mut self, show_tile_age_heatmap: bool) -> RenderContextBuilder {
+        self.show_tile_age_heatmap = show_tile_age_heatmap;
+        self
+    }
+
+    /// Shows how many layers are drawn over each pixel instead of their content.
+    pub fn overdraw(mut self, show_overdraw: bool) -> RenderContextBuilder {
+        self.show_overdraw = show_overdraw;
+        self
+    }
+
+    /// Draws only the outlines of layers and tiles.
+    pub fn wireframe(mut self, wireframe: bool) -> RenderContextBuilder {
+        self.wireframe = wireframe;
+        self
+    }
+
+    /// Samples textures with nearest filtering instead of linear filtering.
+    pub fn near_texture_filter(mut self, force_near_texture_filter: bool)
+                               -> RenderContextBuilder {
+        self.force_near_texture_filter = force_near_texture_filter;
+        self
+    }
+
+    /// Antialiases the edges of layers that aren't aligned to the pixel grid. This is the
+    /// default.
+    pub fn antialias_edges(mut self, antialias_edges: bool) -> RenderContextBuilder {
+        self.antialias_edges = antialias_edges;
+        self
+    }
+
+    /// Treats the framebuffer as sRGB-encoded, so that blending happens in linear space. The
+    /// embedder must have created an sRGB-capable framebuffer.
+    pub fn srgb(mut self, srgb: bool) -> RenderContextBuilder {
+        self.srgb = srgb;
+        self
+    }
+
+    /// Renders the scene and raster cache textures into multisampled framebuffers with the given
+    /// number of samples per pixel, and resolves them afterwards. The sample count is clamped to
+    /// what the GL implementation supports, and multisampling is disabled if it is 1 or less.
+    pub fn sample_count(mut self, sample_count: usize) -> RenderContextBuilder {
+        self.sample_count = sample_count;
+        self
+    }
+
+    /// Uploads at most the given number of bytes of tiles per frame, visible tiles first, in
+    /// scenes that don't set `Scene::max_upload_bytes_per_frame`. There is no limit by default.
+    pub fn texture_budget(mut self, max_bytes_per_frame: Option<usize>) -> RenderContextBuilder {
+        self.texture_budget = max_bytes_per_frame;
+        self
+    }
+
+    /// Makes the given size the default size of the tiles of composited layers, in place of the
+    /// size they were created with, e.g. to match the texture size the GPU handles best. Layers
+    /// given a tile size with `Layer::set_tile_size` keep theirs. See
+    /// `Layer::set_default_tile_size`.
+    ///
+    /// Panics if the size is empty.
+    pub fn tile_size(mut self, tile_size: Size2D<usize>) -> RenderContextBuilder {
+        assert!(tile_size.width > 0 && tile_size.height > 0, "tiles must not be empty");
+        self.tile_size = Some(tile_size);
+        self
+    }
+
+    /// Tells the render context how the embedder presents frames, so that the frame pacing of
+    /// the composited scenes counts missed vsyncs accordingly. See `Scene::frame_pacing`.
+    pub fn vsync(mut self, vsync: VsyncMode) -> RenderContextBuilder {
+        self.vsync = Some(vsync);
+        self
+    }
+
+    /// Labels GL objects after layers with `glObjectLabel`.
+    pub fn object_label_function(mut self, object_label: Option<ObjectLabelFn>)
+                                 -> RenderContextBuilder {
+        self.object_label = object_label;
+        self
+    }
+
+    /// Multiplies the channels of composited frames with the given factors, as with
+    /// `RenderContext::set_white_point`.
+    pub fn white_point(mut self, white_point: Option<Color>) -> RenderContextBuilder {
+        self.white_point = white_point;
+        self
+    }
+
+    /// Stalls texture uploads, draws of textured content and the receipt of painted buffers on
+    /// this thread by the given delays, to test the embedder under a slow GPU and slow
+    /// painting. See the `delays` module.
+    pub fn simulated_delays(mut self, simulated_delays: SimulatedDelays)
+                            -> RenderContextBuilder {
+        self.simulated_delays = Some(simulated_delays);
+        self
+    }
+
+    /// Creates the render context for the GL context current on this thread.
+    ///
+    /// The context owns GL resources that are reused across frames, and must be dropped while
+    /// its GL context is current.
+    pub fn build(self) -> RenderContext {
+        if let Some(simulated_delays) = self.simulated_delays {
+            SimulatedDelays::set_current(simulated_delays);
+        }
+
+        let capabilities = GlCapabilities::current();
+        if !capabilities.core_profile {
+            gl::enable(gl::TEXTURE_2D);
+        }
+
+        // Each layer uses premultiplied alpha!
+        gl::enable(gl::BLEND);
+        gl::blend_func(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+
+        let solid_color_program = SolidColorProgram::new(false);
+        let rounded_clip_solid_color_program = SolidColorProgram::new(true);
+        let box_shadow_program = BoxShadowProgram::new();
+        let checkerboard_program = CheckerboardProgram::new(0);
+        let glyph_program = GlyphProgram::new();
+        let backdrop_program = BackdropProgram::new(BACKDROP_BLUR_FRAGMENT_SHADER_SOURCE);
+        let backdrop_downsample_program =
+            BackdropProgram::new(BACKDROP_DOWNSAMPLE_FRAGMENT_SHADER_SOURCE);
+
+        let glyph_atlas = Texture::new(TextureTarget2D, debugfont::atlas_size());
+        glyph_atlas.upload_bgra(debugfont::atlas_size(), &debugfont::atlas_pixels());
+        glyph_atlas.set_filter_mode(FilterMode::Nearest);
+
+        RenderContext {
+            texture_programs: TexturePrograms::new(),
+            solid_color_program: solid_color_program,
+            rounded_clip_solid_color_program: rounded_clip_solid_color_program,
+            box_shadow_program: box_shadow_program,
+            checkerboard_program: checkerboard_program,
+            masked_checkerboard_programs: RefCell::new(HashMap::new()),
+            glyph_program: glyph_program,
+            backdrop_program: backdrop_program,
+            backdrop_downsample_program: backdrop_downsample_program,
+            backdrop_targets: RefCell::new(None),
+            buffers: RenderContext::init_buffers(),
+            glyph_atlas: glyph_atlas,
+            compositing_display: self.compositing_display,
+            show_debug_borders: self.show_debug_borders,
+            force_near_texture_filter: self.force_near_texture_filter,
+            tile_placeholder: TilePlaceholder::Background,
+            scene_scale: ScaleFactor::new(1.0),
+            pixel_snapping: PixelSnapping::Never,
+            object_label: self.object_label,
+            show_tile_age_heatmap: self.show_tile_age_heatmap,
+            show_debug_labels: self.show_debug_labels,
+            wireframe: self.wireframe,
+            y_axis_up: false,
+            mirrored: false,
+            antialias_edges: self.antialias_edges,
+            sample_count: multisample::supported_sample_count(self.sample_count),
+            scene_target: None,
+            raster_cache_target: RefCell::new(None),
+            texture_budget: self.texture_budget,
+            tile_size: self.tile_size,
+            vsync: self.vsync,
+            show_overdraw: self.show_overdraw,
+            srgb: self.srgb,
+            overlay_items: vec!(),
+            white_point: self.white_point,
+            samplers: Samplers::new(),
+            capabilities: capabilities,
+            rounded_clip: Cell::new(None),
+            damage_rect: None,
+        }
+    }
+}
+
+impl RenderContext {
+    /// Creates a render context for the GL context current on this thread, with the defaults of
+    /// `RenderContextBuilder` for everything else.
+    pub fn new(compositing_display: NativeDisplay,
+               show_debug_borders: bool,
+               force_near_texture_filter: bool) -> RenderContext {
+        RenderContextBuilder::new(compositing_display)
+            .debug_borders(show_debug_borders)
+            .near_texture_filter(force_near_texture_filter)
+            .build()
+    }
+
+    /// The debug flags of the frame constants.
+    fn debug_flags(&self) -> i32 {
+        let mut debug_flags = 0;
+        if self.show_overdraw {
+            debug_flags |= frameconstants::DEBUG_FLAG_SHOW_OVERDRAW;
+        }
+        if self.wireframe {
+            debug_flags |= frameconstants::DEBUG_FLAG_WIREFRAME;
+        }
+        if self.show_tile_age_heatmap {
+            debug_flags |= frameconstants::DEBUG_FLAG_TILE_AGE_HEATMAP;
+        }
+        if self.show_debug_borders {
+            debug_flags |= frameconstants::DEBUG_FLAG_DEBUG_BORDERS;
+        }
+        debug_flags
+    }
+
+    /// The optional features of the GL context, which the compositor falls back from when they
+    /// are missing.
+    pub fn capabilities(&self) -> GlCapabilities {
+        self.capabilities
+    }
+
+    /// Whether layer contents are drawn, as opposed to one of the views that replace them.
+    fn draws_content(&self) -> bool {
+        !self.wireframe && !self.show_overdraw
+    }
+
+    /// The number of samples per pixel used for rendering, after clamping to what the GL
+    /// implementation supports. This is 0 if multisampling is disabled.
+    pub fn sample_count(&self) -> usize {
+        self.sample_count
+    }
+
+    /// Enables or disables the tile age heatmap debug overlay.
+    pub fn set_show_tile_age_heatmap(&mut self, show_tile_age_heatmap: bool) {
+        self.show_tile_age_heatmap = show_tile_age_heatmap;
+    }
+
+    /// Enables or disables labels on layer debug borders. Labels are only drawn when debug
+    /// borders are shown.
+    pub fn set_show_debug_labels(&mut self, show_debug_labels: bool) {
+        self.show_debug_labels = show_debug_labels;
+    }
+
+    /// Enables or disables antialiasing of the edges of layers that aren't aligned to the pixel
+    /// grid, and of clip edges that fall between device pixels. It is enabled by default.
+    pub fn set_antialias_edges(&mut self, antialias_edges: bool) {
+        self.antialias_edges = antialias_edges;
+    }
+
+    /// Enables or disables the overdraw view, in which every layer with content adds a shade
+    /// of red to the pixels it covers, so that areas drawn many times stand out.
+    pub fn set_show_overdraw(&mut self, show_overdraw: bool) {
+        self.show_overdraw = show_overdraw;
+    }
+
+    /// Enables or disables wireframe mode, in which no textures are drawn and only the outlines
+    /// of layers and tiles are rendered. This shows the tiling structure even where content
+    /// would hide the debug borders, and isolates the cost of traversal and draw calls.
+    pub fn set_wireframe(&mut self, wireframe: bool) {
+        self.wireframe = wireframe;
+    }
+
+    /// Sets the `glObjectLabel` entry point, which the embedder can load through the same
+    /// mechanism as the rest of GL when KHR_debug is available. Textures and framebuffers created
+    /// for layers are then labeled with the layer's debug name in GL debuggers.
+    pub fn set_object_label_function(&mut self, object_label: Option<ObjectLabelFn>) {
+        self.object_label = object_label;
+    }
+
+    /// Shifts the white point of composited frames by multiplying their channels with the
+    /// given factors, e.g. `Color::from_temperature(3400.0)` for a night light. This is applied
+    /// to whole frames as they are composited, so content doesn't need to be repainted. `None`
+    /// leaves the colors alone, which is the default.
+    pub fn set_white_point(&mut self, white_point: Option<Color>) {
+        self.white_point = white_point;
+    }
+
+    /// Queues a shape to draw over the next frame rendered with this context. Items are drawn
+    /// in the order they were added, in device pixels relative to the scene's viewport and with
+    /// the scene's coordinate conventions, unaffected by the pinch transform.
+    pub fn add_overlay_item(&mut self, item: OverlayItem) {
+        self.overlay_items.push(item);
+    }
+
+    /// Removes the shapes queued for the next frame.
+    pub fn clear_overlay_items(&mut self) {
+        self.overlay_items.clear();
+    }
+
+    fn render_overlay_items(&mut self, projection: &Matrix4D<f32>) {
+        if self.overlay_items.is_empty() {
+            return;
+        }
+
+        gl::disable(gl::DEPTH_TEST);
+        for item in &self.overlay_items {
+            let color = item.color();
+            if let OverlayItem::Text(origin, ref text, pixel_size, _) = *item {
+                self.render_text(text, &origin.to_untyped(), pixel_size, &color, projection);
+                continue;
+            }
+            for quad in item.quads() {
+                let vertices = [
+                    ColorVertex::new(quad[0]),
+                    ColorVertex::new(quad[1]),
+                    ColorVertex::new(quad[2]),
+                    ColorVertex::new(quad[3]),
+                ];
+                self.bind_and_render_solid_quad(&vertices,
+                                                &Matrix4D::identity(),
+                                                projection,
+                                                &color);
+            }
+        }
+        gl::enable(gl::DEPTH_TEST);
+        self.overlay_items.clear();
+    }
+
+    /// Multiplies the frame composited so far with the white point, by drawing it over the
+    /// viewport with multiplicative blending.
+    fn render_white_point(&self,
+                          viewport_size: &TypedSize2D<f32, DevicePixel>,
+                          projection: &Matrix4D<f32>) {
+        let white_point = match self.white_point {
+            Some(white_point) => Color { a: 1.0, ..white_point },
+            None => return,
+        };
+
+        let rect = Rect::new(Point2D::zero(), viewport_size.to_untyped());
+        let vertices = [
+            ColorVertex::new(rect.origin),
+            ColorVertex::new(rect.top_right()),
+            ColorVertex::new(rect.bottom_left()),
+            ColorVertex::new(rect.bottom_right()),
+        ];
+        gl::disable(gl::DEPTH_TEST);
+        gl::blend_func(gl::DST_COLOR, gl::ZERO);
+        self.bind_and_render_solid_quad(&vertices, &Matrix4D::identity(), projection, &white_point);
+        gl::blend_func(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+        gl::enable(gl::DEPTH_TEST);
+    }
+
+    /// Compiles the texture program variants that most frames draw with, and creates the
+    /// multisampled framebuffer for a viewport of the given size, so that the first frame
+    /// doesn't wait for them. Other variants are still compiled when first drawn with.
+    fn prepare(&mut self, viewport_size: Size2D<GLsizei>) {
+        let mut variants = vec!(0, TEXTURE_PROGRAM_OPACITY);
+        // GPU-painted buffers are IOSurfaces bound to rectangle textures.
+        if cfg!(all(target_os = "macos", feature = "native-surfaces")) &&
+           self.capabilities.rectangle_textures {
+            variants.push(TEXTURE_PROGRAM_RECTANGLE);
+            variants.push(TEXTURE_PROGRAM_RECTANGLE | TEXTURE_PROGRAM_OPACITY);
+        }
+        for &features in &variants {
+            self.texture_programs.get(features);
+        }
+
+        if self.sample_count > 1 {
+            let framebuffer = gl::get_integer_v(gl::FRAMEBUFFER_BINDING) as GLuint;
+            self.bind_scene_target(viewport_size);
+            gl::bind_framebuffer(gl::FRAMEBUFFER, framebuffer);
+        }
+    }
+
+    /// Binds the multisampled framebuffer for the scene, creating it if the viewport size
+    /// changed. Returns false if it couldn't be created, in which case the current framebuffer
+    /// is left bound.
+    fn bind_scene_target(&mut self, size: Size2D<GLsizei>) -> bool {
+        let reusable = self.scene_target.as_ref().map_or(false, |target| {
+            target.matches(size, self.sample_count)
+        });
+        if !reusable {
+            self.scene_target = None;
+            self.scene_target = MultisampleTarget::new(size, self.sample_count);
+        }
+        match self.scene_target {
+            Some(ref target) => {
+                target.bind();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn label_object(&self, identifier: GLenum, name: GLuint, label: &str) {
+        if let Some(object_label) = self.object_label {
+            object_label(identifier, name, label.len() as GLsizei, label.as_ptr() as *const c_char);
+        }
+    }
+
+    fn init_buffers() -> Buffers {
+        let quad_vertex_buffer = gl::gen_buffers(1)[0];
+        gl::bind_buffer(gl::ARRAY_BUFFER, quad_vertex_buffer);
+
+        let line_quad_vertex_buffer = gl::gen_buffers(1)[0];
+        gl::bind_buffer(gl::ARRAY_BUFFER, line_quad_vertex_buffer);
+
+        let vertex_array = if GlCapabilities::current().core_profile {
+            let vertex_array = gl::gen_vertex_arrays(1)[0];
+            gl::bind_vertex_array(vertex_array);
+            Some(vertex_array)
+        } else {
+            None
+        };
+
+        Buffers {
+            quad_vertex_buffer: quad_vertex_buffer,
+            line_quad_vertex_buffer: line_quad_vertex_buffer,
+            vertex_array: vertex_array,
+            frame_constants: FrameConstants::new(),
+        }
+    }
+
+    fn bind_and_render_solid_quad(&self,
+                                  vertices: &[ColorVertex; 4],
+                                  transform: &Matrix4D<f32>,
+                                  projection: &Matrix4D<f32>,
+                                  color: &Color) {
+        self.bind_and_render_antialiased_solid_quad(vertices, transform, projection, color, None)
+    }
+
+    fn bind_and_render_antialiased_solid_quad(&self,
+                                              vertices: &[ColorVertex; 4],
+                                              transform: &Matrix4D<f32>,
+                                              projection: &Matrix4D<f32>,
+                                              color: &Color,
+                                              edge_antialiasing: Option<&EdgeAntialiasing>) {
+        let rounded_clip = self.rounded_clip.get();
+        let program = if rounded_clip.is_some() {
+            &self.rounded_clip_solid_color_program
+        } else {
+            &self.solid_color_program
+        };
+        program.enable_attribute_arrays();
+        gl::use_program(program.program.id);
+        program.bind_uniforms_and_attributes_for_quad(vertices,
+                                                      transform,
+                                                      projection,
+                                                      &self.buffers,
+                                                      color,
+                                                      edge_antialiasing);
+        if let Some(ref rounded_clip) = rounded_clip {
+            program.rounded_clip_uniforms.bind(rounded_clip);
+        }
+        gl::draw_arrays(gl::TRIANGLE_STRIP, 0, 4);
+        program.disable_attribute_arrays();
+    }
+
+    /// Draws a textured quad, whose vertices `transform` maps from `Src` units to device
+    /// pixels. Content textures, as opposed to textures rendered by the compositor itself, are
+    /// flipped to stay upright in scenes with a y-up or mirrored coordinate system.
+    fn bind_and_render_quad<Src>(&self,
+                                 vertices: &[TextureVertex; 4],
+                                 texture: &Texture,
+                                 is_content: bool,
+                                 mask: Option<&QuadMask>,
+                                 edge_antialiasing: Option<&EdgeAntialiasing>,
+                                 transform: &TypedTransform<Src, DevicePixel>,
+                                 projection: &TypedTransform<DevicePixel, ClipSpace>,
+                                 opacity: f32) {
+        let mut features = self.texture_features(texture, opacity);
+        if let Some(mask) = mask {
+            features |= mask.features();
+        }
+        let rounded_clip = self.rounded_clip.get();
+        let program = self.texture_programs.get(features);
+        program.enable_attribute_arrays();
+
+        gl::use_program(program.program.id);
+        let content_unit = [(CONTENT_TEXTURE_UNIT, texture)];
+        let content_and_mask_units;
+        let units: &[(GLuint, &Texture)] = match mask {
+            Some(mask) => {
+                content_and_mask_units = [(CONTENT_TEXTURE_UNIT, texture),
+                                          (MASK_TEXTURE_UNIT, mask.texture)];
+                &content_and_mask_units
+            }
+            None => &content_unit,
+        };
+        let _bound_units = BoundTextureUnits::bind(units);
+
+        let _bound_sampler = self.bind_content_sampler(texture.target, texture.wrap_mode());
+
+        let rectangle_size = match texture.target {
+            TextureTargetRectangle => Some(texture.size),
+            _ => None,
+        };
+        let texture_transform =
+            self.texture_transform(rectangle_size, texture.orientation, is_content);
+        if features & TEXTURE_PROGRAM_NEAREST_FILTER != 0 {
+            program.bind_texture_size(&nearest_filter_size(texture));
+        }
+
+        program.bind_uniforms_and_attributes(vertices,
+                                             &transform.to_untyped(),
+                                             &projection.to_untyped(),
+                                             &texture_transform,
+                                             mask.map(|mask| &mask.transform),
+                                             edge_antialiasing,
+                                             rounded_clip.as_ref(),
+                                             &self.buffers,
+                                             opacity);
+
+        // Draw!
+        delays::simulate(SimulatedDelays::current().draw_batch);
+        gl::draw_arrays(gl::TRIANGLE_STRIP, 0, 4);
+        program.disable_attribute_arrays()
+    }
+
+    /// Draws the part `rect` of a video frame stretched over `frame_rect`, converting it to RGB.
+    fn render_video_frame(&self,
+                          frame: &VideoFrame,
+                          rect: &Rect<f32>,
+                          frame_rect: &Rect<f32>,
+                          mask: Option<&QuadMask>,
+                          edge_antialiasing: Option<&EdgeAntialiasing>,
+                          transform: &TypedTransform<LayerPixel, DevicePixel>,
+                          projection: &TypedTransform<DevicePixel, ClipSpace>,
+                          opacity: f32) {
+        if frame_rect.is_empty() {
+            return;
+        }
+
+        let rounded_clip = self.rounded_clip.get();
+        let mut features = self.texture_features(&frame.planes[0], opacity) | TEXTURE_PROGRAM_YUV;
+        if frame.format == YuvFormat::NV12 {
+            features |= TEXTURE_PROGRAM_NV12;
+        }
+        if let Some(mask) = mask {
+            features |= mask.features();
+        }
+        let program = self.texture_programs.get(features);
+
+        let vertices = stretched_quad_vertices(rect, frame_rect, edge_antialiasing);
+        program.enable_attribute_arrays();
+        gl::use_program(program.program.id);
+        let mut units: Vec<(GLuint, &Texture)> = [CONTENT_TEXTURE_UNIT,
+                                                  CHROMA_TEXTURE_UNITS[0],
+                                                  CHROMA_TEXTURE_UNITS[1]].iter()
+                                                                          .cloned()
+                                                                          .zip(frame.planes.iter())
+                                                                          .collect();
+        if let Some(mask) = mask {
+            units.push((MASK_TEXTURE_UNIT, mask.texture));
+        }
+        let _bound_units = BoundTextureUnits::bind(&units);
+        let _bound_sampler = self.bind_content_sampler(TextureTarget2D,
+                                                       frame.planes[0].wrap_mode());
+
+        let texture_transform = self.texture_transform(None, Orientation::identity(), true);
+        program.bind_uniforms_and_attributes(&vertices,
+                                             &transform.to_untyped(),
+                                             &projection.to_untyped(),
+                                             &texture_transform,
+                                             mask.map(|mask| &mask.transform),
+                                             edge_antialiasing,
+                                             rounded_clip.as_ref(),
+                                             &self.buffers,
+                                             opacity);
+        program.bind_yuv_uniforms(&frame.color_matrix);
+        if features & TEXTURE_PROGRAM_NEAREST_FILTER != 0 {
+            program.bind_texture_size(&nearest_filter_size(&frame.planes[0]));
+        }
+        gl::draw_arrays(gl::TRIANGLE_STRIP, 0, 4);
+        program.disable_attribute_arrays();
+    }
+
+    /// Draws the part `rect` of a texture owned by the embedder stretched over `texture_rect`.
+    fn render_texture_layer(&self,
+                            texture_layer: &TextureLayer,
+                            rect: &Rect<f32>,
+                            texture_rect: &Rect<f32>,
+                            mask: Option<&QuadMask>,
+                            edge_antialiasing: Option<&EdgeAntialiasing>,
+                            transform: &TypedTransform<LayerPixel, DevicePixel>,
+                            projection: &TypedTransform<DevicePixel, ClipSpace>,
+                            opacity: f32) {
+        if texture_rect.is_empty() {
+            return;
+        }
+        let vertices = stretched_quad_vertices(rect, texture_rect, edge_antialiasing);
+        self.bind_and_render_quad(&vertices,
+                                  texture_layer.texture(),
+                                  true,
+                                  mask,
+                                  edge_antialiasing,
+                                  transform,
+                                  projection,
+                                  opacity);
+    }
+
+    /// Binds the sampler that content textures with the given wrap modes are drawn with to the
+    /// content texture unit, or filters the texture bound there linearly if there are no
+    /// sampler objects. sRGB-encoded textures are linearized only for sRGB framebuffers, which
+    /// blend in linear space.
+    fn bind_content_sampler(&self, target: TextureTarget, wrap_mode: (WrapMode, WrapMode))
+                            -> Option<BoundSampler> {
+        let filter_mode = if self.force_near_texture_filter {
+            FilterMode::Nearest
+        } else {
+            FilterMode::Linear
+        };
+        match self.samplers {
+            Some(ref samplers) => {
+                Some(samplers.bind(CONTENT_TEXTURE_UNIT, filter_mode, wrap_mode, self.srgb))
+            }
+            None => {
+                // The program picks the nearest texel itself; see
+                // `TEXTURE_PROGRAM_NEAREST_FILTER`.
+                let target = target.as_gl_target();
+                gl::tex_parameter_i(target, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+                gl::tex_parameter_i(target, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+                None
+            }
+        }
+    }
+
+    /// The `TEXTURE_PROGRAM_*` features of the program that draws `texture` with `opacity`,
+    /// other than masks and video.
+    fn texture_features(&self, texture: &Texture, opacity: f32) -> u32 {
+        let mut features = 0;
+        if let TextureTargetRectangle = texture.target {
+            features |= TEXTURE_PROGRAM_RECTANGLE;
+        }
+        if self.force_near_texture_filter && self.samplers.is_none() {
+            features |= TEXTURE_PROGRAM_NEAREST_FILTER;
+        }
+        if texture.shader_swizzle() == Swizzle::Bgra {
+            features |= TEXTURE_PROGRAM_SWIZZLE;
+        }
+        if opacity != 1.0 {
+            features |= TEXTURE_PROGRAM_OPACITY;
+        }
+        if self.rounded_clip.get().is_some() {
+            features |= TEXTURE_PROGRAM_ROUNDED_CLIP;
+        }
+        features
+    }
+
+    /// The transformation matrix for texture coordinates, which orients the texture and scales
+    /// the coordinates to the size of rectangle textures.
+    fn texture_transform(&self,
+                         rectangle_size: Option<Size2D<usize>>,
+                         orientation: Orientation,
+                         is_content: bool)
+                         -> Matrix4D<f32> {
+        let mut texture_transform = Matrix4D::identity();
+        if let Some(size) = rectangle_size {
+            texture_transform =
+                texture_transform.pre_scaled(size.width as f32, size.height as f32, 1.0);
+        }
+        let orientation_transform =
+            orientation.texture_transform(is_content && self.mirrored,
+                                          is_content && self.y_axis_up);
+        texture_transform.pre_mul(&orientation_transform)
+    }
+
+    /// Adds a textured quad to `batch`, drawing the quads already in it first if the new one
+    /// can't be drawn along with them. See `bind_and_render_quad` for the parameters.
+    fn batch_quad(&self,
+                  batch: &mut QuadBatch,
+                  vertices: &[TextureVertex; 4],
+                  texture: &Texture,
+                  is_content: bool,
+                  edge_antialiasing: Option<&EdgeAntialiasing>,
+                  transform: &TypedTransform<LayerPixel, DevicePixel>,
+                  projection: &TypedTransform<DevicePixel, ClipSpace>,
+                  opacity: f32) {
+        let rounded_clip = self.rounded_clip.get();
+        let features = self.texture_features(texture, opacity);
+        let rectangle_size = match texture.target {
+            TextureTargetRectangle => Some(texture.size),
+            _ => None,
+        };
+        let nearest_filter_size = if features & TEXTURE_PROGRAM_NEAREST_FILTER != 0 {
+            Some(nearest_filter_size(texture))
+        } else {
+            None
+        };
+        let state = QuadBatchState {
+            features: features,
+            target: texture.target,
+            rectangle_size: rectangle_size,
+            nearest_filter_size: nearest_filter_size,
+            orientation: texture.orientation,
+            wrap_mode: texture.wrap_mode(),
+            is_content: is_content,
+            edge_antialiasing: edge_antialiasing.cloned(),
+            rounded_clip: rounded_clip,
+            transform: transform.to_untyped(),
+            projection: projection.to_untyped(),
+            opacity: opacity,
+        };
+        if !batch.accepts(&state) {
+            self.render_quad_batch(batch);
+        }
+        batch.push(state, texture.native_texture(), vertices);
+    }
+
+    /// Draws the quads of `batch`, and empties it.
+    fn render_quad_batch(&self, batch: &mut QuadBatch) {
+        let state = match batch.state {
+            Some(state) => state,
+            None => return,
+        };
+        let program = self.texture_programs.get(state.features);
+        program.enable_attribute_arrays();
+        gl::use_program(program.program.id);
+
+        let texture_transform =
+            self.texture_transform(state.rectangle_size, state.orientation, state.is_content);
+        program.bind_uniforms_and_attributes(&batch.vertices,
+                                             &state.transform,
+                                             &state.projection,
+                                             &texture_transform,
+                                             None,
+                                             state.edge_antialiasing.as_ref(),
+                                             state.rounded_clip.as_ref(),
+                                             &self.buffers,
+                                             state.opacity);
+        if let Some(ref nearest_filter_size) = state.nearest_filter_size {
+            program.bind_texture_size(nearest_filter_size);
+        }
+
+        let target = state.target.as_gl_target();
+        let mut first = 0;
+        for &(texture, count) in &batch.runs {
+            gl::bind_texture(target, texture);
+            let _bound_sampler = self.bind_content_sampler(state.target, state.wrap_mode);
+            delays::simulate(SimulatedDelays::current().draw_batch);
+            gl::draw_arrays(gl::TRIANGLES, first as GLint, count as GLsizei);
+            first += count;
+        }
+        gl::bind_texture(target, 0);
+        program.disable_attribute_arrays();
+        batch.clear();
+    }
+
+    pub fn bind_and_render_quad_lines(&self,
+                                      vertices: &[ColorVertex; 5],
+                                      transform: &Matrix4D<f32>,
+                                      projection: &Matrix4D<f32>,
+                                      color: &Color,
+                                      line_thickness: usize) {
+        self.solid_color_program.enable_attribute_arrays();
+        gl::use_program(self.solid_color_program.program.id);
+        self.solid_color_program.bind_uniforms_and_attributes_for_lines(vertices,
+                                                                        transform,
+                                                                        projection,
                                                                         &self.buffers,
                                                                         color);
         gl::line_width(line_thickness as GLfloat);
@@ -624,87 +2947,853 @@ impl RenderContext {
 
     fn render_layer<T>(&self,
                        layer: Rc<Layer<T>>,
-                       transform: &Matrix4D<f32>,
-                       projection: &Matrix4D<f32>,
-                       clip_rect: Option<Rect<f32>>,
-                       gfx_context: &NativeDisplay) {
+                       transform: &TypedTransform<LayerPixel, DevicePixel>,
+                       projection: &TypedTransform<DevicePixel, ClipSpace>,
+                       clip_rect: Option<TypedRect<f32, LayerPixel>>) {
+        let _span = profiler::span_with(|| Cow::Owned(layer.debug_name()));
+        if let Some(drawn_rect) = layer_screen_rect(&layer) {
+            if !self.is_damaged(&(drawn_rect * self.scene_scale)) {
+                return;
+            }
+        }
+
         let ts = layer.transform_state.borrow();
-        let transform = transform.pre_mul(&ts.final_transform);
-        let background_color = *layer.background_color.borrow();
+        let layer_transform = snapped_layer_transform(self.pixel_snapping, &ts, transform);
 
-        // Create native textures for this layer
-        layer.create_textures(gfx_context);
+        // The helpers for solid colors, borders and debugging take untyped values.
+        let transform = layer_transform.to_untyped();
+        let untyped_projection = projection.to_untyped();
+        let untyped_clip_rect = clip_rect.map(|clip_rect| clip_rect.to_untyped());
+        let background_color = *layer.background_color.borrow();
 
-        let layer_rect = clip_rect.map_or(ts.world_rect, |clip_rect| {
+        let layer_rect = untyped_clip_rect.map_or(ts.world_rect, |clip_rect| {
             match clip_rect.intersection(&ts.world_rect) {
                 Some(layer_rect) => layer_rect,
                 None => Rect::zero(),
             }
         });
 
+        if self.wireframe {
+            if !layer_rect.is_empty() {
+                layer.do_for_all_tiles(|tile: &Tile| {
+                    self.render_tile_outline(tile,
+                                             &ts.world_rect.origin,
+                                             &transform,
+                                             &untyped_projection,
+                                             untyped_clip_rect);
+                });
+                self.render_layer_outline(&layer_rect, &transform, &untyped_projection);
+            }
+            return;
+        }
+
+        if self.show_overdraw {
+            let has_content = background_color.a != 0.0 || layer.tile_count() > 0;
+            if has_content && !layer_rect.is_empty() {
+                let vertices = [
+                    ColorVertex::new(layer_rect.origin),
+                    ColorVertex::new(layer_rect.top_right()),
+                    ColorVertex::new(layer_rect.bottom_left()),
+                    ColorVertex::new(layer_rect.bottom_right()),
+                ];
+                self.bind_and_render_solid_quad(&vertices,
+                                                &transform,
+                                                &untyped_projection,
+                                                &OVERDRAW_COLOR);
+            }
+            return;
+        }
+
+        if let Some(ref shadow) = *layer.shadow.borrow() {
+            self.render_box_shadow(shadow,
+                                   &ts.world_rect,
+                                   untyped_clip_rect,
+                                   &transform,
+                                   &untyped_projection);
+        }
+
         if layer_rect.is_empty() {
             return;
         }
 
-        if background_color.a != 0.0 {
-            let bg_vertices = [
-                ColorVertex::new(layer_rect.origin),
-                ColorVertex::new(layer_rect.top_right()),
-                ColorVertex::new(layer_rect.bottom_left()),
-                ColorVertex::new(layer_rect.bottom_right()),
-            ];
+        if layer.hole.borrow().is_some() {
+            self.render_hole(&layer_rect, &transform, &untyped_projection);
+            return;
+        }
+
+        if let Some(ref backdrop_filter) = *layer.backdrop_filter.borrow() {
+            self.render_backdrop_filter(backdrop_filter,
+                                        &layer_rect,
+                                        &transform,
+                                        &untyped_projection,
+                                        ts.opacity);
+        }
+
+        let edge_antialiasing = if !self.antialias_edges {
+            None
+        } else if clip_rect.is_some() {
+            EdgeAntialiasing::for_transform(&layer_rect, &transform).or_else(|| {
+                EdgeAntialiasing::for_fractional_clip(&layer_rect, &transform)
+            })
+        } else {
+            EdgeAntialiasing::for_transform(&layer_rect, &transform)
+        };
+
+        if background_color.a != 0.0 {
+            let bg_rect = edge_antialiasing.map_or(layer_rect, |edge_antialiasing| {
+                edge_antialiasing.expand(&layer_rect)
+            });
+            let bg_vertices = [
+                ColorVertex::new(bg_rect.origin),
+                ColorVertex::new(bg_rect.top_right()),
+                ColorVertex::new(bg_rect.bottom_left()),
+                ColorVertex::new(bg_rect.bottom_right()),
+            ];
+
+            self.bind_and_render_antialiased_solid_quad(&bg_vertices,
+                                                        &transform,
+                                                        &untyped_projection,
+                                                        &background_color,
+                                                        edge_antialiasing.as_ref());
+        }
+
+        // The mask is stretched over the bounds of the layer.
+        let layer_mask = layer.mask.borrow();
+        let mask = match *layer_mask {
+            Some(ref mask_texture) => QuadMask::new(mask_texture, &ts.world_rect),
+            None => None,
+        };
+
+        if layer_mask.is_some() && mask.is_none() {
+            // Nothing of the content shows through an empty mask.
+        } else if let Some(ref frame) = *layer.video_frame.borrow() {
+            self.render_video_frame(frame,
+                                    &layer_rect,
+                                    &ts.world_rect,
+                                    mask.as_ref(),
+                                    edge_antialiasing.as_ref(),
+                                    &layer_transform,
+                                    projection,
+                                    ts.opacity);
+        } else if let Some(ref texture_layer) = *layer.texture_layer.borrow() {
+            self.render_texture_layer(texture_layer,
+                                      &layer_rect,
+                                      &ts.world_rect,
+                                      mask.as_ref(),
+                                      edge_antialiasing.as_ref(),
+                                      &layer_transform,
+                                      projection,
+                                      ts.opacity);
+        } else {
+            let mut batch = QuadBatch::new();
+            match *layer.content_repeat.borrow() {
+                Some(ref repeat) => {
+                    // Draw the pattern's tiles once per repetition, clipped to the layer.
+                    // Patterns smaller than a pixel are not worth drawing.
+                    let pattern_size = repeat.pattern_size.to_untyped();
+                    let (columns, rows) = if pattern_size.width < 1.0 || pattern_size.height < 1.0 {
+                        (0, 0)
+                    } else {
+                        (if repeat.mode.repeats_x() {
+                             (ts.world_rect.size.width / pattern_size.width).ceil() as usize
+                         } else {
+                             1
+                         },
+                         if repeat.mode.repeats_y() {
+                             (ts.world_rect.size.height / pattern_size.height).ceil() as usize
+                         } else {
+                             1
+                         })
+                    };
+
+                    // The repetitions of each tile are consecutive, so that they are drawn with
+                    // a single draw call. Repetitions don't overlap, so their order doesn't matter.
+                    layer.do_for_all_tiles(|tile: &Tile| {
+                        for row in 0..rows {
+                            for column in 0..columns {
+                                let origin = Point2D::new(
+                                    ts.world_rect.origin.x + column as f32 * pattern_size.width,
+                                    ts.world_rect.origin.y + row as f32 * pattern_size.height);
+                                self.render_tile(&mut batch,
+                                                 tile,
+                                                 &origin,
+                                                 &layer_transform,
+                                                 projection,
+                                                 Some(TypedRect::from_untyped(&layer_rect)),
+                                                 mask.as_ref(),
+                                                 edge_antialiasing.as_ref(),
+                                                 ts.opacity);
+                            }
+                        }
+                    });
+                }
+                None => {
+                    self.render_tile_placeholders(&layer,
+                                                  &ts.world_rect.origin,
+                                                  &layer_transform,
+                                                  projection,
+                                                  clip_rect,
+                                                  mask.as_ref());
+                    layer.do_for_all_tiles(|tile: &Tile| {
+                       self.render_tile(&mut batch,
+                                        tile,
+                                        &ts.world_rect.origin,
+                                        &layer_transform,
+                                        projection,
+                                        clip_rect,
+                                        mask.as_ref(),
+                                        edge_antialiasing.as_ref(),
+                                        ts.opacity);
+                    });
+                }
+            }
+            self.render_quad_batch(&mut batch);
+        }
+
+        for highlight in layer.highlights.borrow().iter() {
+            // Colors are premultiplied by the solid color shader, so every channel is faded.
+            let color = Color {
+                r: highlight.color.r * ts.opacity,
+                g: highlight.color.g * ts.opacity,
+                b: highlight.color.b * ts.opacity,
+                a: highlight.color.a * ts.opacity,
+            };
+            let rect = highlight.rect.to_untyped().translate(&ts.world_rect.origin);
+            if let Some(rect) = rect.intersection(&layer_rect) {
+                let vertices = [
+                    ColorVertex::new(rect.origin),
+                    ColorVertex::new(rect.top_right()),
+                    ColorVertex::new(rect.bottom_left()),
+                    ColorVertex::new(rect.bottom_right()),
+                ];
+                self.bind_and_render_solid_quad(&vertices,
+                                                &transform,
+                                                &untyped_projection,
+                                                &color);
+            }
+        }
+
+        if let Some(ref border) = *layer.border.borrow() {
+            self.render_border(border,
+                               &ts.world_rect,
+                               untyped_clip_rect,
+                               &transform,
+                               &untyped_projection);
+        }
+
+        if self.show_debug_borders {
+            self.render_layer_outline(&layer_rect, &transform, &untyped_projection);
+
+            let aabb = ts.screen_rect.as_ref().unwrap().rect;
+            let debug_vertices = [
+                ColorVertex::new(aabb.origin),
+                ColorVertex::new(aabb.top_right()),
+                ColorVertex::new(aabb.bottom_right()),
+                ColorVertex::new(aabb.bottom_left()),
+                ColorVertex::new(aabb.origin),
+            ];
+            self.bind_and_render_quad_lines(&debug_vertices,
+                                            &Matrix4D::identity(),
+                                            &untyped_projection,
+                                            &LAYER_AABB_DEBUG_BORDER_COLOR,
+                                            LAYER_AABB_DEBUG_BORDER_THICKNESS);
+
+            if self.show_debug_labels {
+                let bounds = layer.bounds.borrow();
+                let label = format!("{}\n{}x{} at {},{}\nscale {:.2}",
+                                    layer.debug_name(),
+                                    bounds.size.width,
+                                    bounds.size.height,
+                                    bounds.origin.x,
+                                    bounds.origin.y,
+                                    self.scene_scale.get());
+                self.render_debug_label(&label, &aabb.origin, &untyped_projection);
+            }
+        }
+    }
+
+    fn render_layer_outline(&self,
+                            layer_rect: &Rect<f32>,
+                            transform: &Matrix4D<f32>,
+                            projection: &Matrix4D<f32>) {
+        let debug_vertices = [
+            ColorVertex::new(layer_rect.origin),
+            ColorVertex::new(layer_rect.top_right()),
+            ColorVertex::new(layer_rect.bottom_right()),
+            ColorVertex::new(layer_rect.bottom_left()),
+            ColorVertex::new(layer_rect.origin),
+        ];
+        self.bind_and_render_quad_lines(&debug_vertices,
+                                        transform,
+                                        projection,
+                                        &LAYER_DEBUG_BORDER_COLOR,
+                                        LAYER_DEBUG_BORDER_THICKNESS);
+    }
+
+    /// Draws `text` in the embedded debug font, with its top left corner at `origin` in screen
+    /// coordinates.
+    fn render_debug_label(&self,
+                          text: &str,
+                          origin: &Point2D<f32>,
+                          projection: &Matrix4D<f32>) {
+        let (width, height) = debugfont::text_size(text);
+        let background_rect =
+            Rect::new(*origin,
+                      Size2D::new(width as f32 * LAYER_DEBUG_LABEL_PIXEL_SIZE +
+                                      LAYER_DEBUG_LABEL_PADDING * 2.0,
+                                  height as f32 * LAYER_DEBUG_LABEL_PIXEL_SIZE +
+                                      LAYER_DEBUG_LABEL_PADDING * 2.0));
+        self.render_debug_label_quad(&background_rect,
+                                     projection,
+                                     &LAYER_DEBUG_LABEL_BACKGROUND_COLOR);
+
+        let text_origin = Point2D::new(origin.x + LAYER_DEBUG_LABEL_PADDING,
+                                       origin.y + LAYER_DEBUG_LABEL_PADDING);
+        self.render_text(text,
+                         &text_origin,
+                         LAYER_DEBUG_LABEL_PIXEL_SIZE,
+                         &LAYER_DEBUG_LABEL_COLOR,
+                         projection);
+    }
+
+    /// Draws `text` in the embedded bitmap font with its top left corner at `origin` in screen
+    /// coordinates, scaling each font pixel to `pixel_size` screen pixels. The text is kept
+    /// readable in y-up and mirrored scenes.
+    fn render_text(&self,
+                   text: &str,
+                   origin: &Point2D<f32>,
+                   pixel_size: f32,
+                   color: &Color,
+                   projection: &Matrix4D<f32>) {
+        let (width, height) = debugfont::text_size(text);
+        let atlas_size = debugfont::atlas_size();
+        let program = self.glyph_program;
+        program.enable_attribute_arrays();
+        gl::use_program(program.program.id);
+        let units = [(CONTENT_TEXTURE_UNIT, &self.glyph_atlas)];
+        let _bound_units = BoundTextureUnits::bind(&units);
+
+        debugfont::for_each_glyph(text, |position, atlas_rect| {
+            let x = if self.mirrored {
+                width - (position.x + atlas_rect.size.width)
+            } else {
+                position.x
+            };
+            let y = if self.y_axis_up {
+                height - (position.y + atlas_rect.size.height)
+            } else {
+                position.y
+            };
+            let rect = Rect::new(Point2D::new(origin.x + x as f32 * pixel_size,
+                                              origin.y + y as f32 * pixel_size),
+                                 Size2D::new(atlas_rect.size.width as f32 * pixel_size,
+                                             atlas_rect.size.height as f32 * pixel_size));
+
+            let mut u = (atlas_rect.min_x() as f32 / atlas_size.width as f32,
+                         atlas_rect.max_x() as f32 / atlas_size.width as f32);
+            let mut v = (atlas_rect.min_y() as f32 / atlas_size.height as f32,
+                         atlas_rect.max_y() as f32 / atlas_size.height as f32);
+            if self.mirrored {
+                u = (u.1, u.0);
+            }
+            if self.y_axis_up {
+                v = (v.1, v.0);
+            }
+            let vertices = [
+                TextureVertex::new(rect.origin, Point2D::new(u.0, v.0)),
+                TextureVertex::new(rect.top_right(), Point2D::new(u.1, v.0)),
+                TextureVertex::new(rect.bottom_left(), Point2D::new(u.0, v.1)),
+                TextureVertex::new(rect.bottom_right(), Point2D::new(u.1, v.1)),
+            ];
+            program.bind_uniforms_and_attributes(&vertices, projection, &self.buffers, color);
+            gl::draw_arrays(gl::TRIANGLE_STRIP, 0, 4);
+        });
+
+        program.disable_attribute_arrays();
+    }
+
+    fn render_debug_label_quad(&self,
+                               rect: &Rect<f32>,
+                               projection: &Matrix4D<f32>,
+                               color: &Color) {
+        let vertices = [
+            ColorVertex::new(rect.origin),
+            ColorVertex::new(rect.top_right()),
+            ColorVertex::new(rect.bottom_left()),
+            ColorVertex::new(rect.bottom_right()),
+        ];
+        self.bind_and_render_solid_quad(&vertices, &Matrix4D::identity(), projection, color);
+    }
+
+    fn render_checkerboard(&self,
+                           vertices: &[ColorVertex; 4],
+                           transform: &Matrix4D<f32>,
+                           projection: &Matrix4D<f32>,
+                           mask: Option<&QuadMask>,
+                           colors: (&Color, &Color),
+                           square_size: f32) {
+        let program = match mask {
+            Some(mask) => {
+                let features = mask.features();
+                *self.masked_checkerboard_programs.borrow_mut().entry(features).or_insert_with(|| {
+                    debug!("Compiling checkerboard program variant {:#x}", features);
+                    CheckerboardProgram::new(features)
+                })
+            }
+            None => self.checkerboard_program,
+        };
+        program.enable_attribute_arrays();
+        gl::use_program(program.program.id);
+        let mask_unit;
+        let units: &[(GLuint, &Texture)] = match mask {
+            Some(mask) => {
+                mask_unit = [(MASK_TEXTURE_UNIT, mask.texture)];
+                &mask_unit
+            }
+            None => &[],
+        };
+        let _bound_units = BoundTextureUnits::bind(units);
+        program.bind_uniforms_and_attributes(vertices,
+                                             transform,
+                                             projection,
+                                             mask.map(|mask| &mask.transform),
+                                             &self.buffers,
+                                             colors,
+                                             square_size);
+        gl::draw_arrays(gl::TRIANGLE_STRIP, 0, 4);
+        program.disable_attribute_arrays();
+    }
+
+    fn render_tile_placeholders<T>(&self,
+                                   layer: &Layer<T>,
+                                   layer_origin: &Point2D<f32>,
+                                   transform: &TypedTransform<LayerPixel, DevicePixel>,
+                                   projection: &TypedTransform<DevicePixel, ClipSpace>,
+                                   clip_rect: Option<TypedRect<f32, LayerPixel>>,
+                                   mask: Option<&QuadMask>) {
+        if let TilePlaceholder::Background = self.tile_placeholder {
+            return;
+        }
+
+        // The solid color and checkerboard helpers take the matrices themselves.
+        let transform_matrix = transform.to_untyped();
+        let projection_matrix = projection.to_untyped();
+
+        let missing_tile_rects = layer.missing_tile_rects(self.scene_scale);
+        for tile_rect in &missing_tile_rects {
+            let tile_rect = tile_rect.to_untyped().translate(layer_origin);
+            let tile_rect = match clip_rect {
+                Some(clip_rect) => match clip_rect.to_untyped().intersection(&tile_rect) {
+                    Some(tile_rect) => tile_rect,
+                    None => continue,
+                },
+                None => tile_rect,
+            };
+
+            let vertices = [
+                ColorVertex::new(tile_rect.origin),
+                ColorVertex::new(tile_rect.top_right()),
+                ColorVertex::new(tile_rect.bottom_left()),
+                ColorVertex::new(tile_rect.bottom_right()),
+            ];
+
+            match self.tile_placeholder {
+                TilePlaceholder::Background => {}
+                TilePlaceholder::SolidColor(default_color) => {
+                    let color = layer.placeholder_color.borrow().unwrap_or(default_color);
+                    match mask {
+                        // A checkerboard of a single color is the solid color, masked.
+                        Some(_) => self.render_checkerboard(&vertices,
+                                                            &transform_matrix,
+                                                            &projection_matrix,
+                                                            mask,
+                                                            (&color, &color),
+                                                            1.0),
+                        None => self.bind_and_render_solid_quad(&vertices,
+                                                                &transform_matrix,
+                                                                &projection_matrix,
+                                                                &color),
+                    }
+                }
+                TilePlaceholder::Checkerboard(ref color0, ref color1, square_size) => {
+                    self.render_checkerboard(&vertices,
+                                             &transform_matrix,
+                                             &projection_matrix,
+                                             mask,
+                                             (color0, color1),
+                                             square_size);
+                }
+                TilePlaceholder::LowResPreview => {
+                    layer.with_low_res_preview(|preview| {
+                        let preview_rect = match preview.bounds {
+                            Some(bounds) => bounds.to_untyped().translate(layer_origin),
+                            None => return,
+                        };
+                        let tile_rect = match preview_rect.intersection(&tile_rect) {
+                            Some(tile_rect) => tile_rect,
+                            None => return,
+                        };
+
+                        let vertex = |point: Point2D<f32>| {
+                            let offset = point - preview_rect.origin;
+                            TextureVertex::new(point,
+                                               Point2D::new(offset.x / preview_rect.size.width,
+                                                            offset.y / preview_rect.size.height))
+                        };
+                        let vertices = [
+                            vertex(tile_rect.origin),
+                            vertex(tile_rect.top_right()),
+                            vertex(tile_rect.bottom_left()),
+                            vertex(tile_rect.bottom_right()),
+                        ];
+                        self.bind_and_render_quad(&vertices,
+                                                  &preview.texture,
+                                                  true,
+                                                  mask,
+                                                  None,
+                                                  transform,
+                                                  projection,
+                                                  layer.transform_state.borrow().opacity);
+                    });
+                }
+            }
+        }
+    }
+
+    fn render_box_shadow(&self,
+                         shadow: &BoxShadow,
+                         rect: &Rect<f32>,
+                         clip_rect: Option<Rect<f32>>,
+                         transform: &Matrix4D<f32>,
+                         projection: &Matrix4D<f32>) {
+        if shadow.color.a == 0.0 {
+            return;
+        }
+
+        // The blur radius is twice the standard deviation of the gaussian, and the gaussian is
+        // negligible beyond three standard deviations.
+        let sigma = (shadow.blur_radius * 0.5).max(0.001);
+        let shadow_box = rect.translate(&shadow.offset);
+        let shadow_rect = shadow_box.inflate(sigma * 3.0, sigma * 3.0);
+        let shadow_rect = match clip_rect {
+            Some(clip_rect) => match clip_rect.intersection(&shadow_rect) {
+                Some(shadow_rect) => shadow_rect,
+                None => return,
+            },
+            None => shadow_rect,
+        };
+
+        let vertices = [
+            ColorVertex::new(shadow_rect.origin),
+            ColorVertex::new(shadow_rect.top_right()),
+            ColorVertex::new(shadow_rect.bottom_left()),
+            ColorVertex::new(shadow_rect.bottom_right()),
+        ];
+
+        let program = self.box_shadow_program;
+        program.enable_attribute_arrays();
+        gl::use_program(program.program.id);
+        program.bind_uniforms_and_attributes(&vertices,
+                                             transform,
+                                             projection,
+                                             &self.buffers,
+                                             &shadow_box,
+                                             sigma,
+                                             &shadow.color);
+        gl::draw_arrays(gl::TRIANGLE_STRIP, 0, 4);
+        program.disable_attribute_arrays();
+    }
+
+    /// Replaces what was drawn behind `rect` with transparent pixels, for the native widget
+    /// behind the GL surface to show through. Rounded clips still apply, so the hole is erased
+    /// in proportion to their coverage.
+    fn render_hole(&self, rect: &Rect<f32>, transform: &Matrix4D<f32>, projection: &Matrix4D<f32>) {
+        let vertices = [
+            ColorVertex::new(rect.origin),
+            ColorVertex::new(rect.top_right()),
+            ColorVertex::new(rect.bottom_left()),
+            ColorVertex::new(rect.bottom_right()),
+        ];
+        gl::blend_func(gl::ZERO, gl::ONE_MINUS_SRC_ALPHA);
+        self.bind_and_render_solid_quad(&vertices, transform, projection, &HOLE_COLOR);
+        gl::blend_func(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+    }
+
+    /// Blurs the composited scene behind the blur-behind regions of the layers, each with a
+    /// backdrop filter pass limited to the region.
+    fn render_blur_behind_regions<T>(&self,
+                                     root_layer: &Rc<Layer<T>>,
+                                     transform: &TypedTransform<LayerPixel, DevicePixel>,
+                                     projection: &TypedTransform<DevicePixel, ClipSpace>) {
+        let projection = projection.to_untyped();
+        walk_subtree(root_layer, |layer| {
+            let ts = layer.transform_state.borrow();
+            if ts.screen_rect.is_none() {
+                return false; // The layer and its children are entirely clipped.
+            }
+
+            let regions = layer.blur_behind_regions.borrow();
+            if regions.is_empty() {
+                return true;
+            }
+            let layer_transform = snapped_layer_transform(self.pixel_snapping, &ts, transform);
+            for region in regions.iter() {
+                let rect = region.rect.to_untyped().translate(&ts.world_rect.origin);
+                let filter = BackdropFilter {
+                    blur_radius: region.blur_radius,
+                };
+                self.render_backdrop_filter(&filter,
+                                            &rect,
+                                            &layer_transform.to_untyped(),
+                                            &projection,
+                                            1.0);
+            }
+            true
+        });
+    }
+
+    /// Snapshots the part of the framebuffer behind `rect` into a texture, and draws it back
+    /// over `rect` with the filter applied. The blur is separable, so it is drawn as a
+    /// horizontal pass into a texture and a vertical pass back over `rect`, each with as many
+    /// taps as the blur reaches. Blurs that reach further than `MAX_BACKDROP_BLUR_TAPS` texels
+    /// are drawn from a downsampled snapshot instead.
+    fn render_backdrop_filter(&self,
+                              backdrop_filter: &BackdropFilter,
+                              rect: &Rect<f32>,
+                              transform: &Matrix4D<f32>,
+                              projection: &Matrix4D<f32>,
+                              opacity: f32) {
+        let sigma = backdrop_filter.blur_radius * self.scene_scale.get();
+        if sigma <= 0.0 || opacity == 0.0 {
+            return;
+        }
+
+        // Find the window rect behind the layer, extended by the reach of the blur.
+        let mut viewport = [0 as GLint; 4];
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        }
+        let screen_rect = match project_rect_to_screen(rect, &projection.pre_mul(transform)) {
+            Some(screen_rect) => screen_rect.rect,
+            None => return,
+        };
+        let reach = sigma * 3.0;
+        let min_x = viewport[0] as f32 + (screen_rect.min_x() + 1.0) * 0.5 * viewport[2] as f32;
+        let min_y = viewport[1] as f32 + (screen_rect.min_y() + 1.0) * 0.5 * viewport[3] as f32;
+        let max_x = viewport[0] as f32 + (screen_rect.max_x() + 1.0) * 0.5 * viewport[2] as f32;
+        let max_y = viewport[1] as f32 + (screen_rect.max_y() + 1.0) * 0.5 * viewport[3] as f32;
+        let x0 = cmp::max((min_x - reach).floor() as GLint, viewport[0]);
+        let y0 = cmp::max((min_y - reach).floor() as GLint, viewport[1]);
+        let x1 = cmp::min((max_x + reach).ceil() as GLint, viewport[0] + viewport[2]);
+        let y1 = cmp::min((max_y + reach).ceil() as GLint, viewport[1] + viewport[3]);
+        if x1 <= x0 || y1 <= y0 {
+            return;
+        }
+        let backdrop_rect = Rect::new(Point2D::new(x0, y0), Size2D::new(x1 - x0, y1 - y0));
+
+        // The snapshot is downsampled until the blur reaches at most as many texels as a pass
+        // has taps. Blurs wider than the largest downsampling still reach as far, with fewer
+        // taps than texels.
+        let downsample = (reach / MAX_BACKDROP_BLUR_TAPS as f32).ceil() as GLsizei;
+        let downsample = cmp::max(cmp::min(downsample, MAX_BACKDROP_DOWNSAMPLE), 1);
+        let sigma = sigma / downsample as f32;
+        let taps = (sigma * 3.0).ceil().min(MAX_BACKDROP_BLUR_TAPS as f32);
+        let blurred_size = Size2D::new((backdrop_rect.size.width + downsample - 1) / downsample,
+                                       (backdrop_rect.size.height + downsample - 1) / downsample);
+
+        let mut backdrop_targets = self.backdrop_targets.borrow_mut();
+        let fits = backdrop_targets.as_ref().map_or(false, |targets| {
+            targets.size.width >= backdrop_rect.size.width &&
+                targets.size.height >= backdrop_rect.size.height
+        });
+        if !fits {
+            let size = backdrop_targets.as_ref().map_or(backdrop_rect.size, |targets| {
+                Size2D::new(cmp::max(targets.size.width, backdrop_rect.size.width),
+                            cmp::max(targets.size.height, backdrop_rect.size.height))
+            });
+            *backdrop_targets = None;
+            *backdrop_targets = Some(BackdropTargets::new(size));
+        }
+        let targets = backdrop_targets.as_ref().unwrap();
+        let texture_size = Size2D::new(targets.size.width as f32, targets.size.height as f32);
+
+        // Copy the backdrop into the first texture. Multisampled framebuffers can't be copied
+        // from directly, so they are resolved into it with a blit. Blits and the intermediate
+        // passes aren't limited by the scissor box of a partial recomposite.
+        let framebuffer = gl::get_integer_v(gl::FRAMEBUFFER_BINDING) as GLuint;
+        let scissor_test = gl::is_enabled(gl::SCISSOR_TEST) != 0;
+        if scissor_test {
+            gl::disable(gl::SCISSOR_TEST);
+        }
+        if gl::get_integer_v(gl::SAMPLE_BUFFERS) > 0 {
+            gl::bind_framebuffer(gl::DRAW_FRAMEBUFFER, targets.framebuffers[0]);
+            unsafe {
+                gl::BlitFramebuffer(x0, y0, x1, y1,
+                                    0, 0, backdrop_rect.size.width, backdrop_rect.size.height,
+                                    gl::COLOR_BUFFER_BIT,
+                                    gl::NEAREST);
+            }
+            gl::bind_framebuffer(gl::FRAMEBUFFER, framebuffer);
+        } else {
+            let _bound_texture = targets.textures[0].bind();
+            gl::copy_tex_sub_image_2d(gl::TEXTURE_2D, 0, 0, 0, x0, y0,
+                                      backdrop_rect.size.width, backdrop_rect.size.height);
+        }
+
+        // Draw the downsampling, if any, and the horizontal pass between the two textures,
+        // without blending.
+        let backdrop_size = Size2D::new(backdrop_rect.size.width as f32,
+                                        backdrop_rect.size.height as f32);
+        let blurred_size_f32 = Size2D::new(blurred_size.width as f32,
+                                           blurred_size.height as f32);
+        let mut passes = vec!();
+        if downsample > 1 {
+            passes.push((self.backdrop_downsample_program, BackdropPass {
+                dest_origin: Point2D::zero(),
+                source_scale: 1.0,
+                source_size: backdrop_size,
+                texture_size: texture_size,
+                direction: Point2D::zero(),
+                sigma: sigma,
+                taps: 0.0,
+                downsample: downsample as f32,
+                opacity: 1.0,
+            }));
+        }
+        passes.push((self.backdrop_program, BackdropPass {
+            dest_origin: Point2D::zero(),
+            source_scale: 1.0,
+            source_size: blurred_size_f32,
+            texture_size: texture_size,
+            direction: Point2D::new(1.0, 0.0),
+            sigma: sigma,
+            taps: taps,
+            downsample: 1.0,
+            opacity: 1.0,
+        }));
+        let blurred_vertices = [
+            ColorVertex::new(Point2D::zero()),
+            ColorVertex::new(Point2D::new(blurred_size_f32.width, 0.0)),
+            ColorVertex::new(Point2D::new(0.0, blurred_size_f32.height)),
+            ColorVertex::new(Point2D::new(blurred_size_f32.width, blurred_size_f32.height)),
+        ];
+        let blurred_projection = Matrix4D::ortho(0.0, blurred_size_f32.width,
+                                                 0.0, blurred_size_f32.height,
+                                                 ORTHO_NEAR_PLANE, ORTHO_FAR_PLANE);
+        gl::disable(gl::BLEND);
+        gl::viewport(0, 0, blurred_size.width, blurred_size.height);
+        let mut source = 0;
+        for &(program, ref pass) in &passes {
+            gl::bind_framebuffer(gl::FRAMEBUFFER, targets.framebuffers[1 - source]);
+            self.draw_backdrop_pass(program,
+                                    &targets.textures[source],
+                                    &blurred_vertices,
+                                    &Matrix4D::identity(),
+                                    &blurred_projection,
+                                    pass);
+            source = 1 - source;
+        }
+        gl::bind_framebuffer(gl::FRAMEBUFFER, framebuffer);
+        gl::viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+        gl::enable(gl::BLEND);
+        if scissor_test {
+            gl::enable(gl::SCISSOR_TEST);
+        }
+
+        // Draw the vertical pass over the layer, upsampling the downsampled blur.
+        let vertices = [
+            ColorVertex::new(rect.origin),
+            ColorVertex::new(rect.top_right()),
+            ColorVertex::new(rect.bottom_left()),
+            ColorVertex::new(rect.bottom_right()),
+        ];
+        self.draw_backdrop_pass(self.backdrop_program,
+                                &targets.textures[source],
+                                &vertices,
+                                transform,
+                                projection,
+                                &BackdropPass {
+                                    dest_origin: Point2D::new(x0 as f32, y0 as f32),
+                                    source_scale: 1.0 / downsample as f32,
+                                    source_size: blurred_size_f32,
+                                    texture_size: texture_size,
+                                    direction: Point2D::new(0.0, 1.0),
+                                    sigma: sigma,
+                                    taps: taps,
+                                    downsample: 1.0,
+                                    opacity: opacity,
+                                });
+    }
+
+    fn draw_backdrop_pass(&self,
+                          program: BackdropProgram,
+                          source: &Texture,
+                          vertices: &[ColorVertex; 4],
+                          transform: &Matrix4D<f32>,
+                          projection: &Matrix4D<f32>,
+                          pass: &BackdropPass) {
+        program.enable_attribute_arrays();
+        gl::use_program(program.program.id);
+        let units = [(CONTENT_TEXTURE_UNIT, source)];
+        let _bound_units = BoundTextureUnits::bind(&units);
+        program.bind_uniforms_and_attributes(vertices, transform, projection, &self.buffers, pass);
+        gl::draw_arrays(gl::TRIANGLE_STRIP, 0, 4);
+        program.disable_attribute_arrays();
+    }
 
-            self.bind_and_render_solid_quad(&bg_vertices,
-                                            &transform,
-                                            &projection,
-                                            &background_color);
-        }
+    fn render_border(&self,
+                     border: &Border,
+                     rect: &Rect<f32>,
+                     clip_rect: Option<Rect<f32>>,
+                     transform: &Matrix4D<f32>,
+                     projection: &Matrix4D<f32>) {
+        let inner_height = (rect.size.height - border.top.width - border.bottom.width).max(0.0);
+        let sides = [
+            (&border.top, Rect::new(rect.origin, Size2D::new(rect.size.width, border.top.width))),
+            (&border.bottom, Rect::new(Point2D::new(rect.origin.x,
+                                                    rect.max_y() - border.bottom.width),
+                                       Size2D::new(rect.size.width, border.bottom.width))),
+            (&border.left, Rect::new(Point2D::new(rect.origin.x, rect.origin.y + border.top.width),
+                                     Size2D::new(border.left.width, inner_height))),
+            (&border.right, Rect::new(Point2D::new(rect.max_x() - border.right.width,
+                                                   rect.origin.y + border.top.width),
+                                      Size2D::new(border.right.width, inner_height))),
+        ];
 
-        layer.do_for_all_tiles(|tile: &Tile| {
-           self.render_tile(tile,
-                            &ts.world_rect.origin,
-                            &transform,
-                            projection,
-                            clip_rect,
-                            *layer.opacity.borrow());
-        });
+        for &(side, side_rect) in &sides {
+            if side.width <= 0.0 || side.color.a == 0.0 {
+                continue;
+            }
 
-        if self.show_debug_borders {
-            let debug_vertices = [
-                ColorVertex::new(layer_rect.origin),
-                ColorVertex::new(layer_rect.top_right()),
-                ColorVertex::new(layer_rect.bottom_right()),
-                ColorVertex::new(layer_rect.bottom_left()),
-                ColorVertex::new(layer_rect.origin),
-            ];
-            self.bind_and_render_quad_lines(&debug_vertices,
-                                            &transform,
-                                            projection,
-                                            &LAYER_DEBUG_BORDER_COLOR,
-                                            LAYER_DEBUG_BORDER_THICKNESS);
+            let side_rect = match clip_rect {
+                Some(clip_rect) => match clip_rect.intersection(&side_rect) {
+                    Some(side_rect) => side_rect,
+                    None => continue,
+                },
+                None => side_rect,
+            };
 
-            let aabb = ts.screen_rect.as_ref().unwrap().rect;
-            let debug_vertices = [
-                ColorVertex::new(aabb.origin),
-                ColorVertex::new(aabb.top_right()),
-                ColorVertex::new(aabb.bottom_right()),
-                ColorVertex::new(aabb.bottom_left()),
-                ColorVertex::new(aabb.origin),
+            let vertices = [
+                ColorVertex::new(side_rect.origin),
+                ColorVertex::new(side_rect.top_right()),
+                ColorVertex::new(side_rect.bottom_left()),
+                ColorVertex::new(side_rect.bottom_right()),
             ];
-            self.bind_and_render_quad_lines(&debug_vertices,
-                                            &Matrix4D::identity(),
-                                            projection,
-                                            &LAYER_AABB_DEBUG_BORDER_COLOR,
-                                            LAYER_AABB_DEBUG_BORDER_THICKNESS);
+            self.bind_and_render_solid_quad(&vertices, transform, projection, &side.color);
         }
     }
 
+    /// Draws a tile, or adds it to `batch` if it can be drawn along with other quads. The
+    /// batch must be drawn before anything else is.
     fn render_tile(&self,
+                   batch: &mut QuadBatch,
                    tile: &Tile,
                    layer_origin: &Point2D<f32>,
-                   transform: &Matrix4D<f32>,
-                   projection: &Matrix4D<f32>,
-                   clip_rect: Option<Rect<f32>>,
+                   transform: &TypedTransform<LayerPixel, DevicePixel>,
+                   projection: &TypedTransform<DevicePixel, ClipSpace>,
+                   clip_rect: Option<TypedRect<f32, LayerPixel>>,
+                   mask: Option<&QuadMask>,
+                   edge_antialiasing: Option<&EdgeAntialiasing>,
                    opacity: f32) {
         if tile.texture.is_zero() || !tile.bounds.is_some() {
             return;
@@ -712,7 +3801,7 @@ impl RenderContext {
 
         let tile_rect = tile.bounds.unwrap().to_untyped().translate(layer_origin);
         let clipped_tile_rect = clip_rect.map_or(tile_rect, |clip_rect| {
-            match clip_rect.intersection(&tile_rect) {
+            match clip_rect.to_untyped().intersection(&tile_rect) {
                 Some(clipped_tile_rect) => clipped_tile_rect,
                 None => Rect::zero(),
             }
@@ -722,48 +3811,286 @@ impl RenderContext {
            return;
         }
 
-        let texture_rect_origin = clipped_tile_rect.origin - tile_rect.origin;
+        if self.damage_rect.is_some() {
+            // The transform includes the scene scale, so the tile is projected to device pixels.
+            let device_rect = project_rect_to_screen(&clipped_tile_rect, &transform.to_untyped())
+                .map(|screen_rect| TypedRect::from_untyped(&screen_rect.rect));
+            match device_rect {
+                Some(ref device_rect) if self.is_damaged(device_rect) => {}
+                _ => return,
+            }
+        }
+
+        // Tiles on antialiased edges extend past the layer; texture sampling clamps there.
+        let quad_rect = edge_antialiasing.map_or(clipped_tile_rect, |edge_antialiasing| {
+            edge_antialiasing.expand(&clipped_tile_rect)
+        });
+        let texture_rect_origin = quad_rect.origin - tile_rect.origin;
         let texture_rect = Rect::new(
             Point2D::new(texture_rect_origin.x / tile_rect.size.width,
                          texture_rect_origin.y / tile_rect.size.height),
-            Size2D::new(clipped_tile_rect.size.width / tile_rect.size.width,
-                        clipped_tile_rect.size.height / tile_rect.size.height));
+            Size2D::new(quad_rect.size.width / tile_rect.size.width,
+                        quad_rect.size.height / tile_rect.size.height));
 
         let tile_vertices: [TextureVertex; 4] = [
-            TextureVertex::new(clipped_tile_rect.origin, texture_rect.origin),
-            TextureVertex::new(clipped_tile_rect.top_right(), texture_rect.top_right()),
-            TextureVertex::new(clipped_tile_rect.bottom_left(), texture_rect.bottom_left()),
-            TextureVertex::new(clipped_tile_rect.bottom_right(), texture_rect.bottom_right()),
+            TextureVertex::new(quad_rect.origin, texture_rect.origin),
+            TextureVertex::new(quad_rect.top_right(), texture_rect.top_right()),
+            TextureVertex::new(quad_rect.bottom_left(), texture_rect.bottom_left()),
+            TextureVertex::new(quad_rect.bottom_right(), texture_rect.bottom_right()),
         ];
 
+        // Masked tiles and tiles with debugging overlays are drawn on their own, since the
+        // overlays go between them.
+        if mask.is_none() && !self.show_debug_borders && !self.show_tile_age_heatmap {
+            self.batch_quad(batch,
+                            &tile_vertices,
+                            &tile.texture,
+                            true,
+                            edge_antialiasing,
+                            transform,
+                            projection,
+                            opacity);
+            return;
+        }
+        self.render_quad_batch(batch);
+
         if self.show_debug_borders {
-            let debug_vertices = [
-                // The weird ordering is converting from triangle-strip into a line-strip.
-                ColorVertex::new(clipped_tile_rect.origin),
-                ColorVertex::new(clipped_tile_rect.top_right()),
-                ColorVertex::new(clipped_tile_rect.bottom_right()),
-                ColorVertex::new(clipped_tile_rect.bottom_left()),
-                ColorVertex::new(clipped_tile_rect.origin),
-            ];
-            self.bind_and_render_quad_lines(&debug_vertices,
-                                            &transform,
-                                            projection,
-                                            &TILE_DEBUG_BORDER_COLOR,
-                                            TILE_DEBUG_BORDER_THICKNESS);
+            self.render_tile_rect_outline(&clipped_tile_rect,
+                                          &transform.to_untyped(),
+                                          &projection.to_untyped());
         }
 
         self.bind_and_render_quad(&tile_vertices,
                                   &tile.texture,
-                                  &transform,
+                                  true,
+                                  mask,
+                                  edge_antialiasing,
+                                  transform,
                                   projection,
                                   opacity);
+
+        if self.show_tile_age_heatmap {
+            if let Some(age) = tile.texture_age() {
+                let age = age.as_secs() as f32 + age.subsec_nanos() as f32 / 1.0e9;
+                let staleness = (age / TILE_AGE_HEATMAP_STALE_SECONDS).min(1.0);
+
+                // Colors are premultiplied.
+                let color = Color {
+                    r: staleness * TILE_AGE_HEATMAP_ALPHA,
+                    g: (1.0 - staleness) * TILE_AGE_HEATMAP_ALPHA,
+                    b: 0.0,
+                    a: TILE_AGE_HEATMAP_ALPHA,
+                };
+                let vertices = [
+                    ColorVertex::new(clipped_tile_rect.origin),
+                    ColorVertex::new(clipped_tile_rect.top_right()),
+                    ColorVertex::new(clipped_tile_rect.bottom_left()),
+                    ColorVertex::new(clipped_tile_rect.bottom_right()),
+                ];
+                self.bind_and_render_solid_quad(&vertices,
+                                                &transform.to_untyped(),
+                                                &projection.to_untyped(),
+                                                &color);
+            }
+        }
+    }
+
+    /// Draws the outline of a tile, whether or not it has content, for wireframe mode.
+    fn render_tile_outline(&self,
+                           tile: &Tile,
+                           layer_origin: &Point2D<f32>,
+                           transform: &Matrix4D<f32>,
+                           projection: &Matrix4D<f32>,
+                           clip_rect: Option<Rect<f32>>) {
+        let tile_rect = match tile.bounds {
+            Some(bounds) => bounds.to_untyped().translate(layer_origin),
+            None => return,
+        };
+        let tile_rect = match clip_rect {
+            Some(clip_rect) => match clip_rect.intersection(&tile_rect) {
+                Some(tile_rect) => tile_rect,
+                None => return,
+            },
+            None => tile_rect,
+        };
+        self.render_tile_rect_outline(&tile_rect, transform, projection);
+    }
+
+    fn render_tile_rect_outline(&self,
+                                tile_rect: &Rect<f32>,
+                                transform: &Matrix4D<f32>,
+                                projection: &Matrix4D<f32>) {
+        let debug_vertices = [
+            // The weird ordering is converting from triangle-strip into a line-strip.
+            ColorVertex::new(tile_rect.origin),
+            ColorVertex::new(tile_rect.top_right()),
+            ColorVertex::new(tile_rect.bottom_right()),
+            ColorVertex::new(tile_rect.bottom_left()),
+            ColorVertex::new(tile_rect.origin),
+        ];
+        self.bind_and_render_quad_lines(&debug_vertices,
+                                        transform,
+                                        projection,
+                                        &TILE_DEBUG_BORDER_COLOR,
+                                        TILE_DEBUG_BORDER_THICKNESS);
+    }
+
+    /// Renders the subtree rooted at `layer` into a texture covering its on-screen area, and
+    /// stores it in the layer's raster cache.
+    fn render_layer_to_cache<T>(&self, layer: &Rc<Layer<T>>, scene: &Scene<T>) {
+        let _span = profiler::span("raster cache");
+        let viewport_rect = TypedRect::new(TypedPoint2D::zero(), scene.viewport.size);
+        let rect = match subtree_screen_rect(layer) {
+            Some(rect) => rect * scene.scale,
+            None => return,
+        };
+        let rect = match rect.intersection(&viewport_rect) {
+            Some(rect) => rect.to_untyped(),
+            None => return,
+        };
+
+        // Snap to device pixels so that the cached texture is drawn without resampling.
+        let (x0, y0) = (rect.min_x().floor(), rect.min_y().floor());
+        let (x1, y1) = (rect.max_x().ceil(), rect.max_y().ceil());
+        let rect = Rect::new(Point2D::new(x0, y0), Size2D::new(x1 - x0, y1 - y0));
+        let (width, height) = (rect.size.width as GLsizei, rect.size.height as GLsizei);
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        // Rendering into the texture flips it vertically, with respect to uploaded tiles.
+        let mut texture = Texture::new(TextureTarget2D, Size2D::new(width as usize, height as usize));
+        texture.orientation = Orientation::vertical_flip();
+        {
+            let _bound_texture = texture.bind();
+            gl::tex_image_2d(gl::TEXTURE_2D, 0, gl::RGBA as GLint, width, height, 0,
+                             gl::RGBA, gl::UNSIGNED_BYTE, None);
+        }
+
+        let previous_framebuffer = gl::get_integer_v(gl::FRAMEBUFFER_BINDING) as GLuint;
+        let framebuffer = gl::gen_framebuffers(1)[0];
+        gl::bind_framebuffer(gl::FRAMEBUFFER, framebuffer);
+        gl::framebuffer_texture_2d(gl::FRAMEBUFFER,
+                                   gl::COLOR_ATTACHMENT0,
+                                   gl::TEXTURE_2D,
+                                   texture.native_texture(),
+                                   0);
+
+        if self.object_label.is_some() {
+            let name = layer.debug_name();
+            self.label_object(gl::TEXTURE, texture.native_texture(), &format!("{} cache", name));
+            self.label_object(gl::FRAMEBUFFER, framebuffer, &format!("{} cache target", name));
+        }
+
+        let complete = gl::check_frame_buffer_status(gl::FRAMEBUFFER) == gl::FRAMEBUFFER_COMPLETE;
+        let mut multisample_target = self.raster_cache_target.borrow_mut().take();
+        if complete && self.sample_count > 1 {
+            let size = Size2D::new(width, height);
+            let reusable = multisample_target.as_ref().map_or(false, |target| {
+                target.matches(size, self.sample_count)
+            });
+            if !reusable {
+                multisample_target = None;
+                multisample_target = MultisampleTarget::new(size, self.sample_count);
+            }
+        }
+        let multisample_target = if complete { multisample_target } else { None };
+        if let Some(ref multisample_target) = multisample_target {
+            multisample_target.bind();
+        }
+
+        if complete {
+            gl::viewport(0, 0, width, height);
+            gl::clear_color(0.0, 0.0, 0.0, 0.0);
+            gl::clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+
+            let transform = scene_transform(scene.scale);
+            let projection = TypedTransform::from_untyped(&Matrix4D::ortho(rect.min_x(),
+                                                                           rect.max_x(),
+                                                                           rect.max_y(),
+                                                                           rect.min_y(),
+                                                                           ORTHO_NEAR_PLANE,
+                                                                           ORTHO_FAR_PLANE));
+            self.render_3d_context(&RenderContext3D::new(layer.clone()), &transform, &projection);
+        }
+
+        if let Some(ref multisample_target) = multisample_target {
+            multisample_target.resolve(framebuffer, Point2D::zero());
+        }
+        gl::bind_framebuffer(gl::FRAMEBUFFER, previous_framebuffer);
+        gl::delete_framebuffers(&[framebuffer]);
+        *self.raster_cache_target.borrow_mut() = multisample_target;
+
+        if complete {
+            layer.raster_cache.borrow_mut().store(texture, TypedRect::from_untyped(&rect));
+        } else {
+            warn!("Could not create a framebuffer for the raster cache of {}.",
+                  layer.debug_name());
+        }
+    }
+
+    /// Draws a subtree from its cached texture.
+    fn render_cached_layer<T>(&self,
+                              layer: &Rc<Layer<T>>,
+                              clip_rect: Option<TypedRect<f32, LayerPixel>>,
+                              transform: &TypedTransform<LayerPixel, DevicePixel>,
+                              projection: &TypedTransform<DevicePixel, ClipSpace>) {
+        let cache = layer.raster_cache.borrow();
+        let texture = match cache.texture {
+            Some(ref texture) => texture,
+            None => return,
+        };
+
+        // The cache is in device pixels, while clip rects are not scaled yet.
+        if !self.is_damaged(&cache.rect) {
+            return;
+        }
+        let rect = cache.rect.to_untyped();
+        let clipped_rect = match clip_rect {
+            Some(clip_rect) => {
+                let device_clip_rect =
+                    transform.to_untyped().to_2d().transform_rect(&clip_rect.to_untyped());
+                match device_clip_rect.intersection(&rect) {
+                    Some(clipped_rect) => clipped_rect,
+                    None => return,
+                }
+            }
+            None => rect,
+        };
+
+        let texture_rect = Rect::new(
+            Point2D::new((clipped_rect.origin.x - rect.origin.x) / rect.size.width,
+                         (clipped_rect.origin.y - rect.origin.y) / rect.size.height),
+            Size2D::new(clipped_rect.size.width / rect.size.width,
+                        clipped_rect.size.height / rect.size.height));
+
+        let vertices: [TextureVertex; 4] = [
+            TextureVertex::new(clipped_rect.origin, texture_rect.origin),
+            TextureVertex::new(clipped_rect.top_right(), texture_rect.top_right()),
+            TextureVertex::new(clipped_rect.bottom_left(), texture_rect.bottom_left()),
+            TextureVertex::new(clipped_rect.bottom_right(), texture_rect.bottom_right()),
+        ];
+
+        self.bind_and_render_quad(&vertices,
+                                  texture,
+                                  false,
+                                  None,
+                                  None,
+                                  &TypedTransform::<DevicePixel, DevicePixel>::identity(),
+                                  projection,
+                                  1.0);
+    }
+
+    /// Returns true if `rect`, in device pixels before the pinch transform, needs to be drawn
+    /// this frame.
+    fn is_damaged(&self, rect: &TypedRect<f32, DevicePixel>) -> bool {
+        self.damage_rect.map_or(true, |damage_rect| damage_rect.intersects(rect))
     }
 
     fn render_3d_context<T>(&self,
                             context: &RenderContext3D<T>,
-                            transform: &Matrix4D<f32>,
-                            projection: &Matrix4D<f32>,
-                            gfx_context: &NativeDisplay) {
+                            transform: &TypedTransform<LayerPixel, DevicePixel>,
+                            projection: &TypedTransform<DevicePixel, ClipSpace>) {
         if context.children.is_empty() {
             return;
         }
@@ -777,71 +4104,758 @@ impl RenderContext {
         // Render child layers with z-testing.
         for child in &context.children {
             if let Some(ref layer) = child.layer {
+                // Cached subtrees are drawn in device pixels, other layers in their own
+                // coordinates.
+                let is_cached = self.draws_content() && layer.raster_cache.borrow().is_active();
+                let vertex_transform = if is_cached {
+                    Matrix4D::identity()
+                } else {
+                    transform.pre_mul(&layer.transform_state.borrow().final_transform).to_untyped()
+                };
+                let rounded_clip = RoundedClip::new(&child.clip_layers,
+                                                    &transform.to_untyped(),
+                                                    &vertex_transform);
+                self.rounded_clip.set(rounded_clip);
+
+                if is_cached {
+                    let clip_rect = context.clip_rect.map(|cr| TypedRect::from_untyped(&cr));
+                    self.render_cached_layer(layer, clip_rect, transform, projection);
+                    self.rounded_clip.set(None);
+                    continue;
+                }
+
                 // TODO(gw): Disable clipping on 3d layers for now.
                 // Need to implement proper polygon clipping to
                 // make this work correctly.
                 let clip_rect = context.clip_rect.and_then(|cr| {
-                    let m = layer.transform_state.borrow().final_transform;
-
-                    // See https://drafts.csswg.org/css-transforms/#2d-matrix
-                    let is_3d_transform = m.m31 != 0.0 || m.m32 != 0.0 ||
-                                          m.m13 != 0.0 || m.m23 != 0.0 ||
-                                          m.m43 != 0.0 || m.m14 != 0.0 ||
-                                          m.m24 != 0.0 || m.m34 != 0.0 ||
-                                          m.m33 != 1.0 || m.m44 != 1.0;
+                    let ts = layer.transform_state.borrow();
+                    let m = ts.final_transform;
 
-                    if is_3d_transform {
+                    if ts.transform_kind.is_3d() {
                         None
                     } else {
                         // If the transform is 2d, invert it and back-transform
                         // the clip rect into world space.
                         let transform = m.inverse().unwrap();
                         let xform_2d = transform.to_2d();
-                        Some(xform_2d.transform_rect(&cr))
+                        Some(TypedRect::from_untyped(&xform_2d.transform_rect(&cr)))
                     }
 
                 });
                 self.render_layer(layer.clone(),
                                   transform,
                                   projection,
-                                  clip_rect,
-                                  gfx_context);
+                                  clip_rect);
+                self.rounded_clip.set(None);
             }
 
             if let Some(ref context) = child.context {
                 self.render_3d_context(context,
                                        transform,
-                                       projection,
-                                       gfx_context);
+                                       projection);
+
+            }
+        }
+    }
+}
+
+/// The vertices of the quad covering `rect`, expanded for edge antialiasing, that show an image
+/// stretched over `image_rect`.
+fn stretched_quad_vertices(rect: &Rect<f32>,
+                           image_rect: &Rect<f32>,
+                           edge_antialiasing: Option<&EdgeAntialiasing>)
+                           -> [TextureVertex; 4] {
+    let quad_rect = edge_antialiasing.map_or(*rect, |edge_antialiasing| {
+        edge_antialiasing.expand(rect)
+    });
+    let texture_coordinates = |point: Point2D<f32>| {
+        Point2D::new((point.x - image_rect.origin.x) / image_rect.size.width,
+                     (point.y - image_rect.origin.y) / image_rect.size.height)
+    };
+    [
+        TextureVertex::new(quad_rect.origin, texture_coordinates(quad_rect.origin)),
+        TextureVertex::new(quad_rect.top_right(), texture_coordinates(quad_rect.top_right())),
+        TextureVertex::new(quad_rect.bottom_left(), texture_coordinates(quad_rect.bottom_left())),
+        TextureVertex::new(quad_rect.bottom_right(), texture_coordinates(quad_rect.bottom_right())),
+    ]
+}
+
+/// The transform that a layer with the transform state `ts` is drawn with, given the
+/// `transform` of its scene.
+fn snapped_layer_transform(policy: PixelSnapping,
+                           ts: &TransformState,
+                           transform: &TypedTransform<LayerPixel, DevicePixel>)
+                           -> TypedTransform<LayerPixel, DevicePixel> {
+    snap_to_device_pixels(policy, ts, &transform.pre_mul(&ts.final_transform))
+}
+
+/// Adjusts the transform from layer to device pixels so that the origin of the layer lands on
+/// a device pixel, if the policy calls for snapping layers with the transform state `ts`.
+fn snap_to_device_pixels(policy: PixelSnapping,
+                         ts: &TransformState,
+                         transform: &TypedTransform<LayerPixel, DevicePixel>)
+                         -> TypedTransform<LayerPixel, DevicePixel> {
+    const EPSILON: f32 = 1.0e-5;
+    let snaps = match policy {
+        PixelSnapping::Never => false,
+        PixelSnapping::Always => ts.transform_kind.is_axis_aligned(),
+        PixelSnapping::TranslationsOnly => {
+            let m = &ts.final_transform;
+            ts.transform_kind <= TransformKind::IntegerTranslation ||
+                (ts.transform_kind == TransformKind::ScaleTranslation &&
+                 (m.m11 - 1.0).abs() <= EPSILON && (m.m22 - 1.0).abs() <= EPSILON)
+        }
+    };
+    if !snaps {
+        return *transform;
+    }
+
+    let origin = ts.world_rect.origin;
+    let mut snapped = transform.to_untyped();
+    let x = origin.x * snapped.m11 + origin.y * snapped.m21 + snapped.m41;
+    let y = origin.x * snapped.m12 + origin.y * snapped.m22 + snapped.m42;
+    snapped.m41 += x.round() - x;
+    snapped.m42 += y.round() - y;
+    TypedTransform::from_untyped(&snapped)
+}
 
+/// The holes punched by the visible layers of a scene, in tree order.
+fn punched_holes<T>(root_layer: &Rc<Layer<T>>,
+                    scene_scale: ScaleFactor<f32, LayerPixel, DevicePixel>,
+                    pinch_transform: Option<&Matrix4D<f32>>)
+                    -> Vec<PunchedHole> {
+    let mut holes = vec!();
+    walk_subtree(root_layer, |layer| {
+        let ts = layer.transform_state.borrow();
+        let screen_rect = match ts.screen_rect {
+            Some(ref screen_rect) => screen_rect.rect,
+            None => return false,
+        };
+        let id = match *layer.hole.borrow() {
+            Some(id) if ts.visible => id,
+            _ => return true,
+        };
+        let rect = TypedRect::from_untyped(&screen_rect) * scene_scale;
+        let rect = match pinch_transform {
+            Some(pinch_transform) => {
+                project_rect_to_screen(&rect.to_untyped(), pinch_transform).map(|screen_rect| {
+                    TypedRect::from_untyped(&screen_rect.rect)
+                })
+            }
+            None => Some(rect),
+        };
+        if let Some(rect) = rect {
+            holes.push(PunchedHole {
+                id: id,
+                rect: rect,
+            });
+        }
+        true
+    });
+    holes
+}
+
+/// Returns the screen rect of a layer, including its shadow.
+fn layer_screen_rect<T>(layer: &Layer<T>) -> Option<TypedRect<f32, LayerPixel>> {
+    layer.transform_state.borrow().screen_rect.as_ref().map(|screen_rect| {
+        let rect = match *layer.shadow.borrow() {
+            Some(ref shadow) => {
+                let extent = shadow.blur_radius * 1.5;
+                screen_rect.rect.union(&screen_rect.rect
+                                                   .translate(&shadow.offset)
+                                                   .inflate(extent, extent))
             }
+            None => screen_rect.rect,
+        };
+        TypedRect::from_untyped(&rect)
+    })
+}
+
+/// Returns the union of the screen rects of all layers in a subtree, including their shadows.
+fn subtree_screen_rect<T>(layer: &Rc<Layer<T>>) -> Option<TypedRect<f32, LayerPixel>> {
+    let mut rect: Option<TypedRect<f32, LayerPixel>> = None;
+    walk_subtree(layer, |layer| {
+        let layer_rect = layer_screen_rect(layer);
+
+        rect = match (rect, layer_rect) {
+            (Some(rect), Some(layer_rect)) => Some(rect.union(&layer_rect)),
+            (rect, layer_rect) => rect.or(layer_rect),
+        };
+        true
+    });
+    rect
+}
+
+// From OpenGL 3.0, `GL_ARB_framebuffer_sRGB` and `GL_EXT_sRGB_write_control`, which the
+// bindings may not include.
+const FRAMEBUFFER_SRGB: GLenum = 0x8DB9;
+
+/// Enables or disables conversion to sRGB on writes to the framebuffer, where the GL
+/// implementation lets the compositor control it. Elsewhere, sRGB surfaces are always converted
+/// to.
+fn enable_srgb_framebuffer(enabled: bool) {
+    if !GlCapabilities::current().srgb_write_control {
+        return;
+    }
+    if enabled {
+        gl::enable(FRAMEBUFFER_SRGB);
+    } else {
+        gl::disable(FRAMEBUFFER_SRGB);
+    }
+}
+
+/// The scissor box covering `damage_rect`, in device pixels of the scene, for a viewport at
+/// `origin` in window coordinates.
+fn damage_scissor_box(damage_rect: &TypedRect<f32, DevicePixel>,
+                      origin: &Point2D<GLint>,
+                      viewport_size: &Size2D<GLsizei>,
+                      y_axis_up: bool,
+                      mirrored: bool)
+                      -> Rect<GLint> {
+    let damage_rect = damage_rect.to_untyped();
+    let (width, height) = (viewport_size.width, viewport_size.height);
+    let clamp = |value: f32, max: GLint| cmp::min(cmp::max(value as GLint, 0), max);
+    let (mut x0, mut x1) = (clamp(damage_rect.min_x().floor(), width),
+                            clamp(damage_rect.max_x().ceil(), width));
+    let (mut y0, mut y1) = (clamp(damage_rect.min_y().floor(), height),
+                            clamp(damage_rect.max_y().ceil(), height));
+
+    // Window coordinates start at the bottom left.
+    if mirrored {
+        let (min_x, max_x) = (width - x1, width - x0);
+        x0 = min_x;
+        x1 = max_x;
+    }
+    if !y_axis_up {
+        let (min_y, max_y) = (height - y1, height - y0);
+        y0 = min_y;
+        y1 = max_y;
+    }
+    Rect::new(Point2D::new(origin.x + x0, origin.y + y0), Size2D::new(x1 - x0, y1 - y0))
+}
+
+/// The update pass of a composite. Applies the latest input, drops expired property overrides
+/// and shows the delayed buffers that are due, then caches the world transform, screen rect,
+/// opacity and visibility of every layer in its transform state, so that the draw pass and
+/// everything else during the frame see the same values. Layers entirely outside of the viewport
+/// are culled, unless a pinch zoom may bring them into view.
+fn update_layers<T>(scene: &Scene<T>, overrides: &PresentOverrides, now: Instant) {
+    let _span = profiler::span("update layers");
+    scene.expire_property_overrides(now);
+    scene.release_delayed_buffers(now);
+    for (index, root_layer) in scene.roots.iter().enumerate() {
+        // The overridden scroll offset only applies to the frame, and the content offset of the
+        // root layer is left for the embedder to update.
+        let content_offset = match overrides.root_scroll_offset {
+            Some(root_scroll_offset) if index == 0 => root_scroll_offset,
+            _ => *root_layer.content_offset.borrow(),
+        };
+        root_layer.update_transform_state_with_offset(&Matrix4D::identity(),
+                                                      &Matrix4D::identity(),
+                                                      &Point2D::zero(),
+                                                      &content_offset);
+
+        if overrides.pinch_transform.is_none() {
+            let viewport_size = scene.viewport.size / scene.scale;
+            root_layer.cull_to_rect(&Rect::new(Point2D::zero(), viewport_size.to_untyped()));
+        }
+    }
+}
+
+/// Composites the root layers of the scene, in order, and returns statistics about the frame.
+/// The checkerboard callback of the scene is called if some visible tiles were missing. The
+/// render context keeps the state of the scene it last rendered until the next call.
+pub fn render_scene<T>(render_context: &mut RenderContext, scene: &Scene<T>) -> FrameStats {
+    render_damaged_scene(render_context, scene, scene.damage_rect)
+}
+
+/// Like `render_scene`, but only recomposites the union of `damage`, in device pixels of the
+/// scene, instead of `scene.damage_rect`. An empty list draws nothing but still runs the
+/// update pass, e.g. for a frame in which only animations were ticked. `None` recomposites the
+/// whole viewport. See `Scene::damage_rect` for what must hold for the rest of the framebuffer.
+pub fn render_scene_with_damage<T>(render_context: &mut RenderContext,
+                                   scene: &Scene<T>,
+                                   damage: Option<&[TypedRect<f32, DevicePixel>]>)
+                                   -> FrameStats {
+    let damage_rect = damage.map(|damage| {
+        damage.iter().fold(None, |union: Option<TypedRect<f32, DevicePixel>>, rect| {
+            Some(union.map_or(*rect, |union| union.union(rect)))
+        }).unwrap_or(TypedRect::zero())
+    });
+    render_damaged_scene(render_context, scene, damage_rect)
+}
+
+fn render_damaged_scene<T>(render_context: &mut RenderContext,
+                           scene: &Scene<T>,
+                           damage_rect: Option<TypedRect<f32, DevicePixel>>)
+                           -> FrameStats {
+    // There is no surface to composite into while the application is in the background.
+    if scene.is_paused() {
+        debug!("Not compositing {} while paused",
+               scene.debug_name.as_ref().map_or("scene", |name| &**name));
+        return FrameStats::new();
+    }
+
+    let _span = profiler::span("composite");
+    let start_time = Instant::now();
+    glcapture::begin_frame();
+    let stats = composite_scene(render_context, scene, SceneLayering::Only, damage_rect,
+                                start_time);
+    if let Err(error) = glcapture::end_frame() {
+        warn!("Could not write the GL command capture: {}", error);
+    }
+    finish_scene(scene, &stats, start_time);
+    stats
+}
+
+/// Prepares `render_context` to composite the first frame of `scene`. See
+/// `Scene::prepare_first_frame`.
+pub fn prepare_first_frame<T>(render_context: &mut RenderContext, scene: &Scene<T>) {
+    if scene.is_paused() {
+        return;
+    }
+
+    let _span = profiler::span("prepare first frame");
+    let start_time = Instant::now();
+    let viewport = scene.presented_viewport().to_untyped();
+    render_context.prepare(Size2D::new(viewport.size.width as GLsizei,
+                                       viewport.size.height as GLsizei));
+    scene.upload_all_textures(&render_context.compositing_display);
+    debug!("Prepared the first frame of {} in {:?}",
+           scene.debug_name.as_ref().map_or("scene", |name| &**name),
+           start_time.elapsed());
+}
+
+/// Composites several independent scenes into the current framebuffer, in order, e.g. the
+/// browser chrome, the content and a devtools overlay. Each scene is drawn into its own
+/// viewport, which may overlap the viewports of the scenes before it; only the first scene
+/// clears the framebuffer, so the later ones are drawn over what is already there. The render
+/// context is shared; its overlay items are drawn over all of the scenes, and its white point
+/// is applied once to each part of the window they are shown in, at the end.
+///
+/// Scenes without root layers or that are paused are skipped. The returned stats are in the
+/// order of `scenes`.
+pub fn render_scenes<T>(render_context: &mut RenderContext, scenes: &[&Scene<T>])
+                        -> Vec<FrameStats> {
+    let _span = profiler::span("composite scenes");
+    let start_time = Instant::now();
+    glcapture::begin_frame();
+
+    // Overlay items, the white point and the capture streams are left until all scenes are
+    // drawn, so that the overlay items are on top, the parts of the window covered by several
+    // scenes are shifted once, and the streams capture what is shown.
+    let mut all_stats = vec!();
+    let mut composited_scenes = vec!();
+    for scene in scenes {
+        if scene.roots.is_empty() || scene.is_paused() {
+            all_stats.push(FrameStats::new());
+            continue;
+        }
+        let layering = if composited_scenes.is_empty() {
+            SceneLayering::Base
+        } else {
+            SceneLayering::Overlay
+        };
+        all_stats.push(composite_scene(render_context, scene, layering, scene.damage_rect,
+                                       start_time));
+        composited_scenes.push(*scene);
+    }
+
+    if render_context.srgb {
+        enable_srgb_framebuffer(true);
+    }
+
+    // Overlay items are in device pixels of the first scene, which is usually the window.
+    if let Some(base_scene) = composited_scenes.first() {
+        let v = base_scene.presented_viewport().to_untyped();
+        gl::viewport(v.origin.x as GLint, v.origin.y as GLint,
+                     v.size.width as GLsizei, v.size.height as GLsizei);
+        let projection = create_ortho(&base_scene.viewport.size,
+                                      base_scene.y_axis_up,
+                                      base_scene.mirrored);
+        render_context.render_overlay_items(&projection.to_untyped());
+    }
+
+    // Each part of the window is shifted once, however many scenes cover it.
+    let mut shifted_rects: Vec<Rect<GLint>> = vec!();
+    for scene in &composited_scenes {
+        let mut rects = match shown_rect(*scene) {
+            Some(rect) => vec!(rect),
+            None => continue,
+        };
+        for shifted_rect in &shifted_rects {
+            rects = rects.iter().flat_map(|rect| subtract_rect(rect, shifted_rect)).collect();
+        }
+        for rect in &rects {
+            gl::viewport(rect.origin.x, rect.origin.y, rect.size.width, rect.size.height);
+            let size = TypedSize2D::new(rect.size.width as f32, rect.size.height as f32);
+            let projection = create_ortho(&size, false, false);
+            render_context.render_white_point(&size, &projection.to_untyped());
+        }
+        shifted_rects.extend(rects);
+    }
+
+    if render_context.srgb {
+        enable_srgb_framebuffer(false);
+    }
+
+    for scene in &composited_scenes {
+        if let Some(captured_rect) = shown_rect(*scene) {
+            scene.capture_streams.capture(&captured_rect, start_time);
+        }
+    }
+
+    if let Err(error) = glcapture::end_frame() {
+        warn!("Could not write the GL command capture: {}", error);
+    }
+    for (scene, stats) in scenes.iter().zip(all_stats.iter()) {
+        if !scene.roots.is_empty() && !scene.is_paused() {
+            finish_scene(scene, stats, start_time);
+        }
+    }
+    all_stats
+}
+
+/// The part of the window a scene is shown in: its presented viewport, cropped to its
+/// presentation rect.
+fn shown_rect<T>(scene: &Scene<T>) -> Option<Rect<GLint>> {
+    let v = scene.presented_viewport().to_untyped();
+    let presented_rect = Rect::new(Point2D::new(v.origin.x as GLint, v.origin.y as GLint),
+                                   Size2D::new(v.size.width as GLsizei, v.size.height as GLsizei));
+    match scene.presentation_rect {
+        Some(area) => {
+            let area = area.to_untyped();
+            let clip = Rect::new(Point2D::new(area.origin.x.round() as GLint,
+                                              area.origin.y.round() as GLint),
+                                 Size2D::new(area.size.width.round() as GLsizei,
+                                             area.size.height.round() as GLsizei));
+            clip.intersection(&presented_rect)
         }
+        None => Some(presented_rect),
     }
 }
 
-pub fn render_scene<T>(root_layer: Rc<Layer<T>>,
-                       render_context: RenderContext,
-                       scene: &Scene<T>) {
+/// The parts of `rect` not covered by `other`, as up to four disjoint rects.
+fn subtract_rect(rect: &Rect<GLint>, other: &Rect<GLint>) -> Vec<Rect<GLint>> {
+    let overlap = match rect.intersection(other) {
+        Some(overlap) => overlap,
+        None => return vec!(*rect),
+    };
+    let mut parts = vec!(
+        // Above and below the overlap, over the whole width of `rect`.
+        Rect::new(rect.origin, Size2D::new(rect.size.width, overlap.min_y() - rect.min_y())),
+        Rect::new(Point2D::new(rect.min_x(), overlap.max_y()),
+                  Size2D::new(rect.size.width, rect.max_y() - overlap.max_y())),
+        // Left and right of the overlap, over its height.
+        Rect::new(Point2D::new(rect.min_x(), overlap.min_y()),
+                  Size2D::new(overlap.min_x() - rect.min_x(), overlap.size.height)),
+        Rect::new(Point2D::new(overlap.max_x(), overlap.min_y()),
+                  Size2D::new(rect.max_x() - overlap.max_x(), overlap.size.height)),
+    );
+    parts.retain(|part| part.size.width > 0 && part.size.height > 0);
+    parts
+}
+
+/// How a scene is composited relative to what is already in the framebuffer.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum SceneLayering {
+    /// The scene is the only one of the frame, clears the framebuffer and finishes the frame
+    /// with the overlay items, the white point and the capture streams.
+    Only,
+
+    /// The scene is the first of several of the frame, and clears the framebuffer.
+    Base,
+
+    /// The scene is drawn over the scenes before it, without clearing their contents and
+    /// without multisampling, since the multisampled framebuffer can't start out with what is
+    /// already there.
+    Overlay,
+}
+
+/// Composites one scene into the current framebuffer: the update pass, texture uploads, raster
+/// caching and drawing, followed by the measurement of the frame. The only scene of a frame
+/// also draws the overlay items and the white point and feeds the capture streams.
+fn composite_scene<T>(render_context: &mut RenderContext,
+                      scene: &Scene<T>,
+                      layering: SceneLayering,
+                      damage_rect: Option<TypedRect<f32, DevicePixel>>,
+                      start_time: Instant)
+                      -> FrameStats {
+    let finishes_frame = layering == SceneLayering::Only;
+    render_context.tile_placeholder = scene.tile_placeholder;
+    render_context.damage_rect = None;
+    render_context.pixel_snapping = scene.pixel_snapping;
+    render_context.scene_scale = scene.scale;
+    render_context.y_axis_up = scene.y_axis_up;
+    render_context.mirrored = scene.mirrored;
+    if let Some(vsync) = render_context.vsync {
+        scene.frame_pacing.borrow_mut().frame_interval = vsync.frame_interval();
+    }
+    render_context.buffers.frame_constants.begin_frame(render_context.scene_scale.get(),
+                                                       render_context.debug_flags());
+    // The embedder may have bound a vertex array of its own since the last frame.
+    if let Some(vertex_array) = render_context.buffers.vertex_array {
+        gl::bind_vertex_array(vertex_array);
+    }
+
+    let overrides = scene.present_overrides.get();
+    if let Some(tile_size) = render_context.tile_size {
+        for root_layer in &scene.roots {
+            walk_subtree(root_layer, |layer| {
+                layer.set_default_tile_size(tile_size);
+                true
+            });
+        }
+    }
+    update_layers(scene, &overrides, start_time);
+    if cfg!(debug_assertions) {
+        for (index, root_layer) in scene.roots.iter().enumerate() {
+            let content_offset = match overrides.root_scroll_offset {
+                Some(root_scroll_offset) if index == 0 => root_scroll_offset,
+                _ => *root_layer.content_offset.borrow(),
+            };
+            validate::validate_scene(root_layer, &content_offset, scene.validation_mode);
+        }
+    }
+
+    // Create native textures for any newly received buffers, within the upload budget.
+    scene.upload_textures_with_default_budget(&render_context.compositing_display,
+                                              render_context.texture_budget);
+
+    // Render static subtrees that have become worth caching into textures, along with the
+    // subtrees collapsed to stay within the resource limits.
+    scene.enforce_resource_limits();
+    let mut layers_to_cache = vec!();
+    for root_layer in &scene.roots {
+        rastercache::update_signatures(root_layer);
+        rastercache::select_cached_layers(root_layer,
+                                          &scene.raster_cache_config,
+                                          &mut layers_to_cache);
+    }
+    if render_context.draws_content() {
+        for layer in &layers_to_cache {
+            render_context.render_layer_to_cache(layer, scene);
+        }
+    }
+
+    // A scene with a presentation rect is scaled into it by the viewport transform. The parts of
+    // the presentation rect it doesn't cover are filled with the letterbox color, and the parts
+    // of the scene outside of the presentation rect are cropped with the scissor test.
+    let presentation_clip = scene.presentation_rect.map(|area| {
+        let area = area.to_untyped();
+        Rect::new(Point2D::new(area.origin.x.round() as GLint, area.origin.y.round() as GLint),
+                  Size2D::new(area.size.width.round() as GLsizei,
+                              area.size.height.round() as GLsizei))
+    });
+    if let (Some(clip), Some(color)) = (presentation_clip,
+                                        scene.presentation_mode.letterbox_color()) {
+        gl::enable(gl::SCISSOR_TEST);
+        gl::scissor(clip.origin.x, clip.origin.y, clip.size.width, clip.size.height);
+        gl::clear_color(color.r, color.g, color.b, color.a);
+        gl::clear(gl::COLOR_BUFFER_BIT);
+        gl::disable(gl::SCISSOR_TEST);
+    }
+
+    // Render into a multisampled framebuffer covering the viewport if requested. It is
+    // resolved into the framebuffer that was bound by the embedder at the end of the frame.
+    let v = scene.presented_viewport().to_untyped();
+    let viewport_origin = Point2D::new(v.origin.x as GLint, v.origin.y as GLint);
+    let viewport_size = Size2D::new(v.size.width as GLsizei, v.size.height as GLsizei);
+    let target_framebuffer = gl::get_integer_v(gl::FRAMEBUFFER_BINDING) as GLuint;
+    let multisampled = layering != SceneLayering::Overlay &&
+                       render_context.sample_count > 1 &&
+                       render_context.bind_scene_target(viewport_size);
+
     // Set the viewport.
-    let v = scene.viewport.to_untyped();
-    gl::viewport(v.origin.x as GLint, v.origin.y as GLint,
-                 v.size.width as GLsizei, v.size.height as GLsizei);
+    if multisampled {
+        gl::viewport(0, 0, viewport_size.width, viewport_size.height);
+    } else {
+        gl::viewport(viewport_origin.x, viewport_origin.y,
+                     viewport_size.width, viewport_size.height);
+    }
 
     // Enable depth testing for 3d transforms. Set z-mode to LESS-EQUAL
     // so that layers with equal Z are able to paint correctly in
     // the order they are specified.
     gl::enable(gl::DEPTH_TEST);
-    gl::clear_color(1.0, 1.0, 1.0, 1.0);
-    gl::clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+
+    // When only the damage is recomposited, the rest of the framebuffer is kept from the
+    // previous frame. Limiting the clear as well as the drawing matters on tiled GPUs, where a
+    // full clear forces the whole framebuffer to be resolved.
+    // Damage rects are in device pixels of the scene, so presented scenes, which may be scaled,
+    // are always recomposited entirely. The multisampled framebuffer is cropped when it is
+    // resolved instead.
+    let damage_rect = if presentation_clip.is_some() { None } else { damage_rect };
+    let damage_scissor = damage_rect.map(|damage_rect| {
+        let origin = if multisampled { Point2D::zero() } else { viewport_origin };
+        damage_scissor_box(&damage_rect, &origin, &viewport_size, scene.y_axis_up, scene.mirrored)
+    });
+    let scissor = if multisampled { damage_scissor } else { damage_scissor.or(presentation_clip) };
+    if let Some(scissor) = scissor {
+        gl::enable(gl::SCISSOR_TEST);
+        gl::scissor(scissor.origin.x, scissor.origin.y, scissor.size.width, scissor.size.height);
+    }
+
+    if layering != SceneLayering::Overlay {
+        if render_context.show_overdraw {
+            gl::clear_color(0.0, 0.0, 0.0, 1.0);
+        } else {
+            gl::clear_color(1.0, 1.0, 1.0, 1.0);
+        }
+        gl::clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+    } else {
+        gl::clear(gl::DEPTH_BUFFER_BIT);
+    }
     gl::depth_func(gl::LEQUAL);
 
+    // The overdraw view accumulates a color for every layer drawn over a pixel.
+    if render_context.show_overdraw {
+        gl::blend_func(gl::ONE, gl::ONE);
+    }
+    if render_context.srgb {
+        enable_srgb_framebuffer(true);
+    }
+
     // Set up the initial modelview matrix.
-    let transform = Matrix4D::identity().pre_scaled(scene.scale.get(), scene.scale.get(), 1.0);
-    let projection = create_ortho(&scene.viewport.size.to_untyped());
-
-    // Build the list of render items
-    render_context.render_3d_context(&RenderContext3D::new(root_layer.clone()),
-                                     &transform,
-                                     &projection,
-                                     &render_context.compositing_display);
+    let transform = scene_transform(scene.scale);
+    let mut projection = create_ortho(&scene.viewport.size,
+                                      scene.y_axis_up,
+                                      scene.mirrored);
+
+    // The pinch transform is in device pixels, so it goes into the projection, which also
+    // applies to cached subtrees and debug overlays drawn in device pixels.
+    if let Some(ref pinch_transform) = overrides.pinch_transform {
+        projection = projection.pre_mul(pinch_transform);
+    }
+
+    // Layers and tiles outside of the damage aren't drawn at all, rather than only clipped,
+    // unless the pinch transform moves them around in device pixels.
+    if overrides.pinch_transform.is_none() {
+        render_context.damage_rect = damage_rect;
+    }
+
+    // Build the list of render items of each root. The depth buffer is cleared between roots,
+    // so that each one is drawn over the ones before it whatever its 3d transforms.
+    for (index, root_layer) in scene.roots.iter().enumerate() {
+        if index > 0 {
+            gl::clear(gl::DEPTH_BUFFER_BIT);
+        }
+        render_context.render_3d_context(&RenderContext3D::new(root_layer.clone()),
+                                         &transform,
+                                         &projection);
+    }
+
+    if render_context.show_overdraw {
+        gl::blend_func(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+    }
+
+    // Blur what ends up under the embedder's translucent native UI.
+    if render_context.draws_content() {
+        for root_layer in &scene.roots {
+            render_context.render_blur_behind_regions(root_layer, &transform, &projection);
+        }
+    }
+
+    // Draw the embedder's overlay items on top, in device pixels, and shift the white point of
+    // the whole frame last, so that everything is affected alike. When several scenes are
+    // composited, this is left until the last one is drawn.
+    if finishes_frame {
+        let overlay_projection = create_ortho(&scene.viewport.size,
+                                              scene.y_axis_up,
+                                              scene.mirrored);
+        let overlay_projection = overlay_projection.to_untyped();
+        render_context.render_overlay_items(&overlay_projection);
+        render_context.render_white_point(&scene.viewport.size, &overlay_projection);
+    }
+
+    if render_context.srgb {
+        enable_srgb_framebuffer(false);
+    }
+    if scissor.is_some() {
+        gl::disable(gl::SCISSOR_TEST);
+    }
+    render_context.damage_rect = None;
+
+    if multisampled {
+        if let Some(clip) = presentation_clip {
+            gl::enable(gl::SCISSOR_TEST);
+            gl::scissor(clip.origin.x, clip.origin.y, clip.size.width, clip.size.height);
+        }
+        if let Some(ref scene_target) = render_context.scene_target {
+            scene_target.resolve(target_framebuffer, viewport_origin);
+        }
+        if presentation_clip.is_some() {
+            gl::disable(gl::SCISSOR_TEST);
+        }
+        gl::viewport(viewport_origin.x, viewport_origin.y,
+                     viewport_size.width, viewport_size.height);
+    }
+
+    // Streams capture the part of the scene that is shown.
+    if finishes_frame {
+        if let Some(captured_rect) = shown_rect(scene) {
+            scene.capture_streams.capture(&captured_rect, start_time);
+        }
+    }
+
+    let mut holes = vec!();
+    for root_layer in &scene.roots {
+        holes.extend(punched_holes(root_layer, scene.scale, overrides.pinch_transform.as_ref()));
+    }
+    *scene.punched_holes.borrow_mut() = holes;
+
+    // Tiles that showed through as a checkerboard are asked to be painted sooner.
+    scene.record_checkerboard_misses();
+    let mut stats = scene.measure_frame();
+    let frame_id = scene.frame_sequence.borrow_mut().record_frame(scene.shown_epoch());
+    stats.frame_id = Some(frame_id);
+    debug!("Composited {}: {} subtrees cached, {} of {} visible pixels checkerboarded",
+           scene.debug_name.as_ref().map_or("scene", |name| &**name),
+           layers_to_cache.len(),
+           stats.checkerboarded_pixels,
+           stats.visible_pixels);
+    if stats.checkerboarded_pixels > 0.0 {
+        if let Some(ref callback) = scene.checkerboard_callback {
+            callback(&stats);
+        }
+    }
+    stats
+}
+
+/// The bookkeeping of a scene once its frame has been submitted.
+fn finish_scene<T>(scene: &Scene<T>, stats: &FrameStats, start_time: Instant) {
+    if let Some(ref slow_frame_capture) = scene.slow_frame_capture {
+        slow_frame_capture.frame_finished(scene, stats, start_time.elapsed());
+    }
+
+    // Layers damaged since the last frame asked for this one.
+    let mut frame_pacing = scene.frame_pacing.borrow_mut();
+    for root_layer in &scene.roots {
+        walk_subtree(root_layer, |layer| {
+            if let Some(damaged_at) = layer.take_damage_time() {
+                frame_pacing.record_request(damaged_at);
+            }
+            true
+        });
+    }
+    frame_pacing.record_composite(Instant::now());
+}
+
+/// Reads back the contents of the scene's viewport, where it was presented, from the current
+/// framebuffer, as tightly packed RGBA rows ordered from top to bottom. This is intended for
+/// reftests and screenshots; it stalls the pipeline.
+pub fn read_back_scene<T>(scene: &Scene<T>) -> Vec<u8> {
+    let v = scene.presented_viewport().to_untyped();
+    let (width, height) = (v.size.width as usize, v.size.height as usize);
+    let pixels = gl::read_pixels(v.origin.x as GLint, v.origin.y as GLint,
+                                 width as GLsizei, height as GLsizei,
+                                 gl::RGBA, gl::UNSIGNED_BYTE);
+
+    // OpenGL returns rows bottom-up.
+    let stride = width * 4;
+    let mut flipped = Vec::with_capacity(pixels.len());
+    for row in (0..height).rev() {
+        flipped.extend_from_slice(&pixels[row * stride..(row + 1) * stride]);
+    }
+    flipped
 }
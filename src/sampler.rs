@@ -0,0 +1,172 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Sampler objects, which keep the filtering state of a draw out of the texture objects.
+//!
+//! Without them, the filter mode of each quad is set on its texture, which makes drivers
+//! revalidate the texture whenever the mode changes. With OpenGL 3.3, OpenGL ES 3.0 or
+//! `GL_ARB_sampler_objects`, one sampler object per combination of states is created up front
+//! and bound to the texture unit for the draw instead. Since a bound sampler overrides all of
+//! the sampling state of the texture, the combinations include the wrap modes, and the sampler
+//! for the wrap modes of the drawn texture is chosen.
+//!
+//! With `GL_EXT_texture_sRGB_decode`, the samplers also control whether sRGB-encoded textures
+//! are linearized when sampled, so that they can be drawn both into sRGB framebuffers, which
+//! blend in linear space, and into ordinary ones, which take the encoded values as they are.
+
+use glcapture as gl;
+use gleam::gl::{GLenum, GLint, GLuint};
+
+use capabilities::GlCapabilities;
+use texturegl::{FilterMode, WrapMode};
+
+const FILTER_MODES: [FilterMode; 2] = [FilterMode::Nearest, FilterMode::Linear];
+const WRAP_MODES: [WrapMode; 3] =
+    [WrapMode::ClampToEdge, WrapMode::Repeat, WrapMode::MirroredRepeat];
+
+// From `GL_EXT_texture_sRGB_decode`, which the bindings may not include.
+const TEXTURE_SRGB_DECODE_EXT: GLenum = 0x8A48;
+const DECODE_EXT: GLenum = 0x8A49;
+const SKIP_DECODE_EXT: GLenum = 0x8A4A;
+
+/// The sampler objects of a render context.
+pub struct Samplers {
+    /// Indexed by `index`.
+    samplers: Vec<GLuint>,
+
+    /// Whether the samplers control sRGB decoding.
+    srgb_decode: bool,
+}
+
+impl Samplers {
+    /// Creates the samplers, or returns `None` if the GL context current on this thread doesn't
+    /// support sampler objects, in which case filter modes are set on the textures.
+    pub fn new() -> Option<Samplers> {
+        let capabilities = GlCapabilities::current();
+        if !capabilities.sampler_objects {
+            return None;
+        }
+
+        let srgb_decode = capabilities.srgb_decode;
+        let mut samplers = vec![0; FILTER_MODES.len() * WRAP_MODES.len() * WRAP_MODES.len() * 2];
+        unsafe {
+            gl::GenSamplers(samplers.len() as GLint, samplers.as_mut_ptr());
+        }
+        for &filter_mode in &FILTER_MODES {
+            for &wrap_s in &WRAP_MODES {
+                for &wrap_t in &WRAP_MODES {
+                    for &decode in &[false, true] {
+                        let sampler = samplers[index(filter_mode, (wrap_s, wrap_t), decode)];
+                        set_parameters(sampler, filter_mode, (wrap_s, wrap_t));
+                        if srgb_decode {
+                            let decode = if decode { DECODE_EXT } else { SKIP_DECODE_EXT };
+                            unsafe {
+                                gl::SamplerParameteri(sampler,
+                                                      TEXTURE_SRGB_DECODE_EXT,
+                                                      decode as GLint);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        info!("Using sampler objects{}",
+              if srgb_decode { " with sRGB decode control" } else { "" });
+
+        Some(Samplers {
+            samplers: samplers,
+            srgb_decode: srgb_decode,
+        })
+    }
+
+    /// Whether sRGB-encoded textures can be sampled without being linearized.
+    pub fn controls_srgb_decode(&self) -> bool {
+        self.srgb_decode
+    }
+
+    /// Binds the sampler for the given filter mode and the wrap modes of the texture drawn, as
+    /// returned by `Texture::wrap_mode`, to a texture unit until the returned guard is dropped.
+    /// `decode_srgb` chooses whether sRGB-encoded textures are linearized; it is ignored without
+    /// `GL_EXT_texture_sRGB_decode`, where they always are.
+    pub fn bind(&self,
+                unit: GLuint,
+                filter_mode: FilterMode,
+                wrap_mode: (WrapMode, WrapMode),
+                decode_srgb: bool)
+                -> BoundSampler {
+        unsafe {
+            gl::BindSampler(unit, self.samplers[index(filter_mode, wrap_mode, decode_srgb)]);
+        }
+        BoundSampler {
+            unit: unit,
+        }
+    }
+}
+
+impl Drop for Samplers {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteSamplers(self.samplers.len() as GLint, self.samplers.as_ptr());
+        }
+    }
+}
+
+/// A sampler bound to a texture unit. Dropping it unbinds the sampler, so that textures drawn
+/// afterwards use their own filtering state again.
+pub struct BoundSampler {
+    unit: GLuint,
+}
+
+impl Drop for BoundSampler {
+    fn drop(&mut self) {
+        unsafe {
+            gl::BindSampler(self.unit, 0);
+        }
+    }
+}
+
+fn set_parameters(sampler: GLuint,
+                  filter_mode: FilterMode,
+                  (wrap_s, wrap_t): (WrapMode, WrapMode)) {
+    let gl_filter_mode = match filter_mode {
+        FilterMode::Nearest => gl::NEAREST,
+        FilterMode::Linear => gl::LINEAR,
+    } as GLint;
+    let gl_wrap_mode = |mode| {
+        match mode {
+            WrapMode::ClampToEdge => gl::CLAMP_TO_EDGE,
+            WrapMode::Repeat => gl::REPEAT,
+            WrapMode::MirroredRepeat => gl::MIRRORED_REPEAT,
+        } as GLint
+    };
+    unsafe {
+        gl::SamplerParameteri(sampler, gl::TEXTURE_MAG_FILTER, gl_filter_mode);
+        gl::SamplerParameteri(sampler, gl::TEXTURE_MIN_FILTER, gl_filter_mode);
+        gl::SamplerParameteri(sampler, gl::TEXTURE_WRAP_S, gl_wrap_mode(wrap_s));
+        gl::SamplerParameteri(sampler, gl::TEXTURE_WRAP_T, gl_wrap_mode(wrap_t));
+    }
+}
+
+fn index(filter_mode: FilterMode, (wrap_s, wrap_t): (WrapMode, WrapMode), decode_srgb: bool)
+         -> usize {
+    let filter_index = match filter_mode {
+        FilterMode::Nearest => 0,
+        FilterMode::Linear => 1,
+    };
+    let wrap_index = |mode| {
+        match mode {
+            WrapMode::ClampToEdge => 0,
+            WrapMode::Repeat => 1,
+            WrapMode::MirroredRepeat => 2,
+        }
+    };
+    let modes = WRAP_MODES.len();
+    ((filter_index * modes + wrap_index(wrap_s)) * modes + wrap_index(wrap_t)) * 2 +
+        if decode_srgb { 1 } else { 0 }
+}
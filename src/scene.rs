@@ -7,31 +7,395 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use euclid::rect::TypedRect;
+use animation::{ScaleAnimation, ScrollAnimation, ZoomAnimation};
+use capturestream::{CaptureConfig, CaptureStreams, CapturedFrame};
+use color::Color;
+use euclid::rect::{Rect, TypedRect};
 use euclid::scale_factor::ScaleFactor;
 use euclid::size::TypedSize2D;
-use euclid::point::TypedPoint2D;
+use euclid::point::{Point2D, TypedPoint2D};
 use geometry::{DevicePixel, LayerPixel};
-use layers::{BufferRequest, Layer, LayerBuffer};
+use hittest::{self, HitTestResult, PaintedLayer};
+use layers::{BufferRequest, HoleId, Layer, LayerBuffer, walk_subtree};
+use limits::{self, ResourceLimits};
+use overrides::PresentOverridesHandle;
+use profiler;
+use rendergl::{self, RenderContext};
+use slowframe::SlowFrameCapture;
+use platform::surface::NativeDisplay;
+use rastercache::RasterCacheConfig;
+use scrolling::{self, ResizeBehavior};
+use sequence::{Epoch, FrameSequence};
+use stats::{ContentValidity, FramePacing, FramePacingReport, FrameStats, SceneStats};
+use stats::TileValidityMask;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashMap;
 use std::rc::Rc;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+use texturegl::{self, TexturePool};
+use tiling::{Tile, TileEviction, device_rect_to_layer_rect};
+use upload::UploadBudget;
+use util::project_rect_to_screen;
+use validate::ValidationMode;
+
+/// What is drawn in place of tiles that haven't been painted yet.
+#[derive(Copy, Clone, Debug)]
+pub enum TilePlaceholder {
+    /// Nothing, so the background color of the layer shows through.
+    Background,
+    /// A solid color: the layer's `placeholder_color`, or the given color for layers without
+    /// one.
+    SolidColor(Color),
+    /// A checkerboard pattern of the two colors, with squares of the given size in device
+    /// pixels.
+    Checkerboard(Color, Color, f32),
+    /// The layer's low-resolution preview, stretched over the missing tiles. Nothing is drawn
+    /// for layers without a preview.
+    LowResPreview,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, TilePlaceholder);
+
+/// Which layers are moved to the nearest device pixel when they are composited. Snapping keeps
+/// text and thin lines crisp, but makes slow animations step from pixel to pixel instead of
+/// moving smoothly.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PixelSnapping {
+    /// Snap every layer whose transform keeps it axis-aligned, including scaled layers. This
+    /// suits text-heavy documents.
+    Always,
+    /// Snap only layers that are translated, without any scale, rotation or perspective.
+    TranslationsOnly,
+    /// Draw layers where their transforms put them. This suits animation-heavy interfaces, and
+    /// is the default.
+    Never,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, PixelSnapping);
+
+/// How a scene is fitted into its presentation rect when the sizes differ, as when a
+/// fixed-resolution scene is shown in a window of any size.
+#[derive(Copy, Clone, Debug)]
+pub enum PresentationMode {
+    /// Scale each axis independently to fill the presentation rect, distorting the scene if the
+    /// aspect ratios differ.
+    Stretch,
+    /// Scale uniformly so that the whole scene fits, centered, and fill the bars on either side
+    /// with the given color.
+    AspectFit(Color),
+    /// Scale uniformly so that the scene covers the whole presentation rect, centered, and crop
+    /// what overflows.
+    AspectFill,
+    /// Scale by the largest whole factor at which the scene fits, and at least 1, so that pixels
+    /// stay square and sharp. The scene is centered, the rest is filled with the given color, and
+    /// scenes that don't fit at 1 are cropped.
+    IntegerScale(Color),
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, PresentationMode);
+
+/// A hole punched through a composited frame by a layer, for the native widget behind the GL
+/// surface to show through.
+#[derive(Copy, Clone, Debug)]
+pub struct PunchedHole {
+    pub id: HoleId,
+
+    /// The bounding rect of the hole in device pixels, after the pinch transform.
+    pub rect: TypedRect<f32, DevicePixel>,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, PunchedHole);
+
+impl PresentationMode {
+    /// The color the presentation rect is filled with around the scene, if any.
+    pub fn letterbox_color(&self) -> Option<Color> {
+        match *self {
+            PresentationMode::AspectFit(color) | PresentationMode::IntegerScale(color) => {
+                Some(color)
+            }
+            PresentationMode::Stretch | PresentationMode::AspectFill => None,
+        }
+    }
+}
 
 pub struct Scene<T> {
-    pub root: Option<Rc<Layer<T>>>,
+    /// The root layers of the scene, composited in order, each over the ones before it. The
+    /// first is the main root, which is scrolled and resized with the viewport; the others are
+    /// overlays such as IME candidate windows or devtools highlights. A scene without root
+    /// layers only clears its viewport.
+    pub roots: Vec<Rc<Layer<T>>>,
     pub viewport: TypedRect<f32, DevicePixel>,
 
     /// The scene scale, to allow for zooming and high-resolution painting.
     pub scale: ScaleFactor<f32, LayerPixel, DevicePixel>,
+
+    /// The maximum number of texture bytes to upload in a single frame, or `None` for no limit.
+    /// Tiles that don't fit are uploaded on subsequent frames, visible tiles first.
+    pub max_upload_bytes_per_frame: Option<usize>,
+
+    /// The heuristics used to decide which static subtrees are cached in textures.
+    pub raster_cache_config: RasterCacheConfig,
+
+    /// Limits on the resources used by the layer tree. Subtrees are collapsed into cached
+    /// textures to stay within them.
+    pub resource_limits: ResourceLimits,
+
+    /// What to draw in place of missing tiles.
+    pub tile_placeholder: TilePlaceholder,
+
+    /// Which layers are snapped to device pixels.
+    pub pixel_snapping: PixelSnapping,
+
+    /// Called after each frame in which some visible tiles were missing.
+    pub checkerboard_callback: Option<Box<Fn(&FrameStats)>>,
+
+    /// Called with the tiles that a layer dropped along with their buffers, e.g. as they left
+    /// the viewport, so that the painting side can drop what it cached to paint them: as
+    /// buffers are requested, or as the buffers of removed layers are collected with
+    /// `collect_buffers_for_subtree`. They are requested again if they are needed later.
+    pub tile_eviction_callback: Option<Box<Fn(&Rc<Layer<T>>, &[TileEviction])>>,
+
+    /// A human-readable name for this scene, used in logs, dumps and profiles.
+    pub debug_name: Option<String>,
+
+    /// If set, frames that take too long to composite are dumped to disk for later diagnosis.
+    pub slow_frame_capture: Option<SlowFrameCapture>,
+
+    /// The streams composited frames are sent on, as started with `capture_frames`.
+    pub capture_streams: CaptureStreams,
+
+    /// The pacing of the frames of this scene, for detecting dropped frames. It assumes a 60Hz
+    /// display until the embedder sets the actual refresh interval. Embedders with presentation
+    /// timestamps report them with `FramePacing::record_presentation`; otherwise the times at
+    /// which `render_scene` finishes are used.
+    pub frame_pacing: RefCell<FramePacing>,
+
+    /// The numbers of the frames composited for this scene, and the epochs of the layers drawn
+    /// in recent ones, so that embedders can tell when an update is on screen.
+    pub frame_sequence: RefCell<FrameSequence>,
+
+    /// The holes punched through the last frame composited for this scene, in tree order, for
+    /// the embedder to move its native widgets to. Parts of them may be covered by layers drawn
+    /// above.
+    pub punched_holes: RefCell<Vec<PunchedHole>>,
+
+    /// Whether the y axis of the scene points up, with the origin at the bottom left of the
+    /// viewport, as in frameworks that follow the OpenGL conventions. Layer contents stay
+    /// upright.
+    pub y_axis_up: bool,
+
+    /// Whether the scene is presented mirrored horizontally, with the origin on the right, as
+    /// in right-to-left user interfaces. Layer contents are not mirrored.
+    pub mirrored: bool,
+
+    /// The maximum number of request frames whose tiles may be in flight between the buffer
+    /// request and the texture upload, usually 1, 2 or 3, or `None` for no limit. Lower values
+    /// shorten the delay between painting and compositing at the expense of painting
+    /// throughput.
+    pub max_frame_latency: Option<usize>,
+
+    /// Properties that other threads can override right before the next frames are composited.
+    pub present_overrides: PresentOverridesHandle,
+
+    /// What happens to the scroll offset of the root layer in `set_root_layer_size`.
+    pub resize_behavior: ResizeBehavior,
+
+    /// How inconsistencies in the layer tree are reported. The tree is only validated in debug
+    /// builds.
+    pub validation_mode: ValidationMode,
+
+    /// If set, only this part of the viewport, in device pixels, is recomposited by the next
+    /// `render_scene`: the clear and all drawing, including overlay items, are limited to it
+    /// with the scissor test. The rest of the framebuffer must have been preserved from the
+    /// previous frame. This is typically the rect returned by `take_damage`.
+    pub damage_rect: Option<TypedRect<f32, DevicePixel>>,
+
+    /// If set, the scene is composited into this rect of the window, in window coordinates,
+    /// instead of at `viewport`, and scaled to it according to `presentation_mode`. The size of
+    /// `viewport` stays the resolution of the scene; its origin is ignored. Scenes presented
+    /// this way are always recomposited entirely.
+    pub presentation_rect: Option<TypedRect<f32, DevicePixel>>,
+
+    /// How the scene is fitted into `presentation_rect`.
+    pub presentation_mode: PresentationMode,
+
+    /// The request frame of the next batch of buffer requests.
+    next_request_frame: u64,
+
+    /// Whether the application is in the background, with no surface to composite into.
+    paused: bool,
 }
 
 impl<T> Scene<T> {
     pub fn new(viewport: TypedRect<f32, DevicePixel>) -> Scene<T> {
         Scene {
-            root: None,
+            roots: vec!(),
             viewport: viewport,
             scale: ScaleFactor::new(1.0),
+            max_upload_bytes_per_frame: None,
+            raster_cache_config: RasterCacheConfig::new(),
+            resource_limits: ResourceLimits::new(),
+            tile_placeholder: TilePlaceholder::Background,
+            pixel_snapping: PixelSnapping::Never,
+            checkerboard_callback: None,
+            tile_eviction_callback: None,
+            debug_name: None,
+            slow_frame_capture: None,
+            capture_streams: CaptureStreams::new(),
+            frame_pacing: RefCell::new(FramePacing::new(Duration::new(0, 16_666_667))),
+            frame_sequence: RefCell::new(FrameSequence::new()),
+            punched_holes: RefCell::new(vec!()),
+            y_axis_up: false,
+            mirrored: false,
+            max_frame_latency: None,
+            present_overrides: PresentOverridesHandle::new(),
+            resize_behavior: ResizeBehavior::PreserveTopLeft,
+            validation_mode: ValidationMode::Disabled,
+            damage_rect: None,
+            presentation_rect: None,
+            presentation_mode: PresentationMode::Stretch,
+            next_request_frame: 0,
+            paused: false,
         }
     }
 
+    pub fn set_debug_name(&mut self, name: &str) {
+        self.debug_name = Some(name.to_owned());
+    }
+
+    /// The main root layer, if the scene has any root layers.
+    pub fn root(&self) -> Option<&Rc<Layer<T>>> {
+        self.roots.first()
+    }
+
+    /// The rect of the window the scene is drawn into, in window coordinates. This is the
+    /// viewport unless the scene has a presentation rect; with `AspectFill` and `IntegerScale`,
+    /// it may extend beyond the presentation rect.
+    pub fn presented_viewport(&self) -> TypedRect<f32, DevicePixel> {
+        let area = match self.presentation_rect {
+            Some(area) => area,
+            None => return self.viewport,
+        };
+        let size = self.viewport.size;
+        if !(size.width > 0.0 && size.height > 0.0) {
+            return area;
+        }
+
+        let (scale_x, scale_y) = (area.size.width / size.width, area.size.height / size.height);
+        let scale = match self.presentation_mode {
+            PresentationMode::Stretch => return area,
+            PresentationMode::AspectFit(_) => scale_x.min(scale_y),
+            PresentationMode::AspectFill => scale_x.max(scale_y),
+            PresentationMode::IntegerScale(_) => scale_x.min(scale_y).floor().max(1.0),
+        };
+        let presented_size = size * scale;
+        let origin = TypedPoint2D::new(
+            (area.origin.x + (area.size.width - presented_size.width) * 0.5).round(),
+            (area.origin.y + (area.size.height - presented_size.height) * 0.5).round());
+        TypedRect::new(origin, presented_size)
+    }
+
+    /// Maps a point in window coordinates, such as the position of an input event, to device
+    /// pixels of the scene. Returns `None` for points outside of the part of the scene that is
+    /// shown, e.g. in the letterbox bars.
+    pub fn window_point_to_viewport(&self, point: &TypedPoint2D<f32, DevicePixel>)
+                                    -> Option<TypedPoint2D<f32, DevicePixel>> {
+        let presented_viewport = self.presented_viewport();
+        let shown_rect = match self.presentation_rect {
+            Some(area) => match area.intersection(&presented_viewport) {
+                Some(shown_rect) => shown_rect,
+                None => return None,
+            },
+            None => presented_viewport,
+        };
+        if !shown_rect.contains(point) {
+            return None;
+        }
+        let size = self.viewport.size;
+        Some(TypedPoint2D::new(
+            (point.x - presented_viewport.origin.x) * size.width / presented_viewport.size.width,
+            (point.y - presented_viewport.origin.y) * size.height / presented_viewport.size.height))
+    }
+
+    /// The latest update of the layer tree that a frame composited now shows entirely, as of
+    /// the current transform state and textures, if any. That is the latest epoch any layer
+    /// carries, drawn or not, unless a visible tile of a layer draws content painted for an
+    /// older update than the layer carries, or no content at all; the frame then only shows the
+    /// updates before the one the layer carries.
+    pub fn shown_epoch(&self) -> Option<Epoch> {
+        let mut latest = None;
+        let mut stale = vec!();
+        for root_layer in &self.roots {
+            walk_subtree(root_layer, |layer| {
+                let layer_epoch = match *layer.epoch.borrow() {
+                    Some(layer_epoch) => layer_epoch,
+                    None => return true,
+                };
+                latest = cmp::max(latest, Some(layer_epoch));
+                if !layer.transform_state.borrow().visible {
+                    return true;
+                }
+                layer.do_for_all_tiles(|tile| {
+                    // The latest update this tile shows, if it is older than the layer's.
+                    let shown = if tile.texture.is_zero() {
+                        layer_epoch.0.checked_sub(1).map(Epoch)
+                    } else {
+                        match tile.epoch() {
+                            Some(epoch) if epoch < layer_epoch => Some(epoch),
+                            _ => return,
+                        }
+                    };
+                    if self.tile_is_visible(layer, tile) {
+                        stale.push(shown);
+                    }
+                });
+                true
+            });
+        }
+        stale.into_iter().fold(latest, cmp::min)
+    }
+
+    /// Summarizes the pacing of recent frames, for telemetry on scrolling and animation
+    /// smoothness.
+    pub fn frame_pacing_report(&self) -> FramePacingReport {
+        self.frame_pacing.borrow().report()
+    }
+
+    /// Starts a stream of the frames composited from now on, e.g. for tab capture or screen
+    /// sharing. The frames are read back after each `render_scene`, at most at the configured
+    /// frame rate and scaled down as configured, and are sent after the next `render_scene` if
+    /// pixel buffer objects are supported. Frames are dropped while the receiver has
+    /// `MAX_QUEUED_FRAMES` of them queued. Dropping the receiver ends the stream.
+    pub fn capture_frames(&self, config: CaptureConfig) -> Receiver<CapturedFrame> {
+        self.capture_streams.subscribe(config)
+    }
+
+    /// Returns a textual dump of the layer tree, one layer per line, indented by depth.
+    pub fn dump_layer_tree(&self) -> String {
+        let mut dump = format!("{}\n", self.debug_name.as_ref().map_or("scene", |name| &**name));
+        let mut stack: Vec<_> = self.roots.iter().rev().map(|root| (root.clone(), 1)).collect();
+        while let Some((layer, depth)) = stack.pop() {
+            let bounds = layer.bounds.borrow().to_untyped();
+            dump.push_str(&format!("{:indent$}{} bounds={:?} tiles={} opacity={}\n",
+                                   "",
+                                   layer.debug_name(),
+                                   bounds,
+                                   layer.tile_count(),
+                                   layer.effective_opacity(),
+                                   indent = depth * 2));
+            for kid in layer.children().iter().rev() {
+                stack.push((kid.clone(), depth + 1));
+            }
+        }
+        dump
+    }
+
     pub fn get_buffer_requests_for_layer(&mut self,
                                          layer: Rc<Layer<T>>,
                                          dirty_rect: TypedRect<f32, LayerPixel>,
@@ -39,81 +403,718 @@ impl<T> Scene<T> {
                                          layers_and_requests: &mut Vec<(Rc<Layer<T>>,
                                                                         Vec<BufferRequest>)>,
                                          unused_buffers: &mut Vec<Box<LayerBuffer>>) {
-        // Get buffers for this layer, in global (screen) coordinates.
-        let requests = layer.get_buffer_requests(dirty_rect, viewport_rect, self.scale);
-        if !requests.is_empty() {
-            layers_and_requests.push((layer.clone(), requests));
-        }
-        unused_buffers.extend(layer.collect_unused_buffers().into_iter());
+        // Walk the tree with an explicit stack, since layer trees can be deep enough to
+        // overflow the call stack. Children are pushed in reverse to keep paint order.
+        let pinch_transform = self.present_overrides.get().pinch_transform;
+        let mut stack = vec!((layer, dirty_rect));
+        while let Some((layer, dirty_rect)) = stack.pop() {
+            // Get buffers for this layer, in global (screen) coordinates.
+            let requests = layer.get_buffer_requests(dirty_rect,
+                                                     viewport_rect,
+                                                     self.scale,
+                                                     pinch_transform.as_ref(),
+                                                     self.next_request_frame);
+            if !requests.is_empty() {
+                layers_and_requests.push((layer.clone(), requests));
+            }
+            unused_buffers.extend(layer.collect_unused_buffers().into_iter());
+            self.report_tile_evictions(&layer);
 
-        // If this layer masks its children, we don't need to ask for tiles outside the
-        // boundaries of this layer.
-        let child_dirty_rect = if !*layer.masks_to_bounds.borrow() {
-            dirty_rect
-        } else {
-            match layer.transform_state.borrow().screen_rect {
-                Some(ref screen_rect) => {
-                    match dirty_rect.to_untyped().intersection(&screen_rect.rect) {
-                        Some(ref child_dirty_rect) => TypedRect::from_untyped(child_dirty_rect),
-                        None => return, // The layer is entirely outside the dirty rect.
-                    }
-                },
-                None => return, // The layer is entirely clipped.
+            // Collapsed subtrees are drawn from their cached texture, so the tiles of their
+            // descendants can go, and no new ones are needed.
+            let collapsed = {
+                let cache = layer.raster_cache.borrow();
+                cache.is_collapsed() && cache.is_active()
+            };
+            if collapsed {
+                for kid in layer.children().iter() {
+                    unused_buffers.extend(self.collect_buffers_for_subtree(kid).into_iter());
+                }
+                continue;
             }
-        };
 
-        for kid in layer.children().iter() {
-            self.get_buffer_requests_for_layer(kid.clone(),
-                                               child_dirty_rect,
-                                               viewport_rect,
-                                               layers_and_requests,
-                                               unused_buffers);
+            // If this layer masks its children, we don't need to ask for tiles outside the
+            // boundaries of this layer.
+            let child_dirty_rect = if !*layer.masks_to_bounds.borrow() {
+                dirty_rect
+            } else {
+                match layer.transform_state.borrow().screen_rect {
+                    Some(ref screen_rect) => {
+                        match dirty_rect.to_untyped().intersection(&screen_rect.rect) {
+                            Some(ref child_dirty_rect) => TypedRect::from_untyped(child_dirty_rect),
+                            None => continue, // The layer is entirely outside the dirty rect.
+                        }
+                    },
+                    None => continue, // The layer is entirely clipped.
+                }
+            };
+
+            for kid in layer.children().iter().rev() {
+                stack.push((kid.clone(), child_dirty_rect));
+            }
+        }
+    }
+
+    /// Drops the tiles of every layer of the subtree rooted at `layer`, as when it is removed
+    /// from the tree or collapsed, and returns their buffers. The dropped tiles are passed to
+    /// the tile eviction callback right away, since removed layers aren't walked again.
+    pub fn collect_buffers_for_subtree(&self, layer: &Rc<Layer<T>>) -> Vec<Box<LayerBuffer>> {
+        let mut buffers = vec!();
+        walk_subtree(layer, |descendant| {
+            let (descendant_buffers, evictions) = descendant.collect_buffers();
+            buffers.extend(descendant_buffers.into_iter());
+            self.report_evictions(descendant, &evictions);
+            true
+        });
+        buffers
+    }
+
+    /// Passes the tiles that the layer dropped since the last call to the tile eviction
+    /// callback, if any.
+    fn report_tile_evictions(&self, layer: &Rc<Layer<T>>) {
+        self.report_evictions(layer, &layer.take_tile_evictions());
+    }
+
+    fn report_evictions(&self, layer: &Rc<Layer<T>>, evictions: &[TileEviction]) {
+        if evictions.is_empty() {
+            return;
+        }
+        if let Some(ref callback) = self.tile_eviction_callback {
+            callback(layer, evictions);
         }
     }
 
     pub fn get_buffer_requests(&mut self,
                                requests: &mut Vec<(Rc<Layer<T>>, Vec<BufferRequest>)>,
                                unused_buffers: &mut Vec<Box<LayerBuffer>>) {
-        let root_layer = match self.root {
-            Some(ref root_layer) => root_layer.clone(),
-            None => return,
+        if self.roots.is_empty() {
+            return;
+        }
+
+        // Nothing is visible in an empty viewport, e.g. while the window is minimized. The
+        // tiles are kept for when it's restored.
+        if !(self.viewport.size.width > 0.0 && self.viewport.size.height > 0.0) ||
+                !(self.scale.get() > 0.0) {
+            debug!(target: "layers::tiling",
+                   "Not requesting buffers for an empty viewport {:?} at scale {}",
+                   self.viewport,
+                   self.scale.get());
+            return;
+        }
+
+        // Hold new requests back until the oldest frame in flight has been uploaded.
+        let frames_in_flight = self.frames_in_flight();
+        if self.frame_latency_reached(frames_in_flight) {
+            debug!(target: "layers::tiling",
+                   "Not requesting buffers, {} frames in flight",
+                   frames_in_flight);
+            return;
+        }
+
+        let _span = profiler::span("buffer requests");
+        let mut new_requests = vec!();
+        for root_layer in self.roots.clone() {
+            let bounds = *root_layer.bounds.borrow();
+            self.get_buffer_requests_for_layer(root_layer,
+                                               bounds,
+                                               bounds,
+                                               &mut new_requests,
+                                               unused_buffers);
+        }
+        if !new_requests.is_empty() {
+            self.next_request_frame += 1;
+        }
+
+        // Layers with checkerboarded tiles go first, otherwise layers stay in tree order, root
+        // after root. The requests already in `requests` are left where they are.
+        let max_priority = |requests: &Vec<BufferRequest>| {
+            requests.iter().map(|request| request.priority).max().unwrap_or(0)
         };
+        new_requests.sort_by(|a, b| max_priority(&b.1).cmp(&max_priority(&a.1)));
+        requests.extend(new_requests);
 
-        self.get_buffer_requests_for_layer(root_layer.clone(),
-                                           *root_layer.bounds.borrow(),
-                                           *root_layer.bounds.borrow(),
-                                           requests,
-                                           unused_buffers);
+        debug!(target: "layers::tiling",
+               "{} buffer requests for {} layers, {} unused buffers",
+               requests.iter().map(|&(_, ref requests)| requests.len()).sum::<usize>(),
+               requests.len(),
+               unused_buffers.len());
     }
 
-    pub fn mark_layer_contents_as_changed_recursively_for_layer(&self, layer: Rc<Layer<T>>) {
-        layer.contents_changed();
-        for kid in layer.children().iter() {
-            self.mark_layer_contents_as_changed_recursively_for_layer(kid.clone());
+    /// The number of request frames with tiles that have been requested but not uploaded yet.
+    pub fn frames_in_flight(&self) -> usize {
+        let mut frames = vec!();
+        for root_layer in &self.roots {
+            walk_subtree(root_layer, |layer| {
+                layer.add_request_frames_in_flight(&mut frames);
+                true
+            });
+        }
+        frames.sort();
+        frames.dedup();
+        frames.len()
+    }
+
+    fn frame_latency_reached(&self, frames_in_flight: usize) -> bool {
+        match self.max_frame_latency {
+            Some(max_frame_latency) => frames_in_flight >= cmp::max(max_frame_latency, 1),
+            None => false,
         }
     }
 
+    pub fn mark_layer_contents_as_changed_recursively_for_layer(&self, layer: Rc<Layer<T>>) {
+        walk_subtree(&layer, |layer| {
+            layer.contents_changed();
+            true
+        });
+    }
+
     pub fn mark_layer_contents_as_changed_recursively(&self) {
-        let root_layer = match self.root {
-            Some(ref root_layer) => root_layer.clone(),
-            None => return,
-        };
-        self.mark_layer_contents_as_changed_recursively_for_layer(root_layer);
+        for root_layer in &self.roots {
+            self.mark_layer_contents_as_changed_recursively_for_layer(root_layer.clone());
+        }
     }
 
+    /// Resizes the main root layer to cover a viewport of the given size, and adjusts its scroll
+    /// offset according to the scene's resize behavior. Tiles that are still needed are kept.
+    /// Negative sizes, as reported by some window systems for minimized windows, are treated as
+    /// empty. Overlay roots keep their bounds.
     pub fn set_root_layer_size(&self, new_size: TypedSize2D<f32, DevicePixel>) {
-        if let Some(ref root_layer) = self.root {
-            *root_layer.bounds.borrow_mut() = TypedRect::new(TypedPoint2D::zero(),
-                                                             new_size / self.scale);
+        if let Some(root_layer) = self.root() {
+            let old_size = root_layer.bounds.borrow().size;
+            let new_size = TypedSize2D::new(new_size.width.max(0.0), new_size.height.max(0.0));
+            let new_size = if self.scale.get() > 0.0 {
+                new_size / self.scale
+            } else {
+                TypedSize2D::zero()
+            };
+            *root_layer.bounds.borrow_mut() = TypedRect::new(TypedPoint2D::zero(), new_size);
+
+            let content_size = scrolling::scrollable_content_size(root_layer);
+            let mut content_offset = root_layer.content_offset.borrow_mut();
+            *content_offset = self.resize_behavior.resized_content_offset(*content_offset,
+                                                                          old_size,
+                                                                          new_size,
+                                                                          content_size);
         }
     }
 
     /// Calculate the amount of memory used by all the layers in the
     /// scene graph. The memory may be allocated on the heap or in GPU memory.
     pub fn get_memory_usage(&self) -> usize {
-        match self.root {
-            Some(ref root_layer) => root_layer.get_memory_usage(),
-            None => 0,
+        self.roots.iter().map(|root_layer| root_layer.get_memory_usage()).sum()
+    }
+
+    /// Collapses or expands subtrees so that the layer tree stays within the resource limits.
+    /// The transform state of the layers must be up to date.
+    pub fn enforce_resource_limits(&self) {
+        let visible_rect = TypedRect::new(TypedPoint2D::zero(), self.viewport.size) / self.scale;
+        for root_layer in &self.roots {
+            limits::enforce_limits(root_layer, &self.resource_limits, &visible_rect.to_untyped());
+        }
+    }
+
+    /// Records that the application went into the background, as on Android, where the window
+    /// surface is destroyed while the GL context may survive. Until `resume`, `render_scene`
+    /// and `upload_textures` don't touch GL, while buffers can still be requested and received.
+    pub fn pause(&mut self) {
+        debug!("Pausing {}", self.debug_name.as_ref().map_or("scene", |name| &**name));
+        self.paused = true;
+    }
+
+    /// Records that the application came back to the foreground and the window surface was
+    /// acquired again. If the GL context was lost while paused, the textures of the tiles and
+    /// of the raster cache are forgotten, and tiles are bound to new textures from their
+    /// buffers on the next upload; the embedder must also create a new `RenderContext`. The
+    /// whole viewport is recomposited by the next frame.
+    pub fn resume(&mut self, context_lost: bool) {
+        debug!("Resuming {}{}",
+               self.debug_name.as_ref().map_or("scene", |name| &**name),
+               if context_lost { " after losing the GL context" } else { "" });
+        self.paused = false;
+        self.damage_rect = None;
+        if !context_lost {
+            return;
+        }
+        texturegl::forget_pixel_buffers();
+        self.capture_streams.forget_buffers();
+        TexturePool::forget();
+        for root_layer in &self.roots {
+            walk_subtree(root_layer, |layer| {
+                layer.forget_textures();
+                layer.raster_cache.borrow_mut().forget();
+                true
+            });
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Does the work that would otherwise make the first composite of this scene slow, so that
+    /// it can be done before the window is shown: compiles the shader programs most frames
+    /// draw with, creates the multisampled framebuffer for the viewport, and uploads all of the
+    /// buffers received so far regardless of the upload budget. The GL context of
+    /// `render_context` must be current.
+    pub fn prepare_first_frame(&self, render_context: &mut RenderContext) {
+        rendergl::prepare_first_frame(render_context, self)
+    }
+
+    /// Creates textures for newly received buffers, within the per-frame upload budget. Tiles
+    /// that intersect the viewport are uploaded before tiles that are offscreen.
+    pub fn upload_textures(&self, display: &NativeDisplay) {
+        self.upload_textures_with_default_budget(display, None)
+    }
+
+    /// Like `upload_textures`, with the given number of bytes per frame as the budget if
+    /// `max_upload_bytes_per_frame` isn't set.
+    pub fn upload_textures_with_default_budget(&self,
+                                               display: &NativeDisplay,
+                                               default_max_upload_bytes: Option<usize>) {
+        // When the frame latency limit is reached, new requests wait for the frames in flight, so
+        // their buffers are uploaded regardless of the budget to keep the latency bounded.
+        let max_upload_bytes = if self.frame_latency_reached(self.frames_in_flight()) {
+            None
+        } else {
+            self.max_upload_bytes_per_frame.or(default_max_upload_bytes)
+        };
+        self.upload_textures_within(display, max_upload_bytes);
+    }
+
+    /// Creates textures for all received buffers, regardless of the upload budget.
+    pub fn upload_all_textures(&self, display: &NativeDisplay) {
+        self.upload_textures_within(display, None);
+    }
+
+    fn upload_textures_within(&self, display: &NativeDisplay, max_upload_bytes: Option<usize>) {
+        if self.paused {
+            return;
+        }
+        if self.roots.is_empty() {
+            return;
+        }
+
+        let _span = profiler::span("upload textures");
+        let mut budget = UploadBudget::new(max_upload_bytes);
+        for &visible_only in &[true, false] {
+            for root_layer in &self.roots {
+                walk_subtree(root_layer, |layer| {
+                    if budget.is_exhausted() {
+                        return false;
+                    }
+
+                    layer.create_textures_with_budget(display, &mut budget, |tile| {
+                        !visible_only || self.tile_is_visible(layer, tile)
+                    });
+                    true
+                });
+            }
+        }
+
+        debug!(target: "layers::tiling",
+               "Uploaded {} bytes of tiles{}",
+               budget.uploaded_bytes(),
+               if budget.is_exhausted() { ", upload budget exhausted" } else { "" });
+    }
+
+    fn tile_is_visible(&self, layer: &Layer<T>, tile: &Tile) -> bool {
+        let tile_rect = match tile.buffer_rect() {
+            Some(tile_rect) => tile_rect,
+            None => return false,
+        };
+
+        let ts = layer.transform_state.borrow();
+        let tile_rect = tile_rect.to_untyped().translate(&ts.world_rect.origin);
+        let visible_rect = TypedRect::new(TypedPoint2D::zero(), self.viewport.size) / self.scale;
+        match project_rect_to_screen(&tile_rect, &ts.final_transform) {
+            Some(screen_rect) => screen_rect.rect.intersects(&visible_rect.to_untyped()),
+            None => false,
+        }
+    }
+
+    /// Records a checkerboard miss for each tile in the viewport that has no texture to draw, as
+    /// of the current transform state, so that the next buffer requests ask for it first.
+    pub fn record_checkerboard_misses(&self) {
+        let visible_rect = (TypedRect::new(TypedPoint2D::zero(), self.viewport.size) /
+                            self.scale).to_untyped();
+        for root_layer in &self.roots {
+            walk_subtree(root_layer, |layer| {
+                let ts = layer.transform_state.borrow();
+                if ts.screen_rect.is_none() {
+                    return false; // The layer and its children are entirely clipped.
+                }
+
+                // Tiles without textures have no bounds yet, so they are found by their index.
+                let tile_rects: HashMap<_, _> =
+                    layer.tile_device_rects(self.scale).into_iter().collect();
+                let tile_scale = layer.tile_scale(self.scale);
+                layer.do_for_all_tiles_with_index(|index, tile| {
+                    if !tile.texture.is_zero() {
+                        return;
+                    }
+                    let rect = match tile_rects.get(index) {
+                        Some(&rect) => device_rect_to_layer_rect(rect, tile_scale),
+                        None => return,
+                    };
+                    let rect = rect.to_untyped().translate(&ts.world_rect.origin);
+                    let visible = project_rect_to_screen(&rect, &ts.final_transform)
+                        .map_or(false, |screen_rect| screen_rect.rect.intersects(&visible_rect));
+                    if visible {
+                        tile.record_checkerboard_miss();
+                    }
+                });
+                true
+            });
+        }
+    }
+
+    /// Measures how much of the visible content is covered by tiles with textures, as of the
+    /// current transform state. Tiles are assumed not to overlap.
+    pub fn measure_frame(&self) -> FrameStats {
+        let mut stats = FrameStats::new();
+        for root_layer in &self.roots {
+            walk_subtree(root_layer, |layer| {
+                // Skip layers that are entirely clipped, along with their children.
+                if layer.transform_state.borrow().screen_rect.is_none() {
+                    return false;
+                }
+                if let Some((visible_pixels, valid_pixels)) =
+                        self.measure_layer_content(layer, None) {
+                    stats.visible_pixels += visible_pixels;
+                    stats.checkerboarded_pixels += visible_pixels - valid_pixels;
+                }
+                true
+            });
+        }
+        stats
+    }
+
+    /// How much of the visible part of a layer's own content has tiles with textures, as of the
+    /// last composite. Returns `None` for layers that are entirely clipped or that don't have
+    /// content of their own.
+    pub fn layer_content_validity(&self, layer: &Layer<T>) -> Option<ContentValidity> {
+        let mut tiles = vec!();
+        self.measure_layer_content(layer, Some(&mut tiles)).map(|(visible_pixels, valid_pixels)| {
+            ContentValidity {
+                visible_pixels: visible_pixels,
+                valid_pixels: valid_pixels,
+                tile_mask: TileValidityMask::new(&tiles),
+            }
+        })
+    }
+
+    /// Returns the visible area of a layer with tiles and the part of it covered by tiles with
+    /// textures, in device pixels. The visible tiles are added to `tiles` if given.
+    fn measure_layer_content(&self,
+                             layer: &Layer<T>,
+                             mut tiles: Option<&mut Vec<(Point2D<usize>, bool)>>)
+                             -> Option<(f32, f32)> {
+        let visible_rect = (TypedRect::new(TypedPoint2D::zero(), self.viewport.size) /
+                            self.scale).to_untyped();
+        let area_scale = self.scale.get() * self.scale.get();
+        let visible_area = |rect: &Rect<f32>| {
+            rect.intersection(&visible_rect).map_or(0.0, |rect| rect.size.width * rect.size.height)
+        };
+
+        let ts = layer.transform_state.borrow();
+        let layer_area = match ts.screen_rect {
+            Some(ref screen_rect) => visible_area(&screen_rect.rect),
+            None => return None,
+        };
+
+        // Layers without any tiles don't have content of their own.
+        let mut has_tiles = false;
+        let mut covered_area = 0.0;
+        layer.do_for_all_tiles_with_index(|index, tile| {
+            has_tiles = true;
+            let bounds = match tile.bounds {
+                Some(bounds) => bounds,
+                None => return,
+            };
+            let rect = bounds.to_untyped().translate(&ts.world_rect.origin);
+            let tile_area = match project_rect_to_screen(&rect, &ts.final_transform) {
+                Some(screen_rect) => visible_area(&screen_rect.rect),
+                None => return,
+            };
+            if tile_area <= 0.0 {
+                return;
+            }
+            let valid = !tile.texture.is_zero();
+            if valid {
+                covered_area += tile_area;
+            }
+            if let Some(ref mut tiles) = tiles {
+                tiles.push((*index, valid));
+            }
+        });
+        if !has_tiles {
+            return None;
+        }
+
+        // Repeated content is drawn from a single set of tiles.
+        if layer.content_repeat.borrow().is_some() && covered_area > 0.0 {
+            covered_area = layer_area;
+        }
+        Some((layer_area * area_scale, covered_area.min(layer_area) * area_scale))
+    }
+
+    /// Counts the layers and tiles of the tree, and the texture memory they hold.
+    pub fn measure_layer_tree(&self) -> SceneStats {
+        let mut stats = SceneStats::new();
+        let mut stack: Vec<_> = self.roots.iter().map(|root| (root.clone(), 1)).collect();
+        while let Some((layer, depth)) = stack.pop() {
+            stats.layer_count += 1;
+            stats.max_depth = cmp::max(stats.max_depth, depth);
+            for tile in layer.tile_infos(self.scale) {
+                stats.tile_count += 1;
+                if tile.valid {
+                    stats.valid_tile_count += 1;
+                }
+                if tile.resident {
+                    stats.resident_tile_count += 1;
+                    stats.resident_texture_bytes += tile.memory_usage;
+                }
+            }
+            stack.extend(layer.children().iter().map(|child| (child.clone(), depth + 1)));
+        }
+        stats
+    }
+
+    /// Returns true if some buffers are still waiting to be uploaded because they didn't fit in
+    /// the upload budget. Embedders should schedule another composite when this is the case.
+    pub fn has_pending_uploads(&self) -> bool {
+        let mut has_pending_uploads = false;
+        for root_layer in &self.roots {
+            walk_subtree(root_layer, |layer| {
+                has_pending_uploads = has_pending_uploads || layer.has_pending_uploads();
+                !has_pending_uploads
+            });
+        }
+        has_pending_uploads
+    }
+
+    /// Removes the property overrides of all layers that expired by `now`. Returns true if a
+    /// transform override was removed, in which case the transform state needs to be updated.
+    pub fn expire_property_overrides(&self, now: Instant) -> bool {
+        let mut removed_transform = false;
+        for root_layer in &self.roots {
+            walk_subtree(root_layer, |layer| {
+                removed_transform = layer.expire_property_overrides(now) || removed_transform;
+                true
+            });
+        }
+        removed_transform
+    }
+
+    /// When the first of the property overrides of all layers that expire does, so that the
+    /// embedder can composite a frame then for `expire_property_overrides` to remove it.
+    pub fn next_property_override_expiry(&self) -> Option<Instant> {
+        let mut next_expiry: Option<Instant> = None;
+        for root_layer in &self.roots {
+            walk_subtree(root_layer, |layer| {
+                if let Some(expiry) = layer.next_property_override_expiry() {
+                    next_expiry = Some(next_expiry.map_or(expiry, |next| cmp::min(next, expiry)));
+                }
+                true
+            });
+        }
+        next_expiry
+    }
+
+    /// Hands the tiles of all layers the buffers held back by the simulated buffer reply delay
+    /// until `now`. See `SimulatedDelays::buffer_reply`.
+    pub fn release_delayed_buffers(&self, now: Instant) {
+        for root_layer in &self.roots {
+            walk_subtree(root_layer, |layer| {
+                layer.release_delayed_buffers(now);
+                true
+            });
+        }
+    }
+
+    /// When the first of the buffers held back by the simulated buffer reply delay is due, so
+    /// that the embedder can composite a frame then to show it.
+    pub fn next_delayed_buffer_release(&self) -> Option<Instant> {
+        let mut next_release: Option<Instant> = None;
+        for root_layer in &self.roots {
+            walk_subtree(root_layer, |layer| {
+                if let Some(due) = layer.next_delayed_buffer_release() {
+                    next_release = Some(next_release.map_or(due, |next| cmp::min(next, due)));
+                }
+                true
+            });
+        }
+        next_release
+    }
+
+    /// Starts scrolling the main root layer so that the top left of `rect` is at the top left of
+    /// the viewport, as far as the scrollable range allows. `rect` is in the coordinates of the
+    /// root layer's content, i.e. before scrolling. Returns `None` if the scene has no root
+    /// layer.
+    pub fn animate_scroll_to_rect(&self,
+                                  rect: TypedRect<f32, LayerPixel>,
+                                  start_time: Instant,
+                                  duration: Duration)
+                                  -> Option<ScrollAnimation> {
+        let root_layer = match self.root() {
+            Some(root_layer) => root_layer,
+            None => return None,
+        };
+        let content_size = scrolling::scrollable_content_size(root_layer);
+        let end_offset = scrolling::clamp_content_offset(-rect.origin,
+                                                         root_layer.bounds.borrow().size,
+                                                         content_size);
+        let start_offset = *root_layer.content_offset.borrow();
+        Some(ScrollAnimation::new(start_offset, end_offset, start_time, duration))
+    }
+
+    /// Starts zooming the scene so that `rect` fits the viewport, centered in it as far as the
+    /// scrollable range allows. `rect` is in the coordinates of the main root layer's content, and
+    /// should be clamped to sensible zoom limits by the embedder. Returns `None` if the scene
+    /// has no root layer or `rect` is empty.
+    pub fn animate_scale_to_fit_rect(&self,
+                                     rect: TypedRect<f32, LayerPixel>,
+                                     start_time: Instant,
+                                     duration: Duration)
+                                     -> Option<ZoomAnimation> {
+        let root_layer = match self.root() {
+            Some(root_layer) => root_layer,
+            None => return None,
+        };
+        if rect.size.width <= 0.0 || rect.size.height <= 0.0 {
+            return None;
+        }
+
+        let viewport_size = self.viewport.size.to_untyped();
+        let end_scale = ScaleFactor::new((viewport_size.width / rect.size.width)
+                                             .min(viewport_size.height / rect.size.height));
+        let end_viewport_size = self.viewport.size / end_scale;
+
+        // The root layer covers the viewport at the destination scale, so the scrollable range
+        // is computed for that size rather than the current one.
+        let mut content_size = end_viewport_size;
+        for child in root_layer.children().iter() {
+            let bounds = child.bounds.borrow();
+            content_size.width = content_size.width.max(bounds.max_x());
+            content_size.height = content_size.height.max(bounds.max_y());
+        }
+        let center = TypedPoint2D::new(rect.origin.x + rect.size.width / 2.0,
+                                       rect.origin.y + rect.size.height / 2.0);
+        let end_offset = TypedPoint2D::new(end_viewport_size.width / 2.0 - center.x,
+                                           end_viewport_size.height / 2.0 - center.y);
+        let end_offset = scrolling::clamp_content_offset(end_offset,
+                                                         end_viewport_size,
+                                                         content_size);
+
+        let start_offset = *root_layer.content_offset.borrow();
+        Some(ZoomAnimation {
+            scale: ScaleAnimation::new(self.scale, end_scale, start_time, duration),
+            scroll: ScrollAnimation::new(start_offset, end_offset, start_time, duration),
+        })
+    }
+
+    /// Returns the topmost layer under a point of the viewport, in device pixels, looking through
+    /// the root layers from the last to the first. The transform state of the layers must be up
+    /// to date.
+    pub fn hit_test(&self, point: TypedPoint2D<f32, DevicePixel>) -> Option<HitTestResult<T>> {
+        let point = (point / self.scale).to_untyped();
+        self.roots.iter().rev().filter_map(|root_layer| {
+            hittest::hit_test(root_layer, &point)
+        }).next()
+    }
+
+    /// Returns the layers whose screen rects intersect `rect`, in device pixels, from back to
+    /// front, for e.g. devtools highlighting and accessibility hit regions. The transform state
+    /// of the layers must be up to date.
+    pub fn layers_in_rect(&self, rect: &TypedRect<f32, DevicePixel>) -> Vec<PaintedLayer<T>> {
+        let mut layers = vec!();
+        for root_layer in &self.roots {
+            layers.extend(hittest::layers_in_rect(root_layer, rect, self.scale));
+        }
+        layers
+    }
+
+    /// Takes the damage accumulated by all layers since the last call and returns its bounding
+    /// box in device pixels, or `None` if nothing changed. Damage near a blur-behind region
+    /// extends over the whole region and the reach of its blur, since the blur mixes the damaged
+    /// pixels into all of them.
+    pub fn take_damage(&self) -> Option<TypedRect<f32, DevicePixel>> {
+        let mut damage = None;
+        for root_layer in &self.roots {
+            walk_subtree(root_layer, |layer| {
+                self.take_damage_for_layer(layer, &mut damage);
+                true
+            });
+        }
+        match damage {
+            Some(damage) => Some(self.inflate_damage_for_blur_behind_regions(damage)),
+            None => None,
+        }
+    }
+
+    fn inflate_damage_for_blur_behind_regions(&self, mut damage: TypedRect<f32, DevicePixel>)
+                                              -> TypedRect<f32, DevicePixel> {
+        let mut blurred_rects = vec!();
+        for root_layer in &self.roots {
+            walk_subtree(root_layer, |layer| {
+                let ts = layer.transform_state.borrow();
+                if ts.screen_rect.is_none() {
+                    return false;
+                }
+                for region in layer.blur_behind_regions.borrow().iter() {
+                    let rect = region.rect.to_untyped().translate(&ts.world_rect.origin);
+                    if let Some(screen_rect) = project_rect_to_screen(&rect, &ts.final_transform) {
+                        let screen_rect: TypedRect<f32, LayerPixel> =
+                            TypedRect::from_untyped(&screen_rect.rect);
+                        // The blur reaches three standard deviations, as it is drawn.
+                        let reach = region.blur_radius * self.scale.get() * 3.0;
+                        let device_rect = (screen_rect * self.scale).inflate(reach, reach);
+                        blurred_rects.push(device_rect);
+                    }
+                }
+                true
+            });
+        }
+
+        // Extending the damage over a region may bring it near another one.
+        loop {
+            let count = blurred_rects.len();
+            blurred_rects.retain(|blurred_rect| {
+                if !blurred_rect.intersects(&damage) {
+                    return true;
+                }
+                damage = damage.union(blurred_rect);
+                false
+            });
+            if blurred_rects.len() == count {
+                return damage;
+            }
+        }
+    }
+
+    fn take_damage_for_layer(&self,
+                             layer: &Rc<Layer<T>>,
+                             damage: &mut Option<TypedRect<f32, DevicePixel>>) {
+        if let Some(world_damage) = layer.take_world_damage() {
+            let world_damage: TypedRect<f32, LayerPixel> = TypedRect::from_untyped(&world_damage);
+            let device_rect = world_damage * self.scale;
+            *damage = Some(match *damage {
+                Some(ref damage) => damage.union(&device_rect),
+                None => device_rect,
+            });
+        }
+        if let Some(layer_damage) = layer.take_damage() {
+            let ts = layer.transform_state.borrow();
+            let rect = layer_damage.to_untyped().translate(&ts.world_rect.origin);
+            if let Some(screen_rect) = project_rect_to_screen(&rect, &ts.final_transform) {
+                let screen_rect: TypedRect<f32, LayerPixel> =
+                    TypedRect::from_untyped(&screen_rect.rect);
+                let device_rect = screen_rect * self.scale;
+                *damage = Some(match *damage {
+                    Some(ref damage) => damage.union(&device_rect),
+                    None => device_rect,
+                });
+            }
         }
     }
 }
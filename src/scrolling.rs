@@ -0,0 +1,92 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Scroll offsets of the root layer.
+//!
+//! The root layer is scrolled by its content offset, which is negative when the content is
+//! scrolled down or right. The scrollable content is the union of the bounds of the root's
+//! children.
+
+use euclid::point::TypedPoint2D;
+use euclid::size::TypedSize2D;
+use geometry::LayerPixel;
+use layers::Layer;
+
+/// What happens to the scroll offset of the root layer when the viewport is resized.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ResizeBehavior {
+    /// The content at the top left of the viewport stays in place. This is the default.
+    PreserveTopLeft,
+    /// The content at the center of the viewport stays in place.
+    PreserveCenter,
+    /// The scroll offset stays at the same fraction of the scrollable range, so that e.g. a
+    /// page scrolled to the bottom stays scrolled to the bottom.
+    PreserveScrollFraction,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, ResizeBehavior);
+
+/// The size of the content scrolled by the root layer, which is at least the size of the
+/// viewport.
+pub fn scrollable_content_size<T>(root_layer: &Layer<T>) -> TypedSize2D<f32, LayerPixel> {
+    let mut size = root_layer.bounds.borrow().size;
+    for child in root_layer.children().iter() {
+        let bounds = child.bounds.borrow();
+        size.width = size.width.max(bounds.max_x());
+        size.height = size.height.max(bounds.max_y());
+    }
+    size
+}
+
+impl ResizeBehavior {
+    /// Returns the content offset of the root layer after the viewport is resized from
+    /// `old_size` to `new_size`. Except when preserving the top left, the offset is clamped to
+    /// the scrollable range.
+    pub fn resized_content_offset(&self,
+                                  content_offset: TypedPoint2D<f32, LayerPixel>,
+                                  old_size: TypedSize2D<f32, LayerPixel>,
+                                  new_size: TypedSize2D<f32, LayerPixel>,
+                                  content_size: TypedSize2D<f32, LayerPixel>)
+                                  -> TypedPoint2D<f32, LayerPixel> {
+        let (x, y) = match *self {
+            ResizeBehavior::PreserveTopLeft => return content_offset,
+            ResizeBehavior::PreserveCenter => {
+                (content_offset.x + (new_size.width - old_size.width) / 2.0,
+                 content_offset.y + (new_size.height - old_size.height) / 2.0)
+            }
+            ResizeBehavior::PreserveScrollFraction => {
+                let rescale = |offset: f32, old_extent: f32, new_extent: f32, content: f32| {
+                    let old_range = content - old_extent;
+                    if old_range <= 0.0 {
+                        return 0.0;
+                    }
+                    offset / old_range * (content - new_extent)
+                };
+                (rescale(content_offset.x, old_size.width, new_size.width, content_size.width),
+                 rescale(content_offset.y, old_size.height, new_size.height, content_size.height))
+            }
+        };
+
+        clamp_content_offset(TypedPoint2D::new(x, y), new_size, content_size)
+    }
+}
+
+/// Clamps a content offset of the root layer to the scrollable range of content of the given
+/// size, shown in a viewport of the given size.
+pub fn clamp_content_offset(content_offset: TypedPoint2D<f32, LayerPixel>,
+                            viewport_size: TypedSize2D<f32, LayerPixel>,
+                            content_size: TypedSize2D<f32, LayerPixel>)
+                            -> TypedPoint2D<f32, LayerPixel> {
+    let clamp = |offset: f32, extent: f32, content: f32| {
+        offset.max(-(content - extent).max(0.0)).min(0.0)
+    };
+    TypedPoint2D::new(clamp(content_offset.x, viewport_size.width, content_size.width),
+                      clamp(content_offset.y, viewport_size.height, content_size.height))
+}
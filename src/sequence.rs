@@ -0,0 +1,92 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Numbering of composited frames, so that embedders can tell when an update of the layer tree
+//! has reached the screen.
+//!
+//! Embedders stamp the layers they change in an update with the update's `Epoch`, through
+//! `Layer::epoch`, and hand the buffers painted for it to `Layer::assign_painted_buffers` with
+//! the same epoch. Every frame composited by `render_scene` gets the next `FrameId`, and the
+//! scene's `FrameSequence` remembers the latest update each recent frame shows. Since updates
+//! are applied to the tree atomically, a frame shows the latest update any layer carries, even
+//! one that only changed layers that aren't drawn, unless a visible tile still draws content
+//! painted for an older update than its layer carries, or no content at all. See
+//! `Scene::shown_epoch`.
+
+use std::collections::VecDeque;
+
+/// An update of the layer tree, numbered by the embedder in increasing order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Epoch(pub u64);
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, Epoch);
+
+/// A composited frame, numbered in increasing order from 0 for each scene.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FrameId(pub u64);
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, FrameId);
+
+/// The number of recent frames whose shown updates are remembered.
+pub const FRAME_HISTORY_LENGTH: usize = 64;
+
+/// The frames composited for a scene, with the latest update shown by the recent ones.
+pub struct FrameSequence {
+    next_frame: u64,
+
+    /// The recent frames, oldest first, each with the latest update it shows, if any.
+    history: VecDeque<(FrameId, Option<Epoch>)>,
+}
+
+impl FrameSequence {
+    pub fn new() -> FrameSequence {
+        FrameSequence {
+            next_frame: 0,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Numbers a newly composited frame that shows the updates up to `shown_epoch`.
+    pub fn record_frame(&mut self, shown_epoch: Option<Epoch>) -> FrameId {
+        let id = FrameId(self.next_frame);
+        self.next_frame += 1;
+        self.history.push_back((id, shown_epoch));
+        while self.history.len() > FRAME_HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        id
+    }
+
+    /// The last frame composited, if any.
+    pub fn last_frame(&self) -> Option<FrameId> {
+        self.history.back().map(|&(id, _)| id)
+    }
+
+    /// The latest update a recent frame shows, or `None` if it shows none, is too old to be
+    /// remembered or hasn't been composited yet.
+    pub fn shown_epoch(&self, frame: FrameId) -> Option<Epoch> {
+        self.history.iter().find(|&&(id, _)| id == frame).and_then(|&(_, shown_epoch)| shown_epoch)
+    }
+
+    /// Whether a frame contains the update with the given epoch, i.e. it shows that update or a
+    /// later one.
+    pub fn frame_contains(&self, frame: FrameId, epoch: Epoch) -> bool {
+        self.shown_epoch(frame).map_or(false, |shown_epoch| shown_epoch >= epoch)
+    }
+
+    /// The first remembered frame that contains the update with the given epoch, which is on
+    /// screen once that frame is presented.
+    pub fn first_frame_containing(&self, epoch: Epoch) -> Option<FrameId> {
+        self.history.iter().find(|&&(_, shown_epoch)| {
+            shown_epoch.map_or(false, |shown_epoch| shown_epoch >= epoch)
+        }).map(|&(id, _)| id)
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Automatic capture of frames that exceed a time budget.
+//!
+//! When a scene has a `SlowFrameCapture`, every composite that takes longer than its threshold
+//! writes a JSON file describing the scene, the layer tree and the frame statistics to the
+//! capture directory, optionally along with a screenshot. This makes intermittent jank
+//! diagnosable after the fact, from reports sent by testers.
+//!
+//! Frames are timed on the CPU, from the start of compositing until the frame is submitted.
+//! The time the GPU then spends on the frame isn't included, so frames that are slow because of
+//! GPU work aren't captured.
+
+use compare;
+use layers::Layer;
+use rendergl;
+use rustc_serialize::json;
+use scene::Scene;
+use stats::FrameStats;
+
+use euclid::Rect;
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// The configuration and state of slow frame capture for a scene.
+pub struct SlowFrameCapture {
+    /// Frames that take longer than this to composite on the CPU are captured.
+    pub threshold: Duration,
+
+    /// The directory the captures are written to. It is created if necessary.
+    pub directory: PathBuf,
+
+    /// Whether to read back and save the composited frame as well. Reading back stalls the GPU
+    /// pipeline, which slows down the captured frame further.
+    pub screenshots: bool,
+
+    /// The maximum number of frames to capture, so that a slow device doesn't fill its storage.
+    pub max_captures: u32,
+
+    captures: Cell<u32>,
+}
+
+impl SlowFrameCapture {
+    pub fn new(threshold: Duration, directory: PathBuf) -> SlowFrameCapture {
+        SlowFrameCapture {
+            threshold: threshold,
+            directory: directory,
+            screenshots: false,
+            max_captures: 100,
+            captures: Cell::new(0),
+        }
+    }
+
+    /// The number of frames captured so far.
+    pub fn captures(&self) -> u32 {
+        self.captures.get()
+    }
+
+    /// Captures the frame that was just composited if it took longer than the threshold.
+    pub fn frame_finished<T>(&self, scene: &Scene<T>, stats: &FrameStats, elapsed: Duration) {
+        if elapsed <= self.threshold || self.captures.get() >= self.max_captures {
+            return;
+        }
+
+        let index = self.captures.get();
+        self.captures.set(index + 1);
+        match self.write_capture(index, scene, stats, elapsed) {
+            Ok(path) => info!("Frame took {}ms, captured to {}", milliseconds(elapsed), path),
+            Err(error) => warn!("Could not capture slow frame: {}", error),
+        }
+    }
+
+    fn write_capture<T>(&self,
+                        index: u32,
+                        scene: &Scene<T>,
+                        stats: &FrameStats,
+                        elapsed: Duration)
+                        -> io::Result<String> {
+        try!(fs::create_dir_all(&self.directory));
+
+        let capture = CapturedFrame {
+            scene: scene.debug_name.clone().unwrap_or("scene".to_owned()),
+            cpu_elapsed_ms: milliseconds(elapsed),
+            scale: scene.scale.get(),
+            viewport: rect_to_vec(&scene.viewport.to_untyped()),
+            stats: CapturedStats {
+                visible_pixels: stats.visible_pixels,
+                checkerboarded_pixels: stats.checkerboarded_pixels,
+            },
+            layers: captured_layers(&scene.roots),
+        };
+
+        let json_path = self.directory.join(format!("slow-frame-{}.json", index));
+        let mut writer = BufWriter::new(try!(File::create(&json_path)));
+        try!(writeln!(writer, "{}", json::as_pretty_json(&capture)));
+        try!(writer.flush());
+
+        if self.screenshots {
+            let screenshot_path = self.directory.join(format!("slow-frame-{}.ppm", index));
+            try!(write_screenshot(scene, &screenshot_path));
+        }
+        Ok(json_path.display().to_string())
+    }
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 / 1.0e6
+}
+
+fn rect_to_vec(rect: &Rect<f32>) -> Vec<f32> {
+    vec!(rect.origin.x, rect.origin.y, rect.size.width, rect.size.height)
+}
+
+/// A captured frame, as written to its JSON file.
+#[derive(RustcEncodable)]
+struct CapturedFrame {
+    scene: String,
+    /// The CPU time spent compositing the frame. GPU time isn't measured.
+    cpu_elapsed_ms: f64,
+    scale: f32,
+    viewport: Vec<f32>,
+    stats: CapturedStats,
+    /// The layers of all root layer trees, in paint order.
+    layers: Vec<CapturedLayer>,
+}
+
+#[derive(RustcEncodable)]
+struct CapturedStats {
+    visible_pixels: f32,
+    checkerboarded_pixels: f32,
+}
+
+#[derive(RustcEncodable)]
+struct CapturedLayer {
+    name: String,
+    /// The number of ancestors of the layer. Roots have none.
+    depth: usize,
+    bounds: Vec<f32>,
+    screen_rect: Option<Vec<f32>>,
+    opacity: f32,
+    tiles: usize,
+    tile_bytes: usize,
+    cached: bool,
+    collapsed: bool,
+}
+
+/// Layers are listed in paint order with their depth, rather than nested, so that deep trees
+/// can be written without recursion.
+fn captured_layers<T>(root_layers: &[Rc<Layer<T>>]) -> Vec<CapturedLayer> {
+    let mut layers = vec!();
+    let mut stack: Vec<_> = root_layers.iter().rev().map(|root| (root.clone(), 0)).collect();
+    while let Some((layer, depth)) = stack.pop() {
+        for kid in layer.children().iter().rev() {
+            stack.push((kid.clone(), depth + 1));
+        }
+
+        let screen_rect = layer.transform_state.borrow().screen_rect.as_ref().map(|screen_rect| {
+            rect_to_vec(&screen_rect.rect)
+        });
+        let raster_cache = layer.raster_cache.borrow();
+        layers.push(CapturedLayer {
+            name: layer.debug_name(),
+            depth: depth,
+            bounds: rect_to_vec(&layer.bounds.borrow().to_untyped()),
+            screen_rect: screen_rect,
+            opacity: layer.effective_opacity(),
+            tiles: layer.tile_count(),
+            tile_bytes: layer.tile_memory_usage(),
+            cached: raster_cache.is_active(),
+            collapsed: raster_cache.is_collapsed(),
+        });
+    }
+    layers
+}
+
+/// Writes the scene's viewport as a binary PPM image.
+fn write_screenshot<T>(scene: &Scene<T>, path: &Path) -> io::Result<()> {
+    let viewport = scene.presented_viewport().to_untyped();
+    let (width, height) = (viewport.size.width as usize, viewport.size.height as usize);
+    let pixels = rendergl::read_back_scene(scene);
+
+    let mut writer = BufWriter::new(try!(File::create(path)));
+    try!(compare::write_ppm(&mut writer, &pixels, width, height));
+    writer.flush()
+}
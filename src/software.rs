@@ -0,0 +1,374 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A software compositor, for when OpenGL is unavailable or blacklisted. It composites the same
+//! scenes as the GL renderer into a framebuffer in CPU memory, which the embedder may place in
+//! shared memory and present however it likes.
+//!
+//! Only 2d transforms are supported; layers with 3d transforms are skipped. Embedders choose
+//! between the two compositors at runtime with `CompositorBackend::select`.
+
+use capabilities::GlCapabilities;
+use color::Color;
+use layers::Layer;
+use scene::Scene;
+use tiling::Tile;
+
+use euclid::{Matrix2D, Point2D, Rect, Size2D};
+use std::rc::Rc;
+
+/// The compositors a scene can be composited with.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CompositorBackend {
+    /// `rendergl::render_scene`, into the GL context current on the compositor thread.
+    Gl,
+    /// `render_scene_software`, into a `SoftwareFramebuffer`.
+    Software,
+}
+
+impl CompositorBackend {
+    /// Chooses the compositor for the GL context current on this thread, given its
+    /// capabilities, or `None` if no context could be created. The software compositor is
+    /// chosen without a context, for contexts older than OpenGL 2.1 or OpenGL ES 2.0, and for
+    /// those `is_blacklisted` rejects, e.g. by the embedder's list of broken drivers.
+    pub fn select<F>(capabilities: Option<&GlCapabilities>, is_blacklisted: F)
+                     -> CompositorBackend
+                     where F: FnOnce(&GlCapabilities) -> bool {
+        let capabilities = match capabilities {
+            Some(capabilities) => capabilities,
+            None => return CompositorBackend::Software,
+        };
+        let minimum_version = if capabilities.is_gles { (2, 0) } else { (2, 1) };
+        if capabilities.version < minimum_version {
+            info!("Software compositor: GL {:?} is too old", capabilities.version);
+            return CompositorBackend::Software;
+        }
+        if is_blacklisted(capabilities) {
+            info!("Software compositor: GL implementation is blacklisted");
+            return CompositorBackend::Software;
+        }
+        CompositorBackend::Gl
+    }
+}
+
+/// A BGRA framebuffer with premultiplied alpha, borrowed from the embedder.
+pub struct SoftwareFramebuffer<'a> {
+    pixels: &'a mut [u8],
+    width: usize,
+    height: usize,
+
+    /// The number of bytes between the starts of two consecutive rows.
+    stride: usize,
+}
+
+impl<'a> SoftwareFramebuffer<'a> {
+    pub fn new(pixels: &'a mut [u8], width: usize, height: usize, stride: usize)
+               -> SoftwareFramebuffer<'a> {
+        assert!(stride >= width * 4);
+        assert!(pixels.len() >= stride * height);
+        SoftwareFramebuffer {
+            pixels: pixels,
+            width: width,
+            height: height,
+            stride: stride,
+        }
+    }
+
+    fn bounds(&self) -> Rect<f32> {
+        Rect::new(Point2D::zero(), Size2D::new(self.width as f32, self.height as f32))
+    }
+
+    fn clear(&mut self, color: &Color) {
+        let pixel = color_to_bgra(color, 1.0);
+        for y in 0..self.height {
+            let row = &mut self.pixels[y * self.stride..y * self.stride + self.width * 4];
+            for dst in row.chunks_mut(4) {
+                dst.copy_from_slice(&pixel);
+            }
+        }
+    }
+
+    /// Blends a run of identical pixels into the given row.
+    fn blend_span(&mut self, y: usize, x0: usize, x1: usize, src: &[u8; 4]) {
+        let start = y * self.stride;
+        let row = &mut self.pixels[start + x0 * 4..start + x1 * 4];
+        for dst in row.chunks_mut(4) {
+            blend_pixel(dst, src);
+        }
+    }
+
+    /// Blends a row of source pixels, scaled by `opacity` out of 255, into the given row from
+    /// `x0` on.
+    fn blend_row(&mut self, y: usize, x0: usize, src: &[u8], opacity: u32) {
+        let start = y * self.stride + x0 * 4;
+        let row = &mut self.pixels[start..start + src.len()];
+        for (dst, src) in row.chunks_mut(4).zip(src.chunks(4)) {
+            blend_pixel(dst, &scale_pixel(src, opacity));
+        }
+    }
+
+    fn blend_at(&mut self, x: usize, y: usize, src: &[u8; 4]) {
+        let offset = y * self.stride + x * 4;
+        blend_pixel(&mut self.pixels[offset..offset + 4], src);
+    }
+}
+
+fn color_to_bgra(color: &Color, opacity: f32) -> [u8; 4] {
+    let to_byte = |value: f32| (value * opacity * 255.0).max(0.0).min(255.0).round() as u8;
+    [to_byte(color.b), to_byte(color.g), to_byte(color.r), to_byte(color.a)]
+}
+
+// Pixels are blended with their four channels in the 16-bit lanes of a `u64`, so that all of
+// them are multiplied and divided at once; the Rust versions supported have no SIMD intrinsics.
+
+/// The low byte of each lane.
+const LANE_MASK: u64 = 0x00ff00ff00ff00ff;
+
+#[inline]
+fn to_lanes(pixel: &[u8]) -> u64 {
+    pixel[0] as u64 | (pixel[1] as u64) << 16 | (pixel[2] as u64) << 32 | (pixel[3] as u64) << 48
+}
+
+#[inline]
+fn from_lanes(lanes: u64) -> [u8; 4] {
+    [lanes as u8, (lanes >> 16) as u8, (lanes >> 32) as u8, (lanes >> 48) as u8]
+}
+
+/// Divides each lane, at most 255 * 255, by 255 with rounding.
+#[inline]
+fn div_255(lanes: u64) -> u64 {
+    let lanes = lanes + 0x0080008000800080;
+    ((lanes + ((lanes >> 8) & LANE_MASK)) >> 8) & LANE_MASK
+}
+
+/// Premultiplied source-over blending.
+#[inline]
+fn blend_pixel(dst: &mut [u8], src: &[u8; 4]) {
+    let inverse_alpha = 255 - src[3] as u64;
+    if inverse_alpha == 0 {
+        dst.copy_from_slice(src);
+        return;
+    }
+    let mut sum = to_lanes(src) + div_255(to_lanes(dst) * inverse_alpha);
+    // Saturate the channels of sources that aren't properly premultiplied.
+    sum |= ((sum >> 8) & 0x0001000100010001) * 0xff;
+    dst.copy_from_slice(&from_lanes(sum));
+}
+
+#[inline]
+fn scale_pixel(pixel: &[u8], opacity: u32) -> [u8; 4] {
+    if opacity == 255 {
+        return [pixel[0], pixel[1], pixel[2], pixel[3]];
+    }
+    from_lanes(div_255(to_lanes(pixel) * opacity as u64))
+}
+
+/// Composites the root layers of the scene into the framebuffer, in order. The transform state
+/// of the layers must be up to date, exactly as for `rendergl::render_scene`.
+pub fn render_scene_software<T>(scene: &Scene<T>, framebuffer: &mut SoftwareFramebuffer) {
+    framebuffer.clear(&Color { r: 1.0, g: 1.0, b: 1.0, a: 1.0 });
+    let clip_rect = framebuffer.bounds();
+
+    // Layers are visited in paint order with an explicit stack, so that deep trees don't
+    // overflow the call stack.
+    let mut stack: Vec<_> = scene.roots.iter().rev().map(|root| {
+        (root.clone(), clip_rect)
+    }).collect();
+    while let Some((layer, parent_clip_rect)) = stack.pop() {
+        if let Some(child_clip_rect) = render_layer(&layer,
+                                                    scene.scale.get(),
+                                                    parent_clip_rect,
+                                                    framebuffer) {
+            for child in layer.children().iter().rev() {
+                stack.push((child.clone(), child_clip_rect));
+            }
+        }
+    }
+}
+
+/// Renders a single layer, returning the clip rect for its children, or `None` if the children
+/// can be skipped.
+fn render_layer<T>(layer: &Rc<Layer<T>>,
+                   scale: f32,
+                   parent_clip_rect: Rect<f32>,
+                   framebuffer: &mut SoftwareFramebuffer)
+                   -> Option<Rect<f32>> {
+    let ts = layer.transform_state.borrow();
+    let screen_rect = match ts.screen_rect {
+        Some(ref screen_rect) => screen_rect.rect,
+        None => return None, // The layer is entirely clipped.
+    };
+
+    if ts.transform_kind.is_3d() {
+        debug!("Software compositor: skipping layer with a 3d transform");
+        return None;
+    }
+
+    // Map from world coordinates to device pixels, and back.
+    let to_device = ts.final_transform.to_2d().post_scaled(scale, scale);
+    let from_device = match to_device.inverse() {
+        Some(from_device) => from_device,
+        None => return None, // The layer is collapsed to nothing.
+    };
+
+    let device_rect = Rect::new(screen_rect.origin * scale, screen_rect.size * scale);
+    let layer_clip_rect = match device_rect.intersection(&parent_clip_rect) {
+        Some(layer_clip_rect) => layer_clip_rect,
+        None => return None,
+    };
+
+    let opacity = ts.opacity;
+    let background_color = *layer.background_color.borrow();
+    if background_color.a != 0.0 {
+        let pixel = color_to_bgra(&background_color, opacity);
+        fill_rect(&ts.world_rect, &pixel, &to_device, &from_device, layer_clip_rect, framebuffer);
+    }
+
+    layer.do_for_all_tiles(|tile: &Tile| {
+        render_tile(tile,
+                    &ts.world_rect.origin,
+                    opacity,
+                    &to_device,
+                    &from_device,
+                    layer_clip_rect,
+                    framebuffer);
+    });
+
+    for highlight in layer.highlights.borrow().iter() {
+        let pixel = color_to_bgra(&highlight.color, opacity);
+        let rect = highlight.rect.to_untyped().translate(&ts.world_rect.origin);
+        fill_rect(&rect, &pixel, &to_device, &from_device, layer_clip_rect, framebuffer);
+    }
+
+    if *layer.masks_to_bounds.borrow() {
+        Some(layer_clip_rect)
+    } else {
+        Some(parent_clip_rect)
+    }
+}
+
+/// Returns the range of pixel rows and columns whose centers lie in `rect`.
+fn pixel_range(rect: &Rect<f32>) -> (usize, usize, usize, usize) {
+    let x0 = (rect.min_x() - 0.5).ceil().max(0.0) as usize;
+    let y0 = (rect.min_y() - 0.5).ceil().max(0.0) as usize;
+    let x1 = (rect.max_x() - 0.5).ceil().max(0.0) as usize;
+    let y1 = (rect.max_y() - 0.5).ceil().max(0.0) as usize;
+    (x0, y0, x1, y1)
+}
+
+/// The part of `clip_rect` that `world_rect` may cover in device pixels, or `None` if it covers
+/// none of it. Only the pixels in it are visited.
+fn device_clip_rect(world_rect: &Rect<f32>, to_device: &Matrix2D<f32>, clip_rect: Rect<f32>)
+                    -> Option<Rect<f32>> {
+    to_device.transform_rect(world_rect).intersection(&clip_rect)
+}
+
+/// Whether `to_device` maps `world_rect` onto whole device pixels, `size` of them, without
+/// rotating or skewing it, so that it can be blitted row by row.
+fn maps_to_pixels(world_rect: &Rect<f32>, to_device: &Matrix2D<f32>, size: Size2D<usize>)
+                  -> bool {
+    let near = |a: f32, b: f32| (a - b).abs() < 0.001;
+    let device_rect = to_device.transform_rect(world_rect);
+    to_device.m12 == 0.0 && to_device.m21 == 0.0 &&
+        to_device.m11 > 0.0 && to_device.m22 > 0.0 &&
+        near(device_rect.origin.x, device_rect.origin.x.round()) &&
+        near(device_rect.origin.y, device_rect.origin.y.round()) &&
+        near(device_rect.size.width, size.width as f32) &&
+        near(device_rect.size.height, size.height as f32)
+}
+
+fn fill_rect(world_rect: &Rect<f32>,
+             pixel: &[u8; 4],
+             to_device: &Matrix2D<f32>,
+             from_device: &Matrix2D<f32>,
+             clip_rect: Rect<f32>,
+             framebuffer: &mut SoftwareFramebuffer) {
+    let clip_rect = match device_clip_rect(world_rect, to_device, clip_rect) {
+        Some(clip_rect) => clip_rect,
+        None => return,
+    };
+    let (x0, y0, x1, y1) = pixel_range(&clip_rect);
+    for y in y0..y1 {
+        // Find the span of this row covered by the rect, which is convex.
+        let mut span = None;
+        for x in x0..x1 {
+            let point = from_device.transform_point(&Point2D::new(x as f32 + 0.5, y as f32 + 0.5));
+            if world_rect.contains(&point) {
+                span = Some(span.map_or((x, x + 1), |(start, _)| (start, x + 1)));
+            }
+        }
+        if let Some((start, end)) = span {
+            framebuffer.blend_span(y, start, end, pixel);
+        }
+    }
+}
+
+fn render_tile(tile: &Tile,
+               layer_origin: &Point2D<f32>,
+               opacity: f32,
+               to_device: &Matrix2D<f32>,
+               from_device: &Matrix2D<f32>,
+               clip_rect: Rect<f32>,
+               framebuffer: &mut SoftwareFramebuffer) {
+    let (buffer, tile_rect) = match (tile.buffer(), tile.buffer_rect()) {
+        (Some(buffer), Some(tile_rect)) => (buffer, tile_rect.to_untyped().translate(layer_origin)),
+        _ => return,
+    };
+    let pixels = match buffer.native_surface.cpu_pixels() {
+        Some(pixels) => pixels,
+        None => {
+            debug!("Software compositor: tile surface is not in CPU memory");
+            return;
+        }
+    };
+
+    let texture_size = buffer.screen_pos.size;
+    if texture_size.width == 0 || texture_size.height == 0 ||
+            pixels.len() < texture_size.width * texture_size.height * 4 {
+        return;
+    }
+
+    let clip_rect = match device_clip_rect(&tile_rect, to_device, clip_rect) {
+        Some(clip_rect) => clip_rect,
+        None => return,
+    };
+    let opacity = (opacity.max(0.0).min(1.0) * 255.0).round() as u32;
+    let (x0, y0, x1, y1) = pixel_range(&clip_rect);
+
+    // Tiles drawn at their own resolution, as most are, are blended a row at a time.
+    if maps_to_pixels(&tile_rect, to_device, texture_size) {
+        // The pixels visited lie within the tile, which may start off the framebuffer.
+        let origin = to_device.transform_point(&tile_rect.origin);
+        let tx0 = (x0 as f32 - origin.x.round()) as usize;
+        for y in y0..y1 {
+            let ty = (y as f32 - origin.y.round()) as usize;
+            let start = (ty * texture_size.width + tx0) * 4;
+            framebuffer.blend_row(y, x0, &pixels[start..start + (x1 - x0) * 4], opacity);
+        }
+        return;
+    }
+
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let point = from_device.transform_point(&Point2D::new(x as f32 + 0.5, y as f32 + 0.5));
+            if !tile_rect.contains(&point) {
+                continue;
+            }
+
+            // Nearest-neighbor sampling of the tile's pixels.
+            let u = (point.x - tile_rect.origin.x) / tile_rect.size.width;
+            let v = (point.y - tile_rect.origin.y) / tile_rect.size.height;
+            let tx = ((u * texture_size.width as f32) as usize).min(texture_size.width - 1);
+            let ty = ((v * texture_size.height as f32) as usize).min(texture_size.height - 1);
+            let offset = (ty * texture_size.width + tx) * 4;
+            let src = scale_pixel(&pixels[offset..offset + 4], opacity);
+            framebuffer.blend_at(x, y, &src);
+        }
+    }
+}
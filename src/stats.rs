@@ -0,0 +1,385 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Per-frame statistics, so that embedders can track compositing quality over time.
+
+use euclid::point::Point2D;
+use euclid::size::Size2D;
+use sequence::FrameId;
+use std::cmp;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Statistics about a single composited frame.
+#[derive(Copy, Clone, Debug)]
+pub struct FrameStats {
+    /// The visible area of layers with content, in device pixels.
+    pub visible_pixels: f32,
+
+    /// The part of `visible_pixels` where tiles were missing, and the background showed through
+    /// instead of content.
+    pub checkerboarded_pixels: f32,
+
+    /// The number of the frame, or `None` if nothing was composited.
+    pub frame_id: Option<FrameId>,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, FrameStats);
+
+impl FrameStats {
+    pub fn new() -> FrameStats {
+        FrameStats {
+            visible_pixels: 0.0,
+            checkerboarded_pixels: 0.0,
+            frame_id: None,
+        }
+    }
+
+    /// The fraction of the visible content area that was checkerboarded, from 0.0 to 1.0.
+    pub fn checkerboard_ratio(&self) -> f32 {
+        if self.visible_pixels <= 0.0 {
+            return 0.0;
+        }
+        (self.checkerboarded_pixels / self.visible_pixels).min(1.0)
+    }
+}
+
+/// How much of the visible part of a layer's own content the compositor has tiles for, as of
+/// the last composite, so that embedders can throttle animations or keep a loading indicator
+/// up until there is content to show.
+#[derive(Clone, Debug)]
+pub struct ContentValidity {
+    /// The visible area of the layer, in device pixels.
+    pub visible_pixels: f32,
+
+    /// The part of `visible_pixels` covered by tiles with textures.
+    pub valid_pixels: f32,
+
+    /// Which of the visible tiles have textures.
+    pub tile_mask: TileValidityMask,
+}
+
+impl ContentValidity {
+    /// The fraction of the visible area with content, from 0.0 to 1.0. Layers that aren't
+    /// visible at all count as entirely valid.
+    pub fn valid_fraction(&self) -> f32 {
+        if self.visible_pixels <= 0.0 {
+            return 1.0;
+        }
+        (self.valid_pixels / self.visible_pixels).min(1.0)
+    }
+}
+
+/// The visible tiles of a layer with textures, as a bitmask over the rectangle of tile indices
+/// spanned by the visible tiles. Tiles in the rectangle that aren't visible are never set.
+#[derive(Clone, Debug)]
+pub struct TileValidityMask {
+    /// The index of the top left tile of the rectangle.
+    pub origin: Point2D<usize>,
+
+    /// The number of tiles in the rectangle along each axis.
+    pub size: Size2D<usize>,
+
+    /// The number of visible tiles.
+    pub visible_tiles: usize,
+
+    /// One bit per tile in the rectangle, in rows from the top.
+    bits: Vec<u64>,
+}
+
+impl TileValidityMask {
+    /// Builds the mask from the indices of the visible tiles, each with whether it has a
+    /// texture.
+    pub fn new(tiles: &[(Point2D<usize>, bool)]) -> TileValidityMask {
+        if tiles.is_empty() {
+            return TileValidityMask {
+                origin: Point2D::zero(),
+                size: Size2D::zero(),
+                visible_tiles: 0,
+                bits: vec!(),
+            };
+        }
+
+        let (mut min, mut max) = (tiles[0].0, tiles[0].0);
+        for &(index, _) in tiles {
+            min = Point2D::new(cmp::min(min.x, index.x), cmp::min(min.y, index.y));
+            max = Point2D::new(cmp::max(max.x, index.x), cmp::max(max.y, index.y));
+        }
+        let size = Size2D::new(max.x - min.x + 1, max.y - min.y + 1);
+        let mut mask = TileValidityMask {
+            origin: min,
+            size: size,
+            visible_tiles: tiles.len(),
+            bits: vec![0; (size.width * size.height + 63) / 64],
+        };
+        for &(index, valid) in tiles {
+            if valid {
+                let bit = mask.bit(&index);
+                mask.bits[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        mask
+    }
+
+    fn bit(&self, index: &Point2D<usize>) -> usize {
+        (index.y - self.origin.y) * self.size.width + (index.x - self.origin.x)
+    }
+
+    /// Whether the tile at the given index is visible and has a texture.
+    pub fn is_valid(&self, index: &Point2D<usize>) -> bool {
+        if index.x < self.origin.x || index.y < self.origin.y ||
+           index.x - self.origin.x >= self.size.width ||
+           index.y - self.origin.y >= self.size.height {
+            return false;
+        }
+        let bit = self.bit(index);
+        self.bits[bit / 64] & (1 << (bit % 64)) != 0
+    }
+
+    /// The number of visible tiles with textures.
+    pub fn valid_tiles(&self) -> usize {
+        self.bits.iter().map(|bits| bits.count_ones() as usize).sum()
+    }
+}
+
+/// Statistics about the layer tree of a scene, so that embedders can base layerization
+/// heuristics, e.g. when layout should stop creating layers, on what the compositor holds.
+#[derive(Copy, Clone, Debug)]
+pub struct SceneStats {
+    /// The number of layers in the tree, including the root.
+    pub layer_count: usize,
+
+    /// The number of tiles of all layers.
+    pub tile_count: usize,
+
+    /// The number of tiles with a buffer painted at the tile scale of their layer with its
+    /// current content.
+    pub valid_tile_count: usize,
+
+    /// The number of tiles whose buffers have been uploaded to textures.
+    pub resident_tile_count: usize,
+
+    /// The memory used by the buffers of resident tiles, in bytes.
+    pub resident_texture_bytes: usize,
+
+    /// The depth of the deepest layer, where the root has a depth of 1.
+    pub max_depth: usize,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, SceneStats);
+
+impl SceneStats {
+    pub fn new() -> SceneStats {
+        SceneStats {
+            layer_count: 0,
+            tile_count: 0,
+            valid_tile_count: 0,
+            resident_tile_count: 0,
+            resident_texture_bytes: 0,
+            max_depth: 0,
+        }
+    }
+
+    /// The fraction of tiles that are valid at their tile scale, from 0.0 to 1.0. A tree without
+    /// tiles is entirely valid.
+    pub fn tile_validity(&self) -> f32 {
+        if self.tile_count == 0 {
+            return 1.0;
+        }
+        self.valid_tile_count as f32 / self.tile_count as f32
+    }
+}
+
+/// The number of frames `FramePacing` reports on, about two seconds at 60 frames per second.
+pub const DEFAULT_PACING_WINDOW: usize = 120;
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1.0e9
+}
+
+/// A summary of the pacing of recent frames.
+#[derive(Copy, Clone, Debug)]
+pub struct FramePacingReport {
+    /// The number of frame intervals the report covers.
+    pub frames: usize,
+
+    /// The mean time between frames, in milliseconds.
+    pub mean_interval_ms: f64,
+
+    /// The standard deviation of the time between frames, in milliseconds. Smooth animations
+    /// have a jitter close to zero.
+    pub jitter_ms: f64,
+
+    /// The longest time between two frames, in milliseconds.
+    pub max_interval_ms: f64,
+
+    /// The number of vsyncs that passed without a new frame.
+    pub dropped_frames: usize,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, FramePacingReport);
+
+/// Tracks when frames reach the screen, to detect missed vsyncs and measure how evenly frames
+/// are paced over a rolling window. The times are the presentation timestamps reported by the
+/// embedder where the platform provides them, and otherwise the times at which frames finished
+/// compositing.
+///
+/// Only the intervals in which a frame was asked for count: frames without a request since the
+/// previous one end a period without anything to composite, however long it was, and aren't
+/// counted. Damage to a layer counts as a request, and embedders report other reasons to
+/// composite with `record_request`. An interval whose first request came more than a frame
+/// interval after the previous frame is measured from the request.
+#[derive(Clone, Debug)]
+pub struct FramePacing {
+    /// The expected time between frames, usually the refresh interval of the display.
+    pub frame_interval: Duration,
+
+    /// The number of recent frames reported on.
+    pub window: usize,
+
+    /// The intervals of the recent frames, with the number of vsyncs each missed.
+    intervals: VecDeque<(Duration, usize)>,
+
+    last_frame: Option<Instant>,
+
+    /// When a frame was first asked for since the last frame.
+    first_request: Option<Instant>,
+
+    /// Whether the embedder reports presentation timestamps, in which case composite times are
+    /// ignored.
+    has_presentation_times: bool,
+
+    /// The number of frames dropped since tracking started.
+    total_dropped_frames: usize,
+}
+
+impl FramePacing {
+    /// Tracks frames presented on a display refreshing every `frame_interval`.
+    pub fn new(frame_interval: Duration) -> FramePacing {
+        FramePacing {
+            frame_interval: frame_interval,
+            window: DEFAULT_PACING_WINDOW,
+            intervals: VecDeque::new(),
+            last_frame: None,
+            first_request: None,
+            has_presentation_times: false,
+            total_dropped_frames: 0,
+        }
+    }
+
+    /// Records that a frame was asked for at the given time, e.g. because an animation is running
+    /// or the embedder scheduled a composite, so that the interval until the next frame counts.
+    /// Damage to layers is recorded by `render_scene`.
+    pub fn record_request(&mut self, requested_at: Instant) {
+        self.first_request = Some(match self.first_request {
+            Some(first_request) if first_request < requested_at => first_request,
+            _ => requested_at,
+        });
+    }
+
+    /// Records the time at which a frame was presented, as reported by the platform. Once this
+    /// has been called, composite times are ignored. Returns the number of vsyncs missed since
+    /// the previous frame.
+    pub fn record_presentation(&mut self, presented_at: Instant) -> usize {
+        self.has_presentation_times = true;
+        self.record(presented_at)
+    }
+
+    /// Records the time at which a frame finished compositing, for platforms without
+    /// presentation timestamps. This is done by `render_scene`.
+    pub fn record_composite(&mut self, composited_at: Instant) -> usize {
+        if self.has_presentation_times {
+            return 0;
+        }
+        self.record(composited_at)
+    }
+
+    fn record(&mut self, time: Instant) -> usize {
+        let last_frame = self.last_frame;
+        self.last_frame = Some(time);
+        let first_request = self.first_request.take();
+        let last_frame = match last_frame {
+            Some(last_frame) if time > last_frame => last_frame,
+            _ => return 0,
+        };
+
+        // Nothing asked for this frame, so it ends an idle period.
+        let requested_at = match first_request {
+            Some(requested_at) => requested_at,
+            None => return 0,
+        };
+        let start = if requested_at > last_frame + self.frame_interval {
+            requested_at
+        } else {
+            last_frame
+        };
+        if time <= start {
+            return 0;
+        }
+        let interval = time.duration_since(start);
+
+        let vsyncs = if self.frame_interval == Duration::new(0, 0) {
+            1.0
+        } else {
+            (seconds(interval) / seconds(self.frame_interval)).round()
+        };
+        let missed = if vsyncs > 1.0 { vsyncs as usize - 1 } else { 0 };
+        if missed > 0 {
+            debug!("Missed {} vsyncs, {:.1}ms between frames",
+                   missed,
+                   seconds(interval) * 1000.0);
+        }
+        self.total_dropped_frames += missed;
+
+        self.intervals.push_back((interval, missed));
+        while self.intervals.len() > self.window {
+            self.intervals.pop_front();
+        }
+        missed
+    }
+
+    /// The number of frames dropped since tracking started.
+    pub fn total_dropped_frames(&self) -> usize {
+        self.total_dropped_frames
+    }
+
+    /// Summarizes the pacing of the frames in the window.
+    pub fn report(&self) -> FramePacingReport {
+        let frames = self.intervals.len();
+        let mut report = FramePacingReport {
+            frames: frames,
+            mean_interval_ms: 0.0,
+            jitter_ms: 0.0,
+            max_interval_ms: 0.0,
+            dropped_frames: 0,
+        };
+        if frames == 0 {
+            return report;
+        }
+
+        let milliseconds = |interval: Duration| seconds(interval) * 1000.0;
+        let mut sum = 0.0;
+        for &(interval, missed) in &self.intervals {
+            let interval = milliseconds(interval);
+            sum += interval;
+            report.max_interval_ms = report.max_interval_ms.max(interval);
+            report.dropped_frames += missed;
+        }
+        report.mean_interval_ms = sum / frames as f64;
+        let variance = self.intervals.iter().map(|&(interval, _)| {
+            let deviation = milliseconds(interval) - report.mean_interval_ms;
+            deviation * deviation
+        }).sum::<f64>() / frames as f64;
+        report.jitter_ms = variance.sqrt();
+        report
+    }
+}
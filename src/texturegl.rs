@@ -9,11 +9,18 @@
 
 //! OpenGL-specific implementation of texturing.
 
+use capabilities::GlCapabilities;
 use layers::LayerBuffer;
 
+use euclid::Matrix4D;
 use euclid::size::Size2D;
-use gleam::gl;
-use gleam::gl::{GLenum, GLint, GLuint};
+use glcapture as gl;
+use gleam::gl::{GLbitfield, GLenum, GLint, GLsizei, GLsizeiptr, GLuint};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::ptr;
+use std::slice;
 
 #[derive(Copy, Clone)]
 pub enum Format {
@@ -33,8 +40,223 @@ pub enum FilterMode {
 #[cfg(feature = "heapsize")]
 known_heap_size!(0, FilterMode);
 
+/// What is sampled outside of the texture, along one axis.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum WrapMode {
+    ClampToEdge,
+    Repeat,
+    MirroredRepeat,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, WrapMode);
+
+/// The order of the color channels in the texture data, as uploaded.
+///
+/// Data stored as BGRA can be uploaded as RGBA and swizzled by the GPU when sampled, which needs
+/// OpenGL 3.3, OpenGL ES 3.0 or `GL_ARB_texture_swizzle`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Swizzle {
+    Rgba,
+    Bgra,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, Swizzle);
+
+// From OpenGL 3.3 and OpenGL ES 3.0, which the bindings may not include.
+const TEXTURE_SWIZZLE_R: GLenum = 0x8E42;
+const TEXTURE_SWIZZLE_G: GLenum = 0x8E43;
+const TEXTURE_SWIZZLE_B: GLenum = 0x8E44;
+const TEXTURE_SWIZZLE_A: GLenum = 0x8E45;
+
+// From `GL_EXT_bgra` and `GL_EXT_texture_format_BGRA8888`.
+const BGRA: GLenum = 0x80E1;
+const UNSIGNED_INT_8_8_8_8_REV: GLenum = 0x8367;
+
+/// How BGRA pixel data is uploaded to textures by the current GL implementation.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UploadFormat {
+    /// Desktop OpenGL: BGRA data with the `UNSIGNED_INT_8_8_8_8_REV` type, which drivers
+    /// generally copy without conversion.
+    DesktopBgra,
+    /// OpenGL ES with `GL_EXT_texture_format_BGRA8888`, which takes BGRA as both the internal
+    /// format and the data format.
+    GlesBgraExtension,
+    /// OpenGL ES 3.0 without the BGRA extension: uploaded as RGBA and swizzled when sampled.
+    RgbaWithSwizzle,
+    /// No BGRA support at all: the red and blue channels are swapped on the CPU before
+    /// uploading as RGBA.
+    RgbaWithConversion,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, UploadFormat);
+
+thread_local!(static UPLOAD_FORMAT: Cell<Option<UploadFormat>> = Cell::new(None));
+
+impl UploadFormat {
+    /// Picks the fastest way to upload BGRA data with the GL context current on this thread.
+    pub fn probe() -> UploadFormat {
+        let capabilities = GlCapabilities::current();
+        if !capabilities.is_gles {
+            UploadFormat::DesktopBgra
+        } else if capabilities.bgra_textures {
+            UploadFormat::GlesBgraExtension
+        } else if capabilities.texture_swizzle {
+            UploadFormat::RgbaWithSwizzle
+        } else {
+            UploadFormat::RgbaWithConversion
+        }
+    }
+
+    /// The upload format for the GL context current on this thread, probed on first use. All
+    /// contexts used by a thread are assumed to be of the same kind.
+    pub fn current() -> UploadFormat {
+        UPLOAD_FORMAT.with(|upload_format| {
+            if let Some(format) = upload_format.get() {
+                return format;
+            }
+            let format = UploadFormat::probe();
+            info!("Uploading BGRA textures using {:?}", format);
+            upload_format.set(Some(format));
+            format
+        })
+    }
+
+    /// Overrides the probed upload format for this thread, e.g. to work around drivers that
+    /// misreport their capabilities.
+    pub fn set_current(format: Option<UploadFormat>) {
+        UPLOAD_FORMAT.with(|upload_format| upload_format.set(format));
+    }
+
+    /// The internal format, format and type to pass to `glTexImage2D`.
+    fn gl_formats(self) -> (GLint, GLenum, GLenum) {
+        match self {
+            UploadFormat::DesktopBgra => (gl::RGBA as GLint, BGRA, UNSIGNED_INT_8_8_8_8_REV),
+            UploadFormat::GlesBgraExtension => (BGRA as GLint, BGRA, gl::UNSIGNED_BYTE),
+            UploadFormat::RgbaWithSwizzle |
+            UploadFormat::RgbaWithConversion => (gl::RGBA as GLint, gl::RGBA, gl::UNSIGNED_BYTE),
+        }
+    }
+}
+
+/// How pixel data in CPU memory is copied into textures. Which is fastest depends on the
+/// driver; see the `uploadbench` module for choosing one by measuring them. Buffers that the
+/// platform can bind to textures without copying are always bound that way.
+#[derive(Copy, Clone, PartialEq, Debug, RustcEncodable, RustcDecodable)]
+pub enum UploadMethod {
+    /// `glTexImage2D` with the data, which allocates the storage and copies at once.
+    TexImage,
+    /// `glTexImage2D` without data to allocate the storage, then `glTexSubImage2D` to copy.
+    TexSubImage,
+    /// Like `TexSubImage`, but the data is first written into a pixel buffer object, from which
+    /// the driver transfers it to the texture asynchronously, instead of the composite loop
+    /// waiting on the transfer. Needs `GlCapabilities::pixel_buffers`, and falls back to
+    /// `TexSubImage` without it. With `GlCapabilities::map_buffer_range`, the buffer is mapped
+    /// and painted into directly, see `Texture::upload_bgra_painted_with`. Writing the buffer
+    /// still takes CPU time, so bursts of uploads are best spread over frames with
+    /// `Scene::max_upload_bytes_per_frame`. This is the default where it is supported.
+    PixelBuffer,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, UploadMethod);
+
+thread_local!(static UPLOAD_METHOD: Cell<Option<UploadMethod>> = Cell::new(None));
+
+// From OpenGL 2.1, OpenGL ES 3.0 and `GL_ARB_map_buffer_range`, which the bindings may not
+// include.
+const PIXEL_UNPACK_BUFFER: GLenum = 0x88EC;
+const MAP_WRITE_BIT: GLbitfield = 0x0002;
+const MAP_INVALIDATE_BUFFER_BIT: GLbitfield = 0x0008;
+
+/// The number of pixel buffer objects uploads cycle through. Refilling a buffer whose data the
+/// driver is still transferring would wait for the transfer, so consecutive uploads use
+/// different buffers; the buffer storage is also orphaned on each upload for drivers that would
+/// wait anyway.
+const PIXEL_BUFFER_COUNT: usize = 4;
+
+/// The pixel buffer objects of the `PixelBuffer` upload method on one thread, created on first
+/// use.
+struct PixelBufferQueue {
+    buffers: Vec<GLuint>,
+    next: usize,
+}
+
+thread_local!(static PIXEL_BUFFERS: RefCell<PixelBufferQueue> = RefCell::new(PixelBufferQueue {
+    buffers: vec!(),
+    next: 0,
+}));
+
+impl PixelBufferQueue {
+    /// The buffer to stage the next upload in.
+    fn next_buffer(&mut self) -> GLuint {
+        if self.buffers.is_empty() {
+            self.buffers = gl::gen_buffers(PIXEL_BUFFER_COUNT as GLsizei);
+        }
+        let buffer = self.buffers[self.next];
+        self.next = (self.next + 1) % self.buffers.len();
+        buffer
+    }
+}
+
+/// Deletes the pixel buffer objects uploads went through on this thread. They are created again
+/// by the next upload with `UploadMethod::PixelBuffer`. Called when a `RenderContext` is
+/// dropped.
+pub fn delete_pixel_buffers() {
+    PIXEL_BUFFERS.with(|queue| {
+        let mut queue = queue.borrow_mut();
+        if !queue.buffers.is_empty() {
+            gl::delete_buffers(&queue.buffers);
+        }
+        queue.buffers.clear();
+        queue.next = 0;
+    })
+}
+
+/// Forgets the pixel buffer objects of this thread without deleting them, for when the GL
+/// context they belonged to was lost.
+pub fn forget_pixel_buffers() {
+    PIXEL_BUFFERS.with(|queue| {
+        let mut queue = queue.borrow_mut();
+        queue.buffers.clear();
+        queue.next = 0;
+    })
+}
+
+impl UploadMethod {
+    /// All of the methods the GL context current on this thread supports, in order of
+    /// preference when they are equally fast.
+    pub fn all() -> Vec<UploadMethod> {
+        let mut methods = vec!(UploadMethod::TexImage, UploadMethod::TexSubImage);
+        if GlCapabilities::current().pixel_buffers {
+            methods.push(UploadMethod::PixelBuffer);
+        }
+        methods
+    }
+
+    /// The method textures are uploaded with on this thread. Unless set otherwise, that is
+    /// `PixelBuffer` if the GL context current on this thread supports pixel buffer objects, and
+    /// `TexImage` if it doesn't.
+    pub fn current() -> UploadMethod {
+        UPLOAD_METHOD.with(|upload_method| upload_method.get()).unwrap_or_else(|| {
+            if GlCapabilities::current().pixel_buffers {
+                UploadMethod::PixelBuffer
+            } else {
+                UploadMethod::TexImage
+            }
+        })
+    }
+
+    /// Sets the method textures are uploaded with on this thread.
+    pub fn set_current(method: UploadMethod) {
+        UPLOAD_METHOD.with(|upload_method| upload_method.set(Some(method)));
+    }
+}
+
 /// The texture target.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub enum TextureTarget {
     /// TEXTURE_2D.
     TextureTarget2D,
@@ -45,22 +267,149 @@ pub enum TextureTarget {
 #[cfg(feature = "heapsize")]
 known_heap_size!(0, TextureTarget);
 
-impl TextureTarget {
+// From `GL_ARB_texture_rectangle`, which the OpenGL ES bindings don't include.
+const TEXTURE_RECTANGLE_ARB: GLenum = 0x84F5;
 
-    #[cfg(not(target_os = "android"))]
+impl TextureTarget {
+    /// Rectangle textures are only created where `GlCapabilities::rectangle_textures` says they
+    /// are supported.
     pub fn as_gl_target(self) -> GLenum {
         match self {
             TextureTarget::TextureTarget2D => gl::TEXTURE_2D,
-            TextureTarget::TextureTargetRectangle => gl::TEXTURE_RECTANGLE_ARB,
+            TextureTarget::TextureTargetRectangle => TEXTURE_RECTANGLE_ARB,
         }
     }
+}
 
-    #[cfg(target_os = "android")]
-    pub fn as_gl_target(self) -> GLenum {
-        match self {
-            TextureTarget::TextureTarget2D => gl::TEXTURE_2D,
-            TextureTarget::TextureTargetRectangle => panic!("android doesn't supported rectangle targets"),
+/// The default capacity of the texture pool of each thread: 32 tiles of 256 by 256 pixels.
+const DEFAULT_TEXTURE_POOL_CAPACITY: usize = 32 * 256 * 256 * 4;
+
+/// Identifies which tiles a pooled texture can be given to: only tiles of the same size and in
+/// the same target, so that the storage the driver allocated for the texture suits the next
+/// buffer too. Only textures that buffers are uploaded to are pooled.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+struct PoolKey {
+    target: GLenum,
+    width: usize,
+    height: usize,
+}
+
+impl PoolKey {
+    /// The number of bytes of GPU memory a texture with this key takes, assuming 8-bit RGBA.
+    fn size_in_bytes(&self) -> usize {
+        self.width * self.height * 4
+    }
+}
+
+/// Textures of dropped tiles, kept to be reused by new tiles of the same size, so that
+/// scrolling doesn't make the driver free and allocate textures all the time. Each thread has
+/// its own pool, which tile textures dropped on the thread go back to as long as the pool stays
+/// within its capacity.
+pub struct TexturePool {
+    textures: HashMap<PoolKey, Vec<GLuint>>,
+    size_in_bytes: usize,
+    capacity: usize,
+}
+
+thread_local!(static TEXTURE_POOL: RefCell<TexturePool> = RefCell::new(TexturePool {
+    textures: HashMap::new(),
+    size_in_bytes: 0,
+    capacity: DEFAULT_TEXTURE_POOL_CAPACITY,
+}));
+
+impl TexturePool {
+    /// The maximum number of bytes of textures the pool of this thread keeps.
+    pub fn capacity() -> usize {
+        TEXTURE_POOL.with(|pool| pool.borrow().capacity)
+    }
+
+    /// Sets the maximum number of bytes of textures the pool of this thread keeps. Zero turns
+    /// recycling off. If the pool holds more than the new capacity, textures are deleted,
+    /// largest first, until it fits.
+    pub fn set_capacity(capacity: usize) {
+        TEXTURE_POOL.with(|pool| {
+            let mut pool = pool.borrow_mut();
+            pool.capacity = capacity;
+            pool.trim_to(capacity);
+        })
+    }
+
+    /// The number of bytes of textures held by the pool of this thread.
+    pub fn size_in_bytes() -> usize {
+        TEXTURE_POOL.with(|pool| pool.borrow().size_in_bytes)
+    }
+
+    /// Deletes the textures held by the pool of this thread, e.g. under memory pressure. The
+    /// pool fills up again as tiles are dropped.
+    pub fn trim() {
+        TEXTURE_POOL.with(|pool| pool.borrow_mut().delete_all())
+    }
+
+    /// Empties the pool of this thread without deleting its textures, for when the GL context
+    /// they belonged to was lost.
+    pub fn forget() {
+        TEXTURE_POOL.with(|pool| {
+            let mut pool = pool.borrow_mut();
+            pool.textures.clear();
+            pool.size_in_bytes = 0;
+        })
+    }
+
+    fn delete_all(&mut self) {
+        for (_, textures) in self.textures.drain() {
+            gl::delete_textures(&textures);
         }
+        self.size_in_bytes = 0;
+    }
+
+    /// Deletes textures, largest first, until the pool holds at most `capacity` bytes.
+    fn trim_to(&mut self, capacity: usize) {
+        let mut keys: Vec<PoolKey> = self.textures.keys().cloned().collect();
+        keys.sort_by(|a, b| b.size_in_bytes().cmp(&a.size_in_bytes()));
+        let mut deleted_textures = vec!();
+        for key in keys {
+            if self.size_in_bytes <= capacity {
+                break;
+            }
+            let textures = self.textures.get_mut(&key).unwrap();
+            while self.size_in_bytes > capacity {
+                match textures.pop() {
+                    Some(texture) => deleted_textures.push(texture),
+                    None => break,
+                }
+                self.size_in_bytes -= key.size_in_bytes();
+            }
+        }
+        self.textures.retain(|_, textures| !textures.is_empty());
+        if !deleted_textures.is_empty() {
+            gl::delete_textures(&deleted_textures);
+        }
+    }
+
+    /// Takes a texture with the given key out of the pool of this thread, if there is one.
+    fn take(key: PoolKey) -> Option<GLuint> {
+        TEXTURE_POOL.with(|pool| {
+            let mut pool = pool.borrow_mut();
+            let texture = pool.textures.get_mut(&key).and_then(|textures| textures.pop());
+            if texture.is_some() {
+                pool.size_in_bytes -= key.size_in_bytes();
+            }
+            texture
+        })
+    }
+
+    /// Puts a texture back into the pool of this thread. Returns false if it doesn't fit, in
+    /// which case the caller deletes it.
+    fn recycle(key: PoolKey, texture: GLuint) -> bool {
+        TEXTURE_POOL.with(|pool| {
+            let mut pool = pool.borrow_mut();
+            if pool.size_in_bytes + key.size_in_bytes() > pool.capacity {
+                return false;
+            }
+            pool.size_in_bytes += key.size_in_bytes();
+            pool.textures.entry(key).or_insert_with(Vec::new).push(texture);
+            true
+        })
     }
 }
 
@@ -78,18 +427,36 @@ pub struct Texture {
     /// the destructor.
     weak: bool,
 
-    // Whether or not this texture needs to be flipped upon display.
-    pub flip: Flip,
+    // How the contents of this texture need to be flipped and rotated upon display.
+    pub orientation: Orientation,
 
     // The size of this texture in device pixels.
-    pub size: Size2D<usize>
+    pub size: Size2D<usize>,
+
+    /// Which tiles this texture can be reused for once dropped, if it goes back to the texture
+    /// pool at all.
+    pool_key: Option<PoolKey>,
+
+    /// The wrap modes in effect, horizontally and vertically, which the sampler objects the
+    /// texture is drawn with must match.
+    wrap_mode: Cell<(WrapMode, WrapMode)>,
+
+    /// The swizzle that the GL implementation couldn't set on the texture, left for the
+    /// texture program to apply.
+    shader_swizzle: Cell<Swizzle>,
 }
 
 impl Drop for Texture {
     fn drop(&mut self) {
-        if !self.weak {
-            gl::delete_textures(&[ self.id ])
+        if self.weak {
+            return;
+        }
+        if let Some(pool_key) = self.pool_key {
+            if TexturePool::recycle(pool_key, self.id) {
+                return;
+            }
         }
+        gl::delete_textures(&[ self.id ])
     }
 }
 
@@ -99,13 +466,23 @@ impl Texture {
             id: 0,
             target: TextureTarget::TextureTarget2D,
             weak: true,
-            flip: Flip::NoFlip,
+            orientation: Orientation::identity(),
             size: Size2D::new(0, 0),
+            pool_key: None,
+            wrap_mode: Cell::new((WrapMode::ClampToEdge, WrapMode::ClampToEdge)),
+            shader_swizzle: Cell::new(Swizzle::Rgba),
         }
     }
     pub fn is_zero(&self) -> bool {
         self.id == 0
     }
+
+    /// Forgets the texture without deleting it, for when the GL context it belonged to was
+    /// lost along with all of its objects.
+    pub fn forget(&mut self) {
+        self.id = 0;
+        self.weak = true;
+    }
 }
 
 /// Encapsulates a bound texture. This ensures that the texture is unbound
@@ -127,51 +504,109 @@ impl Texture {
             id: gl::gen_textures(1)[0],
             target: target,
             weak: false,
-            flip: Flip::NoFlip,
+            orientation: Orientation::identity(),
             size: size,
+            pool_key: None,
+            wrap_mode: Cell::new((WrapMode::ClampToEdge, WrapMode::ClampToEdge)),
+            shader_swizzle: Cell::new(Swizzle::Rgba),
         };
         this.set_default_params();
         this
     }
 
+    /// Wraps a texture created outside of this crate, without taking ownership of it: it is
+    /// weak, so it isn't deleted when dropped.
+    pub fn new_external(id: GLuint,
+                        target: TextureTarget,
+                        size: Size2D<usize>,
+                        orientation: Orientation)
+                        -> Texture {
+        Texture {
+            id: id,
+            target: target,
+            weak: true,
+            orientation: orientation,
+            size: size,
+            pool_key: None,
+            wrap_mode: Cell::new((WrapMode::ClampToEdge, WrapMode::ClampToEdge)),
+            shader_swizzle: Cell::new(Swizzle::Rgba),
+        }
+    }
+
+    /// Creates a texture for the given tile buffer. If the buffer's pixels are uploaded to it,
+    /// a texture from the texture pool of this thread is reused if it has one of the right
+    /// size, and the texture goes back to the pool when dropped. Textures that native surfaces
+    /// are bound to aren't pooled, since their storage belongs to the surface.
     pub fn new_with_buffer(buffer: &Box<LayerBuffer>) -> Texture {
-        let (flip, target) = Texture::texture_flip_and_target(buffer.painted_with_cpu);
-        let mut texture = Texture::new(target, buffer.screen_pos.size);
-        texture.flip = flip;
+        let (orientation, target) =
+            Texture::texture_orientation_and_target(buffer.painted_with_cpu);
+        let size = buffer.screen_pos.size;
+        if buffer.native_surface.cpu_pixels().is_none() {
+            let mut texture = Texture::new(target, size);
+            texture.orientation = orientation;
+            return texture;
+        }
+
+        let pool_key = PoolKey {
+            target: target.as_gl_target(),
+            width: size.width,
+            height: size.height,
+        };
+        let mut texture = match TexturePool::take(pool_key) {
+            Some(id) => {
+                let texture = Texture {
+                    id: id,
+                    target: target,
+                    weak: false,
+                    orientation: Orientation::identity(),
+                    size: size,
+                    pool_key: None,
+                    wrap_mode: Cell::new((WrapMode::ClampToEdge, WrapMode::ClampToEdge)),
+                    shader_swizzle: Cell::new(Swizzle::Rgba),
+                };
+                // Filter and wrap modes may have been changed by its previous tile.
+                texture.set_default_params();
+                texture
+            }
+            None => Texture::new(target, size),
+        };
+        texture.orientation = orientation;
+        texture.pool_key = Some(pool_key);
         texture
     }
 
-    // Returns whether the layer should be vertically flipped.
-    #[cfg(target_os="macos")]
-    pub fn texture_flip_and_target(cpu_painting: bool) -> (Flip, TextureTarget) {
-        let flip = if cpu_painting {
-            Flip::NoFlip
+    // Returns how the layer should be oriented.
+    #[cfg(all(target_os="macos", feature = "native-surfaces"))]
+    pub fn texture_orientation_and_target(cpu_painting: bool) -> (Orientation, TextureTarget) {
+        let orientation = if cpu_painting {
+            Orientation::identity()
         } else {
-            Flip::VerticalFlip
+            Orientation::vertical_flip()
         };
 
-        (flip, TextureTarget::TextureTargetRectangle)
+        // IOSurfaces can only be bound to rectangle textures, so GPU painting needs them.
+        let target = if GlCapabilities::current().rectangle_textures {
+            TextureTarget::TextureTargetRectangle
+        } else {
+            TextureTarget::TextureTarget2D
+        };
+        (orientation, target)
     }
 
-    #[cfg(target_os="android")]
-    pub fn texture_flip_and_target(cpu_painting: bool) -> (Flip, TextureTarget) {
-        let flip = if cpu_painting {
-            Flip::NoFlip
+    #[cfg(all(target_os="android", feature = "native-surfaces"))]
+    pub fn texture_orientation_and_target(cpu_painting: bool) -> (Orientation, TextureTarget) {
+        let orientation = if cpu_painting {
+            Orientation::identity()
         } else {
-            Flip::VerticalFlip
+            Orientation::vertical_flip()
         };
 
-        (flip, TextureTarget::TextureTarget2D)
-    }
-
-    #[cfg(target_os="linux")]
-    pub fn texture_flip_and_target(_: bool) -> (Flip, TextureTarget) {
-        (Flip::NoFlip, TextureTarget::TextureTarget2D)
+        (orientation, TextureTarget::TextureTarget2D)
     }
 
-    #[cfg(target_os="windows")]
-    pub fn texture_flip_and_target(_: bool) -> (Flip, TextureTarget) {
-        (Flip::NoFlip, TextureTarget::TextureTarget2D)
+    #[cfg(not(all(any(target_os="macos", target_os="android"), feature = "native-surfaces")))]
+    pub fn texture_orientation_and_target(_: bool) -> (Orientation, TextureTarget) {
+        (Orientation::identity(), TextureTarget::TextureTarget2D)
     }
 
     /// Returns the raw OpenGL texture underlying this texture.
@@ -186,6 +621,7 @@ impl Texture {
         gl::tex_parameter_i(self.target.as_gl_target(), gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
         gl::tex_parameter_i(self.target.as_gl_target(), gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
         gl::tex_parameter_i(self.target.as_gl_target(), gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+        self.wrap_mode.set((WrapMode::ClampToEdge, WrapMode::ClampToEdge));
     }
 
     /// Sets the filter mode for this texture.
@@ -199,6 +635,241 @@ impl Texture {
         gl::tex_parameter_i(self.target.as_gl_target(), gl::TEXTURE_MIN_FILTER, gl_mode);
     }
 
+    /// Uploads tightly packed BGRA pixel data, converting it only if the GL implementation
+    /// can't take BGRA data directly.
+    pub fn upload_bgra(&self, size: Size2D<usize>, data: &[u8]) {
+        self.upload_bgra_with_method(UploadMethod::current(), size, data)
+    }
+
+    /// Uploads tightly packed BGRA pixel data with the given method.
+    pub fn upload_bgra_with_method(&self, method: UploadMethod, size: Size2D<usize>, data: &[u8]) {
+        let capabilities = GlCapabilities::current();
+        let method = match method {
+            UploadMethod::PixelBuffer if !capabilities.pixel_buffers => UploadMethod::TexSubImage,
+            UploadMethod::PixelBuffer if capabilities.map_buffer_range => {
+                return self.upload_bgra_painted_with(method, size, |pixels| {
+                    pixels.copy_from_slice(data)
+                })
+            }
+            method => method,
+        };
+        let format = UploadFormat::current();
+        let data = match format {
+            UploadFormat::RgbaWithConversion => {
+                let mut rgba = data.to_vec();
+                for pixel in rgba.chunks_mut(4) {
+                    pixel.swap(0, 2);
+                }
+                Cow::Owned(rgba)
+            }
+            _ => Cow::Borrowed(data),
+        };
+        if format == UploadFormat::RgbaWithSwizzle {
+            self.set_swizzle(Swizzle::Bgra);
+        }
+
+        let (internal_format, data_format, data_type) = format.gl_formats();
+        let _bound_texture = self.bind();
+        let target = self.target.as_gl_target();
+        let (width, height) = (size.width as GLsizei, size.height as GLsizei);
+        match method {
+            UploadMethod::TexImage => {
+                gl::tex_image_2d(target,
+                                 0,
+                                 internal_format,
+                                 width,
+                                 height,
+                                 0,
+                                 data_format,
+                                 data_type,
+                                 Some(&*data));
+            }
+            UploadMethod::TexSubImage => {
+                gl::tex_image_2d(target,
+                                 0,
+                                 internal_format,
+                                 width,
+                                 height,
+                                 0,
+                                 data_format,
+                                 data_type,
+                                 None);
+                gl::tex_sub_image_2d(target,
+                                     0,
+                                     0,
+                                     0,
+                                     width,
+                                     height,
+                                     data_format,
+                                     data_type,
+                                     &*data);
+            }
+            UploadMethod::PixelBuffer => {
+                gl::tex_image_2d(target,
+                                 0,
+                                 internal_format,
+                                 width,
+                                 height,
+                                 0,
+                                 data_format,
+                                 data_type,
+                                 None);
+                let buffer = PIXEL_BUFFERS.with(|queue| queue.borrow_mut().next_buffer());
+                gl::bind_buffer(PIXEL_UNPACK_BUFFER, buffer);
+                gl::buffer_data(PIXEL_UNPACK_BUFFER, &*data, gl::STREAM_DRAW);
+                unsafe {
+                    gl::TexSubImage2D(target,
+                                      0,
+                                      0,
+                                      0,
+                                      width,
+                                      height,
+                                      data_format,
+                                      data_type,
+                                      ptr::null());
+                }
+                gl::bind_buffer(PIXEL_UNPACK_BUFFER, 0);
+            }
+        }
+    }
+
+    /// Uploads tightly packed BGRA pixel data of the given size, which `paint` writes into the
+    /// buffer it is given, with the given method. With `UploadMethod::PixelBuffer` and
+    /// `GlCapabilities::map_buffer_range`, the buffer is a mapped pixel buffer object, so the
+    /// data isn't copied on the CPU; its initial contents are undefined, so `paint` must write
+    /// every pixel. Otherwise it is zeroed client memory, uploaded as by
+    /// `upload_bgra_with_method`.
+    pub fn upload_bgra_painted_with<F>(&self, method: UploadMethod, size: Size2D<usize>, paint: F)
+                                       where F: FnOnce(&mut [u8]) {
+        let capabilities = GlCapabilities::current();
+        let length = size.width * size.height * 4;
+        if method != UploadMethod::PixelBuffer || !capabilities.pixel_buffers ||
+                !capabilities.map_buffer_range {
+            let mut pixels = vec![0; length];
+            paint(&mut pixels);
+            return self.upload_bgra_with_method(method, size, &pixels);
+        }
+
+        let format = UploadFormat::current();
+        if format == UploadFormat::RgbaWithSwizzle {
+            self.set_swizzle(Swizzle::Bgra);
+        }
+        let (internal_format, data_format, data_type) = format.gl_formats();
+        let _bound_texture = self.bind();
+        let target = self.target.as_gl_target();
+        let (width, height) = (size.width as GLsizei, size.height as GLsizei);
+        gl::tex_image_2d(target,
+                         0,
+                         internal_format,
+                         width,
+                         height,
+                         0,
+                         data_format,
+                         data_type,
+                         None);
+
+        let buffer = PIXEL_BUFFERS.with(|queue| queue.borrow_mut().next_buffer());
+        gl::bind_buffer(PIXEL_UNPACK_BUFFER, buffer);
+        let mapped = unsafe {
+            gl::BufferData(PIXEL_UNPACK_BUFFER,
+                           length as GLsizeiptr,
+                           ptr::null(),
+                           gl::STREAM_DRAW);
+            gl::MapBufferRange(PIXEL_UNPACK_BUFFER,
+                               0,
+                               length as GLsizeiptr,
+                               MAP_WRITE_BIT | MAP_INVALIDATE_BUFFER_BIT)
+        };
+        if mapped.is_null() {
+            warn!("Couldn't map a pixel buffer of {} bytes", length);
+            gl::bind_buffer(PIXEL_UNPACK_BUFFER, 0);
+            let mut pixels = vec![0; length];
+            paint(&mut pixels);
+            return self.upload_bgra_with_method(UploadMethod::TexSubImage, size, &pixels);
+        }
+
+        {
+            let pixels = unsafe { slice::from_raw_parts_mut(mapped as *mut u8, length) };
+            paint(pixels);
+            if format == UploadFormat::RgbaWithConversion {
+                for pixel in pixels.chunks_mut(4) {
+                    pixel.swap(0, 2);
+                }
+            }
+        }
+        unsafe {
+            gl::UnmapBuffer(PIXEL_UNPACK_BUFFER);
+            gl::TexSubImage2D(target,
+                              0,
+                              0,
+                              0,
+                              width,
+                              height,
+                              data_format,
+                              data_type,
+                              ptr::null());
+        }
+        gl::bind_buffer(PIXEL_UNPACK_BUFFER, 0);
+    }
+
+    /// Sets the wrap modes for this texture, horizontally and vertically. Textures clamp to
+    /// their edges by default. Rectangle textures always do, and so do textures whose sizes
+    /// aren't powers of two unless `GlCapabilities::npot_textures` is set.
+    pub fn set_wrap_mode(&self, wrap_s: WrapMode, wrap_t: WrapMode) {
+        let _bound_texture = self.bind();
+        let can_repeat = match self.target {
+            TextureTarget::TextureTargetRectangle => false,
+            TextureTarget::TextureTarget2D => {
+                GlCapabilities::current().npot_textures ||
+                    (self.size.width.is_power_of_two() && self.size.height.is_power_of_two())
+            }
+        };
+        let effective_mode = |mode| if can_repeat { mode } else { WrapMode::ClampToEdge };
+        let (wrap_s, wrap_t) = (effective_mode(wrap_s), effective_mode(wrap_t));
+        let gl_mode = |mode| {
+            match mode {
+                WrapMode::ClampToEdge => gl::CLAMP_TO_EDGE,
+                WrapMode::Repeat => gl::REPEAT,
+                WrapMode::MirroredRepeat => gl::MIRRORED_REPEAT,
+            } as GLint
+        };
+        gl::tex_parameter_i(self.target.as_gl_target(), gl::TEXTURE_WRAP_S, gl_mode(wrap_s));
+        gl::tex_parameter_i(self.target.as_gl_target(), gl::TEXTURE_WRAP_T, gl_mode(wrap_t));
+        self.wrap_mode.set((wrap_s, wrap_t));
+    }
+
+    /// The wrap modes in effect for this texture, horizontally and vertically, which may be
+    /// clamped even if others were asked for. See `set_wrap_mode`.
+    pub fn wrap_mode(&self) -> (WrapMode, WrapMode) {
+        self.wrap_mode.get()
+    }
+
+    /// Sets the order of the color channels of the data uploaded to this texture, so that it
+    /// is sampled as RGBA. Without texture swizzling in the GL implementation, the compositor
+    /// swizzles when drawing the texture instead.
+    pub fn set_swizzle(&self, swizzle: Swizzle) {
+        if !GlCapabilities::current().texture_swizzle {
+            self.shader_swizzle.set(swizzle);
+            return;
+        }
+        let _bound_texture = self.bind();
+        let (red, blue) = match swizzle {
+            Swizzle::Rgba => (gl::RED, gl::BLUE),
+            Swizzle::Bgra => (gl::BLUE, gl::RED),
+        };
+        let target = self.target.as_gl_target();
+        gl::tex_parameter_i(target, TEXTURE_SWIZZLE_R, red as GLint);
+        gl::tex_parameter_i(target, TEXTURE_SWIZZLE_G, gl::GREEN as GLint);
+        gl::tex_parameter_i(target, TEXTURE_SWIZZLE_B, blue as GLint);
+        gl::tex_parameter_i(target, TEXTURE_SWIZZLE_A, gl::ALPHA as GLint);
+    }
+
+    /// The swizzle that sampling this texture still needs, if the GL implementation couldn't
+    /// set it on the texture. See `set_swizzle`.
+    pub fn shader_swizzle(&self) -> Swizzle {
+        self.shader_swizzle.get()
+    }
+
     /// Binds the texture to the current context.
     pub fn bind(&self) -> BoundTexture {
         gl::bind_texture(self.target.as_gl_target(), self.id);
@@ -209,14 +880,87 @@ impl Texture {
     }
 }
 
-/// Whether a texture should be flipped.
-#[derive(PartialEq, Copy, Clone)]
-pub enum Flip {
-    /// The texture should not be flipped.
-    NoFlip,
-    /// The texture should be flipped vertically.
-    VerticalFlip,
+/// A clockwise rotation by a multiple of 90 degrees.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Rotation {
+    Rotate0,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, Rotation);
+
+/// How the contents of a texture are transformed to be displayed upright: first flipped, then
+/// rotated clockwise. This covers sources like camera frames and rotated window-system surfaces,
+/// in addition to surfaces that are stored bottom-up.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Orientation {
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub rotation: Rotation,
 }
 
 #[cfg(feature = "heapsize")]
-known_heap_size!(0, Flip);
+known_heap_size!(0, Orientation);
+
+impl Orientation {
+    /// The contents are displayed as stored.
+    pub fn identity() -> Orientation {
+        Orientation {
+            flip_x: false,
+            flip_y: false,
+            rotation: Rotation::Rotate0,
+        }
+    }
+
+    /// The contents are stored bottom-up, as when rendered by OpenGL.
+    pub fn vertical_flip() -> Orientation {
+        Orientation {
+            flip_x: false,
+            flip_y: true,
+            rotation: Rotation::Rotate0,
+        }
+    }
+
+    /// Returns the transform from normalized texture coordinates in the drawn rect to the
+    /// normalized coordinates to sample the texture at. The drawn rect can additionally be
+    /// flipped in display space.
+    pub fn texture_transform(&self, display_flip_x: bool, display_flip_y: bool)
+                             -> Matrix4D<f32> {
+        // Undoing a rotation amounts to swapping the axes and flipping some of them.
+        let (swap_axes, rotation_flip_x, rotation_flip_y) = match self.rotation {
+            Rotation::Rotate0 => (false, false, false),
+            Rotation::Rotate90 => (true, false, true),
+            Rotation::Rotate180 => (false, true, true),
+            Rotation::Rotate270 => (true, true, false),
+        };
+
+        // Display flips happen before the axes are swapped back.
+        let (display_flip_x, display_flip_y) = if swap_axes {
+            (display_flip_y, display_flip_x)
+        } else {
+            (display_flip_x, display_flip_y)
+        };
+        let flip_x = (self.flip_x != rotation_flip_x) != display_flip_x;
+        let flip_y = (self.flip_y != rotation_flip_y) != display_flip_y;
+
+        let mut transform = Matrix4D::identity();
+        if flip_x || flip_y {
+            transform = transform.pre_scaled(if flip_x { -1.0 } else { 1.0 },
+                                             if flip_y { -1.0 } else { 1.0 },
+                                             1.0)
+                                 .pre_translated(if flip_x { -1.0 } else { 0.0 },
+                                                 if flip_y { -1.0 } else { 0.0 },
+                                                 0.0);
+        }
+        if swap_axes {
+            transform = transform.pre_mul(&Matrix4D::new(0.0, 1.0, 0.0, 0.0,
+                                                         1.0, 0.0, 0.0, 0.0,
+                                                         0.0, 0.0, 1.0, 0.0,
+                                                         0.0, 0.0, 0.0, 1.0));
+        }
+        transform
+    }
+}
@@ -0,0 +1,82 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Textures owned by the embedder, such as WebGL canvases or camera feeds, composited by layers
+//! in place of painted tiles.
+//!
+//! Unlike the textures of tiles, these are never created or deleted by this crate. The embedder
+//! keeps the texture alive, in the compositor's GL context or one sharing objects with it, for
+//! as long as a layer shows it, and deletes it once `Layer::set_texture_layer` has returned the
+//! handle wrapping it.
+
+use texturegl::{Orientation, Texture, TextureTarget};
+
+use euclid::size::Size2D;
+use gleam::gl::GLuint;
+
+/// How the rows of an external texture are stored.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Flip {
+    /// Top-down, as uploaded from an image.
+    None,
+    /// Bottom-up, as rendered by OpenGL into a framebuffer.
+    Vertical,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, Flip);
+
+/// A handle to a texture owned by the embedder, shown by a layer stretched over its bounds.
+/// Dropping the handle leaves the texture alone. Its contents are expected to have
+/// premultiplied alpha, like those of tiles.
+pub struct TextureLayer {
+    /// A weak texture, which isn't deleted when dropped.
+    texture: Texture,
+
+    /// Counts the changes the embedder made to the contents of the texture.
+    generation: usize,
+}
+
+impl TextureLayer {
+    /// Wraps the texture `id` of the given size and target. Rectangle textures are only
+    /// supported where `GlCapabilities::rectangle_textures` says so.
+    pub fn from_external(id: GLuint, size: Size2D<usize>, target: TextureTarget, flip: Flip)
+                         -> TextureLayer {
+        let orientation = match flip {
+            Flip::None => Orientation::identity(),
+            Flip::Vertical => Orientation::vertical_flip(),
+        };
+        TextureLayer {
+            texture: Texture::new_external(id, target, size, orientation),
+            generation: 0,
+        }
+    }
+
+    /// The texture wrapped by this handle.
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    /// The GL name of the wrapped texture.
+    pub fn native_texture(&self) -> GLuint {
+        self.texture.native_texture()
+    }
+
+    /// Counts the calls to `contents_changed`, so that caches drawn from earlier contents can
+    /// tell they are stale.
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// Records that the embedder drew new contents into the texture. Use
+    /// `Layer::texture_layer_changed` for a texture shown by a layer, so that it is recomposited.
+    pub fn contents_changed(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+}
@@ -7,45 +7,224 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use delays::{self, SimulatedDelays};
 use geometry::{DevicePixel, LayerPixel};
 use layers::{BufferRequest, ContentAge, LayerBuffer};
 use platform::surface::NativeDisplay;
+use profiler;
+use sequence::Epoch;
 use texturegl::Texture;
+use upload::{UploadBudget, UploadFence};
 use util::project_rect_to_screen;
 
-use euclid::length::Length;
 use euclid::{Matrix4D, Point2D, TypedPoint2D};
 use euclid::rect::{Rect, TypedRect};
+use euclid::scale_factor::ScaleFactor;
 use euclid::size::{Size2D, TypedSize2D};
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::collections::hash_map::Entry;
 use std::mem;
+use std::time::{Duration, Instant};
+
+/// Identifies a tile of a layer by its row and column in the layer's tile grid. Ids order tiles
+/// row by row. A tile keeps its id as long as the tile size and the scene scale don't change.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TileId {
+    pub row: usize,
+    pub column: usize,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, TileId);
+
+impl TileId {
+    pub fn new(column: usize, row: usize) -> TileId {
+        TileId {
+            row: row,
+            column: column,
+        }
+    }
+
+    pub fn from_index(index: Point2D<usize>) -> TileId {
+        TileId::new(index.x, index.y)
+    }
+
+    /// The index of the tile in the tile grid, with the column as x and the row as y.
+    pub fn index(&self) -> Point2D<usize> {
+        Point2D::new(self.column, self.row)
+    }
+}
+
+/// A snapshot of the state of a tile, so that embedders and tests can inspect tiling decisions.
+#[derive(Copy, Clone, Debug)]
+pub struct TileInfo {
+    pub id: TileId,
+
+    /// The device pixels covered by the tile at the queried scale.
+    pub device_rect: TypedRect<usize, DevicePixel>,
+
+    /// The scale the buffer of the tile was painted at, if it has received one.
+    pub content_scale: Option<f32>,
+
+    /// The content age of the buffer of the tile, if it has received one.
+    pub content_age: Option<ContentAge>,
+
+    /// Whether the tile has a buffer painted at the queried scale with the current content of
+    /// the layer.
+    pub valid: bool,
+
+    /// Whether a buffer was requested for the tile and hasn't been received yet.
+    pub pending: bool,
+
+    /// Whether the buffer of the tile has been uploaded to a texture.
+    pub resident: bool,
+
+    /// The memory used by the buffer of the tile, in bytes.
+    pub memory_usage: usize,
+}
+
+/// Why the compositor dropped a tile.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EvictionReason {
+    /// The tile moved out of the viewport.
+    OutOfViewport,
+    /// The layer was resized to nothing.
+    EmptyLayer,
+    /// All of the tiles of the layer were discarded, because its subtree was collapsed into a
+    /// cached texture to stay within the resource limits, its buffers were collected, or its
+    /// tiles were laid out again at another size or scale.
+    Discarded,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, EvictionReason);
+
+/// A tile that the compositor dropped along with its buffer. Its content is requested again if
+/// it is needed later, so data the painting side cached to paint it can go.
+#[derive(Copy, Clone, Debug)]
+pub struct TileEviction {
+    pub id: TileId,
+
+    /// The rect in pixels of the buffer request the tile was painted for.
+    pub screen_rect: Rect<usize>,
+
+    /// The rect in page coordinates of the buffer request the tile was painted for.
+    pub page_rect: Rect<f32>,
+
+    pub reason: EvictionReason,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, TileEviction);
 
 pub struct Tile {
     /// The buffer displayed by this tile.
     buffer: Option<Box<LayerBuffer>>,
 
+    /// The update of the layer tree the buffer was painted for, if it was assigned with one.
+    epoch: Option<Epoch>,
+
     /// The content age of any pending buffer request to avoid re-requesting
     /// a buffer while waiting for it to come back from rendering.
     content_age_of_pending_buffer: Option<ContentAge>,
 
+    /// The size in device pixels of any pending buffer request, so that a tile that grows
+    /// while a request is pending is requested again at its new size.
+    size_of_pending_buffer: Option<Size2D<usize>>,
+
     /// A handle to the GPU texture.
     pub texture: Texture,
 
     /// The tile boundaries in the parent layer coordinates.
     pub bounds: Option<TypedRect<f32, LayerPixel>>,
+
+    /// The fence to wait on before sampling a texture that was uploaded on a background context.
+    upload_fence: Option<UploadFence>,
+
+    /// The number of times this tile was composited without a texture since the last buffer
+    /// request pass. Tiles that were checkerboarded are requested with a higher priority.
+    checkerboard_misses: Cell<u32>,
+
+    /// When the current texture was created.
+    uploaded_at: Option<Instant>,
+
+    /// The request frame of the buffer this tile is waiting for, from the time it is requested
+    /// until its texture is created.
+    request_frame: Option<u64>,
 }
 
 impl Tile {
     fn new() -> Tile {
         Tile {
             buffer: None,
+            epoch: None,
             texture: Texture::zero(),
             content_age_of_pending_buffer: None,
+            size_of_pending_buffer: None,
             bounds: None,
+            upload_fence: None,
+            checkerboard_misses: Cell::new(0),
+            uploaded_at: None,
+            request_frame: None,
         }
     }
 
+    /// How long ago the texture of this tile was created, if it has one.
+    pub fn texture_age(&self) -> Option<Duration> {
+        self.uploaded_at.map(|uploaded_at| uploaded_at.elapsed())
+    }
+
+    /// The update of the layer tree the buffer of this tile was painted for, if it was handed
+    /// over by `Layer::assign_painted_buffers` with one.
+    pub fn epoch(&self) -> Option<Epoch> {
+        self.epoch
+    }
+
+    /// Creates a tile that displays the given buffer, outside of any tile grid.
+    pub fn new_with_buffer(buffer: Box<LayerBuffer>) -> Tile {
+        let mut tile = Tile::new();
+        tile.buffer = Some(buffer);
+        tile
+    }
+
+    /// Takes the buffer displayed by this tile, along with its texture.
+    pub fn take_buffer(&mut self) -> Option<Box<LayerBuffer>> {
+        self.texture = Texture::zero();
+        self.bounds = None;
+        self.upload_fence = None;
+        self.request_frame = None;
+        self.buffer.take()
+    }
+
+    /// Ends the request this tile is waiting for without a new buffer, as when the buffer
+    /// painted for it was rejected or the painter dropped it, provided that `content_age` is
+    /// the one requested. The tile is requested again by the next pass if it is still needed.
+    /// A buffer that was accepted but not uploaded yet stays in flight.
+    fn end_request(&mut self, content_age: ContentAge) {
+        let answered = self.content_age_of_pending_buffer.map_or(true, |pending_content_age| {
+            content_age >= pending_content_age
+        });
+        if !answered {
+            return;
+        }
+        self.content_age_of_pending_buffer = None;
+        self.size_of_pending_buffer = None;
+        if !self.needs_texture() {
+            self.request_frame = None;
+        }
+    }
+
+    /// Records that this tile was visible while it had no texture to draw.
+    pub fn record_checkerboard_miss(&self) {
+        self.checkerboard_misses.set(self.checkerboard_misses.get().saturating_add(1));
+    }
+
+    /// The number of times this tile was checkerboarded since the last buffer request pass.
+    pub fn checkerboard_misses(&self) -> u32 {
+        self.checkerboard_misses.get()
+    }
+
     fn should_use_new_buffer(&self, new_buffer: &Box<LayerBuffer>) -> bool {
         match self.buffer {
             Some(ref buffer) => new_buffer.content_age >= buffer.content_age,
@@ -56,17 +235,50 @@ impl Tile {
     fn replace_buffer(&mut self, buffer: Box<LayerBuffer>) -> Option<Box<LayerBuffer>> {
         if !self.should_use_new_buffer(&buffer) {
             warn!("Layer received an old buffer.");
+            self.end_request(buffer.content_age);
             return Some(buffer);
         }
 
         let old_buffer = self.buffer.take();
         self.buffer = Some(buffer);
+        self.epoch = None;
         self.texture = Texture::zero(); // The old texture is bound to the old buffer.
+        self.uploaded_at = None;
         self.content_age_of_pending_buffer = None;
+        self.size_of_pending_buffer = None;
+        self.upload_fence = None;
         old_buffer
     }
 
-    fn create_texture(&mut self, display: &NativeDisplay) {
+    fn replace_buffer_with_texture(&mut self,
+                                   buffer: Box<LayerBuffer>,
+                                   texture: Texture,
+                                   fence: UploadFence)
+                                   -> Option<Box<LayerBuffer>> {
+        if !self.should_use_new_buffer(&buffer) {
+            warn!("Layer received an old buffer.");
+            self.end_request(buffer.content_age);
+            return Some(buffer);
+        }
+
+        let rect = buffer.rect;
+        let old_buffer = self.replace_buffer(buffer);
+        self.texture = texture;
+        self.uploaded_at = Some(Instant::now());
+        self.upload_fence = Some(fence);
+        self.bounds = Some(TypedRect::from_untyped(&rect));
+        self.request_frame = None;
+        old_buffer
+    }
+
+    /// Makes the current context wait for a background upload of this tile's texture.
+    fn sync_background_upload(&mut self) {
+        if let Some(fence) = self.upload_fence.take() {
+            fence.wait_on_gpu();
+        }
+    }
+
+    pub fn create_texture(&mut self, display: &NativeDisplay) {
         if let Some(ref buffer) = self.buffer {
             // If we already have a texture it should still be valid.
             if !self.texture.is_zero() {
@@ -74,28 +286,83 @@ impl Tile {
             }
 
             // Make a new texture and bind the LayerBuffer's surface to it.
+            let _span = profiler::span("tile upload");
             self.texture = Texture::new_with_buffer(buffer);
             debug!("Tile: binding to native surface {}",
                    buffer.native_surface.get_id() as isize);
             buffer.native_surface.bind_to_texture(display, &self.texture);
+            delays::simulate(SimulatedDelays::current().upload);
+            self.uploaded_at = Some(Instant::now());
+            self.request_frame = None;
 
             // Set the layer's rect.
             self.bounds = Some(TypedRect::from_untyped(&buffer.rect));
         }
     }
 
-    fn should_request_buffer(&self, content_age: ContentAge) -> bool {
-        // Don't resend a request if our buffer's content age matches the current content age.
+    /// Forgets the texture of this tile after the GL context was lost, so that the buffer is
+    /// bound to a new texture by the next upload.
+    fn forget_texture(&mut self) {
+        self.texture.forget();
+        self.uploaded_at = None;
+        if let Some(fence) = self.upload_fence.take() {
+            // The sync object went away with the context.
+            mem::forget(fence);
+        }
+    }
+
+    /// Returns true if this tile has a buffer that has not yet been uploaded to a texture.
+    pub fn needs_texture(&self) -> bool {
+        self.buffer.is_some() && self.texture.is_zero()
+    }
+
+    /// The number of bytes that creating a texture for this tile's buffer will upload.
+    pub fn upload_size(&self) -> usize {
+        match self.buffer {
+            Some(ref buffer) => buffer.screen_pos.size.width * buffer.screen_pos.size.height * 4,
+            None => 0,
+        }
+    }
+
+    /// The buffer displayed by this tile, if it has received one.
+    pub fn buffer(&self) -> Option<&LayerBuffer> {
+        self.buffer.as_ref().map(|buffer| &**buffer)
+    }
+
+    /// The boundaries of this tile's buffer in the parent layer coordinates, if it has one.
+    pub fn buffer_rect(&self) -> Option<TypedRect<f32, LayerPixel>> {
+        self.buffer.as_ref().map(|buffer| TypedRect::from_untyped(&buffer.rect))
+    }
+
+    /// Whether a buffer of the given size, in device pixels, was painted for this tile as it is
+    /// now: of the size requested, or within the tile size if nothing is requested. Buffers
+    /// painted before the tile size changed may start where a tile of the new size does, but
+    /// are of the old size.
+    fn fits_buffer_size(&self, size: Size2D<usize>, tile_size: Size2D<usize>) -> bool {
+        match self.size_of_pending_buffer {
+            Some(pending_size) => size == pending_size,
+            None => size.width <= tile_size.width && size.height <= tile_size.height,
+        }
+    }
+
+    fn should_request_buffer(&self, content_age: ContentAge, size: Size2D<usize>) -> bool {
+        // Don't resend a request if our buffer's content age matches the current content age,
+        // and it covers the whole tile. Tiles on the edges of a layer grow along with the layer,
+        // and only the buffers of those need to be painted again.
         if let Some(ref buffer) = self.buffer {
-            if buffer.content_age >= content_age {
+            let buffer_size = buffer.screen_pos.size;
+            let covers_tile = buffer_size.width >= size.width && buffer_size.height >= size.height;
+            if buffer.content_age >= content_age && covers_tile {
                 return false;
             }
         }
 
         // Don't resend a request, if we already have one pending.
-        match self.content_age_of_pending_buffer {
-            Some(pending_content_age) => pending_content_age != content_age,
-            None => true,
+        match (self.content_age_of_pending_buffer, self.size_of_pending_buffer) {
+            (Some(pending_content_age), Some(pending_size)) => {
+                pending_content_age != content_age || pending_size != size
+            }
+            _ => true,
         }
     }
 }
@@ -103,11 +370,14 @@ impl Tile {
 pub struct TileGrid {
     pub tiles: HashMap<Point2D<usize>, Tile>,
 
-    /// The size of tiles in this grid in device pixels.
-    tile_size: Length<usize, DevicePixel>,
+    /// The size of tiles in this grid in device pixels. Tiles needn't be square.
+    tile_size: TypedSize2D<usize, DevicePixel>,
 
     // Buffers that are currently unused.
     unused_buffers: Vec<Box<LayerBuffer>>,
+
+    /// The tiles with buffers that were dropped since `take_evictions` was last called.
+    evictions: Vec<TileEviction>,
 }
 
 pub fn rect_uint_as_rect_f32(rect: Rect<usize>) -> Rect<f32> {
@@ -115,12 +385,57 @@ pub fn rect_uint_as_rect_f32(rect: Rect<usize>) -> Rect<f32> {
                    Size2D::new(rect.size.width as f32, rect.size.height as f32))
 }
 
+/// Converts the device rect of a tile to layer coordinates. The edges are converted rather than
+/// the origin and size, so that the shared edge of adjacent tiles maps to the same coordinate
+/// at fractional scales, and the error doesn't grow with the distance from the layer origin.
+pub fn device_rect_to_layer_rect(rect: TypedRect<usize, DevicePixel>,
+                                 scale: ScaleFactor<f32, LayerPixel, DevicePixel>)
+                                 -> TypedRect<f32, LayerPixel> {
+    let rect = rect.to_untyped();
+    let scale = scale.get();
+    let (min_x, min_y) = (rect.origin.x as f32 / scale, rect.origin.y as f32 / scale);
+    let (max_x, max_y) = (rect.max_x() as f32 / scale, rect.max_y() as f32 / scale);
+    TypedRect::new(TypedPoint2D::new(min_x, min_y),
+                   TypedSize2D::new(max_x - min_x, max_y - min_y))
+}
+
 impl TileGrid {
-    pub fn new(tile_size: usize) -> TileGrid {
+    /// A grid of tiles of `tile_size` device pixels. Which size works best depends on the
+    /// platform, the GPU and the resolution of the display, typically from 128 to 1024 pixels
+    /// along each side.
+    pub fn new(tile_size: Size2D<usize>) -> TileGrid {
+        assert!(tile_size.width > 0 && tile_size.height > 0, "tiles must not be empty");
         TileGrid {
             tiles: HashMap::new(),
-            tile_size: Length::new(tile_size),
+            tile_size: TypedSize2D::from_untyped(&tile_size),
             unused_buffers: Vec::new(),
+            evictions: Vec::new(),
+        }
+    }
+
+    /// The size of tiles in this grid in device pixels.
+    pub fn tile_size(&self) -> Size2D<usize> {
+        self.tile_size.to_untyped()
+    }
+
+    /// Changes the size of the tiles. All tiles are dropped, and their buffers become unused;
+    /// tiles of the new size are requested by the next buffer request pass. Buffers still being
+    /// painted at the old size are rejected when they come back, even those that start where a
+    /// tile of the new size does.
+    pub fn set_tile_size(&mut self, tile_size: Size2D<usize>) {
+        assert!(tile_size.width > 0 && tile_size.height > 0, "tiles must not be empty");
+        if tile_size == self.tile_size() {
+            return;
+        }
+        self.discard_tiles();
+        self.tile_size = TypedSize2D::from_untyped(&tile_size);
+    }
+
+    /// Drops all tiles, as when they are to be laid out again, and makes their buffers unused.
+    pub fn discard_tiles(&mut self) {
+        let tiles: Vec<_> = self.tiles.drain().collect();
+        for (index, tile) in tiles {
+            self.evict_tile(index, tile, EvictionReason::Discarded);
         }
     }
 
@@ -130,13 +445,16 @@ impl TileGrid {
                                    -> TypedRect<usize, DevicePixel> {
 
         let origin : TypedPoint2D<usize, DevicePixel> =
-            TypedPoint2D::new(self.tile_size.get() * tile_index.x,
-                              self.tile_size.get() * tile_index.y);
+            TypedPoint2D::new(self.tile_size.width * tile_index.x,
+                              self.tile_size.height * tile_index.y);
 
-        // Don't let tiles extend beyond the layer boundaries.
-        let tile_size = self.tile_size.get() as f32;
-        let size = Size2D::new(tile_size.min(current_layer_size.width - origin.x as f32),
-                               tile_size.min(current_layer_size.height - origin.y as f32));
+        // Don't let tiles extend beyond the layer boundaries. Tiles past the end of the layer
+        // are empty, rather than negative.
+        let (tile_width, tile_height) =
+            (self.tile_size.width as f32, self.tile_size.height as f32);
+        let size = Size2D::new(tile_width.min(current_layer_size.width - origin.x as f32).max(0.0),
+                               tile_height.min(current_layer_size.height - origin.y as f32)
+                                          .max(0.0));
 
         // Round up to texture pixels.
         let size = TypedSize2D::new(size.width.ceil() as usize, size.height.ceil() as usize);
@@ -156,6 +474,24 @@ impl TileGrid {
         }
     }
 
+    /// Drops a tile that was removed from the grid, recording its eviction if it had a buffer.
+    fn evict_tile(&mut self, index: Point2D<usize>, mut tile: Tile, reason: EvictionReason) {
+        if let Some(buffer) = tile.buffer.take() {
+            self.evictions.push(TileEviction {
+                id: TileId::from_index(index),
+                screen_rect: buffer.screen_pos,
+                page_rect: buffer.rect,
+                reason: reason,
+            });
+            self.unused_buffers.push(buffer);
+        }
+    }
+
+    /// Returns the tiles dropped since the last call.
+    pub fn take_evictions(&mut self) -> Vec<TileEviction> {
+        mem::replace(&mut self.evictions, Vec::new())
+    }
+
     pub fn tile_intersects_rect(&self,
                                 tile_index: &Point2D<usize>,
                                 test_rect: &Rect<f32>,
@@ -196,9 +532,9 @@ impl TileGrid {
             }
         }
 
-        for tile_index in &tile_indexes_to_take {
-            if let Some(ref mut tile) = self.tiles.remove(tile_index) {
-                self.add_unused_buffer(tile.buffer.take());
+        for tile_index in tile_indexes_to_take {
+            if let Some(tile) = self.tiles.remove(&tile_index) {
+                self.evict_tile(tile_index, tile, EvictionReason::OutOfViewport);
             }
         }
     }
@@ -206,7 +542,8 @@ impl TileGrid {
     pub fn get_buffer_request_for_tile(&mut self,
                                        tile_index: Point2D<usize>,
                                        current_layer_size: TypedSize2D<f32, DevicePixel>,
-                                       current_content_age: ContentAge)
+                                       current_content_age: ContentAge,
+                                       request_frame: u64)
                                        -> Option<BufferRequest> {
         let tile_rect = self.get_rect_for_tile_index(tile_index, current_layer_size);
         let tile = match self.tiles.entry(tile_index) {
@@ -214,19 +551,27 @@ impl TileGrid {
             Entry::Vacant(vacant) => vacant.insert(Tile::new()),
         };
 
+        // Misses only boost the priority of the next pass.
+        let checkerboard_misses = tile.checkerboard_misses.get();
+        tile.checkerboard_misses.set(0);
+
         if tile_rect.is_empty() {
             return None;
         }
 
-        if !tile.should_request_buffer(current_content_age) {
+        if !tile.should_request_buffer(current_content_age, tile_rect.size.to_untyped()) {
             return None;
         }
 
         tile.content_age_of_pending_buffer = Some(current_content_age);
-
-        Some(BufferRequest::new(tile_rect.to_untyped(),
-                                tile_rect.to_f32().to_untyped(),
-                                current_content_age))
+        tile.size_of_pending_buffer = Some(tile_rect.size.to_untyped());
+        tile.request_frame = Some(request_frame);
+
+        let mut request = BufferRequest::new(tile_rect.to_untyped(),
+                                             tile_rect.to_f32().to_untyped(),
+                                             current_content_age);
+        request.priority = checkerboard_misses;
+        Some(request)
     }
 
     /// Returns buffer requests inside the given dirty rect, and simultaneously throws out tiles
@@ -237,18 +582,24 @@ impl TileGrid {
                                        current_layer_size: TypedSize2D<f32, DevicePixel>,
                                        layer_world_origin: &Point2D<f32>,
                                        layer_transform: &Matrix4D<f32>,
-                                       current_content_age: ContentAge)
+                                       current_content_age: ContentAge,
+                                       request_frame: u64)
                                        -> Vec<BufferRequest> {
+        let _span = profiler::span("tile buffer requests");
         let mut buffer_requests = Vec::new();
 
-        // Get the range of tiles that can fit into the current layer size.
-        // Step through each, transform/clip them to 2d rect
-        // Check if visible against rect
-
-        let tile_size = self.tile_size.get() as f32;
-        let x_tile_count = ((current_layer_size.to_untyped().width + tile_size - 1.0) / tile_size) as usize;
-        let y_tile_count = ((current_layer_size.to_untyped().height + tile_size - 1.0) / tile_size) as usize;
+        // An empty layer, e.g. one caught in a layout transient, has no use for its tiles.
+        let (x_tile_count, y_tile_count) = self.tile_counts(current_layer_size);
+        if x_tile_count == 0 || y_tile_count == 0 {
+            let tiles: Vec<_> = self.tiles.drain().collect();
+            for (index, tile) in tiles {
+                self.evict_tile(index, tile, EvictionReason::EmptyLayer);
+            }
+            return buffer_requests;
+        }
 
+        // Step through the range of tiles that fit into the current layer size, transform and
+        // clip each to a 2d rect, and check it for visibility against the dirty rect.
         for x in 0..x_tile_count {
             for y in 0..y_tile_count {
                 let tile_index = Point2D::new(x, y);
@@ -259,7 +610,8 @@ impl TileGrid {
                                              layer_transform) {
                     if let Some(buffer) = self.get_buffer_request_for_tile(tile_index,
                                                                            current_layer_size,
-                                                                           current_content_age) {
+                                                                           current_content_age,
+                                                                           request_frame) {
                         buffer_requests.push(buffer);
                     }
                 }
@@ -271,26 +623,147 @@ impl TileGrid {
                                                   layer_transform,
                                                   current_layer_size);
 
+        // Paint tiles that were checkerboarded first. The sort is stable, so the other tiles
+        // keep their order.
+        buffer_requests.sort_by(|a, b| b.priority.cmp(&a.priority));
         buffer_requests
     }
 
-    pub fn get_tile_index_for_point(&self, point: Point2D<usize>) -> Point2D<usize> {
-        assert!(point.x % self.tile_size.get() == 0);
-        assert!(point.y % self.tile_size.get() == 0);
-        Point2D::new((point.x / self.tile_size.get()) as usize,
-                     (point.y / self.tile_size.get()) as usize)
+    /// The number of tile columns and rows needed to cover a layer of the given size. Layers
+    /// scaled by a fractional factor usually have a fractional size in device pixels, and the
+    /// last partial pixel needs a tile too.
+    pub fn tile_counts(&self, current_layer_size: TypedSize2D<f32, DevicePixel>)
+                       -> (usize, usize) {
+        let (tile_width, tile_height) =
+            (self.tile_size.width as f32, self.tile_size.height as f32);
+        let size = current_layer_size.to_untyped();
+        if !(size.width > 0.0 && size.height > 0.0) {
+            return (0, 0);
+        }
+        ((size.width / tile_width).ceil() as usize, (size.height / tile_height).ceil() as usize)
+    }
+
+    /// Returns the device rect covered by each tile of the grid.
+    pub fn tile_device_rects(&self, current_layer_size: TypedSize2D<f32, DevicePixel>)
+                             -> Vec<(Point2D<usize>, TypedRect<usize, DevicePixel>)> {
+        self.tiles.keys().map(|index| {
+            (*index, self.get_rect_for_tile_index(*index, current_layer_size))
+        }).filter(|&(_, rect)| !rect.is_empty()).collect()
     }
 
-    pub fn add_buffer(&mut self, buffer: Box<LayerBuffer>) {
-        let index = self.get_tile_index_for_point(buffer.screen_pos.origin.clone());
-        if !self.tiles.contains_key(&index) {
-            warn!("Received buffer for non-existent tile!");
-            self.add_unused_buffer(Some(buffer));
-            return;
+    /// The index of the tile whose origin is `point`, in device pixels. Returns `None` if no
+    /// tile starts there, as for buffers painted before the tile size changed.
+    pub fn get_tile_index_for_point(&self, point: Point2D<usize>) -> Option<Point2D<usize>> {
+        let (tile_width, tile_height) = (self.tile_size.width, self.tile_size.height);
+        if point.x % tile_width != 0 || point.y % tile_height != 0 {
+            return None;
         }
+        Some(Point2D::new(point.x / tile_width, point.y / tile_height))
+    }
 
-        let replaced_buffer = self.tiles.get_mut(&index).unwrap().replace_buffer(buffer);
+    /// The index of the tile that a buffer covering `rect`, in device pixels, was painted for.
+    /// Returns `None` if the tile is gone, or if the buffer was painted for a tile of another
+    /// size.
+    fn get_tile_index_for_buffer(&self, rect: &Rect<usize>) -> Option<Point2D<usize>> {
+        let index = match self.get_tile_index_for_point(rect.origin) {
+            Some(index) => index,
+            None => return None,
+        };
+        match self.tiles.get(&index) {
+            Some(tile) if tile.fits_buffer_size(rect.size, self.tile_size()) => Some(index),
+            _ => None,
+        }
+    }
+
+    /// Adds a buffer to its tile. Returns true if the buffer is now displayed by the tile.
+    pub fn add_buffer(&mut self, buffer: Box<LayerBuffer>) -> bool {
+        let index = match self.get_tile_index_for_buffer(&buffer.screen_pos) {
+            Some(index) => index,
+            None => {
+                warn!("Received buffer for non-existent tile!");
+                self.add_unused_buffer(Some(buffer));
+                return false;
+            }
+        };
+
+        let tile = self.tiles.get_mut(&index).unwrap();
+        let accepted = tile.should_use_new_buffer(&buffer);
+        let replaced_buffer = tile.replace_buffer(buffer);
         self.add_unused_buffer(replaced_buffer);
+        accepted
+    }
+
+    /// Like `add_buffer`, but hands back a buffer that no tile can use, because its tile is gone
+    /// or already shows newer content, instead of keeping it as an unused buffer. The tile
+    /// remembers `epoch` as the update the buffer was painted for.
+    pub fn add_buffer_or_reject(&mut self, buffer: Box<LayerBuffer>, epoch: Option<Epoch>)
+                                -> Result<(), Box<LayerBuffer>> {
+        let index = match self.get_tile_index_for_buffer(&buffer.screen_pos) {
+            Some(index) => index,
+            None => return Err(buffer),
+        };
+        let replaced_buffer = {
+            let tile = match self.tiles.get_mut(&index) {
+                Some(tile) => tile,
+                None => return Err(buffer),
+            };
+            if !tile.should_use_new_buffer(&buffer) {
+                tile.end_request(buffer.content_age);
+                return Err(buffer);
+            }
+            let replaced_buffer = tile.replace_buffer(buffer);
+            tile.epoch = epoch;
+            replaced_buffer
+        };
+        self.add_unused_buffer(replaced_buffer);
+        Ok(())
+    }
+
+    /// Ends the request of the tile a buffer was painted for, when the buffer is rejected
+    /// without being offered to its tile, e.g. because it was painted for an older update of the
+    /// layer tree.
+    pub fn reject_buffer(&mut self, buffer: &LayerBuffer) {
+        self.cancel_buffer_request(&buffer.screen_pos, buffer.content_age);
+    }
+
+    /// Ends the request for the tile covering `rect`, in device pixels, for content of the given
+    /// age, as when the painter drops it. Requests for tiles that are gone, or that were made
+    /// for another tile size, are ignored.
+    pub fn cancel_buffer_request(&mut self, rect: &Rect<usize>, content_age: ContentAge) {
+        if let Some(index) = self.get_tile_index_for_buffer(rect) {
+            self.tiles.get_mut(&index).unwrap().end_request(content_age);
+        }
+    }
+
+    /// Adds a buffer whose texture was created on the background upload thread. Returns true if
+    /// the buffer is now displayed by its tile.
+    pub fn add_uploaded_buffer(&mut self,
+                               buffer: Box<LayerBuffer>,
+                               texture: Texture,
+                               fence: UploadFence)
+                               -> bool {
+        let index = match self.get_tile_index_for_buffer(&buffer.screen_pos) {
+            Some(index) => index,
+            None => {
+                warn!("Received buffer for non-existent tile!");
+                self.add_unused_buffer(Some(buffer));
+                return false;
+            }
+        };
+
+        let tile = self.tiles.get_mut(&index).unwrap();
+        let accepted = tile.should_use_new_buffer(&buffer);
+        let replaced_buffer = tile.replace_buffer_with_texture(buffer, texture, fence);
+        self.add_unused_buffer(replaced_buffer);
+        accepted
+    }
+
+    /// Returns the rects of the tiles that have no texture to draw yet.
+    pub fn missing_tile_rects(&self, current_layer_size: TypedSize2D<f32, DevicePixel>)
+                              -> Vec<TypedRect<usize, DevicePixel>> {
+        self.tiles.iter().filter(|&(_, tile)| tile.texture.is_zero()).map(|(index, _)| {
+            self.get_rect_for_tile_index(*index, current_layer_size)
+        }).filter(|rect| !rect.is_empty()).collect()
     }
 
     pub fn do_for_all_tiles<F>(&self, mut f: F) where F: FnMut(&Tile) {
@@ -299,18 +772,102 @@ impl TileGrid {
         }
     }
 
-    pub fn collect_buffers(&mut self) -> Vec<Box<LayerBuffer>> {
-        let mut collected_buffers = self.take_unused_buffers();
-        collected_buffers.extend(self.tiles.drain().flat_map(|(_, mut tile)| tile.buffer.take()));
-        collected_buffers
+    /// Like `do_for_all_tiles`, along with the index of each tile in the grid.
+    pub fn do_for_all_tiles_with_index<F>(&self, mut f: F)
+                                          where F: FnMut(&Point2D<usize>, &Tile) {
+        for (index, tile) in &self.tiles {
+            f(index, tile);
+        }
+    }
+
+    /// Drops all tiles, returning the unused buffers along with the tiles dropped since the last
+    /// call to `take_evictions`, these included.
+    pub fn collect_buffers(&mut self) -> (Vec<Box<LayerBuffer>>, Vec<TileEviction>) {
+        let tiles: Vec<_> = self.tiles.drain().collect();
+        for (index, tile) in tiles {
+            self.evict_tile(index, tile, EvictionReason::Discarded);
+        }
+        (self.take_unused_buffers(), self.take_evictions())
     }
 
     pub fn create_textures(&mut self, display: &NativeDisplay) {
         for (_, ref mut tile) in &mut self.tiles {
+            tile.sync_background_upload();
             tile.create_texture(display);
         }
     }
 
+    /// Forgets the textures of all tiles after the GL context was lost. Tiles that still have a
+    /// buffer get a new texture on the next upload.
+    pub fn forget_textures(&mut self) {
+        for (_, ref mut tile) in &mut self.tiles {
+            tile.forget_texture();
+        }
+    }
+
+    /// Creates textures for tiles accepted by `filter`, as long as the upload budget allows.
+    /// Tiles that don't fit in the budget keep their buffer and are uploaded on a later call.
+    pub fn create_textures_with_budget<F>(&mut self,
+                                          display: &NativeDisplay,
+                                          budget: &mut UploadBudget,
+                                          mut filter: F)
+                                          where F: FnMut(&Tile) -> bool {
+        for (_, ref mut tile) in &mut self.tiles {
+            tile.sync_background_upload();
+        }
+
+        for (_, ref mut tile) in &mut self.tiles {
+            if budget.is_exhausted() {
+                return;
+            }
+            if !tile.needs_texture() || !filter(tile) {
+                continue;
+            }
+            if budget.try_consume(tile.upload_size()) {
+                tile.create_texture(display);
+            }
+        }
+    }
+
+    /// Returns the state of a tile, or `None` if the grid has no such tile.
+    pub fn tile_info(&self,
+                     id: TileId,
+                     current_layer_size: TypedSize2D<f32, DevicePixel>,
+                     scale: ScaleFactor<f32, LayerPixel, DevicePixel>,
+                     current_content_age: ContentAge)
+                     -> Option<TileInfo> {
+        let tile = match self.tiles.get(&id.index()) {
+            Some(tile) => tile,
+            None => return None,
+        };
+        let buffer = tile.buffer();
+        Some(TileInfo {
+            id: id,
+            device_rect: self.get_rect_for_tile_index(id.index(), current_layer_size),
+            content_scale: buffer.map(|buffer| buffer.resolution),
+            content_age: buffer.map(|buffer| buffer.content_age),
+            valid: buffer.map_or(false, |buffer| {
+                buffer.is_valid(scale.get()) && buffer.content_age >= current_content_age
+            }),
+            pending: tile.content_age_of_pending_buffer.is_some(),
+            resident: !tile.texture.is_zero(),
+            memory_usage: buffer.map_or(0, |buffer| buffer.get_mem()),
+        })
+    }
+
+    /// Returns true if any tile is waiting for its buffer to be uploaded.
+    pub fn has_pending_uploads(&self) -> bool {
+        self.tiles.values().any(|tile| tile.needs_texture())
+    }
+
+    /// Adds the request frames of the buffers that tiles are waiting for, either to be painted
+    /// or to be uploaded, to `frames`. Requests end when their texture is created, when the
+    /// buffer painted for them is rejected, when they are cancelled, and when their tile is
+    /// dropped.
+    pub fn add_request_frames_in_flight(&self, frames: &mut Vec<u64>) {
+        frames.extend(self.tiles.values().filter_map(|tile| tile.request_frame));
+    }
+
     /// Calculate the amount of memory used by all the tiles in the
     /// tile grid. The memory may be allocated on the heap or in GPU memory.
     pub fn get_memory_usage(&self) -> usize {
@@ -0,0 +1,167 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A profiler that records compositor spans in the trace event format understood by
+//! chrome://tracing and other trace viewers.
+//!
+//! Tracing can be started from code with `start_chrome_trace`, or by setting the
+//! `LAYERS_CHROME_TRACE` environment variable to a file name and calling
+//! `start_chrome_trace_from_env`. The trace is written by `finish_chrome_trace`.
+//!
+//! Only the most recent events are kept, `MAX_TRACE_EVENTS` by default, so that long sessions
+//! don't grow the trace without limit.
+
+use profiler::{self, ProfilerCallbacks};
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Instant;
+
+/// The environment variable holding the file to write a trace to.
+pub const CHROME_TRACE_ENV_VAR: &'static str = "LAYERS_CHROME_TRACE";
+
+/// The number of events a trace keeps by default.
+pub const MAX_TRACE_EVENTS: usize = 1 << 20;
+
+struct TraceEvent {
+    name: String,
+    begin: bool,
+    timestamp_us: u64,
+}
+
+/// Records spans in memory, to be written out as JSON. Once `max_events` events are recorded,
+/// each new one replaces the oldest.
+pub struct ChromeTraceExporter {
+    start: Instant,
+    events: RefCell<VecDeque<TraceEvent>>,
+    max_events: usize,
+}
+
+impl ChromeTraceExporter {
+    pub fn new() -> ChromeTraceExporter {
+        ChromeTraceExporter::with_max_events(MAX_TRACE_EVENTS)
+    }
+
+    pub fn with_max_events(max_events: usize) -> ChromeTraceExporter {
+        ChromeTraceExporter {
+            start: Instant::now(),
+            events: RefCell::new(VecDeque::new()),
+            max_events: max_events,
+        }
+    }
+
+    fn record(&self, name: &str, begin: bool) {
+        let elapsed = self.start.elapsed();
+        let timestamp_us = elapsed.as_secs() * 1000000 + (elapsed.subsec_nanos() / 1000) as u64;
+        let mut events = self.events.borrow_mut();
+        if events.len() >= self.max_events {
+            events.pop_front();
+        }
+        events.push_back(TraceEvent {
+            name: name.to_owned(),
+            begin: begin,
+            timestamp_us: timestamp_us,
+        });
+    }
+
+    /// Writes the recorded spans as a JSON trace. The ends of spans whose beginnings were
+    /// replaced by newer events are left out.
+    pub fn write_json<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        try!(write!(writer, "{{\"traceEvents\":["));
+        let mut open_spans = 0;
+        let mut written = 0;
+        for event in self.events.borrow().iter() {
+            if event.begin {
+                open_spans += 1;
+            } else if open_spans == 0 {
+                continue;
+            } else {
+                open_spans -= 1;
+            }
+            if written > 0 {
+                try!(write!(writer, ","));
+            }
+            written += 1;
+            try!(write!(writer,
+                        "\n{{\"name\":\"{}\",\"cat\":\"layers\",\"ph\":\"{}\",\"ts\":{},\
+                         \"pid\":0,\"tid\":0}}",
+                        escape_json(&event.name),
+                        if event.begin { "B" } else { "E" },
+                        event.timestamp_us));
+        }
+        write!(writer, "\n],\"displayTimeUnit\":\"ms\"}}\n")
+    }
+}
+
+impl ProfilerCallbacks for ChromeTraceExporter {
+    fn begin_span(&self, name: &str) {
+        self.record(name, true);
+    }
+
+    fn end_span(&self, name: &str) {
+        self.record(name, false);
+    }
+}
+
+fn escape_json(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for character in string.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            character if (character as u32) < 0x20 => {
+                escaped.push_str(&format!("\\u{:04x}", character as u32))
+            }
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+thread_local!(static ACTIVE_TRACE: RefCell<Option<(Rc<ChromeTraceExporter>, PathBuf)>> =
+              RefCell::new(None));
+
+/// Starts recording the spans of the current thread, replacing any registered profiler.
+pub fn start_chrome_trace<P: AsRef<Path>>(path: P) {
+    let exporter = Rc::new(ChromeTraceExporter::new());
+    profiler::set_profiler_callbacks(Some(exporter.clone()));
+    ACTIVE_TRACE.with(|trace| *trace.borrow_mut() = Some((exporter, path.as_ref().to_owned())));
+}
+
+/// Starts recording if the `LAYERS_CHROME_TRACE` environment variable is set. Returns true if
+/// tracing was started.
+pub fn start_chrome_trace_from_env() -> bool {
+    match env::var_os(CHROME_TRACE_ENV_VAR) {
+        Some(path) => {
+            start_chrome_trace(path);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Stops recording and writes the trace started on the current thread, if any.
+pub fn finish_chrome_trace() -> io::Result<()> {
+    let trace = ACTIVE_TRACE.with(|trace| trace.borrow_mut().take());
+    let (exporter, path) = match trace {
+        Some(trace) => trace,
+        None => return Ok(()),
+    };
+
+    profiler::set_profiler_callbacks(None);
+    let mut writer = BufWriter::new(try!(File::create(&path)));
+    try!(exporter.write_json(&mut writer));
+    writer.flush()
+}
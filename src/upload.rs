@@ -0,0 +1,224 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Scheduling of texture uploads across frames, and uploading on a background thread that owns
+//! a GL context shared with the compositor.
+
+use capabilities::GlCapabilities;
+use layers::LayerBuffer;
+use platform::surface::NativeDisplay;
+use texturegl::Texture;
+
+use glcapture as gl;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+
+/// Tracks how many texture bytes have been uploaded during the current frame, so that a burst of
+/// newly painted tiles can be spread over several frames instead of stalling a single one.
+pub struct UploadBudget {
+    /// The maximum number of bytes to upload in one frame, or `None` for no limit.
+    max_bytes: Option<usize>,
+
+    /// The number of bytes uploaded so far in this frame.
+    uploaded_bytes: usize,
+
+    /// The number of uploads performed so far in this frame.
+    uploaded_count: usize,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, UploadBudget);
+
+impl UploadBudget {
+    pub fn new(max_bytes: Option<usize>) -> UploadBudget {
+        UploadBudget {
+            max_bytes: max_bytes,
+            uploaded_bytes: 0,
+            uploaded_count: 0,
+        }
+    }
+
+    /// Returns true and charges the budget if an upload of the given size fits in what remains
+    /// of this frame. The first upload of a frame is always allowed, so that a single tile larger
+    /// than the budget cannot be starved forever.
+    pub fn try_consume(&mut self, bytes: usize) -> bool {
+        if let Some(max_bytes) = self.max_bytes {
+            if self.uploaded_count > 0 && self.uploaded_bytes + bytes > max_bytes {
+                return false;
+            }
+        }
+
+        self.uploaded_bytes += bytes;
+        self.uploaded_count += 1;
+        true
+    }
+
+    /// Returns true if no further uploads can happen this frame.
+    pub fn is_exhausted(&self) -> bool {
+        match self.max_bytes {
+            Some(max_bytes) => self.uploaded_count > 0 && self.uploaded_bytes >= max_bytes,
+            None => false,
+        }
+    }
+
+    /// The number of bytes uploaded so far in this frame.
+    pub fn uploaded_bytes(&self) -> usize {
+        self.uploaded_bytes
+    }
+}
+
+/// A GL fence inserted after a texture upload. The compositor must wait on it before sampling
+/// the texture, since the upload happened on another context.
+pub struct UploadFence {
+    /// The fence, or `None` if the GL implementation has no sync objects, in which case the
+    /// upload was finished before the fence was created.
+    sync: Option<gl::GLsync>,
+}
+
+unsafe impl Send for UploadFence {}
+
+impl Drop for UploadFence {
+    fn drop(&mut self) {
+        if let Some(sync) = self.sync {
+            unsafe {
+                gl::DeleteSync(sync);
+            }
+        }
+    }
+}
+
+impl UploadFence {
+    /// Inserts a fence into the command stream of the current context and flushes it, so that
+    /// other contexts in the share group can wait on it. Without sync objects, this blocks
+    /// until the commands issued so far have completed.
+    pub fn new() -> UploadFence {
+        if !GlCapabilities::current().sync_objects {
+            gl::finish();
+            return UploadFence {
+                sync: None,
+            };
+        }
+        let sync = unsafe {
+            gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0)
+        };
+        gl::flush();
+        UploadFence {
+            sync: Some(sync),
+        }
+    }
+
+    /// Makes the current context wait for the upload on the GPU. This does not block the
+    /// calling thread.
+    pub fn wait_on_gpu(&self) {
+        if let Some(sync) = self.sync {
+            unsafe {
+                gl::WaitSync(sync, 0, gl::TIMEOUT_IGNORED);
+            }
+        }
+    }
+}
+
+/// A buffer whose surface was bound to a texture on the background upload thread.
+pub struct UploadedBuffer<K> {
+    /// The key the embedder passed along with the buffer, typically identifying its layer.
+    pub key: K,
+
+    /// The uploaded buffer.
+    pub buffer: Box<LayerBuffer>,
+
+    /// The texture the buffer's surface is bound to.
+    pub texture: Texture,
+
+    /// The fence signalled when the upload completes.
+    pub fence: UploadFence,
+}
+
+enum UploadMsg<K> {
+    Upload(K, Box<LayerBuffer>),
+    Exit,
+}
+
+/// Performs texture uploads on a separate thread with its own GL context, so that large uploads
+/// never block the composite loop. The context must be in the same share group as the
+/// compositor's context; creating it is up to the embedder.
+pub struct BackgroundUploader<K> {
+    sender: Sender<UploadMsg<K>>,
+    receiver: Receiver<UploadedBuffer<K>>,
+
+    /// The upload thread, joined when the uploader is dropped.
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<K: Send + 'static> BackgroundUploader<K> {
+    /// Spawns the upload thread. `make_context_current` is run on the new thread before any
+    /// upload and must make the shared context current there.
+    pub fn spawn<F>(display: NativeDisplay, make_context_current: F) -> BackgroundUploader<K>
+                    where F: FnOnce() + Send + 'static {
+        let (request_sender, request_receiver) = channel();
+        let (result_sender, result_receiver) = channel();
+
+        let builder = thread::Builder::new().name("BackgroundUploader".to_owned());
+        let upload_thread = builder.spawn(move || {
+            make_context_current();
+
+            while let Ok(UploadMsg::Upload(key, buffer)) = request_receiver.recv() {
+                let texture = Texture::new_with_buffer(&buffer);
+                buffer.native_surface.bind_to_texture(&display, &texture);
+                let uploaded = UploadedBuffer {
+                    key: key,
+                    buffer: buffer,
+                    texture: texture,
+                    fence: UploadFence::new(),
+                };
+                if result_sender.send(uploaded).is_err() {
+                    break;
+                }
+            }
+        }).expect("Failed to spawn the background upload thread");
+
+        BackgroundUploader {
+            sender: request_sender,
+            receiver: result_receiver,
+            thread: Some(upload_thread),
+        }
+    }
+
+    /// Queues a buffer for upload.
+    pub fn upload(&self, key: K, buffer: Box<LayerBuffer>) {
+        if self.sender.send(UploadMsg::Upload(key, buffer)).is_err() {
+            warn!("The background upload thread has exited.");
+        }
+    }
+
+    /// Returns the next finished upload, if any, without blocking. The result should be handed
+    /// to `Layer::add_uploaded_buffer`.
+    pub fn try_recv(&self) -> Option<UploadedBuffer<K>> {
+        match self.receiver.try_recv() {
+            Ok(uploaded_buffer) => Some(uploaded_buffer),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                warn!("The background upload thread has exited.");
+                None
+            }
+        }
+    }
+}
+
+impl<K> Drop for BackgroundUploader<K> {
+    /// Stops the upload thread once it has uploaded the buffers queued so far, and waits for
+    /// it, so that its GL context is released before the embedder tears down the share group.
+    fn drop(&mut self) {
+        let _ = self.sender.send(UploadMsg::Exit);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("The background upload thread panicked.");
+            }
+        }
+    }
+}
@@ -0,0 +1,278 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Choosing the upload method by measuring the candidates on the driver at hand.
+//!
+//! Whether `glTexImage2D`, `glTexSubImage2D` or a pixel buffer object copies tiles faster
+//! varies wildly across GPUs and drivers, so instead of guessing, `select_upload_method` times
+//! each supported `UploadMethod` by uploading tile-sized textures, and remembers the fastest one
+//! in a file keyed by the vendor, renderer and version strings of the driver. Later runs on the
+//! same driver read the choice back instead of measuring again, and a driver update measures
+//! anew. Pixel buffer objects are among the candidates wherever the context supports them.
+//!
+//! Buffers that the platform binds to textures without copying, such as IOSurfaces and GLX
+//! pixmaps, aren't uploaded with the chosen method. `select_upload_path` also times filling and
+//! binding those, and falls back to shared memory buffers where they lose to the fastest
+//! upload method.
+
+use glcapture as gl;
+use platform::surface::{NativeDisplay, NativeSurface, NativeSurfaceKind};
+use texturegl::{Texture, UploadMethod};
+use texturegl::TextureTarget::TextureTarget2D;
+
+use euclid::size::Size2D;
+use rustc_serialize::json;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// The size of the textures uploaded by the benchmark, that of a typical tile.
+const BENCHMARK_TEXTURE_SIZE: usize = 256;
+
+/// The number of textures uploaded with each method.
+const BENCHMARK_ITERATIONS: usize = 16;
+
+/// The time one method took to upload the benchmark textures.
+#[derive(Copy, Clone, Debug)]
+pub struct UploadTiming {
+    pub method: UploadMethod,
+    pub duration: Duration,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, UploadTiming);
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct SavedChoice {
+    driver: String,
+    method: UploadMethod,
+
+    /// Whether native surfaces were faster than the method, if `select_upload_path` compared
+    /// them.
+    native_surfaces: Option<bool>,
+}
+
+/// Identifies the driver of the GL context current on this thread.
+fn driver_description() -> String {
+    format!("{} / {} / {}",
+            gl::get_string(gl::VENDOR),
+            gl::get_string(gl::RENDERER),
+            gl::get_string(gl::VERSION))
+}
+
+/// Times every upload method with the GL context current on this thread, in the order of
+/// `UploadMethod::all`. This takes some tens of milliseconds, and stalls the GPU.
+pub fn benchmark_upload_methods() -> Vec<UploadTiming> {
+    let size = Size2D::new(BENCHMARK_TEXTURE_SIZE, BENCHMARK_TEXTURE_SIZE);
+    let pixels = vec![0x80; BENCHMARK_TEXTURE_SIZE * BENCHMARK_TEXTURE_SIZE * 4];
+    UploadMethod::all().iter().map(|&method| {
+        // The first upload with a method may take a slow path while the driver sets up.
+        Texture::new(TextureTarget2D, size).upload_bgra_with_method(method, size, &pixels);
+        gl::finish();
+
+        let start = Instant::now();
+        for _ in 0..BENCHMARK_ITERATIONS {
+            Texture::new(TextureTarget2D, size).upload_bgra_with_method(method, size, &pixels);
+        }
+        gl::finish();
+        let timing = UploadTiming {
+            method: method,
+            duration: start.elapsed(),
+        };
+        debug!("Uploading {} textures with {:?} took {:?}",
+               BENCHMARK_ITERATIONS,
+               method,
+               timing.duration);
+        timing
+    }).collect()
+}
+
+/// Times filling native surfaces of the kind active for `display` and binding them to
+/// textures, which is what painting into them saves uploading. Returns `None` if the display
+/// only has shared memory buffers, which are uploaded with the upload method, or if the
+/// surfaces couldn't be created.
+pub fn benchmark_surface_binding(display: &NativeDisplay) -> Option<Duration> {
+    if NativeSurfaceKind::active(display) == NativeSurfaceKind::MemoryBuffer {
+        return None;
+    }
+    let size = Size2D::new(BENCHMARK_TEXTURE_SIZE, BENCHMARK_TEXTURE_SIZE);
+    let pixels = vec![0x80; BENCHMARK_TEXTURE_SIZE * BENCHMARK_TEXTURE_SIZE * 4];
+    let mut surfaces = vec!();
+    for _ in 0..(BENCHMARK_ITERATIONS + 1) {
+        match NativeSurface::new(display, Size2D::new(size.width as i32, size.height as i32)) {
+            Ok(surface) => surfaces.push(surface),
+            Err(error) => {
+                warn!("Could not create a surface to benchmark: {}", error);
+                for surface in &mut surfaces {
+                    surface.destroy(display);
+                }
+                return None;
+            }
+        }
+    }
+
+    let (_, target) = Texture::texture_orientation_and_target(false);
+    let mut bind = |surface: &mut NativeSurface| {
+        surface.upload(display, &pixels);
+        surface.bind_to_texture(display, &Texture::new(target, size));
+    };
+    // As with uploads, the first bind may take a slow path while the driver sets up.
+    bind(&mut surfaces[0]);
+    gl::finish();
+
+    let start = Instant::now();
+    for surface in &mut surfaces[1..] {
+        bind(surface);
+    }
+    gl::finish();
+    let duration = start.elapsed();
+    debug!("Filling and binding {} surfaces took {:?}", BENCHMARK_ITERATIONS, duration);
+
+    for surface in &mut surfaces {
+        surface.destroy(display);
+    }
+    Some(duration)
+}
+
+/// The fastest method among `timings`, preferring earlier ones on ties.
+pub fn fastest_method(timings: &[UploadTiming]) -> UploadMethod {
+    timings.iter()
+           .fold(None, |fastest: Option<&UploadTiming>, timing| {
+               match fastest {
+                   Some(fastest) if fastest.duration <= timing.duration => Some(fastest),
+                   _ => Some(timing),
+               }
+           })
+           .map_or(UploadMethod::TexImage, |fastest| fastest.method)
+}
+
+/// Reads the choice saved for `driver`. Returns `None` if it was saved for another driver.
+fn load_choice(path: &Path, driver: &str) -> io::Result<Option<SavedChoice>> {
+    let mut encoded = String::new();
+    try!(try!(File::open(path)).read_to_string(&mut encoded));
+    let saved: SavedChoice = try!(json::decode(&encoded).map_err(|error| {
+        io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+    }));
+    if saved.driver != driver {
+        return Ok(None);
+    }
+    Ok(Some(saved))
+}
+
+fn save_choice(path: &Path, choice: &SavedChoice) -> io::Result<()> {
+    let encoded = try!(json::encode(choice).map_err(|error| {
+        io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+    }));
+    let mut file = try!(File::create(path));
+    try!(file.write_all(encoded.as_bytes()));
+    file.flush()
+}
+
+/// Reads the method saved by `save_upload_method_for_driver` for the driver described by
+/// `driver`. Returns `None` if it was saved for another driver.
+pub fn load_upload_method_for_driver(path: &Path, driver: &str)
+                                     -> io::Result<Option<UploadMethod>> {
+    Ok(try!(load_choice(path, driver)).map(|saved| saved.method))
+}
+
+/// Saves the method to use with the driver described by `driver`.
+pub fn save_upload_method_for_driver(path: &Path, driver: &str, method: UploadMethod)
+                                     -> io::Result<()> {
+    save_choice(path, &SavedChoice {
+        driver: driver.to_owned(),
+        method: method,
+        native_surfaces: None,
+    })
+}
+
+/// Reads the method saved by `save_upload_method` for the driver of the GL context current on
+/// this thread. Returns `None` if it was saved for another driver.
+pub fn load_upload_method(path: &Path) -> io::Result<Option<UploadMethod>> {
+    load_upload_method_for_driver(path, &driver_description())
+}
+
+/// Saves the method to use with the driver of the GL context current on this thread.
+pub fn save_upload_method(path: &Path, method: UploadMethod) -> io::Result<()> {
+    save_upload_method_for_driver(path, &driver_description(), method)
+}
+
+/// Makes the fastest upload method the current one for this thread. It is read from the file
+/// at `path` if it was saved there for this driver; otherwise the methods are benchmarked, and
+/// the fastest one is saved. Call it once the GL context is current, e.g. before creating the
+/// render context. Errors reading or writing the file only lose the saved choice.
+pub fn select_upload_method(path: &Path) -> UploadMethod {
+    let method = match load_upload_method(path) {
+        Ok(Some(method)) => method,
+        result => {
+            if let Err(error) = result {
+                if error.kind() != io::ErrorKind::NotFound {
+                    warn!("Could not read the saved upload method: {}", error);
+                }
+            }
+            let method = fastest_method(&benchmark_upload_methods());
+            if let Err(error) = save_upload_method(path, method) {
+                warn!("Could not save the upload method: {}", error);
+            }
+            method
+        }
+    };
+    info!("Uploading textures using {:?}", method);
+    UploadMethod::set_current(method);
+    method
+}
+
+/// Like `select_upload_method`, but also decides whether buffers for `display` are painted
+/// into native surfaces, which are bound to textures without uploading, or into shared memory
+/// buffers uploaded with the chosen method. Native surfaces are kept unless they were measured
+/// to be slower than the fastest upload method; the choice is saved along with the method.
+pub fn select_upload_path(path: &Path, display: &mut NativeDisplay) -> UploadMethod {
+    let driver = driver_description();
+    let choice = match load_choice(path, &driver) {
+        Ok(Some(ref saved)) if saved.native_surfaces.is_some() => {
+            SavedChoice {
+                driver: driver,
+                method: saved.method,
+                native_surfaces: saved.native_surfaces,
+            }
+        }
+        result => {
+            if let Err(error) = result {
+                if error.kind() != io::ErrorKind::NotFound {
+                    warn!("Could not read the saved upload path: {}", error);
+                }
+            }
+            let timings = benchmark_upload_methods();
+            let method = fastest_method(&timings);
+            let upload_duration = timings.iter()
+                                         .find(|timing| timing.method == method)
+                                         .map(|timing| timing.duration);
+            let native_surfaces = match (benchmark_surface_binding(display), upload_duration) {
+                (Some(bind_duration), Some(upload_duration)) => bind_duration <= upload_duration,
+                _ => true,
+            };
+            let choice = SavedChoice {
+                driver: driver,
+                method: method,
+                native_surfaces: Some(native_surfaces),
+            };
+            if let Err(error) = save_choice(path, &choice) {
+                warn!("Could not save the upload path: {}", error);
+            }
+            choice
+        }
+    };
+    if choice.native_surfaces == Some(false) {
+        info!("Painting into shared memory buffers, which upload faster than native surfaces");
+        NativeSurfaceKind::set_active(display, Some(NativeSurfaceKind::MemoryBuffer));
+    }
+    info!("Uploading textures using {:?}", choice.method);
+    UploadMethod::set_current(choice.method);
+    choice.method
+}
@@ -46,6 +46,74 @@ pub fn convert_rgb32_to_rgb24(buffer: &[u8]) -> Vec<u8> {
     }).collect()
 }
 
+/// Returns true if the transform can't be represented as a 2d matrix.
+pub fn is_3d_transform(m: &Matrix4D<f32>) -> bool {
+    // See https://drafts.csswg.org/css-transforms/#2d-matrix
+    m.m31 != 0.0 || m.m32 != 0.0 ||
+    m.m13 != 0.0 || m.m23 != 0.0 ||
+    m.m43 != 0.0 || m.m14 != 0.0 ||
+    m.m24 != 0.0 || m.m34 != 0.0 ||
+    m.m33 != 1.0 || m.m44 != 1.0
+}
+
+/// The simplest class of matrix a transform belongs to, from the cheapest to draw to the most
+/// expensive. Each class includes the ones before it, so e.g. `kind <= ScaleTranslation` means
+/// that the transform keeps rects axis-aligned.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TransformKind {
+    Identity,
+    /// A translation by whole pixels.
+    IntegerTranslation,
+    /// A scale, possibly negative, followed by a translation.
+    ScaleTranslation,
+    /// Any other 2d transform, e.g. a rotation or a skew.
+    General2D,
+    /// A transform that can't be represented as a 2d matrix.
+    ThreeD,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, TransformKind);
+
+impl TransformKind {
+    pub fn is_axis_aligned(&self) -> bool {
+        *self <= TransformKind::ScaleTranslation
+    }
+
+    pub fn is_3d(&self) -> bool {
+        *self == TransformKind::ThreeD
+    }
+}
+
+/// Classifies a transform. Values within a small tolerance of those of a simpler class are
+/// treated as belonging to it.
+pub fn classify_transform(m: &Matrix4D<f32>) -> TransformKind {
+    const EPSILON: f32 = 1.0e-5;
+    if is_3d_transform(m) {
+        return TransformKind::ThreeD;
+    }
+    if m.m12.abs() > EPSILON || m.m21.abs() > EPSILON {
+        return TransformKind::General2D;
+    }
+    if (m.m11 - 1.0).abs() > EPSILON || (m.m22 - 1.0).abs() > EPSILON {
+        return TransformKind::ScaleTranslation;
+    }
+    let is_integer = |value: f32| (value - value.round()).abs() <= EPSILON;
+    if !is_integer(m.m41) || !is_integer(m.m42) {
+        return TransformKind::ScaleTranslation;
+    }
+    if m.m41.abs() <= EPSILON && m.m42.abs() <= EPSILON {
+        TransformKind::Identity
+    } else {
+        TransformKind::IntegerTranslation
+    }
+}
+
+/// The lengths that the unit vectors along x and y have once transformed, ignoring perspective.
+pub fn transform_scale(m: &Matrix4D<f32>) -> (f32, f32) {
+    ((m.m11 * m.m11 + m.m12 * m.m12).sqrt(), (m.m21 * m.m21 + m.m22 * m.m22).sqrt())
+}
+
 // Sutherland-Hodgman clipping algorithm
 fn clip_polygon_to_near_plane(clip_space_vertices: &[Point4D<f32>; 4])
                                   -> Option<Vec<Point4D<f32>>> {
@@ -164,3 +232,28 @@ pub fn project_rect_to_screen(rect: &Rect<f32>,
 
     result
 }
+
+/// Finds the point in the plane of a layer that `transform` projects to the given screen point,
+/// i.e. the inverse of the projection done by `project_rect_to_screen` for a single point.
+/// Returns `None` if no point of the layer projects there, e.g. for layers seen edge-on, or
+/// if the point would be behind the viewer.
+pub fn unproject_point_from_screen(point: &Point2D<f32>,
+                                   transform: &Matrix4D<f32>) -> Option<Point2D<f32>> {
+    // Layer points have z = 0, so the projection of (x, y) is linear in x and y before the
+    // perspective division, and undoing it amounts to solving a 2x2 linear system.
+    let (a, b) = (transform.m11 - point.x * transform.m14, transform.m21 - point.x * transform.m24);
+    let (c, d) = (transform.m12 - point.y * transform.m14, transform.m22 - point.y * transform.m24);
+    let (e, f) = (point.x * transform.m44 - transform.m41, point.y * transform.m44 - transform.m42);
+    let determinant = a * d - b * c;
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let x = (e * d - b * f) / determinant;
+    let y = (a * f - e * c) / determinant;
+    let w = x * transform.m14 + y * transform.m24 + transform.m44;
+    if w <= W_CLIPPING_PLANE {
+        return None;
+    }
+    Some(Point2D::new(x, y))
+}
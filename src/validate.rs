@@ -0,0 +1,234 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Consistency checks of the layer tree, for catching embedder and compositor bugs close to
+//! where they happen rather than as rendering glitches frames later.
+//!
+//! When `Scene::validation_mode` is set, debug builds validate the tree at the start of every
+//! composite. The checks assume that the embedder updates the transform state of the root layer
+//! with identity parent transforms.
+
+use euclid::{Matrix4D, Point2D, Rect};
+use euclid::point::TypedPoint2D;
+use geometry::LayerPixel;
+use layers::{Layer, TransformState};
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+use tiling::Tile;
+
+/// The tolerance when comparing the stored transform state with a freshly computed one.
+const TRANSFORM_EPSILON: f32 = 0.001;
+
+/// What to do when the layer tree is found to be inconsistent.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ValidationMode {
+    /// Don't validate the tree. This is the default.
+    Disabled,
+    /// Log every problem found as an error.
+    Log,
+    /// Panic on the first frame with problems, listing all of them.
+    Panic,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, ValidationMode);
+
+/// A problem found in the layer tree.
+#[derive(Clone, Debug)]
+pub struct ValidationError {
+    /// The path from the root to the offending layer, as the child indices and debug names of
+    /// the layers along it.
+    pub path: String,
+
+    /// What is wrong with the layer.
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}: {}", self.path, self.message)
+    }
+}
+
+/// The state a layer inherits from its parent, as in `Layer::update_transform_state`.
+struct Inherited {
+    path: String,
+    transform: Matrix4D<f32>,
+    perspective: Matrix4D<f32>,
+    origin: Point2D<f32>,
+
+    /// The world rect of the parent, if it masks its children to its bounds.
+    clip_rect: Option<Rect<f32>>,
+
+    /// The content offset the transform state of the layer was updated with, if not its own.
+    content_offset: Option<TypedPoint2D<f32, LayerPixel>>,
+}
+
+fn matrices_match(a: &Matrix4D<f32>, b: &Matrix4D<f32>) -> bool {
+    a.to_row_major_array().iter().zip(b.to_row_major_array().iter()).all(|(a, b)| {
+        (a - b).abs() <= TRANSFORM_EPSILON * a.abs().max(1.0)
+    })
+}
+
+fn rects_match(a: &Rect<f32>, b: &Rect<f32>) -> bool {
+    [(a.origin.x, b.origin.x), (a.origin.y, b.origin.y),
+     (a.size.width, b.size.width), (a.size.height, b.size.height)].iter().all(|&(a, b)| {
+        (a - b).abs() <= TRANSFORM_EPSILON * a.abs().max(1.0)
+    })
+}
+
+/// Checks the invariants of the tree rooted at `root`, returning the problems found:
+///
+/// * Every layer appears in the tree once, so there are no cycles.
+/// * Bounds are finite and not negative in size.
+/// * Transforms are finite. Singular ones, such as a scale of zero, are legitimate.
+/// * The stored transform state matches the layer properties.
+/// * Tiles only hold textures for the buffers they display.
+///
+/// Layers entirely clipped out by their parent are legitimate, e.g. while scrolled out of view,
+/// and are only logged at debug level.
+pub fn validate_tree<T>(root: &Rc<Layer<T>>) -> Vec<ValidationError> {
+    let content_offset = *root.content_offset.borrow();
+    validate_tree_with_offset(root, &content_offset)
+}
+
+/// Like `validate_tree`, for a tree whose transform state was updated with `content_offset` in
+/// place of the content offset of the root, as with `Layer::update_transform_state_with_offset`.
+pub fn validate_tree_with_offset<T>(root: &Rc<Layer<T>>,
+                                    content_offset: &TypedPoint2D<f32, LayerPixel>)
+                                    -> Vec<ValidationError> {
+    let mut errors = vec!();
+    let mut visited = HashSet::new();
+    let mut stack = vec!((root.clone(), Inherited {
+        path: root.debug_name(),
+        transform: Matrix4D::identity(),
+        perspective: Matrix4D::identity(),
+        origin: Point2D::zero(),
+        clip_rect: None,
+        content_offset: Some(*content_offset),
+    }));
+
+    while let Some((layer, inherited)) = stack.pop() {
+        let mut error = |message: String| {
+            errors.push(ValidationError {
+                path: inherited.path.clone(),
+                message: message,
+            });
+        };
+
+        if !visited.insert(&*layer as *const Layer<T>) {
+            error("the layer appears more than once in the tree".to_owned());
+            continue;
+        }
+
+        let bounds = layer.bounds.borrow().to_untyped();
+        let values = [bounds.origin.x, bounds.origin.y, bounds.size.width, bounds.size.height];
+        if values.iter().any(|value| !value.is_finite()) {
+            error(format!("the bounds {:?} are not finite", bounds));
+        } else if bounds.size.width < 0.0 || bounds.size.height < 0.0 {
+            error(format!("the bounds {:?} have a negative size", bounds));
+        }
+
+        for &(name, ref matrix) in &[("transform", *layer.transform.borrow()),
+                                     ("perspective", *layer.perspective.borrow())] {
+            if matrix.to_row_major_array().iter().any(|value| !value.is_finite()) {
+                error(format!("the {} {:?} is not finite", name, matrix));
+            }
+        }
+
+        let content_offset =
+            inherited.content_offset.unwrap_or_else(|| *layer.content_offset.borrow());
+        let (expected, perspective, origin) =
+            layer.compute_transform_state_with_offset(&inherited.transform,
+                                                      &inherited.perspective,
+                                                      &inherited.origin,
+                                                      &content_offset);
+        check_transform_state(&layer.transform_state.borrow(), &expected, &mut error);
+
+        layer.do_for_all_tiles(|tile: &Tile| {
+            if !tile.texture.is_zero() && tile.buffer().is_none() {
+                error(format!("a tile at {:?} has a texture but no buffer", tile.bounds));
+            }
+        });
+
+        if let Some(clip_rect) = inherited.clip_rect {
+            if !expected.world_rect.is_empty() &&
+                    clip_rect.intersection(&expected.world_rect).is_none() {
+                debug!(target: "layers::validate",
+                       "{}: the layer at {:?} is entirely outside of its parent's clip {:?}",
+                       inherited.path,
+                       expected.world_rect,
+                       clip_rect);
+            }
+        }
+
+        let clip_rect = if *layer.masks_to_bounds.borrow() {
+            Some(expected.world_rect)
+        } else {
+            inherited.clip_rect
+        };
+        for (index, child) in layer.children().iter().enumerate().rev() {
+            stack.push((child.clone(), Inherited {
+                path: format!("{} > {}: {}", inherited.path, index, child.debug_name()),
+                transform: expected.final_transform,
+                perspective: perspective,
+                origin: origin,
+                clip_rect: clip_rect,
+                content_offset: None,
+            }));
+        }
+    }
+    errors
+}
+
+fn check_transform_state<F>(actual: &TransformState, expected: &TransformState, error: &mut F)
+                            where F: FnMut(String) {
+    if !rects_match(&actual.world_rect, &expected.world_rect) {
+        error(format!("the world rect is {:?}, but the layer properties give {:?}",
+                      actual.world_rect,
+                      expected.world_rect));
+    }
+    if !matrices_match(&actual.final_transform, &expected.final_transform) {
+        error(format!("the final transform is {:?}, but the layer properties give {:?}",
+                      actual.final_transform,
+                      expected.final_transform));
+    }
+    if actual.screen_rect.is_some() != expected.screen_rect.is_some() {
+        error(format!("the layer is {} on screen, but the layer properties say otherwise",
+                      if actual.screen_rect.is_some() { "visible" } else { "not visible" }));
+    }
+}
+
+/// Validates the tree, whose transform state was updated with `content_offset` as the content
+/// offset of the root, and handles the problems found according to `mode`.
+pub fn validate_scene<T>(root: &Rc<Layer<T>>,
+                         content_offset: &TypedPoint2D<f32, LayerPixel>,
+                         mode: ValidationMode) {
+    if mode == ValidationMode::Disabled {
+        return;
+    }
+
+    let errors = validate_tree_with_offset(root, content_offset);
+    if errors.is_empty() {
+        return;
+    }
+    match mode {
+        ValidationMode::Disabled => {}
+        ValidationMode::Log => {
+            for error in &errors {
+                error!("Invalid layer tree: {}", error);
+            }
+        }
+        ValidationMode::Panic => {
+            let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+            panic!("Invalid layer tree:\n{}", messages.join("\n"));
+        }
+    }
+}
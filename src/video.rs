@@ -0,0 +1,141 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Decoded video frames shown by layers, in the planar YUV formats video decoders output.
+//!
+//! The planes of a frame are kept in textures as they come out of the decoder, and converted to
+//! RGB by the compositor's shaders as the frame is drawn, so that no CPU pass is needed. A layer
+//! showing a frame draws it stretched over its bounds, instead of painted tiles.
+
+use texturegl::Texture;
+
+use euclid::Matrix4D;
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+
+/// The ID of the next video frame created.
+static NEXT_FRAME_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// How the planes of a frame are laid out.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum YuvFormat {
+    /// Three planes: Y at full resolution, then U and V, typically at half resolution. Each
+    /// plane is a single-channel texture, e.g. `GL_LUMINANCE` or `GL_R8`, read from its red
+    /// channel.
+    I420,
+    /// Two planes: Y at full resolution, then U and V interleaved, typically at half
+    /// resolution. The second plane is a two-channel texture with U in its red channel and V in
+    /// its green channel, e.g. `GL_RG8`.
+    NV12,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, YuvFormat);
+
+impl YuvFormat {
+    /// The number of planes of a frame in this format.
+    pub fn plane_count(self) -> usize {
+        match self {
+            YuvFormat::I420 => 3,
+            YuvFormat::NV12 => 2,
+        }
+    }
+}
+
+/// The standard color spaces of video, each with its conversion to RGB.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum YuvColorSpace {
+    /// ITU-R BT.601, used by standard definition video.
+    Rec601,
+    /// ITU-R BT.709, used by high definition video.
+    Rec709,
+}
+
+#[cfg(feature = "heapsize")]
+known_heap_size!(0, YuvColorSpace);
+
+impl YuvColorSpace {
+    /// The matrix converting (Y, U, V, 1) to RGB, for video range samples, which go from 16 to
+    /// 235 for luma and from 16 to 240 for chroma.
+    pub fn color_matrix(self) -> Matrix4D<f32> {
+        match self {
+            YuvColorSpace::Rec601 => video_range_color_matrix(0.299, 0.114),
+            YuvColorSpace::Rec709 => video_range_color_matrix(0.2126, 0.0722),
+        }
+    }
+}
+
+/// The matrix converting video range YUV to RGB for the given luma coefficients of red and
+/// blue. Vectors are transformed as rows, like points by euclid: the first three rows hold the
+/// contributions of Y, U and V to red, green and blue, and the last row the offsets.
+fn video_range_color_matrix(kr: f32, kb: f32) -> Matrix4D<f32> {
+    let kg = 1.0 - kr - kb;
+    let luma_scale = 255.0 / 219.0;
+    let chroma_scale = 255.0 / 224.0;
+    // Chroma is neutral at 128, as the samples are read normalized by 255.
+    let (luma_offset, chroma_offset) = (16.0 / 255.0, 128.0 / 255.0);
+
+    let v_to_r = chroma_scale * 2.0 * (1.0 - kr);
+    let u_to_b = chroma_scale * 2.0 * (1.0 - kb);
+    let u_to_g = -chroma_scale * 2.0 * kb * (1.0 - kb) / kg;
+    let v_to_g = -chroma_scale * 2.0 * kr * (1.0 - kr) / kg;
+
+    let y_offset = -luma_scale * luma_offset;
+    Matrix4D::new(luma_scale, luma_scale, luma_scale, 0.0,
+                  0.0, u_to_g, u_to_b, 0.0,
+                  v_to_r, v_to_g, 0.0, 0.0,
+                  y_offset - v_to_r * chroma_offset,
+                  y_offset - (u_to_g + v_to_g) * chroma_offset,
+                  y_offset - u_to_b * chroma_offset,
+                  1.0)
+}
+
+/// A decoded frame, with its planes in textures. The textures are deleted with the frame.
+pub struct VideoFrame {
+    pub format: YuvFormat,
+
+    /// The planes of the frame, in the order given by `format`. Their sizes may differ; each
+    /// covers the whole frame.
+    pub planes: Vec<Texture>,
+
+    /// The matrix converting (Y, U, V, 1) to RGB, e.g. `YuvColorSpace::color_matrix`. Full range
+    /// or unusual color spaces just need a different matrix.
+    pub color_matrix: Matrix4D<f32>,
+
+    /// Tells frames apart, even when a decoder recycles the textures of earlier frames.
+    id: usize,
+}
+
+impl VideoFrame {
+    /// A frame in the given format. Panics if the number of planes doesn't match the format.
+    pub fn new(format: YuvFormat, planes: Vec<Texture>, color_matrix: Matrix4D<f32>)
+               -> VideoFrame {
+        assert_eq!(planes.len(), format.plane_count());
+        VideoFrame {
+            format: format,
+            planes: planes,
+            color_matrix: color_matrix,
+            id: NEXT_FRAME_ID.fetch_add(1, Ordering::SeqCst),
+        }
+    }
+
+    /// A number unique to this frame among the frames created by the process.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// A frame in I420, with Y, U and V planes.
+    pub fn i420(y: Texture, u: Texture, v: Texture, color_space: YuvColorSpace) -> VideoFrame {
+        VideoFrame::new(YuvFormat::I420, vec!(y, u, v), color_space.color_matrix())
+    }
+
+    /// A frame in NV12, with a Y plane and an interleaved UV plane.
+    pub fn nv12(y: Texture, uv: Texture, color_space: YuvColorSpace) -> VideoFrame {
+        VideoFrame::new(YuvFormat::NV12, vec!(y, uv), color_space.color_matrix())
+    }
+}
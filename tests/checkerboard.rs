@@ -0,0 +1,58 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tiles that show through as a checkerboard are requested first, but only while visible.
+
+extern crate euclid;
+extern crate layers;
+
+mod common;
+
+use common::{new_layer, new_scene, rect, request_buffers};
+use euclid::point::Point2D;
+use euclid::size::TypedSize2D;
+use layers::scene::Scene;
+
+fn checkerboard_misses(scene: &Scene<&'static str>) -> Vec<(Point2D<usize>, u32)> {
+    let mut misses = vec!();
+    scene.roots[0].do_for_all_tiles_with_index(|index, tile| {
+        misses.push((*index, tile.checkerboard_misses()));
+    });
+    misses.sort_by(|a, b| a.0.x.cmp(&b.0.x));
+    misses
+}
+
+#[test]
+fn only_visible_tiles_count_checkerboard_misses() {
+    let layer = new_layer(rect(0.0, 0.0, 512.0, 256.0), "layer");
+    let mut scene = new_scene(&[layer.clone()], 512.0, 256.0);
+    assert_eq!(request_buffers(&mut scene)[0].1.len(), 2);
+
+    // Neither tile has a texture, but only the first one is in the viewport.
+    scene.viewport.size = TypedSize2D::new(256.0, 256.0);
+    scene.record_checkerboard_misses();
+    assert_eq!(checkerboard_misses(&scene),
+               vec!((Point2D::new(0, 0), 1), (Point2D::new(1, 0), 0)));
+}
+
+#[test]
+fn checkerboarded_requests_are_sorted_after_the_callers_requests() {
+    let layer = new_layer(rect(0.0, 0.0, 256.0, 256.0), "layer");
+    let mut scene = new_scene(&[layer.clone()], 256.0, 256.0);
+    request_buffers(&mut scene);
+    scene.record_checkerboard_misses();
+    layer.contents_changed();
+
+    let mut requests = vec!((new_layer(rect(0.0, 0.0, 1.0, 1.0), "earlier"), vec!()));
+    let mut unused_buffers = vec!();
+    scene.get_buffer_requests(&mut requests, &mut unused_buffers);
+    assert_eq!(requests.len(), 2);
+    assert_eq!(*requests[0].0.extra_data.borrow(), "earlier");
+    assert_eq!(requests[1].1[0].priority, 1);
+}
@@ -0,0 +1,58 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Layer and scene factories shared by the integration tests. Each test uses only some of them.
+
+#![allow(dead_code)]
+
+use euclid::Matrix4D;
+use euclid::point::{Point2D, TypedPoint2D};
+use euclid::rect::TypedRect;
+use euclid::size::{Size2D, TypedSize2D};
+use layers::color::Color;
+use layers::geometry::LayerPixel;
+use layers::layers::{BufferRequest, Layer};
+use layers::scene::Scene;
+use std::rc::Rc;
+
+pub const TILE_SIZE: usize = 256;
+
+pub fn rect(x: f32, y: f32, width: f32, height: f32) -> TypedRect<f32, LayerPixel> {
+    TypedRect::new(TypedPoint2D::new(x, y), TypedSize2D::new(width, height))
+}
+
+/// A transparent layer with square tiles of `TILE_SIZE`.
+pub fn new_layer<T>(bounds: TypedRect<f32, LayerPixel>, extra_data: T) -> Rc<Layer<T>> {
+    let transparent = Color { r: 0.0, g: 0.0, b: 0.0, a: 0.0 };
+    let tile_size = Size2D::new(TILE_SIZE, TILE_SIZE);
+    Rc::new(Layer::new(bounds, tile_size, transparent, 1.0, false, extra_data))
+}
+
+/// A scene with a viewport of the given size at the origin, showing `roots` with their
+/// transforms already computed.
+pub fn new_scene<T>(roots: &[Rc<Layer<T>>], width: f32, height: f32) -> Scene<T> {
+    let viewport = TypedRect::new(TypedPoint2D::zero(), TypedSize2D::new(width, height));
+    let mut scene = Scene::new(viewport);
+    scene.roots = roots.to_vec();
+    for root in roots {
+        root.update_transform_state(&Matrix4D::identity(), &Matrix4D::identity(), &Point2D::zero());
+    }
+    scene
+}
+
+pub fn request_buffers<T>(scene: &mut Scene<T>) -> Vec<(Rc<Layer<T>>, Vec<BufferRequest>)> {
+    let mut requests = vec!();
+    let mut unused_buffers = vec!();
+    scene.get_buffer_requests(&mut requests, &mut unused_buffers);
+    requests
+}
+
+pub fn hit_layer_name(scene: &Scene<&'static str>, x: f32, y: f32) -> Option<&'static str> {
+    scene.hit_test(TypedPoint2D::new(x, y)).map(|result| *result.layer.extra_data.borrow())
+}
@@ -0,0 +1,125 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reftests rendered by the software compositor: a test scene and a reference scene that should
+//! look the same are compared within tolerances, and the differing pixels are marked in the diff
+//! image.
+
+extern crate euclid;
+extern crate layers;
+
+mod common;
+
+use common::{new_layer, new_scene, rect};
+use layers::color::Color;
+use layers::compare::{CompareOptions, compare_images};
+use layers::layers::Layer;
+use layers::software::{SoftwareFramebuffer, render_scene_software};
+use std::rc::Rc;
+
+const WIDTH: usize = 4;
+const HEIGHT: usize = 2;
+
+const RED: Color = Color { r: 1.0, g: 0.0, b: 0.0, a: 1.0 };
+
+/// Renders a scene of `roots` and returns its pixels as RGBA, the order `compare_images` takes.
+fn render(roots: &[Rc<Layer<()>>]) -> Vec<u8> {
+    let scene = new_scene(roots, WIDTH as f32, HEIGHT as f32);
+    let stride = WIDTH * 4;
+    let mut pixels = vec!(0; stride * HEIGHT);
+    render_scene_software(&scene,
+                          &mut SoftwareFramebuffer::new(&mut pixels, WIDTH, HEIGHT, stride));
+    for pixel in pixels.chunks_mut(4) {
+        pixel.swap(0, 2);
+    }
+    pixels
+}
+
+/// A red square in the top left corner, drawn by a single layer.
+fn reference() -> Vec<u8> {
+    let root = new_layer(rect(0.0, 0.0, WIDTH as f32, HEIGHT as f32), ());
+    let square = new_layer(rect(0.0, 0.0, 2.0, 2.0), ());
+    *square.background_color.borrow_mut() = RED;
+    root.add_child(square);
+    render(&[root])
+}
+
+/// The same square, drawn as two layers of the given color, one per row.
+fn test(color: Color) -> Vec<u8> {
+    let root = new_layer(rect(0.0, 0.0, WIDTH as f32, HEIGHT as f32), ());
+    for y in 0..2 {
+        let row = new_layer(rect(0.0, y as f32, 2.0, 1.0), ());
+        *row.background_color.borrow_mut() = color;
+        root.add_child(row);
+    }
+    render(&[root])
+}
+
+fn diff_pixel(diff_image: &[u8], x: usize, y: usize) -> &[u8] {
+    &diff_image[(y * WIDTH + x) * 4..(y * WIDTH + x) * 4 + 4]
+}
+
+#[test]
+fn matching_renderings_pass_exactly() {
+    let comparison = compare_images(&reference(), &test(RED), WIDTH, HEIGHT,
+                                    &CompareOptions::exact());
+    assert!(comparison.is_match());
+    assert_eq!(comparison.max_channel_delta, 0);
+    assert_eq!(comparison.diff_image.len(), WIDTH * HEIGHT * 4);
+
+    // The diff image is a faded copy of the reference, with nothing marked.
+    assert!(comparison.diff_image.chunks(4).all(|pixel| pixel != &[255, 0, 0, 255]));
+    assert_eq!(diff_pixel(&comparison.diff_image, 3, 1), &[255, 255, 255, 255]);
+}
+
+#[test]
+fn small_differences_pass_within_the_tolerances() {
+    let almost_red = Color { r: 0.99, g: 0.0, b: 0.0, a: 1.0 };
+    let reference = reference();
+    let test = test(almost_red);
+
+    let exact = compare_images(&reference, &test, WIDTH, HEIGHT, &CompareOptions::exact());
+    assert!(!exact.is_match());
+    assert_eq!(exact.differing_pixels, 4);
+    assert_eq!(exact.max_channel_delta, 3);
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let marked = diff_pixel(&exact.diff_image, x, y) == &[255, 0, 0, 255];
+            assert_eq!(marked, x < 2, "pixel {}, {} of the diff image", x, y);
+        }
+    }
+
+    let options = CompareOptions {
+        channel_tolerance: 3,
+        perceptual_threshold: 0.001,
+    };
+    let tolerant = compare_images(&reference, &test, WIDTH, HEIGHT, &options);
+    assert!(tolerant.is_match(), "max perceptual delta {}", tolerant.max_perceptual_delta);
+    assert_eq!(tolerant.differing_pixels, 0);
+}
+
+#[test]
+fn missing_content_fails_and_is_marked() {
+    let options = CompareOptions {
+        channel_tolerance: 3,
+        perceptual_threshold: 0.001,
+    };
+    let transparent = Color { r: 0.0, g: 0.0, b: 0.0, a: 0.0 };
+    let comparison = compare_images(&reference(), &test(transparent), WIDTH, HEIGHT, &options);
+    assert!(!comparison.is_match());
+    assert_eq!(comparison.differing_pixels, 4);
+    assert_eq!(comparison.perceptually_differing_pixels, 4);
+    assert_eq!(diff_pixel(&comparison.diff_image, 0, 0), &[255, 0, 0, 255]);
+    assert_eq!(diff_pixel(&comparison.diff_image, 2, 0), &[255, 255, 255, 255]);
+
+    let mut ppm = vec!();
+    comparison.write_diff_image(&mut ppm).unwrap();
+    assert!(ppm.starts_with(b"P6\n4 2\n255\n"));
+    assert_eq!(ppm.len(), b"P6\n4 2\n255\n".len() + WIDTH * HEIGHT * 3);
+}
@@ -0,0 +1,42 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Makes sure that tree traversals don't overflow the stack on very deep layer trees.
+
+extern crate euclid;
+extern crate layers;
+
+mod common;
+
+use common::{new_layer, new_scene, rect, request_buffers};
+
+const DEPTH: usize = 100000;
+
+#[test]
+fn deep_tree() {
+    let root = new_layer(rect(0.0, 0.0, 1.0, 1.0), ());
+    let mut parent = root.clone();
+    for _ in 0..DEPTH {
+        let child = new_layer(rect(0.0, 0.0, 1.0, 1.0), ());
+        parent.add_child(child.clone());
+        parent = child;
+    }
+    drop(parent);
+
+    let mut scene = new_scene(&[root.clone()], 100.0, 100.0);
+    drop(request_buffers(&mut scene));
+
+    assert_eq!(scene.get_memory_usage(), 0);
+    assert!(!scene.has_pending_uploads());
+    assert!(scene.take_damage().is_none());
+    scene.mark_layer_contents_as_changed_recursively();
+
+    drop(scene);
+    drop(root);
+}
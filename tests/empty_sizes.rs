@@ -0,0 +1,64 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Makes sure that empty and negative sizes, as seen with minimized windows and during layout
+//! transients, don't produce buffer requests.
+
+extern crate euclid;
+extern crate layers;
+
+mod common;
+
+use common::{new_layer, new_scene, rect, request_buffers};
+use euclid::size::TypedSize2D;
+use layers::layers::Layer;
+use layers::scene::Scene;
+use std::rc::Rc;
+
+fn scene_with_root(width: f32, height: f32, viewport_width: f32, viewport_height: f32)
+                   -> (Scene<()>, Rc<Layer<()>>) {
+    let layer = new_layer(rect(0.0, 0.0, width, height), ());
+    (new_scene(&[layer.clone()], viewport_width, viewport_height), layer)
+}
+
+fn request_count(scene: &mut Scene<()>) -> usize {
+    request_buffers(scene).iter().map(|&(_, ref requests)| requests.len()).sum()
+}
+
+#[test]
+fn empty_and_negative_layers_request_nothing() {
+    for &(width, height) in &[(0.0, 0.0), (0.0, 100.0), (100.0, 0.0), (-10.0, 100.0),
+                              (100.0, -0.5), (-1.0, -1.0)] {
+        let (mut scene, layer) = scene_with_root(width, height, 100.0, 100.0);
+        assert_eq!(request_count(&mut scene), 0, "layer of {}x{}", width, height);
+        assert_eq!(layer.tile_count(), 0, "layer of {}x{}", width, height);
+    }
+}
+
+#[test]
+fn empty_viewports_request_nothing() {
+    for &(width, height) in &[(0.0, 0.0), (0.0, 100.0), (-100.0, 100.0)] {
+        let (mut scene, _) = scene_with_root(100.0, 100.0, width, height);
+        assert_eq!(request_count(&mut scene), 0, "viewport of {}x{}", width, height);
+    }
+}
+
+#[test]
+fn negative_root_layer_sizes_are_empty() {
+    let (mut scene, layer) = scene_with_root(100.0, 100.0, 100.0, 100.0);
+    assert!(request_count(&mut scene) > 0);
+
+    scene.set_root_layer_size(TypedSize2D::new(-50.0, 20.0));
+    let size = layer.bounds.borrow().size;
+    assert_eq!((size.width, size.height), (0.0, 20.0));
+
+    // The tiles of the layer go once it's empty.
+    assert_eq!(request_count(&mut scene), 0);
+    assert_eq!(layer.tile_count(), 0);
+}
@@ -0,0 +1,79 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Makes sure that tile grids cover layers exactly, without gaps or overlaps, at fractional
+//! scales.
+
+extern crate euclid;
+extern crate layers;
+
+mod common;
+
+use common::{TILE_SIZE, new_layer, new_scene, rect, request_buffers};
+use euclid::scale_factor::ScaleFactor;
+use layers::layers::Layer;
+use layers::tiling::device_rect_to_layer_rect;
+use std::rc::Rc;
+
+const SCALES: [f32; 9] = [0.5, 0.75, 1.0, 1.25, 1.333, 1.5, 1.75, 2.0, 2.625];
+
+fn tiled_layer(width: f32, height: f32, scale: f32) -> Rc<Layer<()>> {
+    let layer = new_layer(rect(0.0, 0.0, width, height), ());
+    let mut scene = new_scene(&[layer.clone()], width * scale, height * scale);
+    scene.scale = ScaleFactor::new(scale);
+    request_buffers(&mut scene);
+    layer
+}
+
+#[test]
+fn tiles_cover_fractional_layers_exactly() {
+    for &scale in SCALES.iter() {
+        let (width, height) = (1000.3, 777.7);
+        let layer = tiled_layer(width, height, scale);
+        let rects = layer.tile_device_rects(ScaleFactor::new(scale));
+
+        let covered: usize = rects.iter().map(|&(_, rect)| {
+            rect.size.width * rect.size.height
+        }).sum();
+        let device_width = (width * scale).ceil() as usize;
+        let device_height = (height * scale).ceil() as usize;
+        assert_eq!(covered, device_width * device_height, "scale {}", scale);
+
+        for &(index, rect) in &rects {
+            assert_eq!(rect.origin.x, index.x * TILE_SIZE, "scale {}", scale);
+            assert_eq!(rect.origin.y, index.y * TILE_SIZE, "scale {}", scale);
+            assert!(rect.max_x() <= device_width && rect.max_y() <= device_height,
+                    "scale {}: tile {:?} extends past the layer", scale, rect);
+        }
+    }
+}
+
+#[test]
+fn adjacent_tile_edges_match_in_layer_coordinates() {
+    for &scale in SCALES.iter() {
+        let layer = tiled_layer(20000.0, 300.0, scale);
+        let scale = ScaleFactor::new(scale);
+        let mut rects: Vec<_> = layer.tile_device_rects(scale).into_iter().filter(|&(index, _)| {
+            index.y == 0
+        }).collect();
+        rects.sort_by(|a, b| a.0.x.cmp(&b.0.x));
+        assert!(rects.len() > 1);
+
+        for pair in rects.windows(2) {
+            let left = device_rect_to_layer_rect(pair[0].1, scale);
+            let right = device_rect_to_layer_rect(pair[1].1, scale);
+            let edge = (pair[1].1.origin.x as f32) / scale.get();
+            assert_eq!(right.origin.x, edge);
+
+            // The error of the shared edge must not grow with the distance from the origin.
+            assert!((left.max_x() - right.origin.x).abs() <= edge * 1.0e-6,
+                    "scale {}: gap between {:?} and {:?}", scale.get(), left, right);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Requests that never get a buffer must not stay in flight, or the frame latency limit would
+//! hold back new requests forever.
+
+extern crate euclid;
+extern crate layers;
+
+mod common;
+
+use common::{new_layer, new_scene, rect, request_buffers};
+
+#[test]
+fn dropped_requests_are_no_longer_in_flight() {
+    let layer = new_layer(rect(0.0, 0.0, 100.0, 100.0), ());
+    let mut scene = new_scene(&[layer.clone()], 100.0, 100.0);
+    scene.max_frame_latency = Some(1);
+
+    let requests = request_buffers(&mut scene);
+    assert_eq!(requests.len(), 1);
+    assert_eq!(scene.frames_in_flight(), 1);
+
+    // The latency limit holds new requests back while the first one is in flight.
+    layer.contents_changed();
+    assert!(request_buffers(&mut scene).is_empty());
+
+    // The painter drops the request instead of replying with a buffer.
+    for (layer, requests) in requests {
+        for request in &requests {
+            layer.cancel_buffer_request(request);
+        }
+    }
+    assert_eq!(scene.frames_in_flight(), 0);
+
+    // The tile is requested again, with the new content.
+    let requests = request_buffers(&mut scene);
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].1.len(), 1);
+    assert_eq!(scene.frames_in_flight(), 1);
+}
@@ -0,0 +1,57 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Frame intervals count when a frame was asked for during them, however long they are.
+
+extern crate layers;
+
+use layers::stats::FramePacing;
+use std::time::{Duration, Instant};
+
+fn milliseconds(start: Instant, milliseconds: u64) -> Instant {
+    start + Duration::from_millis(milliseconds)
+}
+
+#[test]
+fn long_janks_are_counted() {
+    let start = Instant::now();
+    let mut pacing = FramePacing::new(Duration::from_millis(16));
+    pacing.record_composite(start);
+
+    // The next frame was asked for right away, but took 400ms.
+    pacing.record_request(milliseconds(start, 1));
+    assert_eq!(pacing.record_composite(milliseconds(start, 400)), 24);
+
+    let report = pacing.report();
+    assert_eq!(report.frames, 1);
+    assert_eq!(report.dropped_frames, 24);
+    assert!(report.max_interval_ms >= 400.0);
+}
+
+#[test]
+fn frames_nobody_asked_for_end_idle_periods() {
+    let start = Instant::now();
+    let mut pacing = FramePacing::new(Duration::from_millis(16));
+    pacing.record_composite(start);
+    assert_eq!(pacing.record_composite(milliseconds(start, 5000)), 0);
+    assert_eq!(pacing.report().frames, 0);
+}
+
+#[test]
+fn intervals_after_idle_periods_start_at_the_request() {
+    let start = Instant::now();
+    let mut pacing = FramePacing::new(Duration::from_millis(16));
+    pacing.record_composite(start);
+
+    pacing.record_request(milliseconds(start, 5000));
+    assert_eq!(pacing.record_composite(milliseconds(start, 5016)), 0);
+    let report = pacing.report();
+    assert_eq!(report.frames, 1);
+    assert!(report.max_interval_ms < 17.0);
+}
@@ -0,0 +1,40 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Highlights are composited without repainting, so changing them must damage the frame.
+
+extern crate euclid;
+extern crate layers;
+
+mod common;
+
+use common::{new_layer, rect};
+use layers::color::Color;
+use layers::layers::Highlight;
+
+#[test]
+fn setting_highlights_damages_the_old_and_new_rects() {
+    let yellow = Color { r: 1.0, g: 1.0, b: 0.0, a: 0.5 };
+    let layer = new_layer(rect(0.0, 0.0, 100.0, 100.0), ());
+    layer.take_damage();
+
+    let old = layer.set_highlights(vec!(Highlight { rect: rect(10.0, 10.0, 10.0, 10.0),
+                                                    color: yellow }));
+    assert!(old.is_empty());
+    assert_eq!(layer.take_damage(), Some(rect(10.0, 10.0, 10.0, 10.0)));
+
+    let old = layer.set_highlights(vec!(Highlight { rect: rect(50.0, 50.0, 10.0, 10.0),
+                                                    color: yellow }));
+    assert_eq!(old.len(), 1);
+    assert_eq!(layer.take_damage(), Some(rect(10.0, 10.0, 50.0, 50.0)));
+
+    layer.set_highlights(vec!());
+    assert_eq!(layer.take_damage(), Some(rect(50.0, 50.0, 10.0, 10.0)));
+    assert!(layer.highlights.borrow().is_empty());
+}
@@ -0,0 +1,100 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Makes sure that layer trees built from descriptions have the described structure and
+//! content.
+
+extern crate euclid;
+extern crate layers;
+
+mod common;
+
+use common::rect;
+use euclid::scale_factor::ScaleFactor;
+use euclid::size::Size2D;
+use layers::color::Color;
+use layers::description::{Bitmap, ContentSource, LayerDescription, LayerTree};
+
+#[test]
+fn children_are_built_in_paint_order() {
+    let tree = LayerTree::build(LayerDescription::new(rect(0.0, 0.0, 100.0, 100.0), 0)
+        .debug_name("root")
+        .child(LayerDescription::new(rect(0.0, 0.0, 10.0, 10.0), 1)
+            .child(LayerDescription::new(rect(1.0, 1.0, 5.0, 5.0), 2)))
+        .child(LayerDescription::new(rect(50.0, 50.0, 20.0, 20.0), 3).opacity(0.5)));
+
+    assert_eq!(tree.root.debug_name(), "root");
+    let children = tree.root.children();
+    assert_eq!(children.len(), 2);
+    assert_eq!(*children[0].extra_data.borrow(), 1);
+    assert_eq!(*children[1].extra_data.borrow(), 3);
+    assert_eq!(*children[1].opacity.borrow(), 0.5);
+    assert_eq!(children[1].bounds.borrow().origin.x, 50.0);
+
+    let grandchildren = children[0].children();
+    assert_eq!(grandchildren.len(), 1);
+    assert_eq!(*grandchildren[0].extra_data.borrow(), 2);
+}
+
+#[test]
+fn content_is_painted_from_the_description() {
+    let red = Color { r: 1.0, g: 0.0, b: 0.0, a: 1.0 };
+    let left_white = Bitmap {
+        size: Size2D::new(2, 1),
+        pixels: vec!(255, 255, 255, 255, 0, 0, 0, 0),
+    };
+    let tree = LayerTree::build(LayerDescription::new(rect(0.0, 0.0, 4.0, 4.0), ())
+        .content(ContentSource::SolidColor(red))
+        .child(LayerDescription::new(rect(0.0, 0.0, 4.0, 4.0), ())
+            .content(ContentSource::Bitmap(left_white))));
+
+    let mut pixels = vec!(0; 4 * 4 * 4);
+    tree.paint(&tree.root, &rect(0.0, 0.0, 4.0, 4.0), ScaleFactor::new(1.0), &mut pixels);
+    assert!(pixels.chunks(4).all(|pixel| pixel == &[0u8, 0, 255, 255]));
+
+    // Painting at twice the scale gives the bitmap's left column to the left half of the tile.
+    let child = tree.root.children()[0].clone();
+    let mut pixels = vec!(0; 8 * 8 * 4);
+    tree.paint(&child, &rect(0.0, 0.0, 4.0, 4.0), ScaleFactor::new(2.0), &mut pixels);
+    for (index, pixel) in pixels.chunks(4).enumerate() {
+        let expected = if index % 8 < 4 { [255u8; 4] } else { [0u8; 4] };
+        assert_eq!(pixel, &expected, "pixel {}", index);
+    }
+}
+
+#[test]
+fn fractional_tiles_are_painted_with_the_buffer_stride() {
+    let white = Bitmap {
+        size: Size2D::new(3, 2),
+        pixels: vec!(255; 3 * 2 * 4),
+    };
+    let tree = LayerTree::build(LayerDescription::new(rect(0.0, 0.0, 3.0, 2.0), ())
+        .content(ContentSource::Bitmap(white)));
+
+    // The tile is 2.4 pixels wide, so its buffer is rounded up to 3.
+    let mut pixels = vec!(0; 3 * 2 * 4);
+    tree.paint(&tree.root, &rect(0.0, 0.0, 2.4, 2.0), ScaleFactor::new(1.0), &mut pixels);
+    for (index, pixel) in pixels.chunks(4).enumerate() {
+        assert_eq!(pixel, &[255u8; 4], "pixel {}", index);
+    }
+}
+
+#[test]
+fn empty_layers_paint_nothing() {
+    let white = Bitmap {
+        size: Size2D::new(1, 1),
+        pixels: vec!(255; 4),
+    };
+    let tree = LayerTree::build(LayerDescription::new(rect(0.0, 0.0, 0.0, 0.0), ())
+        .content(ContentSource::Bitmap(white)));
+
+    let mut pixels = vec!(0; 2 * 2 * 4);
+    tree.paint(&tree.root, &rect(0.0, 0.0, 2.0, 2.0), ScaleFactor::new(1.0), &mut pixels);
+    assert!(pixels.iter().all(|&value| value == 0));
+}
@@ -0,0 +1,82 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Scenes with no root layer, and with overlay roots after the main one.
+
+extern crate euclid;
+extern crate layers;
+
+mod common;
+
+use common::{hit_layer_name, new_layer, new_scene, rect, request_buffers};
+use euclid::point::{Point2D, TypedPoint2D};
+use euclid::rect::{Rect, TypedRect};
+use euclid::size::{Size2D, TypedSize2D};
+
+#[test]
+fn scenes_without_roots_are_empty() {
+    let mut scene = new_scene(&[], 1000.0, 1000.0);
+    assert!(scene.root().is_none());
+    assert_eq!(scene.dump_layer_tree(), "scene\n");
+    assert_eq!(scene.get_memory_usage(), 0);
+    assert!(hit_layer_name(&scene, 10.0, 10.0).is_none());
+
+    let mut requests = vec!();
+    let mut unused_buffers = vec!();
+    scene.get_buffer_requests(&mut requests, &mut unused_buffers);
+    assert!(requests.is_empty());
+    assert!(unused_buffers.is_empty());
+}
+
+#[test]
+fn later_roots_are_hit_first() {
+    let root = new_layer(rect(0.0, 0.0, 1000.0, 1000.0), "root");
+    let overlay = new_layer(rect(100.0, 100.0, 200.0, 50.0), "overlay");
+    let scene = new_scene(&[root.clone(), overlay.clone()], 1000.0, 1000.0);
+
+    assert_eq!(*scene.root().unwrap().extra_data.borrow(), "root");
+    assert_eq!(hit_layer_name(&scene, 150.0, 120.0), Some("overlay"));
+    assert_eq!(scene.hit_test(TypedPoint2D::new(150.0, 120.0)).unwrap().point,
+               TypedPoint2D::new(50.0, 20.0));
+    assert_eq!(hit_layer_name(&scene, 500.0, 500.0), Some("root"));
+
+    let rect = TypedRect::new(TypedPoint2D::new(90.0, 90.0), TypedSize2D::new(20.0, 20.0));
+    let names: Vec<_> = scene.layers_in_rect(&rect).iter().map(|painted| {
+        *painted.layer.extra_data.borrow()
+    }).collect();
+    assert_eq!(names, vec!("root", "overlay"));
+}
+
+#[test]
+fn every_root_requests_its_tiles() {
+    let root = new_layer(rect(0.0, 0.0, 1000.0, 1000.0), "root");
+    let overlay = new_layer(rect(100.0, 100.0, 200.0, 50.0), "overlay");
+    let mut scene = new_scene(&[root.clone(), overlay.clone()], 1000.0, 1000.0);
+
+    let requests = request_buffers(&mut scene);
+    let tiles_of = |name: &str| -> Vec<Rect<usize>> {
+        requests.iter().filter(|&&(ref layer, _)| *layer.extra_data.borrow() == name)
+                       .flat_map(|&(_, ref requests)| requests.iter())
+                       .map(|request| request.screen_rect)
+                       .collect()
+    };
+    assert_eq!(tiles_of("root").len(), 16);
+    assert_eq!(tiles_of("overlay"), vec!(Rect::new(Point2D::new(0, 0), Size2D::new(200, 50))));
+}
+
+#[test]
+fn only_the_main_root_is_resized() {
+    let root = new_layer(rect(0.0, 0.0, 1000.0, 1000.0), "root");
+    let overlay = new_layer(rect(100.0, 100.0, 200.0, 50.0), "overlay");
+    let scene = new_scene(&[root.clone(), overlay.clone()], 1000.0, 1000.0);
+
+    scene.set_root_layer_size(TypedSize2D::new(800.0, 600.0));
+    assert_eq!(root.bounds.borrow().size, TypedSize2D::new(800.0, 600.0));
+    assert_eq!(overlay.bounds.borrow().size, TypedSize2D::new(200.0, 50.0));
+}
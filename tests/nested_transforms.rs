@@ -0,0 +1,258 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Pins down the transforms and screen rects computed for nested layers, which rendering and
+//! buffer requests both rely on, against values worked out by hand.
+
+extern crate euclid;
+extern crate layers;
+
+mod common;
+
+use common::{new_layer, new_scene, rect, request_buffers};
+use euclid::Matrix4D;
+use euclid::point::Point2D;
+use euclid::rect::Rect;
+use euclid::scale_factor::ScaleFactor;
+use euclid::size::Size2D;
+use layers::layers::{BufferRequest, Layer};
+use layers::rendergl::layer_device_transform;
+use layers::scene::{PixelSnapping, Scene};
+use std::rc::Rc;
+
+fn screen_rect(layer: &Rc<Layer<&'static str>>) -> Rect<f32> {
+    layer.transform_state.borrow().screen_rect.as_ref().expect("layer was clipped away").rect
+}
+
+/// The screen rects of the tiles requested for the layer called `name`.
+fn requested_tiles(scene: &mut Scene<&'static str>, name: &str) -> Vec<Rect<usize>> {
+    request_buffers(scene).into_iter()
+                          .filter(|&(ref layer, _)| *layer.extra_data.borrow() == name)
+                          .flat_map(|(_, requests)| requests.into_iter())
+                          .map(|request| request.screen_rect)
+                          .collect()
+}
+
+/// The only tile requested for `layer`, and the device rect that the renderer draws it over.
+fn drawn_tile(scene: &mut Scene<&'static str>, layer: &Rc<Layer<&'static str>>)
+              -> (BufferRequest, Rect<f32>) {
+    let name = *layer.extra_data.borrow();
+    let mut requests: Vec<_> = request_buffers(scene).into_iter()
+        .filter(|&(ref requested, _)| *requested.extra_data.borrow() == name)
+        .flat_map(|(_, requests)| requests.into_iter())
+        .collect();
+    assert_eq!(requests.len(), 1);
+    let request = requests.pop().unwrap();
+
+    // Tiles are drawn over their page rects, in pixels at their raster scale, which are
+    // relative to the layer's world rect.
+    let origin = layer.transform_state.borrow().world_rect.origin;
+    let transform = layer_device_transform(scene, layer).to_untyped().to_2d();
+    let page_rect = request.page_rect / request.raster_scale;
+    let drawn_rect = transform.transform_rect(&page_rect.translate(&origin));
+    (request, drawn_rect)
+}
+
+fn near(a: &Rect<f32>, b: &Rect<f32>) -> bool {
+    let close = |a: f32, b: f32| (a - b).abs() < 1.0e-3;
+    close(a.origin.x, b.origin.x) && close(a.origin.y, b.origin.y) &&
+        close(a.size.width, b.size.width) && close(a.size.height, b.size.height)
+}
+
+#[test]
+fn nested_translations_accumulate() {
+    let root = new_layer(rect(0.0, 0.0, 1000.0, 1000.0), "root");
+    let parent = new_layer(rect(100.0, 50.0, 400.0, 300.0), "parent");
+    *parent.transform.borrow_mut() = Matrix4D::identity().pre_translated(10.0, 20.0, 0.0);
+    let child = new_layer(rect(30.0, 40.0, 100.0, 100.0), "child");
+    *child.transform.borrow_mut() = Matrix4D::identity().pre_translated(1.0, 2.0, 0.0);
+    parent.add_child(child.clone());
+    root.add_child(parent.clone());
+    new_scene(&[root.clone()], 1000.0, 1000.0);
+
+    assert_eq!(parent.transform_state.borrow().final_transform,
+               Matrix4D::new(1.0, 0.0, 0.0, 0.0,
+                             0.0, 1.0, 0.0, 0.0,
+                             0.0, 0.0, 1.0, 0.0,
+                             10.0, 20.0, 0.0, 1.0));
+    assert_eq!(screen_rect(&parent), rect(110.0, 70.0, 400.0, 300.0).to_untyped());
+
+    // The child's bounds are relative to its parent's, and its translation adds to its
+    // parent's.
+    assert_eq!(child.transform_state.borrow().world_rect,
+               rect(130.0, 90.0, 100.0, 100.0).to_untyped());
+    assert_eq!(child.transform_state.borrow().final_transform,
+               Matrix4D::new(1.0, 0.0, 0.0, 0.0,
+                             0.0, 1.0, 0.0, 0.0,
+                             0.0, 0.0, 1.0, 0.0,
+                             11.0, 22.0, 0.0, 1.0));
+    assert_eq!(screen_rect(&child), rect(141.0, 112.0, 100.0, 100.0).to_untyped());
+}
+
+#[test]
+fn scales_pivot_around_the_layer_origin() {
+    let root = new_layer(rect(0.0, 0.0, 1000.0, 1000.0), "root");
+    let parent = new_layer(rect(100.0, 50.0, 200.0, 100.0), "parent");
+    *parent.transform.borrow_mut() = Matrix4D::identity().pre_scaled(2.0, 2.0, 1.0);
+    let child = new_layer(rect(10.0, 20.0, 30.0, 40.0), "child");
+    parent.add_child(child.clone());
+    root.add_child(parent.clone());
+    let mut scene = new_scene(&[root.clone()], 1000.0, 1000.0);
+
+    let scaled = Matrix4D::new(2.0, 0.0, 0.0, 0.0,
+                               0.0, 2.0, 0.0, 0.0,
+                               0.0, 0.0, 1.0, 0.0,
+                               -100.0, -50.0, 0.0, 1.0);
+    assert_eq!(parent.transform_state.borrow().final_transform, scaled);
+    assert_eq!(screen_rect(&parent), rect(100.0, 50.0, 400.0, 200.0).to_untyped());
+
+    // An untransformed child inherits the scale, offset by its position in the parent.
+    assert_eq!(child.transform_state.borrow().final_transform, scaled);
+    assert_eq!(screen_rect(&child), rect(120.0, 90.0, 60.0, 80.0).to_untyped());
+    assert_eq!(child.on_screen_scale(None), 2.0);
+
+    // Tiles are laid out and painted at the scale seen on screen.
+    let requests = request_buffers(&mut scene);
+    let child_requests: Vec<_> = requests.iter().filter(|&&(ref layer, _)| {
+        *layer.extra_data.borrow() == "child"
+    }).flat_map(|&(_, ref requests)| requests.iter()).collect();
+    assert_eq!(child_requests.len(), 1);
+    assert_eq!(child_requests[0].screen_rect, Rect::new(Point2D::new(0, 0), Size2D::new(60, 80)));
+    assert_eq!(child_requests[0].page_rect, rect(0.0, 0.0, 60.0, 80.0).to_untyped());
+    assert_eq!(child_requests[0].raster_scale, 2.0);
+    assert_eq!(child.tile_scale(scene.scale).get(), 2.0);
+}
+
+#[test]
+fn raster_scale_combines_the_scene_scale_and_the_transforms() {
+    let root = new_layer(rect(0.0, 0.0, 1000.0, 1000.0), "root");
+    let parent = new_layer(rect(100.0, 50.0, 200.0, 100.0), "parent");
+    *parent.transform.borrow_mut() = Matrix4D::identity().pre_scaled(1.5, 1.5, 1.0);
+    let child = new_layer(rect(10.0, 20.0, 30.0, 40.0), "child");
+    parent.add_child(child.clone());
+    root.add_child(parent.clone());
+    let mut scene = new_scene(&[root.clone()], 1000.0, 1000.0);
+    scene.scale = ScaleFactor::new(2.0);
+
+    // The child is magnified 3 times on screen, and its buffer by the next power of two of the
+    // transforms' 1.5.
+    let requests = request_buffers(&mut scene);
+    let child_requests: Vec<_> = requests.iter().filter(|&&(ref layer, _)| {
+        *layer.extra_data.borrow() == "child"
+    }).flat_map(|&(_, ref requests)| requests.iter()).collect();
+    assert_eq!(child_requests.len(), 1);
+    assert_eq!(child_requests[0].screen_rect,
+               Rect::new(Point2D::new(0, 0), Size2D::new(120, 160)));
+    assert_eq!(child_requests[0].raster_scale, 4.0);
+}
+
+#[test]
+fn pinch_zoom_raises_the_raster_scale() {
+    let root = new_layer(rect(0.0, 0.0, 1000.0, 1000.0), "root");
+    let child = new_layer(rect(10.0, 20.0, 30.0, 40.0), "child");
+    root.add_child(child.clone());
+    let mut scene = new_scene(&[root.clone()], 1000.0, 1000.0);
+    let pinch_transform = Matrix4D::identity().pre_scaled(3.0, 3.0, 1.0);
+    assert_eq!(child.on_screen_scale(None), 1.0);
+    assert_eq!(child.on_screen_scale(Some(&pinch_transform)), 3.0);
+
+    let (request, _) = drawn_tile(&mut scene, &child);
+    assert_eq!(request.raster_scale, 1.0);
+
+    // Pinching in lays the tiles out again, four times as sharp.
+    scene.present_overrides.set_pinch_transform(Some(pinch_transform));
+    let (request, drawn_rect) = drawn_tile(&mut scene, &child);
+    assert_eq!(request.screen_rect, Rect::new(Point2D::new(0, 0), Size2D::new(120, 160)));
+    assert_eq!(request.raster_scale, 4.0);
+    assert!(near(&drawn_rect, &screen_rect(&child)), "drawn at {:?}", drawn_rect);
+}
+
+#[test]
+fn masking_layers_limit_the_tiles_of_their_children() {
+    let tile_rect = |x: usize, width: usize| Rect::new(Point2D::new(x, 0), Size2D::new(width, 100));
+    for &masks_to_bounds in &[false, true] {
+        let root = new_layer(rect(0.0, 0.0, 1000.0, 1000.0), "root");
+        let parent = new_layer(rect(0.0, 0.0, 300.0, 300.0), "parent");
+        *parent.masks_to_bounds.borrow_mut() = masks_to_bounds;
+        let child = new_layer(rect(200.0, 0.0, 600.0, 100.0), "child");
+        parent.add_child(child.clone());
+        root.add_child(parent.clone());
+        let mut scene = new_scene(&[root.clone()], 1000.0, 1000.0);
+
+        assert_eq!(screen_rect(&child), rect(200.0, 0.0, 600.0, 100.0).to_untyped());
+
+        // Only the first tile of the child, at 200 to 456 on screen, overlaps the parent.
+        let mut tiles = requested_tiles(&mut scene, "child");
+        tiles.sort_by(|a, b| a.origin.x.cmp(&b.origin.x));
+        if masks_to_bounds {
+            assert_eq!(tiles, vec!(tile_rect(0, 256)));
+        } else {
+            assert_eq!(tiles, vec!(tile_rect(0, 256), tile_rect(256, 256), tile_rect(512, 88)));
+        }
+    }
+}
+
+#[test]
+fn tiles_are_drawn_over_the_device_pixels_they_were_requested_for() {
+    let root = new_layer(rect(0.0, 0.0, 1000.0, 1000.0), "root");
+    let parent = new_layer(rect(100.0, 50.0, 200.0, 100.0), "parent");
+    *parent.transform.borrow_mut() = Matrix4D::identity().pre_translated(10.0, 20.0, 0.0);
+    let child = new_layer(rect(10.0, 20.0, 30.0, 40.0), "child");
+    *child.transform.borrow_mut() = Matrix4D::identity().pre_translated(1.0, 2.0, 0.0);
+    parent.add_child(child.clone());
+    root.add_child(parent.clone());
+    let mut scene = new_scene(&[root.clone()], 1000.0, 1000.0);
+    scene.scale = ScaleFactor::new(2.0);
+
+    // The renderer applies the transforms of the layers before the scene scale.
+    let (request, drawn_rect) = drawn_tile(&mut scene, &child);
+    assert_eq!(request.screen_rect, Rect::new(Point2D::new(0, 0), Size2D::new(60, 80)));
+    assert!(near(&drawn_rect, &rect(242.0, 184.0, 60.0, 80.0).to_untyped()),
+            "drawn at {:?}", drawn_rect);
+}
+
+#[test]
+fn scaled_tiles_are_drawn_over_the_screen_rect_of_their_layer() {
+    let root = new_layer(rect(0.0, 0.0, 1000.0, 1000.0), "root");
+    let parent = new_layer(rect(100.0, 50.0, 200.0, 100.0), "parent");
+    *parent.transform.borrow_mut() = Matrix4D::identity().pre_scaled(2.0, 2.0, 1.0);
+    let child = new_layer(rect(10.0, 20.0, 30.0, 40.0), "child");
+    parent.add_child(child.clone());
+    root.add_child(parent.clone());
+    let mut scene = new_scene(&[root.clone()], 1000.0, 1000.0);
+
+    // The buffer covers the screen rect pixel for pixel.
+    let (request, drawn_rect) = drawn_tile(&mut scene, &child);
+    assert_eq!(request.screen_rect, Rect::new(Point2D::new(0, 0), Size2D::new(60, 80)));
+    assert_eq!(request.raster_scale, 2.0);
+    assert!(near(&drawn_rect, &screen_rect(&child)), "drawn at {:?}", drawn_rect);
+    assert!(near(&drawn_rect, &rect(120.0, 90.0, 60.0, 80.0).to_untyped()),
+            "drawn at {:?}", drawn_rect);
+}
+
+#[test]
+fn snapped_tiles_are_drawn_on_whole_device_pixels() {
+    for &(pixel_snapping, x, y) in &[(PixelSnapping::Never, 15.375, 30.45),
+                                     (PixelSnapping::TranslationsOnly, 15.0, 30.0),
+                                     (PixelSnapping::Always, 15.0, 30.0)] {
+        let root = new_layer(rect(0.0, 0.0, 1000.0, 1000.0), "root");
+        let child = new_layer(rect(10.0, 20.0, 30.0, 40.0), "child");
+        *child.transform.borrow_mut() = Matrix4D::identity().pre_translated(0.25, 0.3, 0.0);
+        root.add_child(child.clone());
+        let mut scene = new_scene(&[root.clone()], 1000.0, 1000.0);
+        scene.scale = ScaleFactor::new(1.5);
+        scene.pixel_snapping = pixel_snapping;
+
+        // Snapping moves the tile, but it still covers as many pixels as were requested.
+        let (request, drawn_rect) = drawn_tile(&mut scene, &child);
+        assert_eq!(request.screen_rect, Rect::new(Point2D::new(0, 0), Size2D::new(45, 60)));
+        assert!(near(&drawn_rect, &rect(x, y, 45.0, 60.0).to_untyped()),
+                "{:?}: drawn at {:?}", pixel_snapping, drawn_rect);
+    }
+}
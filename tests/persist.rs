@@ -0,0 +1,102 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Saved scenes load back with the same layer trees, however deep.
+
+extern crate euclid;
+extern crate layers;
+
+mod common;
+
+use common::rect;
+use euclid::Matrix4D;
+use euclid::point::TypedPoint2D;
+use euclid::rect::TypedRect;
+use euclid::scale_factor::ScaleFactor;
+use euclid::size::{Size2D, TypedSize2D};
+use layers::color::Color;
+use layers::description::{LayerDescription, LayerTree};
+use layers::persist;
+use layers::scene::Scene;
+use std::env;
+use std::fs;
+
+fn round_trip(scene: &Scene<()>, name: &str) -> Scene<()> {
+    let path = env::temp_dir().join(format!("layers-persist-{}.json", name));
+    persist::save_scene(scene, &path).unwrap();
+    let (loaded, trees) = persist::load_scene(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(trees.len(), loaded.roots.len());
+    loaded
+}
+
+#[test]
+fn saved_scenes_load_back_with_the_same_layers() {
+    let red = Color { r: 1.0, g: 0.0, b: 0.0, a: 1.0 };
+    let main = LayerTree::build(
+        LayerDescription::new(rect(0.0, 0.0, 400.0, 300.0), ())
+            .debug_name("main")
+            .child(LayerDescription::new(rect(10.0, 20.0, 100.0, 50.0), ())
+                       .debug_name("first")
+                       .background_color(red)
+                       .opacity(0.5)
+                       .child(LayerDescription::new(rect(5.0, 5.0, 10.0, 10.0), ())
+                                  .debug_name("grandchild")
+                                  .tile_size(Size2D::new(512, 128))))
+            .child(LayerDescription::new(rect(200.0, 20.0, 100.0, 50.0), ())
+                       .debug_name("second")
+                       .masks_to_bounds(true)
+                       .transform(Matrix4D::create_translation(3.0, 4.0, 0.0))));
+    let overlay = LayerTree::build(LayerDescription::new(rect(0.0, 0.0, 50.0, 50.0), ())
+                                       .debug_name("overlay"));
+
+    let mut scene = Scene::new(TypedRect::new(TypedPoint2D::zero(),
+                                              TypedSize2D::new(400.0, 300.0)));
+    scene.scale = ScaleFactor::new(2.0);
+    scene.roots = vec!(main.root.clone(), overlay.root.clone());
+
+    let loaded = round_trip(&scene, "layers");
+    assert_eq!(loaded.dump_layer_tree(), scene.dump_layer_tree());
+    assert_eq!(loaded.scale.get(), 2.0);
+
+    let second = loaded.roots[0].children()[1].clone();
+    assert!(*second.masks_to_bounds.borrow());
+    assert_eq!(*second.transform.borrow(), Matrix4D::create_translation(3.0, 4.0, 0.0));
+    let first = loaded.roots[0].children()[0].clone();
+    let background_color = *first.background_color.borrow();
+    assert_eq!((background_color.r, background_color.g, background_color.b, background_color.a),
+               (1.0, 0.0, 0.0, 1.0));
+    assert_eq!(first.children()[0].tile_size(), Size2D::new(512, 128));
+}
+
+#[test]
+fn deep_trees_load_back_without_overflowing_the_stack() {
+    const DEPTH: usize = 10000;
+    let mut description = LayerDescription::new(rect(0.0, 0.0, 1.0, 1.0), ());
+    for _ in 0..DEPTH {
+        description = LayerDescription::new(rect(0.0, 0.0, 1.0, 1.0), ()).child(description);
+    }
+    let tree = LayerTree::build(description);
+    let mut scene = Scene::new(TypedRect::new(TypedPoint2D::zero(),
+                                              TypedSize2D::new(100.0, 100.0)));
+    scene.roots = vec!(tree.root.clone());
+
+    let loaded = round_trip(&scene, "deep");
+    let mut depth = 0;
+    let mut layer = loaded.roots[0].clone();
+    loop {
+        let child = match layer.children().first() {
+            Some(child) => child.clone(),
+            None => break,
+        };
+        layer = child;
+        depth += 1;
+    }
+    assert_eq!(depth, DEPTH);
+}
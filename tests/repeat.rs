@@ -0,0 +1,48 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Layers with repeated content request tiles for their whole pattern, at any scale.
+
+extern crate euclid;
+extern crate layers;
+
+mod common;
+
+use common::{TILE_SIZE, new_layer, new_scene, rect, request_buffers};
+use euclid::scale_factor::ScaleFactor;
+use euclid::size::TypedSize2D;
+use layers::layers::{ContentRepeat, RepeatMode};
+
+/// Scene scales, and the left edges of the layer.
+const CASES: [(f32, f32); 6] = [(0.5, 0.0), (1.0, 0.0), (2.0, 0.0), (3.0, 0.0), (4.0, 0.0),
+                                (4.0, 300.0)];
+
+#[test]
+fn repeated_layers_request_their_whole_pattern_at_any_scale() {
+    for &(scale, x) in CASES.iter() {
+        let layer = new_layer(rect(x, 100.0, 4000.0, 300.0), ());
+        *layer.content_repeat.borrow_mut() = Some(ContentRepeat {
+            mode: RepeatMode::RepeatX,
+            pattern_size: TypedSize2D::new(100.0, 50.0),
+        });
+        let mut scene = new_scene(&[layer.clone()], 800.0 * scale, 400.0 * scale);
+        scene.scale = ScaleFactor::new(scale);
+
+        let requests = request_buffers(&mut scene);
+        let tiles = |length: f32| ((length * scale).ceil() as usize + TILE_SIZE - 1) / TILE_SIZE;
+        let requested: usize = requests.iter().map(|&(_, ref requests)| requests.len()).sum();
+        assert_eq!(requested, tiles(100.0) * tiles(300.0), "scale {}", scale);
+        for &(_, ref requests) in &requests {
+            for request in requests {
+                assert!(request.screen_rect.max_x() <= (100.0 * scale).ceil() as usize,
+                        "scale {}: {:?} is outside of the pattern", scale, request.screen_rect);
+            }
+        }
+    }
+}
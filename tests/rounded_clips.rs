@@ -0,0 +1,52 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Clip regions clip hit tests to their rounded corners, within those of their ancestors.
+
+extern crate euclid;
+extern crate layers;
+
+mod common;
+
+use common::{hit_layer_name, new_layer, new_scene, rect};
+use euclid::point::TypedPoint2D;
+use layers::layers::{BorderRadii, ClipRegion};
+
+#[test]
+fn clip_regions_exclude_the_outside_of_their_corners() {
+    let clip_region = ClipRegion::new(rect(0.0, 0.0, 100.0, 100.0), BorderRadii::uniform(20.0));
+    assert!(clip_region.contains(&TypedPoint2D::new(50.0, 50.0)));
+    assert!(clip_region.contains(&TypedPoint2D::new(0.0, 50.0)));
+    assert!(clip_region.contains(&TypedPoint2D::new(10.0, 10.0)));
+    assert!(!clip_region.contains(&TypedPoint2D::new(5.0, 5.0)));
+    assert!(!clip_region.contains(&TypedPoint2D::new(95.0, 95.0)));
+    assert!(!clip_region.contains(&TypedPoint2D::new(101.0, 50.0)));
+
+    let square = ClipRegion::new(rect(0.0, 0.0, 100.0, 100.0), BorderRadii::uniform(0.0));
+    assert!(square.contains(&TypedPoint2D::new(1.0, 1.0)));
+}
+
+#[test]
+fn hit_tests_are_clipped_by_the_clip_regions_of_ancestors() {
+    let root = new_layer(rect(0.0, 0.0, 100.0, 100.0), "root");
+    *root.clip_region.borrow_mut() = Some(ClipRegion::new(rect(0.0, 0.0, 100.0, 100.0),
+                                                          BorderRadii::uniform(50.0)));
+    let child = new_layer(rect(50.0, 0.0, 100.0, 100.0), "child");
+    *child.clip_region.borrow_mut() = Some(ClipRegion::new(rect(0.0, 0.0, 100.0, 100.0),
+                                                           BorderRadii::uniform(0.0)));
+    root.add_child(child.clone());
+
+    let scene = new_scene(&[root.clone()], 200.0, 200.0);
+
+    assert_eq!(hit_layer_name(&scene, 60.0, 50.0), Some("child"));
+    assert_eq!(hit_layer_name(&scene, 40.0, 50.0), Some("root"));
+    // Outside of the rounded corner of the root, and outside of the root altogether.
+    assert_eq!(hit_layer_name(&scene, 95.0, 5.0), None);
+    assert_eq!(hit_layer_name(&scene, 120.0, 50.0), None);
+}
@@ -0,0 +1,102 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The software compositor blends layers into a framebuffer in CPU memory, and is chosen when
+//! the GL implementation can't be used.
+
+extern crate euclid;
+extern crate layers;
+
+mod common;
+
+use common::{new_layer, new_scene, rect};
+use layers::capabilities::GlCapabilities;
+use layers::color::Color;
+use layers::software::{CompositorBackend, SoftwareFramebuffer, render_scene_software};
+
+fn pixel(pixels: &[u8], stride: usize, x: usize, y: usize) -> &[u8] {
+    &pixels[y * stride + x * 4..y * stride + x * 4 + 4]
+}
+
+#[test]
+fn layers_are_blended_over_the_framebuffer() {
+    let red = Color { r: 1.0, g: 0.0, b: 0.0, a: 1.0 };
+    let translucent_blue = Color { r: 0.0, g: 0.0, b: 0.5, a: 0.5 };
+    let root = new_layer(rect(0.0, 0.0, 4.0, 4.0), ());
+    let opaque = new_layer(rect(1.0, 1.0, 2.0, 1.0), ());
+    *opaque.background_color.borrow_mut() = red;
+    let translucent = new_layer(rect(0.0, 3.0, 4.0, 1.0), ());
+    *translucent.background_color.borrow_mut() = translucent_blue;
+    root.add_child(opaque);
+    root.add_child(translucent);
+    let scene = new_scene(&[root.clone()], 4.0, 4.0);
+
+    let stride = 4 * 4;
+    let mut pixels = vec!(0; stride * 4);
+    render_scene_software(&scene, &mut SoftwareFramebuffer::new(&mut pixels, 4, 4, stride));
+
+    // Pixels are BGRA.
+    assert_eq!(pixel(&pixels, stride, 0, 0), &[255, 255, 255, 255]);
+    assert_eq!(pixel(&pixels, stride, 1, 1), &[0, 0, 255, 255]);
+    assert_eq!(pixel(&pixels, stride, 2, 1), &[0, 0, 255, 255]);
+    assert_eq!(pixel(&pixels, stride, 3, 1), &[255, 255, 255, 255]);
+    assert_eq!(pixel(&pixels, stride, 1, 3), &[255, 127, 127, 255]);
+}
+
+#[test]
+fn backgrounds_are_drawn_with_the_opacity_of_their_layer() {
+    let red = Color { r: 1.0, g: 0.0, b: 0.0, a: 1.0 };
+    let root = new_layer(rect(0.0, 0.0, 2.0, 1.0), ());
+    let faded = new_layer(rect(1.0, 0.0, 1.0, 1.0), ());
+    *faded.background_color.borrow_mut() = red;
+    *faded.opacity.borrow_mut() = 0.5;
+    root.add_child(faded);
+    let scene = new_scene(&[root.clone()], 2.0, 1.0);
+
+    let stride = 2 * 4;
+    let mut pixels = vec!(0; stride);
+    render_scene_software(&scene, &mut SoftwareFramebuffer::new(&mut pixels, 2, 1, stride));
+
+    assert_eq!(pixel(&pixels, stride, 0, 0), &[255, 255, 255, 255]);
+    assert_eq!(pixel(&pixels, stride, 1, 0), &[127, 127, 255, 255]);
+}
+
+#[test]
+fn the_software_compositor_is_chosen_when_gl_cannot_be_used() {
+    let mut capabilities = GlCapabilities {
+        is_gles: false,
+        version: (3, 2),
+        max_texture_size: 8192,
+        core_profile: true,
+        npot_textures: true,
+        rectangle_textures: true,
+        sync_objects: true,
+        timer_queries: true,
+        instancing: true,
+        framebuffer_multisample: true,
+        sampler_objects: true,
+        srgb_decode: true,
+        srgb_write_control: true,
+        uniform_buffers: true,
+        texture_swizzle: true,
+        bgra_textures: true,
+        pixel_buffers: true,
+        map_buffer_range: true,
+    };
+    assert_eq!(CompositorBackend::select(Some(&capabilities), |_| false), CompositorBackend::Gl);
+    assert_eq!(CompositorBackend::select(Some(&capabilities), |_| true),
+               CompositorBackend::Software);
+    assert_eq!(CompositorBackend::select(None, |_| false), CompositorBackend::Software);
+
+    capabilities.version = (2, 0);
+    assert_eq!(CompositorBackend::select(Some(&capabilities), |_| false),
+               CompositorBackend::Software);
+    capabilities.is_gles = true;
+    assert_eq!(CompositorBackend::select(Some(&capabilities), |_| false), CompositorBackend::Gl);
+}
@@ -0,0 +1,30 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Traces keep only their most recent events.
+
+extern crate layers;
+
+use layers::profiler::ProfilerCallbacks;
+use layers::trace::ChromeTraceExporter;
+
+#[test]
+fn traces_keep_the_most_recent_events() {
+    let exporter = ChromeTraceExporter::with_max_events(3);
+    exporter.begin_span("first");
+    exporter.end_span("first");
+    exporter.begin_span("second");
+    exporter.end_span("second");
+
+    let mut json = vec!();
+    exporter.write_json(&mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(!json.contains("first"));
+    assert_eq!(json.matches("\"second\"").count(), 2);
+}
@@ -0,0 +1,66 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Surfaces sent over a Unix socket arrive with their descriptor, and with the file descriptor
+//! that names them.
+
+#![cfg(any(target_os = "linux", target_os = "android"))]
+
+extern crate euclid;
+extern crate layers;
+
+use euclid::size::Size2D;
+use layers::platform::surface::NativeSurfaceDescriptor;
+use layers::platform::transport::{self, SurfaceHandle, SurfaceTransfer};
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::os::unix::net::UnixStream;
+
+fn received_pixels(transfer: &SurfaceTransfer) -> (Size2D<i32>, Vec<u8>) {
+    match transfer.descriptor {
+        NativeSurfaceDescriptor::MemoryBuffer(size, ref pixels) => (size, pixels.clone()),
+        _ => panic!("A memory buffer was sent"),
+    }
+}
+
+#[test]
+fn surfaces_arrive_with_their_file_descriptor() {
+    let path = env::temp_dir().join("layers-transport-fd");
+    File::create(&path).unwrap().write_all(b"dma-buf").unwrap();
+    let fd = File::open(&path).unwrap().into_raw_fd();
+    fs::remove_file(&path).unwrap();
+
+    let (sender, receiver) = UnixStream::pair().unwrap();
+    let descriptor = NativeSurfaceDescriptor::MemoryBuffer(Size2D::new(1, 1), vec!(1, 2, 3, 4));
+    let handle = unsafe { SurfaceHandle::from_raw(fd) };
+    transport::send_surface(&sender, SurfaceTransfer::new(descriptor, Some(handle))).unwrap();
+
+    let transfer = transport::receive_surface(&receiver).unwrap();
+    assert_eq!(received_pixels(&transfer), (Size2D::new(1, 1), vec!(1, 2, 3, 4)));
+    let mut file = unsafe { File::from_raw_fd(transfer.handle.unwrap().into_raw()) };
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "dma-buf");
+}
+
+#[test]
+fn surfaces_without_a_file_descriptor_arrive_without_one() {
+    let (sender, receiver) = UnixStream::pair().unwrap();
+    let transfer = SurfaceTransfer::from_pixels(Size2D::new(2, 1), &[0; 8]);
+    transport::send_surface(&sender, transfer).unwrap();
+    transport::send_surface(&sender, SurfaceTransfer::from_pixels(Size2D::zero(), &[])).unwrap();
+
+    let first = transport::receive_surface(&receiver).unwrap();
+    assert_eq!(received_pixels(&first), (Size2D::new(2, 1), vec!(0; 8)));
+    assert!(first.handle.is_none());
+    let second = transport::receive_surface(&receiver).unwrap();
+    assert_eq!(received_pixels(&second), (Size2D::zero(), vec!()));
+}
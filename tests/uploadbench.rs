@@ -0,0 +1,60 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Choosing and remembering the upload method, without a GL context to measure with.
+
+extern crate layers;
+
+use layers::texturegl::UploadMethod;
+use layers::uploadbench::{UploadTiming, fastest_method, load_upload_method_for_driver};
+use layers::uploadbench::save_upload_method_for_driver;
+use std::env;
+use std::fs;
+use std::time::Duration;
+
+fn timing(method: UploadMethod, milliseconds: u64) -> UploadTiming {
+    UploadTiming {
+        method: method,
+        duration: Duration::from_millis(milliseconds),
+    }
+}
+
+#[test]
+fn fastest_method_picks_the_shortest_duration() {
+    let timings = [timing(UploadMethod::TexImage, 12),
+                   timing(UploadMethod::TexSubImage, 9),
+                   timing(UploadMethod::PixelBuffer, 10)];
+    assert_eq!(fastest_method(&timings), UploadMethod::TexSubImage);
+}
+
+#[test]
+fn fastest_method_prefers_earlier_methods_on_ties() {
+    let timings = [timing(UploadMethod::PixelBuffer, 5),
+                   timing(UploadMethod::TexImage, 5),
+                   timing(UploadMethod::TexSubImage, 7)];
+    assert_eq!(fastest_method(&timings), UploadMethod::PixelBuffer);
+}
+
+#[test]
+fn fastest_method_without_timings_is_tex_image() {
+    assert_eq!(fastest_method(&[]), UploadMethod::TexImage);
+}
+
+#[test]
+fn saved_methods_load_back_for_the_same_driver_only() {
+    let path = env::temp_dir().join("layers-uploadbench-round-trip.json");
+    let driver = "Vendor / Renderer / 1.0";
+    for &method in &[UploadMethod::TexImage, UploadMethod::TexSubImage, UploadMethod::PixelBuffer] {
+        save_upload_method_for_driver(&path, driver, method).unwrap();
+        assert_eq!(load_upload_method_for_driver(&path, driver).unwrap(), Some(method));
+        assert_eq!(load_upload_method_for_driver(&path, "Vendor / Renderer / 2.0").unwrap(),
+                   None);
+    }
+    fs::remove_file(&path).unwrap();
+}
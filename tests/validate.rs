@@ -0,0 +1,83 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The layer tree validator reports stale transform states, and leaves alone the singular
+//! transforms and clipped out layers that real pages have.
+
+extern crate euclid;
+extern crate layers;
+
+mod common;
+
+use common::{new_layer, new_scene, rect};
+use euclid::Matrix4D;
+use euclid::point::{Point2D, TypedPoint2D};
+use layers::validate::{validate_tree, validate_tree_with_offset};
+
+#[test]
+fn singular_transforms_are_valid() {
+    let root = new_layer(rect(0.0, 0.0, 1000.0, 1000.0), "root");
+    let collapsed = new_layer(rect(100.0, 100.0, 200.0, 200.0), "collapsed");
+    *collapsed.transform.borrow_mut() = Matrix4D::identity().pre_scaled(0.0, 0.0, 1.0);
+    let flattened = new_layer(rect(100.0, 100.0, 200.0, 200.0), "flattened");
+    *flattened.perspective.borrow_mut() = Matrix4D::identity().pre_scaled(1.0, 1.0, 0.0);
+    root.add_child(collapsed.clone());
+    root.add_child(flattened.clone());
+    new_scene(&[root.clone()], 1000.0, 1000.0);
+
+    let errors = validate_tree(&root);
+    assert!(errors.is_empty(), "{:?}", errors);
+}
+
+#[test]
+fn clipped_out_children_are_valid() {
+    let root = new_layer(rect(0.0, 0.0, 1000.0, 1000.0), "root");
+    let parent = new_layer(rect(0.0, 0.0, 100.0, 100.0), "parent");
+    *parent.masks_to_bounds.borrow_mut() = true;
+    let child = new_layer(rect(500.0, 500.0, 100.0, 100.0), "child");
+    parent.add_child(child.clone());
+    root.add_child(parent.clone());
+    new_scene(&[root.clone()], 1000.0, 1000.0);
+
+    let errors = validate_tree(&root);
+    assert!(errors.is_empty(), "{:?}", errors);
+}
+
+#[test]
+fn stale_transform_states_are_reported() {
+    let root = new_layer(rect(0.0, 0.0, 1000.0, 1000.0), "root");
+    let child = new_layer(rect(100.0, 100.0, 200.0, 200.0), "child");
+    root.add_child(child.clone());
+    new_scene(&[root.clone()], 1000.0, 1000.0);
+
+    // The child moves without its transform state being updated.
+    *child.bounds.borrow_mut() = rect(300.0, 100.0, 200.0, 200.0);
+    let errors = validate_tree(&root);
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert!(errors[0].message.contains("world rect"), "{}", errors[0]);
+
+    root.update_transform_state(&Matrix4D::identity(), &Matrix4D::identity(), &Point2D::zero());
+    assert!(validate_tree(&root).is_empty());
+}
+
+#[test]
+fn overridden_root_scroll_offsets_are_valid() {
+    let root = new_layer(rect(0.0, 0.0, 1000.0, 1000.0), "root");
+    let child = new_layer(rect(100.0, 100.0, 200.0, 200.0), "child");
+    root.add_child(child.clone());
+    new_scene(&[root.clone()], 1000.0, 1000.0);
+
+    let offset = TypedPoint2D::new(0.0, -50.0);
+    root.update_transform_state_with_offset(&Matrix4D::identity(),
+                                            &Matrix4D::identity(),
+                                            &Point2D::zero(),
+                                            &offset);
+    assert!(validate_tree_with_offset(&root, &offset).is_empty());
+    assert!(!validate_tree(&root).is_empty());
+}
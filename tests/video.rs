@@ -0,0 +1,47 @@
+// Copyright 2016 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The YUV to RGB conversion of video frames, on 8-bit video range samples.
+
+extern crate euclid;
+extern crate layers;
+
+use euclid::Matrix4D;
+use layers::video::YuvColorSpace;
+
+/// Converts 8-bit samples as the shader does, with the samples as a row vector.
+fn to_rgb(matrix: &Matrix4D<f32>, y: u8, u: u8, v: u8) -> [f32; 3] {
+    let (y, u, v) = (y as f32 / 255.0, u as f32 / 255.0, v as f32 / 255.0);
+    [y * matrix.m11 + u * matrix.m21 + v * matrix.m31 + matrix.m41,
+     y * matrix.m12 + u * matrix.m22 + v * matrix.m32 + matrix.m42,
+     y * matrix.m13 + u * matrix.m23 + v * matrix.m33 + matrix.m43]
+}
+
+fn assert_gray(rgb: [f32; 3], expected: f32) {
+    for &channel in &rgb {
+        assert!((channel - expected).abs() < 1e-4, "{:?} isn't gray {}", rgb, expected);
+    }
+}
+
+#[test]
+fn video_range_black_and_white_map_to_0_and_1() {
+    for &color_space in &[YuvColorSpace::Rec601, YuvColorSpace::Rec709] {
+        let matrix = color_space.color_matrix();
+        assert_gray(to_rgb(&matrix, 16, 128, 128), 0.0);
+        assert_gray(to_rgb(&matrix, 235, 128, 128), 1.0);
+    }
+}
+
+#[test]
+fn neutral_chroma_is_gray() {
+    for &color_space in &[YuvColorSpace::Rec601, YuvColorSpace::Rec709] {
+        let matrix = color_space.color_matrix();
+        assert_gray(to_rgb(&matrix, 126, 128, 128), 110.0 / 219.0);
+    }
+}